@@ -0,0 +1,39 @@
+#version 450
+
+// Keeps what is brighter than a threshold, at half resolution. Four bilinear taps average the 4x4
+// input texels around each output texel, which keeps small highlights from flickering.
+
+layout(set = 0, binding = 0) uniform texture2D inputImage;
+layout(set = 0, binding = 1) uniform texture2D secondImage;
+layout(set = 0, binding = 2) uniform sampler inputSampler;
+layout(set = 0, binding = 3) uniform sampler secondSampler;
+
+layout(push_constant) uniform Post {
+  vec2 texel_size;
+  uint mode;
+  vec4 params;
+} post;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleInput(vec2 uv) {
+  return texture(sampler2D(inputImage, inputSampler), uv).rgb;
+}
+
+void main() {
+  vec2 offset = post.texel_size;
+  vec3 color = (sampleInput(fragUv + vec2(-offset.x, -offset.y)) + sampleInput(fragUv + vec2(offset.x, -offset.y)) +
+                sampleInput(fragUv + vec2(-offset.x, offset.y)) + sampleInput(fragUv + offset)) * 0.25;
+
+  // fade in over the knee below the threshold rather than cutting off
+  float threshold = post.params.x;
+  float knee = post.params.y;
+  float brightness = max(color.r, max(color.g, color.b));
+  float soft = clamp(brightness - threshold + knee, 0., 2. * knee);
+  soft = soft * soft / (4. * knee + 1e-5);
+  float contribution = max(soft, brightness - threshold) / max(brightness, 1e-5);
+
+  outColor = vec4(color * contribution, 1.);
+}
@@ -0,0 +1,31 @@
+#version 450
+
+// One direction of a separable 9 tap Gaussian blur, taking 5 samples by sampling between texels.
+// params.xy is the direction, in texels.
+
+layout(set = 0, binding = 0) uniform texture2D inputImage;
+layout(set = 0, binding = 1) uniform texture2D secondImage;
+layout(set = 0, binding = 2) uniform sampler inputSampler;
+layout(set = 0, binding = 3) uniform sampler secondSampler;
+
+layout(push_constant) uniform Post {
+  vec2 texel_size;
+  uint mode;
+  vec4 params;
+} post;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleInput(vec2 uv) {
+  return texture(sampler2D(inputImage, inputSampler), uv).rgb;
+}
+
+void main() {
+  vec2 step = post.texel_size * post.params.xy;
+  vec3 color = sampleInput(fragUv) * 0.2270270270;
+  color += (sampleInput(fragUv + step * 1.3846153846) + sampleInput(fragUv - step * 1.3846153846)) * 0.3162162162;
+  color += (sampleInput(fragUv + step * 3.2307692308) + sampleInput(fragUv - step * 3.2307692308)) * 0.0702702703;
+  outColor = vec4(color, 1.);
+}
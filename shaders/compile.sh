@@ -1,2 +1,26 @@
 glslc shader.vert -o vert.spv
 glslc shader.frag -o frag.spv
+glslc pbr.frag -o pbr_frag.spv
+glslc shadow.vert -o shadow_vert.spv
+glslc skybox.vert -o skybox_vert.spv
+glslc skybox.frag -o skybox_frag.spv
+glslc sky.comp -o sky.spv
+glslc equirect.comp -o equirect.spv
+glslc irradiance.comp -o irradiance.spv
+glslc prefilter.comp -o prefilter.spv
+glslc brdf_lut.comp -o brdf_lut.spv
+glslc fullscreen.vert -o fullscreen_vert.spv
+glslc bloom_threshold.frag -o bloom_threshold_frag.spv
+glslc blur.frag -o blur_frag.spv
+glslc tonemap.frag -o tonemap_frag.spv
+glslc grading.frag -o grading_frag.spv
+glslc fxaa.frag -o fxaa_frag.spv
+glslc gamma.frag -o gamma_frag.spv
+glslc ui.vert -o ui_vert.spv
+glslc ui.frag -o ui_frag.spv
+glslc text.vert -o text_vert.spv
+glslc text.frag -o text_frag.spv
+glslc sprite.vert -o sprite_vert.spv
+glslc sprite.frag -o sprite_frag.spv
+glslc cull.comp -o cull.spv
+glslc depth_pyramid.comp -o depth_pyramid.spv
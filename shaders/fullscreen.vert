@@ -0,0 +1,11 @@
+#version 450
+
+// Covers the screen with one triangle, passing on texture coordinates that go from 0 to 1 across
+// the screen.
+
+layout(location = 0) out vec2 fragUv;
+
+void main() {
+  fragUv = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2));
+  gl_Position = vec4(fragUv * 2. - 1., 0., 1.);
+}
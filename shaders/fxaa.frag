@@ -0,0 +1,60 @@
+#version 450
+
+// FXAA: blurs along the edges found in the luma around each pixel, using the early, single pass
+// version of the algorithm.
+
+layout(set = 0, binding = 0) uniform texture2D inputImage;
+layout(set = 0, binding = 1) uniform texture2D secondImage;
+layout(set = 0, binding = 2) uniform sampler inputSampler;
+layout(set = 0, binding = 3) uniform sampler secondSampler;
+
+layout(push_constant) uniform Post {
+  vec2 texel_size;
+  uint mode;
+  vec4 params;
+} post;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleInput(vec2 uv) {
+  return texture(sampler2D(inputImage, inputSampler), uv).rgb;
+}
+
+const float REDUCE_MIN = 1. / 128.;
+const float REDUCE_MUL = 1. / 8.;
+const float SPAN_MAX = 8.;
+
+// perceptual luma, the colors are linear
+float luma(vec3 color) {
+  return sqrt(dot(clamp(color, 0., 1.), vec3(0.299, 0.587, 0.114)));
+}
+
+void main() {
+  vec2 texel = post.texel_size;
+  vec3 rgb_m = sampleInput(fragUv);
+  float luma_nw = luma(sampleInput(fragUv + vec2(-1., -1.) * texel));
+  float luma_ne = luma(sampleInput(fragUv + vec2(1., -1.) * texel));
+  float luma_sw = luma(sampleInput(fragUv + vec2(-1., 1.) * texel));
+  float luma_se = luma(sampleInput(fragUv + vec2(1., 1.) * texel));
+  float luma_m = luma(rgb_m);
+  float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+  float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+  vec2 direction = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+  float reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+  float scale = 1. / (min(abs(direction.x), abs(direction.y)) + reduce);
+  direction = clamp(direction * scale, -SPAN_MAX, SPAN_MAX) * texel;
+
+  vec3 rgb_a = 0.5 * (sampleInput(fragUv + direction * (1. / 3. - 0.5)) + sampleInput(fragUv + direction * (2. / 3. - 0.5)));
+  vec3 rgb_b = rgb_a * 0.5 + 0.25 * (sampleInput(fragUv - direction * 0.5) + sampleInput(fragUv + direction * 0.5));
+  float luma_b = luma(rgb_b);
+
+  // the wider blur went past the edge if it left the local luma range
+  if (luma_b < luma_min || luma_b > luma_max) {
+    outColor = vec4(rgb_a, 1.);
+  } else {
+    outColor = vec4(rgb_b, 1.);
+  }
+}
@@ -0,0 +1,28 @@
+#version 450
+
+// Encodes linear colors with the gamma whose reciprocal is params.x, for targets that do not
+// encode to sRGB themselves.
+
+layout(set = 0, binding = 0) uniform texture2D inputImage;
+layout(set = 0, binding = 1) uniform texture2D secondImage;
+layout(set = 0, binding = 2) uniform sampler inputSampler;
+layout(set = 0, binding = 3) uniform sampler secondSampler;
+
+layout(push_constant) uniform Post {
+  vec2 texel_size;
+  uint mode;
+  vec4 params;
+} post;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleInput(vec2 uv) {
+  return texture(sampler2D(inputImage, inputSampler), uv).rgb;
+}
+
+void main() {
+  vec3 color = max(sampleInput(fragUv), 0.);
+  outColor = vec4(pow(color, vec3(post.params.x)), 1.);
+}
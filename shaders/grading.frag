@@ -0,0 +1,34 @@
+#version 450
+
+// Adjusts contrast around middle grey by params.x, saturation by params.y and white balance by
+// params.z.
+
+layout(set = 0, binding = 0) uniform texture2D inputImage;
+layout(set = 0, binding = 1) uniform texture2D secondImage;
+layout(set = 0, binding = 2) uniform sampler inputSampler;
+layout(set = 0, binding = 3) uniform sampler secondSampler;
+
+layout(push_constant) uniform Post {
+  vec2 texel_size;
+  uint mode;
+  vec4 params;
+} post;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleInput(vec2 uv) {
+  return texture(sampler2D(inputImage, inputSampler), uv).rgb;
+}
+
+void main() {
+  vec3 color = sampleInput(fragUv);
+
+  color = max((color - 0.18) * post.params.x + 0.18, 0.);
+  float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+  color = max(mix(vec3(luminance), color, post.params.y), 0.);
+  color *= vec3(1. + 0.1 * post.params.z, 1., 1. - 0.1 * post.params.z);
+
+  outColor = vec4(clamp(color, 0., 1.), 1.);
+}
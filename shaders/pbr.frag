@@ -0,0 +1,235 @@
+#version 450
+
+// Metallic-roughness shading as in the glTF specification: a Lambertian diffuse and a GGX
+// specular lobe, lit by the scene's punctual lights and by the environment through the split sum
+// approximation.
+
+layout(set = 0, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 projection;
+  mat4 view_projection;
+  vec4 position;
+} camera;
+
+struct Light {
+  // xyz position, w range or 0 if unlimited
+  vec4 position;
+  // xyz direction the light shines in, w kind: 0 directional, 1 point, 2 spot
+  vec4 direction;
+  // rgb color, w intensity
+  vec4 color;
+  // xy cosines of the inner and outer cone angles of spot lights, z first shadow map layer or -1
+  vec4 cone;
+};
+
+layout(std430, set = 0, binding = 1) readonly buffer Lights {
+  uint lightCount;
+  Light lights[];
+};
+
+layout(set = 0, binding = 2) uniform Shadows {
+  // world to shadow map clip space of each layer
+  mat4 matrices[8];
+  // view space distance at which each cascade ends
+  vec4 cascade_splits;
+  uint layer_count;
+} shadows;
+
+layout(set = 0, binding = 3) uniform texture2DArray shadowMap;
+layout(set = 0, binding = 4) uniform samplerShadow shadowSampler;
+
+layout(push_constant) uniform Object {
+  vec4 base_color;
+  vec4 emissive;
+  // metallic, roughness, normal scale, occlusion strength
+  vec4 material;
+} object;
+
+// each texture is read with the sampler five bindings after it
+layout(set = 1, binding = 0) uniform texture2D baseColorTexture;
+layout(set = 1, binding = 1) uniform texture2D metallicRoughnessTexture;
+layout(set = 1, binding = 2) uniform texture2D normalTexture;
+layout(set = 1, binding = 3) uniform texture2D occlusionTexture;
+layout(set = 1, binding = 4) uniform texture2D emissiveTexture;
+layout(set = 1, binding = 5) uniform sampler baseColorSampler;
+layout(set = 1, binding = 6) uniform sampler metallicRoughnessSampler;
+layout(set = 1, binding = 7) uniform sampler normalSampler;
+layout(set = 1, binding = 8) uniform sampler occlusionSampler;
+layout(set = 1, binding = 9) uniform sampler emissiveSampler;
+
+// likewise, four bindings after
+layout(set = 2, binding = 1) uniform textureCube irradianceMap;
+layout(set = 2, binding = 2) uniform textureCube prefilteredMap;
+layout(set = 2, binding = 3) uniform texture2D brdfLut;
+layout(set = 2, binding = 5) uniform sampler irradianceSampler;
+layout(set = 2, binding = 6) uniform sampler prefilteredSampler;
+layout(set = 2, binding = 7) uniform sampler brdfLutSampler;
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec2 fragUv;
+layout(location = 2) in vec3 fragPosition;
+
+layout(location = 0) out vec4 outColor;
+
+const float PI = 3.14159265359;
+
+// Applies the normal map in a tangent frame derived from screen space derivatives, as meshes do
+// not carry tangents.
+vec3 shadingNormal() {
+  vec3 normal = normalize(fragNormal);
+
+  vec3 uv_dx = dFdx(vec3(fragUv, 0.));
+  vec3 uv_dy = dFdy(vec3(fragUv, 0.));
+  float determinant = uv_dx.x * uv_dy.y - uv_dy.x * uv_dx.y;
+  if (abs(determinant) < 1e-8) {
+    return normal;
+  }
+  vec3 t = (uv_dy.y * dFdx(fragPosition) - uv_dx.y * dFdy(fragPosition)) / determinant;
+  vec3 tangent = t - normal * dot(normal, t);
+  if (dot(tangent, tangent) < 1e-12) {
+    return normal;
+  }
+  tangent = normalize(tangent);
+  vec3 bitangent = cross(normal, tangent);
+
+  vec3 mapped = texture(sampler2D(normalTexture, normalSampler), fragUv).xyz * 2. - 1.;
+  mapped.xy *= object.material.z;
+  return normalize(mat3(tangent, bitangent, normal) * mapped);
+}
+
+float distributionGgx(float n_dot_h, float alpha) {
+  float a2 = alpha * alpha;
+  float denominator = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+  return a2 / (PI * denominator * denominator);
+}
+
+// height correlated Smith visibility, the geometry term divided by 4 n·l n·v
+float visibilitySmithGgx(float n_dot_l, float n_dot_v, float alpha) {
+  float a2 = alpha * alpha;
+  float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1. - a2) + a2);
+  float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1. - a2) + a2);
+  float ggx = ggx_v + ggx_l;
+  return ggx > 0. ? 0.5 / ggx : 0.;
+}
+
+vec3 fresnelSchlick(vec3 f0, float v_dot_h) {
+  return f0 + (1. - f0) * pow(clamp(1. - v_dot_h, 0., 1.), 5.);
+}
+
+// The radiance arriving from `light` and the direction towards it.
+vec3 incomingLight(Light light, out vec3 to_light) {
+  int kind = int(light.direction.w);
+  if (kind == 0) {
+    to_light = -normalize(light.direction.xyz);
+    return light.color.rgb * light.color.w;
+  }
+
+  vec3 offset = light.position.xyz - fragPosition;
+  float distance2 = max(dot(offset, offset), 1e-4);
+  to_light = offset * inversesqrt(distance2);
+
+  float attenuation = 1. / distance2;
+  float range = light.position.w;
+  if (range > 0.) {
+    // fade out smoothly to reach zero at the range, as recommended by KHR_lights_punctual
+    float ratio = sqrt(distance2) / range;
+    attenuation *= clamp(1. - ratio * ratio * ratio * ratio, 0., 1.);
+  }
+
+  if (kind == 2) {
+    float cos_angle = dot(normalize(light.direction.xyz), -to_light);
+    float spot = clamp((cos_angle - light.cone.y) / max(light.cone.x - light.cone.y, 1e-4), 0., 1.);
+    attenuation *= spot * spot;
+  }
+
+  return light.color.rgb * light.color.w * attenuation;
+}
+
+// The fraction of `light` that reaches the fragment past shadow casters, filtered over the
+// neighbouring shadow map texels. Directional lights have a layer per cascade.
+float shadowFactor(Light light) {
+  int layer = int(light.cone.z);
+  if (layer < 0) {
+    return 1.;
+  }
+
+  if (int(light.direction.w) == 0) {
+    float depth = -(camera.view * vec4(fragPosition, 1.)).z;
+    int cascade = 0;
+    while (cascade < 4 && depth > shadows.cascade_splits[cascade]) {
+      cascade++;
+    }
+    if (cascade == 4) {
+      return 1.;
+    }
+    layer += cascade;
+  }
+
+  vec4 clip = shadows.matrices[layer] * vec4(fragPosition, 1.);
+  vec3 coords = clip.xyz / clip.w;
+  if (coords.z <= 0. || coords.z >= 1.) {
+    return 1.;
+  }
+  vec2 uv = coords.xy * 0.5 + 0.5;
+
+  // each tap compares the four texels around it, so 3x3 taps filter over 4x4 texels
+  vec2 texel = 1. / vec2(textureSize(sampler2DArrayShadow(shadowMap, shadowSampler), 0).xy);
+  float lit = 0.;
+  for (int y = -1; y <= 1; y++) {
+    for (int x = -1; x <= 1; x++) {
+      vec4 tap = vec4(uv + vec2(x, y) * texel, float(layer), coords.z);
+      lit += texture(sampler2DArrayShadow(shadowMap, shadowSampler), tap);
+    }
+  }
+  return lit / 9.;
+}
+
+void main() {
+  vec4 base_color = object.base_color * texture(sampler2D(baseColorTexture, baseColorSampler), fragUv);
+  vec4 metallic_roughness = texture(sampler2D(metallicRoughnessTexture, metallicRoughnessSampler), fragUv);
+  float metallic = clamp(object.material.x * metallic_roughness.b, 0., 1.);
+  float roughness = clamp(object.material.y * metallic_roughness.g, 0.03, 1.);
+  float alpha = roughness * roughness;
+  float occlusion = 1. + object.material.w * (texture(sampler2D(occlusionTexture, occlusionSampler), fragUv).r - 1.);
+  vec3 emissive = object.emissive.rgb * texture(sampler2D(emissiveTexture, emissiveSampler), fragUv).rgb;
+
+  vec3 normal = shadingNormal();
+  vec3 view = normalize(camera.position.xyz - fragPosition);
+  float n_dot_v = clamp(dot(normal, view), 1e-4, 1.);
+
+  vec3 diffuse_color = base_color.rgb * (1. - metallic);
+  vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+
+  vec3 color = vec3(0.);
+  for (uint i = 0u; i < lightCount; i++) {
+    vec3 to_light;
+    vec3 radiance = incomingLight(lights[i], to_light);
+
+    float n_dot_l = dot(normal, to_light);
+    if (n_dot_l <= 0.) {
+      continue;
+    }
+    radiance *= shadowFactor(lights[i]);
+
+    vec3 half_vector = normalize(to_light + view);
+    float n_dot_h = max(dot(normal, half_vector), 0.);
+    float v_dot_h = max(dot(view, half_vector), 0.);
+
+    vec3 fresnel = fresnelSchlick(f0, v_dot_h);
+    vec3 specular = fresnel * distributionGgx(n_dot_h, alpha) * visibilitySmithGgx(n_dot_l, n_dot_v, alpha);
+    vec3 diffuse = (1. - fresnel) * diffuse_color / PI;
+    color += (diffuse + specular) * radiance * n_dot_l;
+  }
+
+  // image based lighting, with the Fresnel term adjusted for roughness
+  vec3 fresnel = f0 + (max(vec3(1. - roughness), f0) - f0) * pow(1. - n_dot_v, 5.);
+  vec3 irradiance = texture(samplerCube(irradianceMap, irradianceSampler), normal).rgb;
+  vec3 reflection = reflect(-view, normal);
+  float max_lod = float(textureQueryLevels(samplerCube(prefilteredMap, prefilteredSampler)) - 1);
+  vec3 prefiltered = textureLod(samplerCube(prefilteredMap, prefilteredSampler), reflection, roughness * max_lod).rgb;
+  vec2 brdf = texture(sampler2D(brdfLut, brdfLutSampler), vec2(n_dot_v, roughness)).rg;
+  vec3 ambient = (1. - fresnel) * diffuse_color * irradiance + prefiltered * (fresnel * brdf.x + brdf.y);
+  color += ambient * occlusion + emissive;
+
+  outColor = vec4(color, base_color.a);
+}
@@ -1,10 +1,35 @@
 #version 450
 
+layout(push_constant) uniform Object {
+  vec4 base_color;
+  vec4 emissive;
+  // metallic, roughness, normal scale, occlusion strength
+  vec4 material;
+} object;
+
+// each texture is read with the sampler five bindings after it
+layout(set = 1, binding = 0) uniform texture2D baseColorTexture;
+layout(set = 1, binding = 1) uniform texture2D metallicRoughnessTexture;
+layout(set = 1, binding = 2) uniform texture2D normalTexture;
+layout(set = 1, binding = 3) uniform texture2D occlusionTexture;
+layout(set = 1, binding = 4) uniform texture2D emissiveTexture;
+layout(set = 1, binding = 5) uniform sampler baseColorSampler;
+layout(set = 1, binding = 6) uniform sampler metallicRoughnessSampler;
+layout(set = 1, binding = 7) uniform sampler normalSampler;
+layout(set = 1, binding = 8) uniform sampler occlusionSampler;
+layout(set = 1, binding = 9) uniform sampler emissiveSampler;
+
+layout(location = 0) in vec3 fragNormal;
+layout(location = 1) in vec2 fragUv;
+
 layout(location = 0) out vec4 outColor;
-layout(location = 0) in vec3 fragColor;
 
 void main() {
-  outColor = vec4(fragColor, 1.);
-}
-
+  vec4 base_color = object.base_color * texture(sampler2D(baseColorTexture, baseColorSampler), fragUv);
+  float occlusion = 1. + object.material.w * (texture(sampler2D(occlusionTexture, occlusionSampler), fragUv).r - 1.);
+  vec3 emissive = object.emissive.rgb * texture(sampler2D(emissiveTexture, emissiveSampler), fragUv).rgb;
 
+  vec3 light_direction = normalize(vec3(-0.4, -1., -0.3));
+  float diffuse = max(dot(normalize(fragNormal), -light_direction), 0.);
+  outColor = vec4(base_color.rgb * (0.2 * occlusion + 0.8 * diffuse) + emissive, base_color.a);
+}
@@ -1,23 +1,30 @@
 #version 450
 
+layout(set = 0, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 projection;
+  mat4 view_projection;
+  vec4 position;
+} camera;
 
-layout(location=0) out vec3 fragColor;
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUv;
+// the columns of the instance's model matrix
+layout(location = 3) in vec4 inModel0;
+layout(location = 4) in vec4 inModel1;
+layout(location = 5) in vec4 inModel2;
+layout(location = 6) in vec4 inModel3;
 
-vec2 positions[3] = vec2[](
-  vec2 (0., -0.5),
-  vec2 (0.5, 0.5),
-  vec2 (-0.5, 0.5)
-);
-
-vec3 colors[3] = vec3[](
-  vec3 (1., 0., 0.),
-  vec3 (0., 1., 0.),
-  vec3 (0., 0., 1.)
-);
+layout(location = 0) out vec3 fragNormal;
+layout(location = 1) out vec2 fragUv;
+layout(location = 2) out vec3 fragPosition;
 
 void main() {
-  gl_Position = vec4(positions[gl_VertexIndex], 0., 1.);
-  fragColor = colors[gl_VertexIndex];
+  mat4 model = mat4(inModel0, inModel1, inModel2, inModel3);
+  vec4 position = model * vec4(inPosition, 1.);
+  gl_Position = camera.view_projection * position;
+  fragNormal = mat3(model) * inNormal;
+  fragUv = inUv;
+  fragPosition = position.xyz;
 }
-
-
@@ -0,0 +1,27 @@
+#version 450
+
+// Renders depth only, from a light into one layer of the shadow map.
+
+layout(set = 0, binding = 2) uniform Shadows {
+  // world to shadow map clip space of each layer
+  mat4 matrices[8];
+  // view space distance at which each cascade ends
+  vec4 cascade_splits;
+  uint layer_count;
+} shadows;
+
+layout(push_constant) uniform Layer {
+  uint layer;
+} object;
+
+layout(location = 0) in vec3 inPosition;
+// the columns of the instance's model matrix
+layout(location = 3) in vec4 inModel0;
+layout(location = 4) in vec4 inModel1;
+layout(location = 5) in vec4 inModel2;
+layout(location = 6) in vec4 inModel3;
+
+void main() {
+  mat4 model = mat4(inModel0, inModel1, inModel2, inModel3);
+  gl_Position = shadows.matrices[object.layer] * model * vec4(inPosition, 1.);
+}
@@ -0,0 +1,13 @@
+#version 450
+
+layout(set = 2, binding = 0) uniform textureCube environment;
+layout(set = 2, binding = 4) uniform sampler environmentSampler;
+
+layout(location = 0) in vec3 fragDirection;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+  vec3 color = textureLod(samplerCube(environment, environmentSampler), fragDirection, 0.).rgb;
+  outColor = vec4(color, 1.);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+// Covers the screen with one triangle at the far plane, passing on the direction each pixel
+// looks in.
+
+layout(set = 0, binding = 0) uniform Camera {
+  mat4 view;
+  mat4 projection;
+  mat4 view_projection;
+  vec4 position;
+} camera;
+
+layout(location = 0) out vec3 fragDirection;
+
+void main() {
+  vec2 ndc = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2. - 1.;
+  gl_Position = vec4(ndc, 1., 1.);
+
+  // an orthographic camera looks in the same direction from every pixel
+  vec3 view_direction = vec3(0., 0., -1.);
+  if (camera.projection[3][3] == 0.) {
+    view_direction = vec3(ndc.x / camera.projection[0][0], ndc.y / camera.projection[1][1], -1.);
+  }
+  fragDirection = transpose(mat3(camera.view)) * view_direction;
+}
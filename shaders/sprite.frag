@@ -0,0 +1,44 @@
+#version 450
+
+// Tints the instance's texture, white for plain shapes, and cuts circles out of their quads with
+// an antialiased edge. The output is premultiplied, as the pipeline blends.
+
+layout(set = 0, binding = 0) uniform texture2D spriteTexture;
+layout(set = 0, binding = 1) uniform sampler spriteSampler;
+
+layout(push_constant) uniform Batch {
+  mat4 projection;
+  uint srgb_target;
+} batch;
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 1) in vec2 fragLocal;
+layout(location = 2) in vec4 fragColor;
+layout(location = 3) flat in uint fragShape;
+
+layout(location = 0) out vec4 outColor;
+
+const uint SHAPE_CIRCLE = 1;
+
+vec3 linearFromGamma(vec3 color) {
+  bvec3 cutoff = lessThan(color, vec3(0.04045));
+  vec3 lower = color / 12.92;
+  vec3 higher = pow((color + 0.055) / 1.055, vec3(2.4));
+  return mix(higher, lower, cutoff);
+}
+
+void main() {
+  vec4 tint = fragColor;
+  if (batch.srgb_target != 0) {
+    tint.rgb = linearFromGamma(tint.rgb);
+  }
+  vec4 color = tint * texture(sampler2D(spriteTexture, spriteSampler), fragUv);
+
+  if (fragShape == SHAPE_CIRCLE) {
+    float distance = length(fragLocal);
+    float edge = fwidth(distance);
+    color.a *= 1. - smoothstep(1. - edge, 1., distance);
+  }
+
+  outColor = vec4(color.rgb * color.a, color.a);
+}
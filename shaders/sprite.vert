@@ -0,0 +1,41 @@
+#version 450
+
+// Expands each instance into a quad, rotated around its center. Positions are in pixels, which
+// the orthographic projection maps onto the target.
+
+layout(push_constant) uniform Batch {
+  mat4 projection;
+  uint srgb_target;
+} batch;
+
+layout(location = 0) in vec2 inCenter;
+layout(location = 1) in vec2 inHalfSize;
+layout(location = 2) in vec2 inUvMin;
+layout(location = 3) in vec2 inUvMax;
+layout(location = 4) in vec4 inColor;
+layout(location = 5) in float inRotation;
+layout(location = 6) in uint inShape;
+
+layout(location = 0) out vec2 fragUv;
+layout(location = 1) out vec2 fragLocal;
+layout(location = 2) out vec4 fragColor;
+layout(location = 3) flat out uint fragShape;
+
+const vec2 CORNERS[6] = vec2[](
+  vec2(-1., -1.), vec2(-1., 1.), vec2(1., 1.),
+  vec2(-1., -1.), vec2(1., 1.), vec2(1., -1.)
+);
+
+void main() {
+  vec2 corner = CORNERS[gl_VertexIndex];
+  vec2 offset = corner * inHalfSize;
+  float c = cos(inRotation);
+  float s = sin(inRotation);
+  offset = vec2(c * offset.x - s * offset.y, s * offset.x + c * offset.y);
+
+  fragUv = mix(inUvMin, inUvMax, corner * 0.5 + 0.5);
+  fragLocal = corner;
+  fragColor = inColor;
+  fragShape = inShape;
+  gl_Position = batch.projection * vec4(inCenter + offset, 0., 1.);
+}
@@ -0,0 +1,34 @@
+#version 450
+
+// The atlas holds each glyph's coverage, which scales the color's alpha. The output is
+// premultiplied, as the pipeline blends.
+
+layout(set = 0, binding = 0) uniform texture2D glyphAtlas;
+layout(set = 0, binding = 1) uniform sampler glyphSampler;
+
+layout(push_constant) uniform Text {
+  vec2 screen_size;
+  uint srgb_target;
+} text;
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 linearFromGamma(vec3 color) {
+  bvec3 cutoff = lessThan(color, vec3(0.04045));
+  vec3 lower = color / 12.92;
+  vec3 higher = pow((color + 0.055) / 1.055, vec3(2.4));
+  return mix(higher, lower, cutoff);
+}
+
+void main() {
+  float coverage = texture(sampler2D(glyphAtlas, glyphSampler), fragUv).r;
+  vec3 color = fragColor.rgb;
+  if (text.srgb_target != 0) {
+    color = linearFromGamma(color);
+  }
+  float alpha = fragColor.a * coverage;
+  outColor = vec4(color * alpha, alpha);
+}
@@ -0,0 +1,21 @@
+#version 450
+
+// Draws glyph quads, whose positions are in pixels from the top left corner of the target.
+
+layout(push_constant) uniform Text {
+  vec2 screen_size;
+  uint srgb_target;
+} text;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inUv;
+layout(location = 2) in vec4 inColor;
+
+layout(location = 0) out vec2 fragUv;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+  fragUv = inUv;
+  fragColor = inColor;
+  gl_Position = vec4(inPosition / text.screen_size * 2. - 1., 0., 1.);
+}
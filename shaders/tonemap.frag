@@ -0,0 +1,42 @@
+#version 450
+
+// Adds params.y times the bloom (the second image) to the scene, scales it by the exposure in
+// params.x and tone maps it: mode 0 only clamps, 1 is Reinhard and 2 ACES.
+
+layout(set = 0, binding = 0) uniform texture2D inputImage;
+layout(set = 0, binding = 1) uniform texture2D secondImage;
+layout(set = 0, binding = 2) uniform sampler inputSampler;
+layout(set = 0, binding = 3) uniform sampler secondSampler;
+
+layout(push_constant) uniform Post {
+  vec2 texel_size;
+  uint mode;
+  vec4 params;
+} post;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 sampleInput(vec2 uv) {
+  return texture(sampler2D(inputImage, inputSampler), uv).rgb;
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color) {
+  color *= 0.6;
+  return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0., 1.);
+}
+
+void main() {
+  vec3 bloom = texture(sampler2D(secondImage, secondSampler), fragUv).rgb;
+  vec3 color = (sampleInput(fragUv) + bloom * post.params.y) * post.params.x;
+
+  if (post.mode == 1u) {
+    color = color / (color + 1.);
+  } else if (post.mode == 2u) {
+    color = aces(color);
+  }
+
+  outColor = vec4(clamp(color, 0., 1.), 1.);
+}
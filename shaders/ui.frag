@@ -0,0 +1,32 @@
+#version 450
+
+// egui's colors and textures are gamma encoded with premultiplied alpha. They are blended as they
+// are, and decoded first for targets that encode to sRGB when written to.
+
+layout(set = 0, binding = 0) uniform texture2D uiTexture;
+layout(set = 0, binding = 1) uniform sampler uiSampler;
+
+layout(push_constant) uniform Ui {
+  vec2 screen_size;
+  uint srgb_target;
+} ui;
+
+layout(location = 0) in vec2 fragUv;
+layout(location = 1) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 linearFromGamma(vec3 color) {
+  bvec3 cutoff = lessThan(color, vec3(0.04045));
+  vec3 lower = color / 12.92;
+  vec3 higher = pow((color + 0.055) / 1.055, vec3(2.4));
+  return mix(higher, lower, cutoff);
+}
+
+void main() {
+  vec4 color = fragColor * texture(sampler2D(uiTexture, uiSampler), fragUv);
+  if (ui.srgb_target != 0) {
+    color.rgb = linearFromGamma(color.rgb);
+  }
+  outColor = color;
+}
@@ -0,0 +1,21 @@
+#version 450
+
+// Draws egui's meshes, whose positions are in points from the top left corner of the window.
+
+layout(push_constant) uniform Ui {
+  vec2 screen_size;
+  uint srgb_target;
+} ui;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inUv;
+layout(location = 2) in vec4 inColor;
+
+layout(location = 0) out vec2 fragUv;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+  fragUv = inUv;
+  fragColor = inColor;
+  gl_Position = vec4(inPosition / ui.screen_size * 2. - 1., 0., 1.);
+}
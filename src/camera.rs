@@ -0,0 +1,379 @@
+//! Cameras and the controllers that move them.
+//!
+//! Matrices follow Vulkan conventions: right handed view space looking down -Z, clip space Y
+//! pointing down and depth in `0..1`.
+
+use glam::{Mat4, Vec3};
+use std::collections::HashSet;
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians.
+        fov_y: f32,
+        near: f32,
+        far: f32,
+    },
+    Orthographic {
+        /// Height of the view volume in world units; the width follows from the aspect ratio.
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        let mut projection = match *self {
+            Projection::Perspective { fov_y, near, far } => {
+                Mat4::perspective_rh(fov_y, aspect, near, far)
+            }
+            Projection::Orthographic { height, near, far } => {
+                let half_height = height / 2.;
+                let half_width = half_height * aspect;
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+        };
+        // Vulkan's clip space Y points down
+        projection.y_axis.y *= -1.;
+        projection
+    }
+
+    pub fn near(&self) -> f32 {
+        match *self {
+            Projection::Perspective { near, .. } | Projection::Orthographic { near, .. } => near,
+        }
+    }
+
+    pub fn far(&self) -> f32 {
+        match *self {
+            Projection::Perspective { far, .. } | Projection::Orthographic { far, .. } => far,
+        }
+    }
+
+    /// The same projection with its near and far planes moved to `near` and `far`.
+    pub fn with_depth_range(self, near: f32, far: f32) -> Self {
+        match self {
+            Projection::Perspective { fov_y, .. } => Projection::Perspective { fov_y, near, far },
+            Projection::Orthographic { height, .. } => {
+                Projection::Orthographic { height, near, far }
+            }
+        }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fov_y: 60f32.to_radians(),
+            near: 0.1,
+            far: 100.,
+        }
+    }
+}
+
+/// A camera at `position` looking along the direction given by `yaw` and `pitch`, in radians.
+/// A yaw and pitch of zero look down -Z.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: Projection,
+}
+
+impl Camera {
+    pub fn new(position: Vec3, target: Vec3, projection: Projection) -> Self {
+        let mut camera = Self {
+            position,
+            yaw: 0.,
+            pitch: 0.,
+            projection,
+        };
+        camera.look_at(target);
+        camera
+    }
+
+    /// Turns the camera towards `target`.
+    pub fn look_at(&mut self, target: Vec3) {
+        let direction = (target - self.position).normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return;
+        }
+        self.yaw = (-direction.x).atan2(-direction.z);
+        self.pitch = direction.y.asin();
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(-sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch)
+    }
+
+    pub fn right(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        Vec3::new(cos_yaw, 0., -sin_yaw)
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_to_rh(self.position, self.forward(), Vec3::Y)
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+        self.projection.matrix(aspect)
+    }
+
+    /// World space corners of the part of the view between the distances `near` and `far`.
+    pub fn frustum_corners(&self, aspect: f32, near: f32, far: f32) -> [Vec3; 8] {
+        let projection = self.projection.with_depth_range(near, far).matrix(aspect);
+        let inverse = (projection * self.view_matrix()).inverse();
+
+        let mut corners = [Vec3::ZERO; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let x = if i & 1 == 0 { -1. } else { 1. };
+            let y = if i & 2 == 0 { -1. } else { 1. };
+            let z = if i & 4 == 0 { 0. } else { 1. };
+            *corner = inverse.project_point3(Vec3::new(x, y, z));
+        }
+        corners
+    }
+
+    pub fn uniform(&self, aspect: f32) -> CameraUniform {
+        let view = self.view_matrix();
+        let projection = self.projection_matrix(aspect);
+        CameraUniform {
+            view,
+            projection,
+            view_projection: projection * view,
+            position: self.position.extend(1.),
+        }
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new(Vec3::new(0., 2., 6.), Vec3::ZERO, Projection::default())
+    }
+}
+
+/// Camera data as laid out in the `Camera` uniform block of the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CameraUniform {
+    pub view: Mat4,
+    pub projection: Mat4,
+    pub view_projection: Mat4,
+    pub position: glam::Vec4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControllerMode {
+    /// Dragging with the left mouse button orbits around `target`, the scroll wheel zooms and
+    /// WASD moves the target in the ground plane.
+    Orbit { target: Vec3, distance: f32 },
+    /// Dragging with the right mouse button looks around, WASD moves and Q/E go down and up.
+    Fly,
+}
+
+/// Moves a [`Camera`] from window input. `C` switches between orbiting and flying, `P` between
+/// perspective and orthographic projection.
+pub struct CameraController {
+    pub mode: ControllerMode,
+    /// Movement speed in world units per second.
+    pub speed: f32,
+    /// Rotation in radians per pixel of mouse movement.
+    pub sensitivity: f32,
+    pressed_keys: HashSet<VirtualKeyCode>,
+    pressed_buttons: HashSet<MouseButton>,
+    cursor: Option<PhysicalPosition<f64>>,
+    rotation: (f32, f32),
+    zoom: f32,
+    toggle_requested: bool,
+    projection_toggle_requested: bool,
+}
+
+impl CameraController {
+    pub fn orbit(target: Vec3, distance: f32) -> Self {
+        Self::new(ControllerMode::Orbit { target, distance })
+    }
+
+    pub fn fly() -> Self {
+        Self::new(ControllerMode::Fly)
+    }
+
+    fn new(mode: ControllerMode) -> Self {
+        Self {
+            mode,
+            speed: 3.,
+            sensitivity: 0.005,
+            pressed_keys: HashSet::new(),
+            pressed_buttons: HashSet::new(),
+            cursor: None,
+            rotation: (0., 0.),
+            zoom: 0.,
+            toggle_requested: false,
+            projection_toggle_requested: false,
+        }
+    }
+
+    /// Records input from `event`. Returns whether the event was used.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match *event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                let key = match input.virtual_keycode {
+                    Some(key) => key,
+                    None => return false,
+                };
+                match input.state {
+                    ElementState::Pressed => {
+                        // ignore key repeat so the mode only toggles once per press
+                        if self.pressed_keys.insert(key) {
+                            match key {
+                                VirtualKeyCode::C => self.toggle_requested ^= true,
+                                VirtualKeyCode::P => self.projection_toggle_requested ^= true,
+                                _ => {}
+                            }
+                        }
+                    }
+                    ElementState::Released => {
+                        self.pressed_keys.remove(&key);
+                    }
+                }
+                true
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                match state {
+                    ElementState::Pressed => self.pressed_buttons.insert(button),
+                    ElementState::Released => self.pressed_buttons.remove(&button),
+                };
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(last) = self.cursor {
+                    let dragging = match self.mode {
+                        ControllerMode::Orbit { .. } => MouseButton::Left,
+                        ControllerMode::Fly => MouseButton::Right,
+                    };
+                    if self.pressed_buttons.contains(&dragging) {
+                        self.rotation.0 += (position.x - last.x) as f32;
+                        self.rotation.1 += (position.y - last.y) as f32;
+                    }
+                }
+                self.cursor = Some(position);
+                true
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.zoom += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.,
+                };
+                true
+            }
+            WindowEvent::Focused(false) => {
+                // releases are not delivered to unfocused windows
+                self.pressed_keys.clear();
+                self.pressed_buttons.clear();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Switches between orbiting and flying without moving `camera`. Orbiting starts around the
+    /// point a few units in front of the camera.
+    pub fn toggle_mode(&mut self, camera: &Camera) {
+        self.mode = match self.mode {
+            ControllerMode::Orbit { .. } => ControllerMode::Fly,
+            ControllerMode::Fly => {
+                let distance = 5.;
+                ControllerMode::Orbit {
+                    target: camera.position + camera.forward() * distance,
+                    distance,
+                }
+            }
+        };
+    }
+
+    /// Distance to the point the camera is focused on, used to size orthographic projections.
+    fn focus_distance(&self) -> f32 {
+        match self.mode {
+            ControllerMode::Orbit { distance, .. } => distance,
+            ControllerMode::Fly => 5.,
+        }
+    }
+
+    /// Applies the input recorded since the last update to `camera`, `dt` seconds later.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) {
+        if std::mem::take(&mut self.toggle_requested) {
+            self.toggle_mode(camera);
+        }
+        if std::mem::take(&mut self.projection_toggle_requested) {
+            camera.projection = toggle_projection(camera.projection, self.focus_distance());
+        }
+
+        let (dx, dy) = std::mem::take(&mut self.rotation);
+        let zoom = std::mem::take(&mut self.zoom);
+
+        let axis = |positive, negative| {
+            let pressed = |key| self.pressed_keys.contains(&key) as i32 as f32;
+            pressed(positive) - pressed(negative)
+        };
+        let forward = axis(VirtualKeyCode::W, VirtualKeyCode::S);
+        let right = axis(VirtualKeyCode::D, VirtualKeyCode::A);
+        let up = axis(VirtualKeyCode::E, VirtualKeyCode::Q);
+        let step = self.speed * dt;
+
+        camera.yaw -= dx * self.sensitivity;
+        // stop just short of straight up or down, where the view matrix degenerates
+        let max_pitch = 89f32.to_radians();
+        camera.pitch = (camera.pitch - dy * self.sensitivity).clamp(-max_pitch, max_pitch);
+
+        match &mut self.mode {
+            ControllerMode::Orbit { target, distance } => {
+                *distance = (*distance * 0.9f32.powf(zoom)).max(0.1);
+
+                let flat_forward =
+                    Vec3::new(camera.forward().x, 0., camera.forward().z).normalize_or_zero();
+                *target += (flat_forward * forward + camera.right() * right) * step;
+                *target += Vec3::Y * up * step;
+
+                camera.position = *target - camera.forward() * *distance;
+            }
+            ControllerMode::Fly => {
+                camera.position += camera.forward() * (forward * step + zoom);
+                camera.position += camera.right() * right * step;
+                camera.position += Vec3::Y * up * step;
+            }
+        }
+    }
+}
+
+/// Switches between a perspective and an orthographic projection that show roughly the same
+/// area `distance` units in front of the camera.
+fn toggle_projection(projection: Projection, distance: f32) -> Projection {
+    match projection {
+        Projection::Perspective { fov_y, near, far } => Projection::Orthographic {
+            height: 2. * distance * (fov_y / 2.).tan(),
+            near,
+            far,
+        },
+        Projection::Orthographic { height, near, far } => Projection::Perspective {
+            fov_y: 2. * (height / (2. * distance)).atan(),
+            near,
+            far,
+        },
+    }
+}
@@ -0,0 +1,821 @@
+//! GPU-driven culling of the instances [`IndirectDraws`] batches.
+//!
+//! A compute shader tests each instance's bounding sphere against the camera frustum, and
+//! against a [`DepthPyramid`] built from the previous frame's depth, and writes the visible
+//! instances into separate buffers with their own indirect commands, see `cull.comp`. Nothing of
+//! this is read back before drawing; only the counts of visible and culled instances are, once
+//! the frame has completed.
+
+use crate::draw::{DrawBuffers, IndirectDraws};
+use crate::resources::{
+    Buffer, DescriptorPool, DescriptorSetLayout, Device, Image, ImageView, Pipeline,
+    PipelineLayout, Sampler, ShaderModule,
+};
+use crate::scene::Scene;
+use crate::upload::Uploader;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use glam::{Mat4, Vec2, Vec4};
+use std::ffi::CString;
+use std::sync::Arc;
+
+/// Format of the depth pyramid, holding the farthest depth of the texels each texel covers.
+pub const PYRAMID_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+const PYRAMID_GROUP_SIZE: u32 = 8;
+
+/// Parameters of the culling shader, as laid out in `cull.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CullUniform {
+    pub previous_view_projection: Mat4,
+    pub planes: [Vec4; 6],
+    pub pyramid_size: Vec2,
+    /// 0 if there is no pyramid, which turns occlusion culling off.
+    pub pyramid_levels: u32,
+    _padding: u32,
+}
+
+impl CullUniform {
+    /// Culls against the frustum of `view_projection`, and if there is a `pyramid` against the
+    /// depth it was built from, which was rendered with `previous_view_projection`.
+    pub fn new(
+        view_projection: Mat4,
+        previous_view_projection: Mat4,
+        pyramid: Option<&DepthPyramid>,
+    ) -> Self {
+        let (pyramid_size, pyramid_levels) = match pyramid {
+            Some(pyramid) => (
+                Vec2::new(pyramid.extent.width as f32, pyramid.extent.height as f32),
+                pyramid.levels(),
+            ),
+            None => (Vec2::ZERO, 0),
+        };
+
+        Self {
+            previous_view_projection,
+            planes: frustum_planes(view_projection),
+            pyramid_size,
+            pyramid_levels,
+            _padding: 0,
+        }
+    }
+}
+
+/// The planes bounding what `view_projection` sees, with Vulkan's depth range of 0 to 1. Their
+/// normals point inwards and are normalized, so a point's distance to a plane is
+/// `plane.truncate().dot(point) + plane.w`.
+pub fn frustum_planes(view_projection: Mat4) -> [Vec4; 6] {
+    let row = |i| view_projection.row(i);
+    let planes = [
+        row(3) + row(0),
+        row(3) - row(0),
+        row(3) + row(1),
+        row(3) - row(1),
+        row(2),
+        row(3) - row(2),
+    ];
+    planes.map(|plane| plane / plane.truncate().length())
+}
+
+/// How many instances a frame drew, and how many it culled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub visible: u32,
+    pub frustum_culled: u32,
+    pub occluded: u32,
+}
+
+/// The culling and depth pyramid compute pipelines, shared by every window.
+pub struct CullPipelines {
+    cull_pipeline: Pipeline,
+    cull_layout: PipelineLayout,
+    cull_set_layout: DescriptorSetLayout,
+    pyramid_pipeline: Pipeline,
+    pyramid_layout: PipelineLayout,
+    pyramid_set_layout: DescriptorSetLayout,
+    /// Nearest filtering, as both shaders fetch single texels.
+    sampler: Sampler,
+}
+
+/// Bindings of `cull.comp`.
+const CULL_BINDINGS: [vk::DescriptorType; 11] = [
+    vk::DescriptorType::UNIFORM_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::SAMPLED_IMAGE,
+    vk::DescriptorType::SAMPLER,
+];
+
+/// Bindings of `depth_pyramid.comp`.
+const PYRAMID_BINDINGS: [vk::DescriptorType; 3] = [
+    vk::DescriptorType::SAMPLED_IMAGE,
+    vk::DescriptorType::SAMPLER,
+    vk::DescriptorType::STORAGE_IMAGE,
+];
+
+impl CullPipelines {
+    pub fn new(device: &Arc<Device>) -> Self {
+        let (cull_set_layout, cull_layout, cull_pipeline) =
+            create_compute_pipeline(device, "shaders/cull.spv", &CULL_BINDINGS, 0);
+        let (pyramid_set_layout, pyramid_layout, pyramid_pipeline) = create_compute_pipeline(
+            device,
+            "shaders/depth_pyramid.spv",
+            &PYRAMID_BINDINGS,
+            std::mem::size_of::<[i32; 2]>() as u32,
+        );
+
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::NEAREST)
+            .min_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .expect("failed to create sampler!")
+        };
+
+        Self {
+            cull_pipeline,
+            cull_layout,
+            cull_set_layout,
+            pyramid_pipeline,
+            pyramid_layout,
+            pyramid_set_layout,
+            sampler: Sampler::from_raw(device, sampler),
+        }
+    }
+}
+
+/// Creates a compute pipeline for the shader at `path`, whose set 0 has a binding of each of
+/// `bindings` in order.
+fn create_compute_pipeline(
+    device: &Arc<Device>,
+    path: &str,
+    bindings: &[vk::DescriptorType],
+    push_constants_size: u32,
+) -> (DescriptorSetLayout, PipelineLayout, Pipeline) {
+    let layout_bindings = bindings
+        .iter()
+        .enumerate()
+        .map(|(binding, &ty)| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as u32)
+                .descriptor_type(ty)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&layout_bindings);
+    let set_layout = unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .expect("failed to create descriptor set layout!")
+    };
+    let set_layout = DescriptorSetLayout::from_raw(device, set_layout);
+
+    let push_constant_range = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::COMPUTE,
+        offset: 0,
+        size: push_constants_size,
+    };
+    let push_constant_ranges = if push_constants_size > 0 {
+        std::slice::from_ref(&push_constant_range)
+    } else {
+        &[]
+    };
+    let set_layouts = [set_layout.handle()];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(&set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    let pipeline_layout = unsafe {
+        device
+            .create_pipeline_layout(&layout_info, None)
+            .expect("failed to create pipeline layout!")
+    };
+    let pipeline_layout = PipelineLayout::from_raw(device, pipeline_layout);
+
+    let shader = ShaderModule::load(device, path);
+    let entry_point = CString::new("main").unwrap();
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader.handle())
+        .name(&entry_point);
+    let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage.build())
+        .layout(pipeline_layout.handle());
+
+    let pipeline = unsafe {
+        device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+            .expect("failed to create compute pipeline!")
+    };
+
+    (
+        set_layout,
+        pipeline_layout,
+        Pipeline::from_raw(device, pipeline[0]),
+    )
+}
+
+/// Allocates `count` sets of `set_layout` from `pool`.
+fn allocate_sets(
+    device: &Device,
+    pool: &DescriptorPool,
+    set_layout: &DescriptorSetLayout,
+    count: usize,
+) -> Vec<vk::DescriptorSet> {
+    let set_layouts = vec![set_layout.handle(); count];
+    let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool.handle())
+        .set_layouts(&set_layouts);
+    unsafe {
+        device
+            .allocate_descriptor_sets(&alloc_info)
+            .expect("failed to allocate descriptor sets!")
+    }
+}
+
+/// Creates a pool for `sets` sets, each with a descriptor of each of `bindings`.
+fn create_pool(device: &Arc<Device>, bindings: &[vk::DescriptorType], sets: u32) -> DescriptorPool {
+    let pool_sizes = bindings
+        .iter()
+        .map(|&ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: sets,
+        })
+        .collect::<Vec<_>>();
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .max_sets(sets)
+        .pool_sizes(&pool_sizes);
+    let pool = unsafe {
+        device
+            .create_descriptor_pool(&pool_info, None)
+            .expect("failed to create descriptor pool!")
+    };
+    DescriptorPool::from_raw(device, pool)
+}
+
+/// A mip chain of a window's depth, each texel of a level holding the farthest depth of the
+/// texels it covers in the level below. The first level is the largest power of two that fits
+/// the depth target. It stays in `GENERAL` layout, except where the graph transitions it for a
+/// pass sampling it.
+pub struct DepthPyramid {
+    extent: vk::Extent2D,
+    // one set per level per frame in flight, reducing the level below into that level
+    sets: Vec<vk::DescriptorSet>,
+    pool: DescriptorPool,
+    level_views: Vec<ImageView>,
+    view: ImageView,
+    image: Image,
+    device: Arc<Device>,
+}
+
+impl DepthPyramid {
+    /// Creates a pyramid for depth targets of `depth_extent`, cleared to the far plane so nothing
+    /// is occluded by it until it is built.
+    pub fn new(
+        uploader: &Uploader,
+        pipelines: &CullPipelines,
+        depth_extent: vk::Extent2D,
+        frames_in_flight: usize,
+    ) -> Self {
+        let device = uploader.device();
+        let previous_power_of_two = |size: u32| {
+            let size = size.max(1);
+            if size.is_power_of_two() {
+                size
+            } else {
+                size.next_power_of_two() / 2
+            }
+        };
+        let extent = vk::Extent2D {
+            width: previous_power_of_two(depth_extent.width),
+            height: previous_power_of_two(depth_extent.height),
+        };
+        let levels = 32 - extent.width.max(extent.height).leading_zeros();
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(PYRAMID_FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(levels)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = Image::new(device, &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+
+        let range = |base_mip_level, level_count| vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level,
+            level_count,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let view = ImageView::new(
+            device,
+            image.handle(),
+            vk::ImageViewType::TYPE_2D,
+            PYRAMID_FORMAT,
+            range(0, levels),
+        );
+        let level_views = (0..levels)
+            .map(|level| {
+                ImageView::new(
+                    device,
+                    image.handle(),
+                    vk::ImageViewType::TYPE_2D,
+                    PYRAMID_FORMAT,
+                    range(level, 1),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        uploader.submit(|command_buffer| {
+            let to_general = vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image.handle())
+                .subresource_range(range(0, levels))
+                .build();
+            let far = vk::ClearColorValue {
+                float32: [1., 0., 0., 0.],
+            };
+
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_general],
+                );
+                device.cmd_clear_color_image(
+                    command_buffer,
+                    image.handle(),
+                    vk::ImageLayout::GENERAL,
+                    &far,
+                    &[range(0, levels)],
+                );
+            }
+        });
+
+        let set_count = frames_in_flight * levels as usize;
+        let pool = create_pool(device, &PYRAMID_BINDINGS, set_count as u32);
+        let sets = allocate_sets(device, &pool, &pipelines.pyramid_set_layout, set_count);
+
+        // every level but the first reads the one below it, which does not change
+        for (i, &set) in sets.iter().enumerate() {
+            let level = i % levels as usize;
+            let destination = vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: level_views[level].handle(),
+                image_layout: vk::ImageLayout::GENERAL,
+            };
+            let source = (level > 0).then(|| vk::DescriptorImageInfo {
+                sampler: pipelines.sampler.handle(),
+                image_view: level_views[level - 1].handle(),
+                image_layout: vk::ImageLayout::GENERAL,
+            });
+            write_pyramid_set(device, set, source.as_ref(), &destination);
+        }
+
+        Self {
+            extent,
+            sets,
+            pool,
+            level_views,
+            view,
+            image,
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image.handle()
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.level_views.len() as u32
+    }
+
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// View of every level, to declare the pyramid to a render graph with. Whoever samples it
+    /// uses the same view.
+    pub fn view(&self) -> vk::ImageView {
+        self.view.handle()
+    }
+
+    /// Records building every level from the depth target viewed by `depth_view`, of
+    /// `depth_extent`, in `DEPTH_STENCIL_READ_ONLY_OPTIMAL` layout. The pyramid has to be in
+    /// `GENERAL` layout, and its previous readers done. Uses the sets of frame in flight `frame`.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipelines: &CullPipelines,
+        frame: usize,
+        depth_view: vk::ImageView,
+        depth_extent: vk::Extent2D,
+    ) {
+        let device = &self.device;
+        let levels = self.levels() as usize;
+        let sets = &self.sets[frame * levels..(frame + 1) * levels];
+
+        // the depth target may be a different image every frame
+        let source = vk::DescriptorImageInfo {
+            sampler: pipelines.sampler.handle(),
+            image_view: depth_view,
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        };
+        let destination = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: self.level_views[0].handle(),
+            image_layout: vk::ImageLayout::GENERAL,
+        };
+        write_pyramid_set(device, sets[0], Some(&source), &destination);
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipelines.pyramid_pipeline.handle(),
+            );
+        }
+
+        let mut source_size = [depth_extent.width as i32, depth_extent.height as i32];
+        for (level, &set) in sets.iter().enumerate() {
+            let width = (self.extent.width >> level).max(1);
+            let height = (self.extent.height >> level).max(1);
+
+            unsafe {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipelines.pyramid_layout.handle(),
+                    0,
+                    &[set],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    pipelines.pyramid_layout.handle(),
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    crate::as_bytes(&source_size),
+                );
+                device.cmd_dispatch(
+                    command_buffer,
+                    width.div_ceil(PYRAMID_GROUP_SIZE),
+                    height.div_ceil(PYRAMID_GROUP_SIZE),
+                    1,
+                );
+
+                // the next level reads this one
+                let barrier = vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .old_layout(vk::ImageLayout::GENERAL)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(self.image.handle())
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level as u32,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .build();
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                );
+            }
+
+            source_size = [width as i32, height as i32];
+        }
+    }
+}
+
+/// Points `set` of `depth_pyramid.comp` at `source`, unless it is `None`, and `destination`.
+fn write_pyramid_set(
+    device: &Device,
+    set: vk::DescriptorSet,
+    source: Option<&vk::DescriptorImageInfo>,
+    destination: &vk::DescriptorImageInfo,
+) {
+    let write = |binding, ty, image_info: &vk::DescriptorImageInfo| {
+        vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(ty)
+            .image_info(std::slice::from_ref(image_info))
+            .build()
+    };
+
+    // the image view is ignored by the sampler write and the sampler by the image write
+    let mut writes = vec![write(2, vk::DescriptorType::STORAGE_IMAGE, destination)];
+    if let Some(source) = source {
+        writes.push(write(0, vk::DescriptorType::SAMPLED_IMAGE, source));
+        writes.push(write(1, vk::DescriptorType::SAMPLER, source));
+    }
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// The buffers one frame in flight culls with and draws from.
+#[derive(Default)]
+struct FrameBuffers {
+    uniform: Option<Buffer>,
+    spheres: Option<Buffer>,
+    batches: Option<Buffer>,
+    buckets: Option<Buffer>,
+    instances: Option<Buffer>,
+    commands: Option<Buffer>,
+    counts: Option<Buffer>,
+    stats: Option<Buffer>,
+    /// Batches culled by the frame, so how many stats it wrote.
+    batch_count: usize,
+    /// Buckets culled by the frame, one workgroup each.
+    bucket_count: usize,
+}
+
+/// Culls the instances of a window's [`IndirectDraws`] on the GPU, into buffers of its own for
+/// each frame in flight.
+pub struct GpuCulling {
+    frames: Vec<FrameBuffers>,
+    sets: Vec<vk::DescriptorSet>,
+    pool: DescriptorPool,
+    stats: CullStats,
+    device: Arc<Device>,
+}
+
+impl GpuCulling {
+    pub fn new(device: &Arc<Device>, pipelines: &CullPipelines, frames_in_flight: usize) -> Self {
+        let pool = create_pool(device, &CULL_BINDINGS, frames_in_flight as u32);
+        let sets = allocate_sets(device, &pool, &pipelines.cull_set_layout, frames_in_flight);
+
+        Self {
+            frames: (0..frames_in_flight)
+                .map(|_| FrameBuffers::default())
+                .collect(),
+            sets,
+            pool,
+            stats: CullStats::default(),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Counts of the last frame whose culling results were read back.
+    pub fn stats(&self) -> CullStats {
+        self.stats
+    }
+
+    /// Reads back what frame in flight `frame` culled last time, which must have completed, and
+    /// prepares it to cull the last upload of `draws` with `uniform`. Each sphere bounds the mesh
+    /// of an instance under its model matrix.
+    pub fn upload(
+        &mut self,
+        frame: usize,
+        draws: &IndirectDraws,
+        scene: &Scene,
+        uniform: &CullUniform,
+    ) {
+        let device = Arc::clone(&self.device);
+        let buffers = &mut self.frames[frame];
+
+        if let Some(stats) = &buffers.stats {
+            let totals = stats.read::<[u32; 4]>(buffers.batch_count);
+            self.stats = totals
+                .iter()
+                .fold(CullStats::default(), |stats, batch| CullStats {
+                    visible: stats.visible + batch[0],
+                    frustum_culled: stats.frustum_culled + batch[1],
+                    occluded: stats.occluded + batch[2],
+                });
+        }
+
+        let batches = draws.batches();
+        buffers.batch_count = batches.len();
+        buffers.bucket_count = draws.buckets().len();
+        if batches.is_empty() {
+            return;
+        }
+
+        let mut spheres = Vec::with_capacity(draws.instances().len());
+        for batch in batches {
+            let sphere = scene.mesh(batch.mesh).bounding_sphere();
+            let range = batch.first_instance as usize
+                ..(batch.first_instance + batch.instance_count) as usize;
+            spheres.extend(draws.instances()[range].iter().map(|instance| {
+                let center = instance.model.transform_point3(sphere.truncate());
+                let scale = (0..3)
+                    .map(|i| instance.model.col(i).truncate().length())
+                    .fold(0., f32::max);
+                center.extend(sphere.w * scale)
+            }));
+        }
+        let batch_ranges = batches
+            .iter()
+            .map(|batch| [batch.first_instance, batch.instance_count])
+            .collect::<Vec<_>>();
+        let bucket_ranges = draws
+            .buckets()
+            .iter()
+            .map(|bucket| [bucket.first_batch, bucket.batch_count])
+            .collect::<Vec<_>>();
+        // the shader fills in the instance counts, moves the commands of batches with any to the
+        // start of their bucket, and counts them
+        let counts = vec![0u32; bucket_ranges.len()];
+        let stats = vec![[0u32; 4]; batches.len()];
+        let instance_size = std::mem::size_of_val(draws.instances()) as vk::DeviceSize;
+
+        let storage = vk::BufferUsageFlags::STORAGE_BUFFER;
+        Buffer::write_growing(
+            &device,
+            &mut buffers.uniform,
+            std::slice::from_ref(uniform),
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+        );
+        Buffer::write_growing(&device, &mut buffers.spheres, &spheres, storage);
+        Buffer::write_growing(&device, &mut buffers.batches, &batch_ranges, storage);
+        Buffer::write_growing(&device, &mut buffers.buckets, &bucket_ranges, storage);
+        Buffer::grow(
+            &device,
+            &mut buffers.instances,
+            instance_size,
+            storage | vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        Buffer::write_growing(
+            &device,
+            &mut buffers.commands,
+            draws.commands(),
+            storage | vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+        Buffer::write_growing(
+            &device,
+            &mut buffers.counts,
+            &counts,
+            storage | vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+        Buffer::write_growing(&device, &mut buffers.stats, &stats, storage);
+    }
+
+    /// Records culling the last upload to frame in flight `frame`, reading `all` of the instances
+    /// and `pyramid`, which has to be in `SHADER_READ_ONLY_OPTIMAL` layout. Draws from
+    /// [`Self::buffers`] after it see what it wrote.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipelines: &CullPipelines,
+        frame: usize,
+        all: DrawBuffers,
+        pyramid: &DepthPyramid,
+    ) {
+        let buffers = &self.frames[frame];
+        if buffers.batch_count == 0 {
+            return;
+        }
+        let set = self.sets[frame];
+        let device = &self.device;
+
+        // buffers may have been replaced since the set was last written
+        let buffer_infos = [
+            buffers.uniform.as_ref(),
+            buffers.spheres.as_ref(),
+            Some(all.instances),
+            buffers.instances.as_ref(),
+            buffers.batches.as_ref(),
+            buffers.buckets.as_ref(),
+            buffers.commands.as_ref(),
+            buffers.counts.as_ref(),
+            buffers.stats.as_ref(),
+        ]
+        .map(|buffer| vk::DescriptorBufferInfo {
+            buffer: buffer.expect("culling buffers were not uploaded").handle(),
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        });
+        let image_info = vk::DescriptorImageInfo {
+            sampler: pipelines.sampler.handle(),
+            image_view: pyramid.view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let mut writes = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(CULL_BINDINGS[binding])
+                    .buffer_info(std::slice::from_ref(info))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        for binding in [9, 10] {
+            writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .descriptor_type(CULL_BINDINGS[binding as usize])
+                    .image_info(std::slice::from_ref(&image_info))
+                    .build(),
+            );
+        }
+
+        unsafe {
+            device.update_descriptor_sets(&writes, &[]);
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipelines.cull_pipeline.handle(),
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipelines.cull_layout.handle(),
+                0,
+                &[set],
+                &[],
+            );
+            // a workgroup per bucket
+            device.cmd_dispatch(command_buffer, buffers.bucket_count as u32, 1, 1);
+
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::INDIRECT_COMMAND_READ
+                        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                        | vk::AccessFlags::HOST_READ,
+                )
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT
+                    | vk::PipelineStageFlags::VERTEX_INPUT
+                    | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// The buffers of frame in flight `frame`, drawing only the instances its culling found
+    /// visible.
+    pub fn buffers(&self, frame: usize) -> Option<DrawBuffers<'_>> {
+        let buffers = &self.frames[frame];
+        Some(DrawBuffers {
+            instances: buffers.instances.as_ref()?,
+            commands: buffers.commands.as_ref()?,
+            counts: buffers.counts.as_ref()?,
+        })
+    }
+}
@@ -0,0 +1,707 @@
+use crate::resources::Instance;
+use ash::extensions::ext::DebugUtils;
+use ash::version::EntryV1_0;
+use ash::vk;
+use std::borrow::Cow;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::sync::{Arc, OnceLock};
+
+pub const VALIDATION_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
+
+/// Whether the validation layers are loaded, and which of the optional validation features from
+/// `VK_EXT_validation_features` they should run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ValidationConfig {
+    pub enabled: bool,
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    pub synchronization: bool,
+}
+
+impl ValidationConfig {
+    /// Validation is on by default in debug builds. `VKA_VALIDATION` (`1`/`on`/`true` or
+    /// `0`/`off`/`false`) overrides that in either direction, and `VKA_VALIDATION_FEATURES` takes
+    /// a comma separated list of `gpu-assisted`, `best-practices` and `sync`.
+    pub fn from_env() -> Self {
+        Self::parse(
+            std::env::var("VKA_VALIDATION").ok().as_deref(),
+            std::env::var("VKA_VALIDATION_FEATURES").ok().as_deref(),
+        )
+    }
+
+    /// Parses the values of `VKA_VALIDATION` and `VKA_VALIDATION_FEATURES`, `None` if unset.
+    fn parse(validation: Option<&str>, features: Option<&str>) -> Self {
+        let enabled = match validation.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            Some("1") | Some("on") | Some("true") => true,
+            Some("0") | Some("off") | Some("false") => false,
+            Some(other) => {
+                log::warn!("ignoring unrecognized VKA_VALIDATION value {:?}", other);
+                cfg!(debug_assertions)
+            }
+            None => cfg!(debug_assertions),
+        };
+
+        let mut config = Self {
+            enabled,
+            ..Self::default()
+        };
+
+        for feature in features
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+        {
+            match feature {
+                "gpu-assisted" | "gpu" => config.gpu_assisted = true,
+                "best-practices" => config.best_practices = true,
+                "sync" | "synchronization" => config.synchronization = true,
+                other => log::warn!("ignoring unknown validation feature {:?}", other),
+            }
+        }
+
+        config
+    }
+
+    /// Turns validation off, with a warning, when the layers are not installed instead of
+    /// failing instance creation, and likewise the validation features when neither the loader
+    /// nor the layers provide `VK_EXT_validation_features`.
+    pub fn resolve(self, entry: &ash::Entry) -> Self {
+        let name = vk::ExtValidationFeaturesFn::name();
+        let layer = CString::new(VALIDATION_LAYERS[0]).unwrap();
+        self.resolve_with(
+            || check_validation_layer_support(entry),
+            || {
+                instance_extension_supported(entry, None, name)
+                    || instance_extension_supported(entry, Some(&layer), name)
+            },
+        )
+    }
+
+    /// [`Self::resolve`], with whether the layers and `VK_EXT_validation_features` are available
+    /// found out by the given functions, which are only called if the answer matters.
+    fn resolve_with(
+        self,
+        layers_supported: impl FnOnce() -> bool,
+        features_supported: impl FnOnce() -> bool,
+    ) -> Self {
+        if self.enabled && !layers_supported() {
+            log::warn!(
+                "validation layers requested but not available, continuing without validation"
+            );
+            return Self::default();
+        }
+
+        let name = vk::ExtValidationFeaturesFn::name();
+        if self.has_features() && !features_supported() {
+            log::warn!(
+                "validation features requested but {:?} is not available, continuing without them",
+                name
+            );
+            return Self {
+                enabled: true,
+                ..Self::default()
+            };
+        }
+        self
+    }
+
+    pub fn has_features(&self) -> bool {
+        self.enabled && (self.gpu_assisted || self.best_practices || self.synchronization)
+    }
+
+    pub fn enabled_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+        if !self.enabled {
+            return features;
+        }
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        if self.best_practices {
+            features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+        if self.synchronization {
+            features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        features
+    }
+}
+
+pub fn check_validation_layer_support(entry: &ash::Entry) -> bool {
+    let available_layers = match entry.enumerate_instance_layer_properties() {
+        Ok(layers) => layers,
+        Err(err) => {
+            log::warn!("could not enumerate instance layer properties: {}", err);
+            return false;
+        }
+    };
+
+    let available_layers = available_layers
+        .iter()
+        .map(|x| crate::vk_to_str(&x.layer_name))
+        .collect::<Vec<_>>();
+
+    log::debug!("available instance layers: {:?}", available_layers);
+
+    VALIDATION_LAYERS
+        .iter()
+        .all(|layer| available_layers.contains(layer))
+}
+
+/// Whether the instance extension `name` is available from the loader and drivers or, given
+/// `layer`, from that layer.
+pub fn instance_extension_supported(entry: &ash::Entry, layer: Option<&CStr>, name: &CStr) -> bool {
+    let layer = layer.map_or(std::ptr::null(), CStr::as_ptr);
+    let mut count = 0;
+    let mut properties = Vec::new();
+    let result = unsafe {
+        let result = entry.fp_v1_0().enumerate_instance_extension_properties(
+            layer,
+            &mut count,
+            std::ptr::null_mut(),
+        );
+        if result == vk::Result::SUCCESS {
+            properties.resize(count as usize, vk::ExtensionProperties::default());
+            entry.fp_v1_0().enumerate_instance_extension_properties(
+                layer,
+                &mut count,
+                properties.as_mut_ptr(),
+            )
+        } else {
+            result
+        }
+    };
+    // INCOMPLETE only means extensions were added in between, which are not needed
+    if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+        log::warn!(
+            "could not enumerate instance extension properties: {}",
+            result
+        );
+        return false;
+    }
+
+    properties[..count as usize]
+        .iter()
+        .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == name)
+}
+
+/// Log target used for every message coming from the validation layers, so they can be filtered
+/// separately from the rest of the crate, e.g. `RUST_LOG=vulkan=warn`.
+pub const LOG_TARGET: &str = "vulkan";
+
+/// Message IDs that are never logged. More can be added at runtime through the comma separated
+/// `VKA_SUPPRESS_VUIDS` environment variable, which accepts either the VUID names
+/// (`VUID-vkCmdDraw-None-02859`) or their numeric IDs in decimal or `0x` hex.
+const SUPPRESSED_MESSAGE_IDS: [&str; 0] = [];
+
+fn suppressed_message_ids() -> &'static [String] {
+    static IDS: OnceLock<Vec<String>> = OnceLock::new();
+    IDS.get_or_init(|| {
+        let from_env = std::env::var("VKA_SUPPRESS_VUIDS").unwrap_or_default();
+        SUPPRESSED_MESSAGE_IDS
+            .iter()
+            .map(|id| id.to_string())
+            .chain(
+                from_env
+                    .split(',')
+                    .map(|id| id.trim().to_owned())
+                    .filter(|id| !id.is_empty()),
+            )
+            .collect()
+    })
+}
+
+fn is_suppressed(id_name: &str, id_number: i32) -> bool {
+    matches_any_id(suppressed_message_ids(), id_name, id_number)
+}
+
+/// Whether one of `ids`, a VUID name or a decimal or `0x` hex number, names the message.
+fn matches_any_id(ids: &[String], id_name: &str, id_number: i32) -> bool {
+    ids.iter().any(|id| {
+        id == id_name
+            || id.parse::<i32>() == Ok(id_number)
+            || id
+                .strip_prefix("0x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .is_some_and(|n| n as i32 == id_number)
+    })
+}
+
+pub fn severity_to_level(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> log::Level {
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::Level::Warn
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::Level::Info
+    } else {
+        log::Level::Trace
+    }
+}
+
+/// Severities worth asking the driver for given the logger's current maximum level; there is no
+/// point in having the layers format messages that would be thrown away. Errors and warnings are
+/// always requested, as user callbacks see them whatever the log filter, and validation warnings
+/// are usually real bugs.
+pub fn severity_mask_for(max_level: log::LevelFilter) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+    let mut mask = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+    if max_level >= log::LevelFilter::Info {
+        mask |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+    }
+    if max_level >= log::LevelFilter::Trace {
+        mask |= vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
+    }
+    mask
+}
+
+pub fn message_type_name(message_type: vk::DebugUtilsMessageTypeFlagsEXT) -> &'static str {
+    if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "validation"
+    } else if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "performance"
+    } else {
+        "general"
+    }
+}
+
+unsafe fn ptr_to_str<'a>(ptr: *const c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        Cow::Borrowed("")
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy()
+    }
+}
+
+unsafe fn raw_slice<'a, T>(ptr: *const T, len: u32) -> &'a [T] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len as usize)
+    }
+}
+
+/// An object referenced by a [`DebugMessage`].
+pub struct DebugObject<'a> {
+    pub object_type: vk::ObjectType,
+    pub handle: u64,
+    pub name: Option<Cow<'a, str>>,
+}
+
+/// A message from the validation layers (or the driver), borrowed from the callback data for the
+/// duration of the callback.
+pub struct DebugMessage<'a> {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub id_name: Cow<'a, str>,
+    pub id_number: i32,
+    pub message: Cow<'a, str>,
+    raw_objects: &'a [vk::DebugUtilsObjectNameInfoEXT],
+}
+
+impl<'a> DebugMessage<'a> {
+    pub fn objects(&self) -> impl Iterator<Item = DebugObject<'a>> + 'a {
+        self.raw_objects.iter().map(|object| {
+            let name = unsafe { ptr_to_str(object.p_object_name) };
+            DebugObject {
+                object_type: object.object_type,
+                handle: object.object_handle,
+                name: if name.is_empty() { None } else { Some(name) },
+            }
+        })
+    }
+}
+
+/// The default callback: forwards every message to the `log` crate under [`LOG_TARGET`].
+pub fn log_message(message: &DebugMessage) {
+    let level = severity_to_level(message.severity);
+    if !log::log_enabled!(target: LOG_TARGET, level) {
+        return;
+    }
+
+    let objects = message
+        .objects()
+        .map(|object| match object.name {
+            Some(name) => format!("{:?} {:#x} \"{}\"", object.object_type, object.handle, name),
+            None => format!("{:?} {:#x}", object.object_type, object.handle),
+        })
+        .collect::<Vec<_>>();
+
+    log::log!(
+        target: LOG_TARGET,
+        level,
+        "[{}] {} ({:#x}): {}{}",
+        message_type_name(message.message_type),
+        if message.id_name.is_empty() {
+            "-"
+        } else {
+            &message.id_name
+        },
+        message.id_number as u32,
+        message.message,
+        if objects.is_empty() {
+            String::new()
+        } else {
+            format!(" [objects: {}]", objects.join(", "))
+        },
+    );
+}
+
+type MessageHandler = Box<dyn Fn(&DebugMessage) + Send + Sync>;
+
+/// The function messages are delivered to. Closures can capture whatever user data they need.
+///
+/// The layers hold a raw pointer to the handler for as long as the instance or any messenger
+/// created from [`DebugCallback::messenger_create_info`] exists, so the callback must outlive
+/// both; share it through an `Arc` and drop the last reference after destroying the instance.
+pub struct DebugCallback {
+    // boxed twice so the pointer handed to vulkan is thin and does not move
+    handler: Box<MessageHandler>,
+}
+
+impl DebugCallback {
+    pub fn new<F>(handler: F) -> Self
+    where
+        F: Fn(&DebugMessage) + Send + Sync + 'static,
+    {
+        Self {
+            handler: Box::new(Box::new(handler)),
+        }
+    }
+
+    fn user_data(&self) -> *mut c_void {
+        &*self.handler as *const MessageHandler as *mut c_void
+    }
+
+    /// Create info routing messages to this callback. Chained into `VkInstanceCreateInfo` it also
+    /// covers `vkCreateInstance` and `vkDestroyInstance`, which no messenger can observe.
+    pub fn messenger_create_info(&self) -> vk::DebugUtilsMessengerCreateInfoEXT {
+        vk::DebugUtilsMessengerCreateInfoEXT {
+            s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+            p_next: std::ptr::null(),
+            flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
+            message_severity: severity_mask_for(log::max_level()),
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            pfn_user_callback: Some(debug_callback),
+            p_user_data: self.user_data(),
+        }
+    }
+}
+
+impl Default for DebugCallback {
+    fn default() -> Self {
+        Self::new(log_message)
+    }
+}
+
+unsafe extern "system" fn debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    if p_callback_data.is_null() {
+        return vk::FALSE;
+    }
+    let data = &*p_callback_data;
+
+    let message = DebugMessage {
+        severity: message_severity,
+        message_type,
+        id_name: ptr_to_str(data.p_message_id_name),
+        id_number: data.message_id_number,
+        message: ptr_to_str(data.p_message),
+        raw_objects: raw_slice(data.p_objects, data.object_count),
+    };
+
+    if is_suppressed(&message.id_name, message.id_number) {
+        return vk::FALSE;
+    }
+
+    // unwinding into the driver is undefined behaviour
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        if p_user_data.is_null() {
+            log_message(&message);
+        } else {
+            let handler = &*(p_user_data as *const MessageHandler);
+            handler(&message);
+        }
+    }));
+
+    if result.is_err() {
+        log::error!("debug messenger callback panicked");
+    }
+
+    vk::FALSE
+}
+
+/// Owns a `VkDebugUtilsMessengerEXT` and destroys it on drop, keeping the instance it was created
+/// from alive until then.
+pub struct DebugMessenger {
+    debug_utils: DebugUtils,
+    messenger: vk::DebugUtilsMessengerEXT,
+    _callback: Arc<DebugCallback>,
+    _instance: Arc<Instance>,
+}
+
+impl DebugMessenger {
+    pub fn new(instance: &Arc<Instance>, callback: Arc<DebugCallback>) -> Self {
+        let debug_utils = DebugUtils::new(instance.entry(), &***instance);
+
+        let messenger = unsafe {
+            debug_utils
+                .create_debug_utils_messenger(&callback.messenger_create_info(), None)
+                .expect("could not create debug messenger")
+        };
+
+        Self {
+            debug_utils,
+            messenger,
+            _callback: callback,
+            _instance: Arc::clone(instance),
+        }
+    }
+
+    pub fn debug_utils(&self) -> &DebugUtils {
+        &self.debug_utils
+    }
+}
+
+impl Drop for DebugMessenger {
+    fn drop(&mut self) {
+        unsafe {
+            self.debug_utils
+                .destroy_debug_utils_messenger(self.messenger, None);
+        }
+    }
+}
+
+/// Names objects and labels command buffer regions through `VK_EXT_debug_utils` so validation
+/// messages and captures (RenderDoc etc.) show something readable. When the extension is not
+/// enabled every method returns immediately without formatting or allocating anything.
+#[derive(Clone)]
+pub struct DebugMarker {
+    debug_utils: Option<DebugUtils>,
+    device: vk::Device,
+}
+
+impl DebugMarker {
+    pub fn new(debug_utils: Option<DebugUtils>, device: vk::Device) -> Self {
+        Self {
+            debug_utils,
+            device,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.debug_utils.is_some()
+    }
+
+    pub fn set_object_name<H: vk::Handle>(&self, handle: H, name: &str) {
+        let debug_utils = match &self.debug_utils {
+            Some(debug_utils) => debug_utils,
+            None => return,
+        };
+
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_) => return,
+        };
+
+        let name_info = vk::DebugUtilsObjectNameInfoEXT {
+            s_type: vk::StructureType::DEBUG_UTILS_OBJECT_NAME_INFO_EXT,
+            p_next: std::ptr::null(),
+            object_type: H::TYPE,
+            object_handle: handle.as_raw(),
+            p_object_name: name.as_ptr(),
+        };
+
+        if let Err(err) =
+            unsafe { debug_utils.debug_utils_set_object_name(self.device, &name_info) }
+        {
+            log::warn!("failed to name {:?} {:?}: {}", H::TYPE, name, err);
+        }
+    }
+
+    /// Names each handle in `handles` as `"{prefix} {index}"`.
+    pub fn set_object_names<H, I>(&self, handles: I, prefix: &str)
+    where
+        H: vk::Handle,
+        I: IntoIterator<Item = H>,
+    {
+        if !self.is_enabled() {
+            return;
+        }
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            self.set_object_name(handle, &format!("{} {}", prefix, i));
+        }
+    }
+
+    pub fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+        let debug_utils = match &self.debug_utils {
+            Some(debug_utils) => debug_utils,
+            None => return,
+        };
+
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_) => return,
+        };
+
+        let label = vk::DebugUtilsLabelEXT {
+            s_type: vk::StructureType::DEBUG_UTILS_LABEL_EXT,
+            p_next: std::ptr::null(),
+            p_label_name: name.as_ptr(),
+            color,
+        };
+
+        unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+    }
+
+    pub fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_validation_switch() {
+        let enabled = |value| ValidationConfig::parse(Some(value), None).enabled;
+        for value in ["1", "on", "true", " TRUE "] {
+            assert!(enabled(value), "{:?}", value);
+        }
+        for value in ["0", "off", "false"] {
+            assert!(!enabled(value), "{:?}", value);
+        }
+        assert_eq!(enabled("maybe"), cfg!(debug_assertions));
+        assert_eq!(
+            ValidationConfig::parse(None, None).enabled,
+            cfg!(debug_assertions)
+        );
+    }
+
+    #[test]
+    fn parses_validation_features() {
+        let config = ValidationConfig::parse(Some("1"), Some("gpu, sync,unknown,,"));
+        assert_eq!(
+            config,
+            ValidationConfig {
+                enabled: true,
+                gpu_assisted: true,
+                best_practices: false,
+                synchronization: true,
+            }
+        );
+        assert_eq!(
+            config.enabled_features(),
+            [
+                vk::ValidationFeatureEnableEXT::GPU_ASSISTED,
+                vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT,
+                vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+            ]
+        );
+
+        // features without validation enable nothing
+        let config = ValidationConfig::parse(Some("0"), Some("best-practices"));
+        assert!(!config.has_features());
+        assert!(config.enabled_features().is_empty());
+    }
+
+    #[test]
+    fn resolves_against_what_is_available() {
+        let all = ValidationConfig {
+            enabled: true,
+            gpu_assisted: true,
+            best_practices: true,
+            synchronization: true,
+        };
+        assert_eq!(all.resolve_with(|| true, || true), all);
+        assert_eq!(
+            all.resolve_with(|| false, || unreachable!()),
+            ValidationConfig::default()
+        );
+        assert_eq!(
+            all.resolve_with(|| true, || false),
+            ValidationConfig {
+                enabled: true,
+                ..ValidationConfig::default()
+            }
+        );
+
+        // nothing is looked up for what is not requested
+        let off = ValidationConfig::default();
+        assert_eq!(off.resolve_with(|| unreachable!(), || unreachable!()), off);
+        let plain = ValidationConfig {
+            enabled: true,
+            ..ValidationConfig::default()
+        };
+        assert_eq!(plain.resolve_with(|| true, || unreachable!()), plain);
+    }
+
+    #[test]
+    fn suppresses_by_name() {
+        let ids = ids(&["VUID-vkCmdDraw-None-02859"]);
+        assert!(matches_any_id(&ids, "VUID-vkCmdDraw-None-02859", 1));
+        assert!(!matches_any_id(&ids, "VUID-vkCmdDraw-None-02860", 1));
+    }
+
+    #[test]
+    fn suppresses_by_number() {
+        let ids = ids(&["-1234", "0xdeadbeef"]);
+        assert!(matches_any_id(&ids, "", -1234));
+        assert!(matches_any_id(&ids, "", 0xdeadbeef_u32 as i32));
+        assert!(!matches_any_id(&ids, "", 1234));
+        assert!(!matches_any_id(&[], "VUID-vkCmdDraw-None-02859", -1234));
+    }
+
+    #[test]
+    fn always_requests_errors_and_warnings() {
+        let required = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+            | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+        assert_eq!(severity_mask_for(log::LevelFilter::Off), required);
+        assert_eq!(severity_mask_for(log::LevelFilter::Warn), required);
+        assert_eq!(
+            severity_mask_for(log::LevelFilter::Info),
+            required | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+        );
+        assert_eq!(
+            severity_mask_for(log::LevelFilter::Trace),
+            vk::DebugUtilsMessageSeverityFlagsEXT::all()
+        );
+    }
+
+    #[test]
+    fn maps_severities_to_levels() {
+        assert_eq!(
+            severity_to_level(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR),
+            log::Level::Error
+        );
+        assert_eq!(
+            severity_to_level(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING),
+            log::Level::Warn
+        );
+        assert_eq!(
+            severity_to_level(vk::DebugUtilsMessageSeverityFlagsEXT::INFO),
+            log::Level::Info
+        );
+        assert_eq!(
+            severity_to_level(vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE),
+            log::Level::Trace
+        );
+    }
+}
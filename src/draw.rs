@@ -0,0 +1,317 @@
+//! Instanced, indirect drawing of a scene's meshes.
+//!
+//! Draw items with the same mesh and material make up a [`DrawBatch`], drawn as the instances of
+//! one `vk::DrawIndexedIndirectCommand`. The model matrix of each instance comes from an instance
+//! buffer, so a scene repeating a few meshes takes a few commands however many nodes it has.
+//! Batches with the same material make up a [`DrawBucket`], whose commands are next to each other
+//! and drawn from the scene's merged [`Geometry`](crate::scene::Geometry) with a single indirect
+//! draw, where the device supports `multiDrawIndirect`. The commands, and their count where the
+//! device supports `drawIndirectCount`, are read from buffers, which [`crate::culling`] can fill
+//! on the GPU with only the visible instances.
+
+use crate::resources::{Buffer, Device};
+use crate::scene::{MaterialId, MeshId, Scene, Shading};
+use ash::version::{DeviceV1_0, DeviceV1_2};
+use ash::vk;
+use glam::Mat4;
+use std::sync::Arc;
+
+/// Per-instance data, at binding 1 after the mesh's vertices.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstanceData {
+    pub model: Mat4,
+}
+
+impl InstanceData {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 1,
+            stride: std::mem::size_of::<InstanceData>() as u32,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }
+    }
+
+    /// The columns of the model matrix, at locations 3 to 6.
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 4] {
+        let column = |i: u32| vk::VertexInputAttributeDescription {
+            location: 3 + i,
+            binding: 1,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            offset: i * 16,
+        };
+        [column(0), column(1), column(2), column(3)]
+    }
+}
+
+/// Instances of a mesh drawn with the same material.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawBatch {
+    pub mesh: MeshId,
+    pub material_id: MaterialId,
+    pub shading: Shading,
+    /// Index of the batch's first instance in the instance buffer.
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+/// Groups the draw items of `scene` into batches, ordered by shading, material and mesh so
+/// pipelines and material sets change as rarely as possible. Returns them with the instance data
+/// they index, which keeps node order within a batch.
+pub fn build_batches(scene: &Scene) -> (Vec<DrawBatch>, Vec<InstanceData>) {
+    let mut items = scene
+        .draw_items()
+        .map(|item| {
+            let key = (
+                item.material.shading,
+                item.material_id.index(),
+                item.mesh_id.index(),
+            );
+            (key, item.mesh_id, item.material_id, item.transform)
+        })
+        .collect::<Vec<_>>();
+    items.sort_by_key(|&(key, ..)| key);
+
+    let mut batches = Vec::<DrawBatch>::new();
+    let mut instances = Vec::with_capacity(items.len());
+    for (i, ((shading, ..), mesh, material_id, model)) in items.into_iter().enumerate() {
+        match batches.last_mut() {
+            Some(batch) if batch.mesh == mesh && batch.material_id == material_id => {
+                batch.instance_count += 1;
+            }
+            _ => batches.push(DrawBatch {
+                mesh,
+                material_id,
+                shading,
+                first_instance: i as u32,
+                instance_count: 1,
+            }),
+        }
+        instances.push(InstanceData { model });
+    }
+
+    (batches, instances)
+}
+
+/// Batches drawn with the same pipeline and material by a single indirect draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawBucket {
+    pub material_id: MaterialId,
+    pub shading: Shading,
+    /// Index of the bucket's first batch, and so of its first command.
+    pub first_batch: u32,
+    pub batch_count: u32,
+}
+
+/// Groups `batches`, as sorted by [`build_batches`], into buckets of the same material. Without
+/// `multi_draw` every batch is a bucket of its own, as an indirect draw can only draw one command.
+pub fn build_buckets(batches: &[DrawBatch], multi_draw: bool) -> Vec<DrawBucket> {
+    let mut buckets = Vec::<DrawBucket>::new();
+    for (i, batch) in batches.iter().enumerate() {
+        match buckets.last_mut() {
+            Some(bucket) if multi_draw && bucket.material_id == batch.material_id => {
+                bucket.batch_count += 1;
+            }
+            _ => buckets.push(DrawBucket {
+                material_id: batch.material_id,
+                shading: batch.shading,
+                first_batch: i as u32,
+                batch_count: 1,
+            }),
+        }
+    }
+    buckets
+}
+
+/// Buffers buckets are drawn from: per-instance data, per batch an indirect command, and per
+/// bucket how many of its commands to draw.
+#[derive(Clone, Copy)]
+pub struct DrawBuffers<'b> {
+    pub instances: &'b Buffer,
+    pub commands: &'b Buffer,
+    pub counts: &'b Buffer,
+}
+
+/// The batches of a frame, and the per-frame buffers they are drawn from.
+pub struct IndirectDraws {
+    batches: Vec<DrawBatch>,
+    buckets: Vec<DrawBucket>,
+    instances: Vec<InstanceData>,
+    commands: Vec<vk::DrawIndexedIndirectCommand>,
+    // one of each per frame in flight, grown when a frame's draws no longer fit
+    instance_buffers: Vec<Option<Buffer>>,
+    command_buffers: Vec<Option<Buffer>>,
+    count_buffers: Vec<Option<Buffer>>,
+    device: Arc<Device>,
+}
+
+impl IndirectDraws {
+    pub fn new(device: &Arc<Device>, frames_in_flight: usize) -> Self {
+        let buffers = || (0..frames_in_flight).map(|_| None).collect();
+        Self {
+            batches: Vec::new(),
+            buckets: Vec::new(),
+            instances: Vec::new(),
+            commands: Vec::new(),
+            instance_buffers: buffers(),
+            command_buffers: buffers(),
+            count_buffers: buffers(),
+            device: Arc::clone(device),
+        }
+    }
+
+    /// The batches of the last upload, indexed like their commands.
+    pub fn batches(&self) -> &[DrawBatch] {
+        &self.batches
+    }
+
+    /// The buckets of the last upload, in the order of their batches.
+    pub fn buckets(&self) -> &[DrawBucket] {
+        &self.buckets
+    }
+
+    /// The instances of the last upload, each batch's after the previous one's.
+    pub fn instances(&self) -> &[InstanceData] {
+        &self.instances
+    }
+
+    /// The commands of the last upload, one per batch, drawing all of its instances.
+    pub fn commands(&self) -> &[vk::DrawIndexedIndirectCommand] {
+        &self.commands
+    }
+
+    /// The buffers of frame in flight `frame`, drawing every instance of the last upload to it.
+    pub fn buffers(&self, frame: usize) -> Option<DrawBuffers<'_>> {
+        Some(DrawBuffers {
+            instances: self.instance_buffers[frame].as_ref()?,
+            commands: self.command_buffers[frame].as_ref()?,
+            counts: self.count_buffers[frame].as_ref()?,
+        })
+    }
+
+    /// Batches the draw items of `scene`, whose meshes have to be merged, and writes their
+    /// instances and commands into the buffers of frame in flight `frame`.
+    pub fn upload(&mut self, frame: usize, scene: &Scene) {
+        let (batches, instances) = build_batches(scene);
+        self.buckets = build_buckets(&batches, self.device.multi_draw_indirect());
+        self.batches = batches;
+        self.instances = instances;
+        if self.batches.is_empty() {
+            self.commands.clear();
+            return;
+        }
+
+        let geometry = scene
+            .geometry()
+            .expect("scene meshes have to be merged before drawing");
+        // each bucket binds the instance buffer at its first instance, so without buckets of more
+        // than one batch first_instance stays 0 and drawIndirectFirstInstance is not needed
+        let mut commands = Vec::with_capacity(self.batches.len());
+        for bucket in &self.buckets {
+            let batches =
+                &self.batches[bucket.first_batch as usize..][..bucket.batch_count as usize];
+            let base = batches[0].first_instance;
+            commands.extend(batches.iter().map(|batch| {
+                let range = geometry.range(batch.mesh);
+                vk::DrawIndexedIndirectCommand {
+                    index_count: scene.mesh(batch.mesh).index_count(),
+                    instance_count: batch.instance_count,
+                    first_index: range.first_index,
+                    vertex_offset: range.vertex_offset,
+                    first_instance: batch.first_instance - base,
+                }
+            }));
+        }
+        self.commands = commands;
+        // every command of a bucket is drawn, until something on the GPU decides otherwise
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.batch_count)
+            .collect::<Vec<_>>();
+
+        let device = &self.device;
+        Buffer::write_growing(
+            device,
+            &mut self.instance_buffers[frame],
+            &self.instances,
+            // read by the culling shader as well
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+        );
+        Buffer::write_growing(
+            device,
+            &mut self.command_buffers[frame],
+            &self.commands,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+        Buffer::write_growing(
+            device,
+            &mut self.count_buffers[frame],
+            &counts,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+        );
+    }
+
+    /// Records drawing bucket `index` of the last upload from `buffers`, binding the merged
+    /// meshes of `scene` and the bucket's instances. The pipeline, and whatever else the bucket's
+    /// material needs, have to be bound already.
+    pub fn record_bucket(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffers: DrawBuffers,
+        scene: &Scene,
+        index: usize,
+    ) {
+        let DrawBuffers {
+            instances,
+            commands,
+            counts,
+        } = buffers;
+        let bucket = &self.buckets[index];
+        let geometry = scene
+            .geometry()
+            .expect("scene meshes have to be merged before drawing");
+        let first_instance = self.batches[bucket.first_batch as usize].first_instance;
+        let instance_offset =
+            first_instance as vk::DeviceSize * std::mem::size_of::<InstanceData>() as u64;
+        let stride = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+        let command_offset = bucket.first_batch as vk::DeviceSize * stride as vk::DeviceSize;
+        let device = &self.device;
+
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[geometry.vertex_buffer().handle(), instances.handle()],
+                &[0, instance_offset],
+            );
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                geometry.index_buffer().handle(),
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            if device.draw_indirect_count() {
+                device.cmd_draw_indexed_indirect_count(
+                    command_buffer,
+                    commands.handle(),
+                    command_offset,
+                    counts.handle(),
+                    index as vk::DeviceSize * 4,
+                    bucket.batch_count,
+                    stride,
+                );
+            } else {
+                // culled commands are left with no instances
+                device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    commands.handle(),
+                    command_offset,
+                    bucket.batch_count,
+                    stride,
+                );
+            }
+        }
+    }
+}
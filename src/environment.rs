@@ -0,0 +1,748 @@
+//! Image based lighting: a cube map of the environment around the scene, and what the PBR shader
+//! reads it through. That is an irradiance map for diffuse lighting, a prefiltered map for
+//! specular reflections and the BRDF lookup table of the split sum approximation, all computed
+//! on the GPU when the environment is created.
+
+use crate::as_bytes;
+use crate::resources::{
+    DescriptorPool, DescriptorSetLayout, Device, Image, ImageView, Pipeline, PipelineLayout,
+    ShaderModule,
+};
+use crate::texture::{SamplerDesc, Texture};
+use crate::upload::Uploader;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use glam::Vec3;
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Format of every map, which has to support storage, blits and linear filtering.
+const FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const ENVIRONMENT_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+/// Size of the environment mip level the irradiance is computed from, coarse enough for the
+/// convolution's sample spacing.
+const IRRADIANCE_SOURCE_SIZE: u32 = 64;
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_LEVELS: u32 = 5;
+const PREFILTER_SAMPLES: u32 = 1024;
+const BRDF_LUT_SIZE: u32 = 256;
+const GROUP_SIZE: u32 = 8;
+
+pub enum EnvironmentSource {
+    /// A procedural sky, with the sun in `sun_direction` as seen from the scene.
+    Sky { sun_direction: Vec3 },
+    /// An equirectangular panorama of linear RGBA pixels.
+    Panorama {
+        width: u32,
+        height: u32,
+        pixels: Vec<f32>,
+    },
+}
+
+impl EnvironmentSource {
+    /// Reads an equirectangular panorama, such as a Radiance `.hdr` file.
+    pub fn load_panorama(path: impl AsRef<Path>) -> Result<Self, image::ImageError> {
+        let image = image::open(path)?.into_rgba32f();
+        Ok(Self::Panorama {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+        })
+    }
+}
+
+pub struct Environment {
+    /// The environment itself, with each mip level downsampled from the one before.
+    pub cube: Texture,
+    pub irradiance: Texture,
+    /// Filtered for a roughness going from 0 at the first mip level to 1 at the last.
+    pub prefiltered: Texture,
+    /// Scale and bias to F0 in the red and green channels, by n·v along x and roughness along y.
+    pub brdf_lut: Texture,
+}
+
+impl Environment {
+    /// Computes the maps of `source`, waiting until they are done. The uploader's queue has to
+    /// support compute.
+    pub fn new(uploader: &Uploader, source: &EnvironmentSource) -> Self {
+        let device = uploader.device();
+        let environment_levels = mip_levels(ENVIRONMENT_SIZE);
+
+        let cube = create_map(
+            device,
+            ENVIRONMENT_SIZE,
+            environment_levels,
+            true,
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+        let irradiance = create_map(
+            device,
+            IRRADIANCE_SIZE,
+            1,
+            true,
+            vk::ImageUsageFlags::empty(),
+        );
+        let prefiltered = create_map(
+            device,
+            PREFILTERED_SIZE,
+            PREFILTERED_LEVELS,
+            true,
+            vk::ImageUsageFlags::empty(),
+        );
+        let brdf_lut = create_map(
+            device,
+            BRDF_LUT_SIZE,
+            1,
+            false,
+            vk::ImageUsageFlags::empty(),
+        );
+
+        // linear filtering of 32 bit floats is optional, and the panorama is downsampled along
+        // with the cube map's mip levels anyway
+        let panorama = match source {
+            EnvironmentSource::Sky { .. } => None,
+            EnvironmentSource::Panorama {
+                width,
+                height,
+                pixels,
+            } => Some(Texture::from_pixels(
+                uploader,
+                vk::Extent2D {
+                    width: *width,
+                    height: *height,
+                },
+                vk::Format::R32G32B32A32_SFLOAT,
+                pixels,
+                &SamplerDesc {
+                    mag_filter: vk::Filter::NEAREST,
+                    min_filter: vk::Filter::NEAREST,
+                    address_mode_u: vk::SamplerAddressMode::REPEAT,
+                    address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                },
+            )),
+        };
+
+        let storage = vk::DescriptorType::STORAGE_IMAGE;
+        let sampled = vk::DescriptorType::SAMPLED_IMAGE;
+        let sampler = vk::DescriptorType::SAMPLER;
+
+        let source_pass = match source {
+            EnvironmentSource::Sky { .. } => {
+                ComputePass::new(device, "shaders/sky.spv", &[storage], 16)
+            }
+            EnvironmentSource::Panorama { .. } => ComputePass::new(
+                device,
+                "shaders/equirect.spv",
+                &[storage, sampled, sampler],
+                0,
+            ),
+        };
+        let irradiance_pass = ComputePass::new(
+            device,
+            "shaders/irradiance.spv",
+            &[storage, sampled, sampler],
+            4,
+        );
+        let prefilter_pass = ComputePass::new(
+            device,
+            "shaders/prefilter.spv",
+            &[storage, sampled, sampler],
+            12,
+        );
+        let brdf_pass = ComputePass::new(device, "shaders/brdf_lut.spv", &[storage], 0);
+
+        let set_count = 3 + PREFILTERED_LEVELS;
+        let pool_sizes = [storage, sampled, sampler].map(|ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: set_count,
+        });
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(set_count)
+            .pool_sizes(&pool_sizes);
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .expect("failed to create descriptor pool!")
+        };
+        let pool = DescriptorPool::from_raw(device, pool);
+
+        let storage_view = |map: &Texture, level, layers| {
+            let view_type = if layers == 6 {
+                vk::ImageViewType::TYPE_2D_ARRAY
+            } else {
+                vk::ImageViewType::TYPE_2D
+            };
+            ImageView::new(
+                device,
+                map.image.handle(),
+                view_type,
+                FORMAT,
+                subresource_range(level, 1, layers),
+            )
+        };
+        let storage_info = |view: &ImageView| vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: view.handle(),
+            image_layout: vk::ImageLayout::GENERAL,
+        };
+
+        let cube_storage = storage_view(&cube, 0, 6);
+        let irradiance_storage = storage_view(&irradiance, 0, 6);
+        let prefiltered_storage = (0..PREFILTERED_LEVELS)
+            .map(|level| storage_view(&prefiltered, level, 6))
+            .collect::<Vec<_>>();
+        let brdf_storage = storage_view(&brdf_lut, 0, 1);
+
+        let source_set = match &panorama {
+            Some(panorama) => source_pass.descriptor_set(
+                device,
+                &pool,
+                &[
+                    storage_info(&cube_storage),
+                    panorama.descriptor(),
+                    panorama.descriptor(),
+                ],
+            ),
+            None => source_pass.descriptor_set(device, &pool, &[storage_info(&cube_storage)]),
+        };
+        let irradiance_set = irradiance_pass.descriptor_set(
+            device,
+            &pool,
+            &[
+                storage_info(&irradiance_storage),
+                cube.descriptor(),
+                cube.descriptor(),
+            ],
+        );
+        let prefilter_sets = prefiltered_storage
+            .iter()
+            .map(|view| {
+                prefilter_pass.descriptor_set(
+                    device,
+                    &pool,
+                    &[storage_info(view), cube.descriptor(), cube.descriptor()],
+                )
+            })
+            .collect::<Vec<_>>();
+        let brdf_set = brdf_pass.descriptor_set(device, &pool, &[storage_info(&brdf_storage)]);
+
+        uploader.submit(|command_buffer| {
+            let to_general = |map: &Texture, levels, layers| {
+                image_barrier(
+                    map,
+                    subresource_range(0, levels, layers),
+                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+                    (vk::AccessFlags::empty(), vk::AccessFlags::SHADER_WRITE),
+                )
+            };
+            let to_shader_read = |map: &Texture, levels, layers| {
+                image_barrier(
+                    map,
+                    subresource_range(0, levels, layers),
+                    (
+                        vk::ImageLayout::GENERAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ),
+                )
+            };
+
+            pipeline_barrier(
+                device,
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::TRANSFER,
+                ),
+                &[
+                    to_general(&cube, 1, 6),
+                    image_barrier(
+                        &cube,
+                        subresource_range(1, environment_levels - 1, 6),
+                        (
+                            vk::ImageLayout::UNDEFINED,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        ),
+                        (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+                    ),
+                    to_general(&irradiance, 1, 6),
+                    to_general(&prefiltered, PREFILTERED_LEVELS, 6),
+                    to_general(&brdf_lut, 1, 1),
+                ],
+            );
+
+            let groups = |size: u32, layers| {
+                let count = size.div_ceil(GROUP_SIZE);
+                (count, count, layers)
+            };
+
+            match source {
+                EnvironmentSource::Sky { sun_direction } => source_pass.dispatch(
+                    device,
+                    command_buffer,
+                    source_set,
+                    &sun_direction.extend(0.),
+                    groups(ENVIRONMENT_SIZE, 6),
+                ),
+                EnvironmentSource::Panorama { .. } => source_pass.dispatch(
+                    device,
+                    command_buffer,
+                    source_set,
+                    &(),
+                    groups(ENVIRONMENT_SIZE, 6),
+                ),
+            }
+            brdf_pass.dispatch(
+                device,
+                command_buffer,
+                brdf_set,
+                &(),
+                groups(BRDF_LUT_SIZE, 1),
+            );
+
+            // fill the rest of the mip chain, each level from the one before
+            pipeline_barrier(
+                device,
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::TRANSFER,
+                ),
+                &[image_barrier(
+                    &cube,
+                    subresource_range(0, 1, 6),
+                    (
+                        vk::ImageLayout::GENERAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    ),
+                    (
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                )],
+            );
+            for level in 1..environment_levels {
+                record_downsample(device, command_buffer, &cube, level);
+                pipeline_barrier(
+                    device,
+                    command_buffer,
+                    (
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::TRANSFER,
+                    ),
+                    &[image_barrier(
+                        &cube,
+                        subresource_range(level, 1, 6),
+                        (
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        ),
+                        (
+                            vk::AccessFlags::TRANSFER_WRITE,
+                            vk::AccessFlags::TRANSFER_READ,
+                        ),
+                    )],
+                );
+            }
+            pipeline_barrier(
+                device,
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                ),
+                &[image_barrier(
+                    &cube,
+                    subresource_range(0, environment_levels, 6),
+                    (
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    (
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    ),
+                )],
+            );
+
+            let source_lod = (ENVIRONMENT_SIZE / IRRADIANCE_SOURCE_SIZE) as f32;
+            irradiance_pass.dispatch(
+                device,
+                command_buffer,
+                irradiance_set,
+                &source_lod.log2(),
+                groups(IRRADIANCE_SIZE, 6),
+            );
+
+            for (level, &set) in prefilter_sets.iter().enumerate() {
+                let params = PrefilterParams {
+                    roughness: level as f32 / (PREFILTERED_LEVELS - 1) as f32,
+                    source_size: ENVIRONMENT_SIZE as f32,
+                    sample_count: PREFILTER_SAMPLES,
+                };
+                prefilter_pass.dispatch(
+                    device,
+                    command_buffer,
+                    set,
+                    &params,
+                    groups(PREFILTERED_SIZE >> level, 6),
+                );
+            }
+
+            pipeline_barrier(
+                device,
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                ),
+                &[
+                    to_shader_read(&irradiance, 1, 6),
+                    to_shader_read(&prefiltered, PREFILTERED_LEVELS, 6),
+                    to_shader_read(&brdf_lut, 1, 1),
+                ],
+            );
+        });
+
+        Self {
+            cube,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+        }
+    }
+
+    /// The maps in the order the PBR shader binds them.
+    pub fn textures(&self) -> [&Texture; 4] {
+        [
+            &self.cube,
+            &self.irradiance,
+            &self.prefiltered,
+            &self.brdf_lut,
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PrefilterParams {
+    roughness: f32,
+    source_size: f32,
+    sample_count: u32,
+}
+
+fn mip_levels(size: u32) -> u32 {
+    32 - size.leading_zeros()
+}
+
+fn subresource_range(base_level: u32, levels: u32, layers: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: base_level,
+        level_count: levels,
+        base_array_layer: 0,
+        layer_count: layers,
+    }
+}
+
+/// Creates a square map written by compute shaders and sampled by the PBR shader, a cube map if
+/// `cube` is set.
+fn create_map(
+    device: &Arc<Device>,
+    size: u32,
+    levels: u32,
+    cube: bool,
+    usage: vk::ImageUsageFlags,
+) -> Texture {
+    let layers = if cube { 6 } else { 1 };
+    let image_info = vk::ImageCreateInfo::builder()
+        .flags(if cube {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        })
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(FORMAT)
+        .extent(vk::Extent3D {
+            width: size,
+            height: size,
+            depth: 1,
+        })
+        .mip_levels(levels)
+        .array_layers(layers)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(usage | vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+
+    let image = Image::new(device, &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+    let view = ImageView::new(
+        device,
+        image.handle(),
+        if cube {
+            vk::ImageViewType::CUBE
+        } else {
+            vk::ImageViewType::TYPE_2D
+        },
+        FORMAT,
+        subresource_range(0, levels, layers),
+    );
+
+    // cube maps are filtered across their faces regardless of the address mode
+    let sampler = SamplerDesc {
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        ..SamplerDesc::default()
+    };
+
+    Texture {
+        sampler: sampler.create(device),
+        view,
+        image,
+        extent: vk::Extent2D {
+            width: size,
+            height: size,
+        },
+    }
+}
+
+fn image_barrier(
+    map: &Texture,
+    range: vk::ImageSubresourceRange,
+    (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+    (src_access, dst_access): (vk::AccessFlags, vk::AccessFlags),
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(map.image.handle())
+        .subresource_range(range)
+        .build()
+}
+
+fn pipeline_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    (src_stage, dst_stage): (vk::PipelineStageFlags, vk::PipelineStageFlags),
+    barriers: &[vk::ImageMemoryBarrier],
+) {
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            barriers,
+        );
+    }
+}
+
+/// Blits all faces of mip level `level - 1` of `cube` into level `level`.
+fn record_downsample(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    cube: &Texture,
+    level: u32,
+) {
+    let size = |level: u32| (ENVIRONMENT_SIZE >> level).max(1) as i32;
+    let layers = |level| vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: level,
+        base_array_layer: 0,
+        layer_count: 6,
+    };
+    let corners = |level| {
+        [
+            vk::Offset3D { x: 0, y: 0, z: 0 },
+            vk::Offset3D {
+                x: size(level),
+                y: size(level),
+                z: 1,
+            },
+        ]
+    };
+
+    let blit = vk::ImageBlit {
+        src_subresource: layers(level - 1),
+        src_offsets: corners(level - 1),
+        dst_subresource: layers(level),
+        dst_offsets: corners(level),
+    };
+
+    unsafe {
+        device.cmd_blit_image(
+            command_buffer,
+            cube.image.handle(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            cube.image.handle(),
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+    }
+}
+
+/// A compute pipeline reading and writing images through a single descriptor set.
+struct ComputePass {
+    pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+    set_layout: DescriptorSetLayout,
+    bindings: Vec<vk::DescriptorType>,
+}
+
+impl ComputePass {
+    /// Creates the pipeline for the shader at `path`, whose set 0 has a binding of each of
+    /// `bindings` in order.
+    fn new(
+        device: &Arc<Device>,
+        path: &str,
+        bindings: &[vk::DescriptorType],
+        push_constants_size: u32,
+    ) -> Self {
+        let layout_bindings = bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, &ty)| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding as u32)
+                    .descriptor_type(ty)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&layout_bindings);
+        let set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .expect("failed to create descriptor set layout!")
+        };
+        let set_layout = DescriptorSetLayout::from_raw(device, set_layout);
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: push_constants_size,
+        };
+        let push_constant_ranges = if push_constants_size > 0 {
+            std::slice::from_ref(&push_constant_range)
+        } else {
+            &[]
+        };
+        let set_layouts = [set_layout.handle()];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .expect("failed to create pipeline layout!")
+        };
+        let pipeline_layout = PipelineLayout::from_raw(device, pipeline_layout);
+
+        let shader = ShaderModule::load(device, path);
+        let entry_point = CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader.handle())
+            .name(&entry_point);
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage.build())
+            .layout(pipeline_layout.handle());
+
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info.build()], None)
+                .expect("failed to create compute pipeline!")
+        };
+
+        Self {
+            pipeline: Pipeline::from_raw(device, pipeline[0]),
+            pipeline_layout,
+            set_layout,
+            bindings: bindings.to_vec(),
+        }
+    }
+
+    /// Allocates a set from `pool` with `images` written to the bindings in order.
+    fn descriptor_set(
+        &self,
+        device: &Device,
+        pool: &DescriptorPool,
+        images: &[vk::DescriptorImageInfo],
+    ) -> vk::DescriptorSet {
+        let set_layouts = [self.set_layout.handle()];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool.handle())
+            .set_layouts(&set_layouts);
+        let set = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .expect("failed to allocate descriptor sets!")[0]
+        };
+
+        let writes = images
+            .iter()
+            .zip(&self.bindings)
+            .enumerate()
+            .map(|(binding, (image, &ty))| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding as u32)
+                    .descriptor_type(ty)
+                    .image_info(std::slice::from_ref(image))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+        set
+    }
+
+    /// Records a dispatch of `groups` work groups with `set` bound and `push_constants` pushed,
+    /// unless they are zero sized.
+    fn dispatch<T: Copy>(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        set: vk::DescriptorSet,
+        push_constants: &T,
+        (x, y, z): (u32, u32, u32),
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline.handle(),
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout.handle(),
+                0,
+                &[set],
+                &[],
+            );
+            if std::mem::size_of::<T>() > 0 {
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout.handle(),
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    as_bytes(push_constants),
+                );
+            }
+            device.cmd_dispatch(command_buffer, x, y, z);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! Device extensions newer than the headers ash 0.32 is generated from: `VK_KHR_dynamic_rendering`,
+//! which begins rendering straight into image views without render pass and framebuffer objects,
+//! and `VK_KHR_synchronization2`, whose barriers carry their own stage masks. Only the parts the
+//! renderer uses are declared, laid out as in the Vulkan registry.
+//!
+//! The device is created with whichever of them it supports, see [`ExtensionSupport`], and
+//! [`Device`](crate::resources::Device) loads their commands. Optional Vulkan 1.2 features are
+//! queried and enabled alongside them.
+
+use crate::vk_to_str;
+use ash::version::{InstanceV1_0, InstanceV1_1};
+use ash::vk;
+use std::ffi::{c_void, CStr};
+use std::mem;
+
+pub const DYNAMIC_RENDERING_NAME: &str = "VK_KHR_dynamic_rendering";
+pub const SYNCHRONIZATION2_NAME: &str = "VK_KHR_synchronization2";
+
+const RENDERING_INFO_KHR: vk::StructureType = vk::StructureType::from_raw(1000044000);
+const RENDERING_ATTACHMENT_INFO_KHR: vk::StructureType = vk::StructureType::from_raw(1000044001);
+const PIPELINE_RENDERING_CREATE_INFO_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1000044002);
+const PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1000044003);
+const IMAGE_MEMORY_BARRIER_2_KHR: vk::StructureType = vk::StructureType::from_raw(1000314002);
+const DEPENDENCY_INFO_KHR: vk::StructureType = vk::StructureType::from_raw(1000314003);
+const PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1000314007);
+
+/// `VkPipelineStageFlags2KHR`. The stages of `vk::PipelineStageFlags` keep their bits.
+pub type PipelineStageFlags2KHR = u64;
+/// `VkAccessFlags2KHR`. The accesses of `vk::AccessFlags` keep their bits.
+pub type AccessFlags2KHR = u64;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RenderingAttachmentInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub image_view: vk::ImageView,
+    pub image_layout: vk::ImageLayout,
+    pub resolve_mode: vk::ResolveModeFlags,
+    pub resolve_image_view: vk::ImageView,
+    pub resolve_image_layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+impl RenderingAttachmentInfoKHR {
+    /// An attachment that is not resolved.
+    pub fn new(
+        image_view: vk::ImageView,
+        image_layout: vk::ImageLayout,
+        load_op: vk::AttachmentLoadOp,
+        store_op: vk::AttachmentStoreOp,
+        clear_value: vk::ClearValue,
+    ) -> Self {
+        Self {
+            s_type: RENDERING_ATTACHMENT_INFO_KHR,
+            p_next: std::ptr::null(),
+            image_view,
+            image_layout,
+            resolve_mode: vk::ResolveModeFlags::NONE,
+            resolve_image_view: vk::ImageView::null(),
+            resolve_image_layout: vk::ImageLayout::UNDEFINED,
+            load_op,
+            store_op,
+            clear_value,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RenderingInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub flags: vk::Flags,
+    pub render_area: vk::Rect2D,
+    pub layer_count: u32,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachments: *const RenderingAttachmentInfoKHR,
+    pub p_depth_attachment: *const RenderingAttachmentInfoKHR,
+    pub p_stencil_attachment: *const RenderingAttachmentInfoKHR,
+}
+
+impl RenderingInfoKHR {
+    /// Renders a single layer of `render_area` into the given attachments, which must outlive
+    /// the returned value.
+    pub fn new(
+        render_area: vk::Rect2D,
+        color_attachments: &[RenderingAttachmentInfoKHR],
+        depth_attachment: Option<&RenderingAttachmentInfoKHR>,
+    ) -> Self {
+        Self {
+            s_type: RENDERING_INFO_KHR,
+            p_next: std::ptr::null(),
+            flags: 0,
+            render_area,
+            layer_count: 1,
+            view_mask: 0,
+            color_attachment_count: color_attachments.len() as u32,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: depth_attachment.map_or(std::ptr::null(), |depth| depth),
+            p_stencil_attachment: std::ptr::null(),
+        }
+    }
+}
+
+/// Chained to a `vk::GraphicsPipelineCreateInfo` without a render pass, giving the formats of the
+/// attachments the pipeline renders to.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PipelineRenderingCreateInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachment_formats: *const vk::Format,
+    pub depth_attachment_format: vk::Format,
+    pub stencil_attachment_format: vk::Format,
+}
+
+impl PipelineRenderingCreateInfoKHR {
+    /// `color_formats` must outlive the returned value.
+    pub fn new(color_formats: &[vk::Format], depth_format: Option<vk::Format>) -> Self {
+        Self {
+            s_type: PIPELINE_RENDERING_CREATE_INFO_KHR,
+            p_next: std::ptr::null(),
+            view_mask: 0,
+            color_attachment_count: color_formats.len() as u32,
+            p_color_attachment_formats: color_formats.as_ptr(),
+            depth_attachment_format: depth_format.unwrap_or(vk::Format::UNDEFINED),
+            stencil_attachment_format: vk::Format::UNDEFINED,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ImageMemoryBarrier2KHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub src_stage_mask: PipelineStageFlags2KHR,
+    pub src_access_mask: AccessFlags2KHR,
+    pub dst_stage_mask: PipelineStageFlags2KHR,
+    pub dst_access_mask: AccessFlags2KHR,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub src_queue_family_index: u32,
+    pub dst_queue_family_index: u32,
+    pub image: vk::Image,
+    pub subresource_range: vk::ImageSubresourceRange,
+}
+
+impl ImageMemoryBarrier2KHR {
+    /// The equivalent of `barrier` executed between `src_stages` and `dst_stages`.
+    pub fn new(
+        barrier: &vk::ImageMemoryBarrier,
+        src_stages: vk::PipelineStageFlags,
+        dst_stages: vk::PipelineStageFlags,
+    ) -> Self {
+        Self {
+            s_type: IMAGE_MEMORY_BARRIER_2_KHR,
+            p_next: std::ptr::null(),
+            src_stage_mask: src_stages.as_raw() as PipelineStageFlags2KHR,
+            src_access_mask: barrier.src_access_mask.as_raw() as AccessFlags2KHR,
+            dst_stage_mask: dst_stages.as_raw() as PipelineStageFlags2KHR,
+            dst_access_mask: barrier.dst_access_mask.as_raw() as AccessFlags2KHR,
+            old_layout: barrier.old_layout,
+            new_layout: barrier.new_layout,
+            src_queue_family_index: barrier.src_queue_family_index,
+            dst_queue_family_index: barrier.dst_queue_family_index,
+            image: barrier.image,
+            subresource_range: barrier.subresource_range,
+        }
+    }
+}
+
+/// Only image barriers are declared, so the memory and buffer barrier pointers are untyped.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DependencyInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub dependency_flags: vk::DependencyFlags,
+    pub memory_barrier_count: u32,
+    pub p_memory_barriers: *const c_void,
+    pub buffer_memory_barrier_count: u32,
+    pub p_buffer_memory_barriers: *const c_void,
+    pub image_memory_barrier_count: u32,
+    pub p_image_memory_barriers: *const ImageMemoryBarrier2KHR,
+}
+
+impl DependencyInfoKHR {
+    /// `image_barriers` must outlive the returned value.
+    pub fn new(image_barriers: &[ImageMemoryBarrier2KHR]) -> Self {
+        Self {
+            s_type: DEPENDENCY_INFO_KHR,
+            p_next: std::ptr::null(),
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barrier_count: 0,
+            p_memory_barriers: std::ptr::null(),
+            buffer_memory_barrier_count: 0,
+            p_buffer_memory_barriers: std::ptr::null(),
+            image_memory_barrier_count: image_barriers.len() as u32,
+            p_image_memory_barriers: image_barriers.as_ptr(),
+        }
+    }
+}
+
+#[repr(C)]
+struct PhysicalDeviceDynamicRenderingFeaturesKHR {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    dynamic_rendering: vk::Bool32,
+}
+
+#[repr(C)]
+struct PhysicalDeviceSynchronization2FeaturesKHR {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    synchronization2: vk::Bool32,
+}
+
+/// Which of this module's extensions a physical device supports, both the extension and its
+/// feature, and which optional Vulkan 1.2 features. The extensions need Vulkan 1.2, which
+/// provides what they depend on.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExtensionSupport {
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    /// `drawIndirectCount`, for indirect draws whose count is read from a buffer.
+    pub draw_indirect_count: bool,
+    /// `timelineSemaphore`, for semaphores counting up rather than being signaled or not.
+    pub timeline_semaphore: bool,
+}
+
+impl ExtensionSupport {
+    pub fn query(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        if vk::version_major(properties.api_version) == 1
+            && vk::version_minor(properties.api_version) < 2
+        {
+            return Self::default();
+        }
+
+        let extensions = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .expect("could not enumerate device extension properties!")
+        };
+        let has_extension = |name| {
+            extensions
+                .iter()
+                .any(|extension| vk_to_str(&extension.extension_name) == name)
+        };
+
+        let mut dynamic_rendering = PhysicalDeviceDynamicRenderingFeaturesKHR {
+            s_type: PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR,
+            p_next: std::ptr::null_mut(),
+            dynamic_rendering: vk::FALSE,
+        };
+        let mut synchronization2 = PhysicalDeviceSynchronization2FeaturesKHR {
+            s_type: PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES_KHR,
+            p_next: std::ptr::null_mut(),
+            synchronization2: vk::FALSE,
+        };
+        let mut vulkan12 = vk::PhysicalDeviceVulkan12Features::default();
+        // chaining the structure of an extension the device does not have is invalid
+        if has_extension(SYNCHRONIZATION2_NAME) {
+            synchronization2.p_next = vulkan12.p_next;
+            vulkan12.p_next = &mut synchronization2 as *mut _ as *mut c_void;
+        }
+        if has_extension(DYNAMIC_RENDERING_NAME) {
+            dynamic_rendering.p_next = vulkan12.p_next;
+            vulkan12.p_next = &mut dynamic_rendering as *mut _ as *mut c_void;
+        }
+        let mut features = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut vulkan12 as *mut _ as *mut c_void,
+            ..Default::default()
+        };
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+
+        Self {
+            dynamic_rendering: has_extension(DYNAMIC_RENDERING_NAME)
+                && dynamic_rendering.dynamic_rendering == vk::TRUE,
+            synchronization2: has_extension(SYNCHRONIZATION2_NAME)
+                && synchronization2.synchronization2 == vk::TRUE,
+            draw_indirect_count: vulkan12.draw_indirect_count == vk::TRUE,
+            timeline_semaphore: vulkan12.timeline_semaphore == vk::TRUE,
+        }
+    }
+
+    /// Names of the supported extensions, to enable them.
+    pub fn extension_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.dynamic_rendering {
+            names.push(DYNAMIC_RENDERING_NAME);
+        }
+        if self.synchronization2 {
+            names.push(SYNCHRONIZATION2_NAME);
+        }
+        names
+    }
+
+    /// Calls `create` with a chain of feature structures enabling the supported features, to be
+    /// the `p_next` of the `vk::DeviceCreateInfo` it creates the device with.
+    pub fn with_features<T>(&self, create: impl FnOnce(*const c_void) -> T) -> T {
+        let mut next = std::ptr::null_mut();
+
+        let mut dynamic_rendering = PhysicalDeviceDynamicRenderingFeaturesKHR {
+            s_type: PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR,
+            p_next: next,
+            dynamic_rendering: vk::TRUE,
+        };
+        if self.dynamic_rendering {
+            next = &mut dynamic_rendering as *mut _ as *mut c_void;
+        }
+
+        let mut synchronization2 = PhysicalDeviceSynchronization2FeaturesKHR {
+            s_type: PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES_KHR,
+            p_next: next,
+            synchronization2: vk::TRUE,
+        };
+        if self.synchronization2 {
+            next = &mut synchronization2 as *mut _ as *mut c_void;
+        }
+
+        let mut vulkan12 = vk::PhysicalDeviceVulkan12Features {
+            p_next: next,
+            draw_indirect_count: self.draw_indirect_count as vk::Bool32,
+            timeline_semaphore: self.timeline_semaphore as vk::Bool32,
+            ..Default::default()
+        };
+        if self.draw_indirect_count || self.timeline_semaphore {
+            next = &mut vulkan12 as *mut _ as *mut c_void;
+        }
+
+        create(next)
+    }
+}
+
+type CmdBeginRenderingKHR = unsafe extern "system" fn(vk::CommandBuffer, *const RenderingInfoKHR);
+type CmdEndRenderingKHR = unsafe extern "system" fn(vk::CommandBuffer);
+type CmdPipelineBarrier2KHR =
+    unsafe extern "system" fn(vk::CommandBuffer, *const DependencyInfoKHR);
+
+/// Looks up a device command, which must exist since its extension is enabled.
+unsafe fn load<F: Copy>(instance: &ash::Instance, device: &ash::Device, name: &[u8]) -> F {
+    let name = CStr::from_bytes_with_nul(name).unwrap();
+    let function = instance
+        .get_device_proc_addr(device.handle(), name.as_ptr())
+        .unwrap_or_else(|| panic!("failed to load {:?}!", name));
+    mem::transmute_copy(&function)
+}
+
+/// Commands of `VK_KHR_dynamic_rendering`.
+#[derive(Clone)]
+pub struct DynamicRendering {
+    cmd_begin_rendering: CmdBeginRenderingKHR,
+    cmd_end_rendering: CmdEndRenderingKHR,
+}
+
+impl DynamicRendering {
+    pub fn new(instance: &ash::Instance, device: &ash::Device) -> Self {
+        unsafe {
+            Self {
+                cmd_begin_rendering: load(instance, device, b"vkCmdBeginRenderingKHR\0"),
+                cmd_end_rendering: load(instance, device, b"vkCmdEndRenderingKHR\0"),
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording, and follow the valid usage of `vkCmdBeginRenderingKHR`.
+    pub unsafe fn cmd_begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        rendering_info: &RenderingInfoKHR,
+    ) {
+        (self.cmd_begin_rendering)(command_buffer, rendering_info);
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording, and follow the valid usage of `vkCmdEndRenderingKHR`.
+    pub unsafe fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        (self.cmd_end_rendering)(command_buffer);
+    }
+}
+
+/// Commands of `VK_KHR_synchronization2`.
+#[derive(Clone)]
+pub struct Synchronization2 {
+    cmd_pipeline_barrier2: CmdPipelineBarrier2KHR,
+}
+
+impl Synchronization2 {
+    pub fn new(instance: &ash::Instance, device: &ash::Device) -> Self {
+        unsafe {
+            Self {
+                cmd_pipeline_barrier2: load(instance, device, b"vkCmdPipelineBarrier2KHR\0"),
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `command_buffer` must be recording, and follow the valid usage of
+    /// `vkCmdPipelineBarrier2KHR`.
+    pub unsafe fn cmd_pipeline_barrier2(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        dependency_info: &DependencyInfoKHR,
+    ) {
+        (self.cmd_pipeline_barrier2)(command_buffer, dependency_info);
+    }
+}
@@ -0,0 +1,1339 @@
+//! A render graph: passes declare the images they read and write, and the graph takes care of
+//! the rest. When executed it culls the passes whose results are never used, creates the
+//! transient images the others need, sharing memory between images that are never alive at the
+//! same time, begins and ends render passes, and inserts the layout transitions and barriers
+//! between passes.
+//!
+//! Images are either transient, which the graph creates and which only hold anything while it
+//! executes, or imported, such as swapchain images, which the graph leaves in a given layout.
+//! The graph is built anew every frame; what it creates is kept in a [`RenderGraphCache`].
+
+use crate::debug::DebugMarker;
+use crate::ext::{
+    DependencyInfoKHR, ImageMemoryBarrier2KHR, RenderingAttachmentInfoKHR, RenderingInfoKHR,
+};
+use crate::profiler::GpuProfiler;
+use crate::resources::{AliasedImage, Device, DeviceMemory, Framebuffer, ImageView, RenderPass};
+use crate::sync::DeletionQueue;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Accesses that write, and so have to be made available to whatever comes after them.
+const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags::SHADER_WRITE.as_raw()
+        | vk::AccessFlags::MEMORY_WRITE.as_raw(),
+);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+/// Shape of a transient image. Its usage follows from how passes access it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+/// The layout of an image, and the stages and accesses that have to complete before it is used
+/// differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stages: vk::PipelineStageFlags,
+    pub access: vk::AccessFlags,
+}
+
+/// An image the graph does not own, e.g. a swapchain image or a layer of a shadow map.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportedImage {
+    pub image: vk::Image,
+    /// View of the single layer passes use.
+    pub view: vk::ImageView,
+    pub layer: u32,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// State before the graph executes. Its stages are waited for before the image is first
+    /// used, e.g. the stage a semaphore wait for a swapchain image is at.
+    pub initial: ImageState,
+    /// Layout to leave the image in once the graph has executed.
+    pub final_layout: vk::ImageLayout,
+}
+
+/// How a pass uses an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    ColorAttachment,
+    DepthAttachment,
+    /// Read by fragment or compute shaders.
+    Sampled,
+    TransferSrc,
+    TransferDst,
+    /// Read and written as a storage image by compute shaders.
+    Storage,
+}
+
+impl Access {
+    fn is_write(self) -> bool {
+        matches!(
+            self,
+            Access::ColorAttachment
+                | Access::DepthAttachment
+                | Access::TransferDst
+                | Access::Storage
+        )
+    }
+
+    fn usage(self) -> vk::ImageUsageFlags {
+        match self {
+            Access::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            Access::DepthAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            Access::Sampled => vk::ImageUsageFlags::SAMPLED,
+            Access::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+            Access::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+            Access::Storage => vk::ImageUsageFlags::STORAGE,
+        }
+    }
+
+    fn state(self, format: vk::Format) -> ImageState {
+        let (layout, stages, access) = match self {
+            Access::ColorAttachment => (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            Access::DepthAttachment => (
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ),
+            Access::Sampled => (
+                if aspect_mask(format) == vk::ImageAspectFlags::COLOR {
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+                } else {
+                    vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL
+                },
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            Access::TransferSrc => (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            Access::TransferDst => (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            Access::Storage => (
+                vk::ImageLayout::GENERAL,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            ),
+        };
+
+        ImageState {
+            layout,
+            stages,
+            access,
+        }
+    }
+}
+
+/// What happens to an attachment's previous contents at the start of a pass.
+#[derive(Clone, Copy)]
+pub enum LoadOp {
+    Clear(vk::ClearValue),
+    Load,
+    DontCare,
+}
+
+impl LoadOp {
+    fn vk(self) -> vk::AttachmentLoadOp {
+        match self {
+            LoadOp::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            LoadOp::Load => vk::AttachmentLoadOp::LOAD,
+            LoadOp::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        }
+    }
+}
+
+/// Formats and load and store operations of a render pass's attachments: any number of color
+/// attachments and an optional depth attachment, in a single subpass. Attachments stay in their
+/// attachment layout throughout, the graph transitions them outside of the render pass.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RenderPassDesc {
+    pub colors: Vec<AttachmentDesc>,
+    pub depth: Option<AttachmentDesc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AttachmentDesc {
+    pub format: vk::Format,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+}
+
+impl RenderPassDesc {
+    /// A render pass compatible with every one the graph creates for attachments of these
+    /// formats, for creating pipelines.
+    pub fn compatible(color_formats: &[vk::Format], depth_format: Option<vk::Format>) -> Self {
+        let attachment = |format| AttachmentDesc {
+            format,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+        };
+
+        Self {
+            colors: color_formats.iter().copied().map(attachment).collect(),
+            depth: depth_format.map(attachment),
+        }
+    }
+
+    pub fn create(&self, device: &Arc<Device>) -> RenderPass {
+        let description = |attachment: &AttachmentDesc, layout| vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format: attachment.format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: attachment.load_op,
+            store_op: attachment.store_op,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: layout,
+            final_layout: layout,
+        };
+
+        let mut attachments = self
+            .colors
+            .iter()
+            .map(|color| description(color, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+            .collect::<Vec<_>>();
+        let color_refs = (0..self.colors.len() as u32)
+            .map(|attachment| vk::AttachmentReference {
+                attachment,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            })
+            .collect::<Vec<_>>();
+        let depth_ref = vk::AttachmentReference {
+            attachment: attachments.len() as u32,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if let Some(depth) = &self.depth {
+            attachments.push(description(
+                depth,
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ));
+            subpass = subpass.depth_stencil_attachment(&depth_ref);
+        }
+
+        let subpasses = [subpass.build()];
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+
+        let render_pass = unsafe {
+            device
+                .create_render_pass(&render_pass_info, None)
+                .expect("failed to create render pass!")
+        };
+
+        RenderPass::from_raw(device, render_pass)
+    }
+}
+
+/// What a pass records its commands with.
+pub struct PassContext<'c> {
+    pub device: &'c ash::Device,
+    pub command_buffer: vk::CommandBuffer,
+    /// The render pass the graph has begun for the pass's attachments, or null if it has none or
+    /// the device renders without render passes.
+    pub render_pass: vk::RenderPass,
+    /// Extent of the attachments, if any.
+    pub extent: vk::Extent2D,
+    images: &'c [ResolvedImage],
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, id: ImageId) -> vk::Image {
+        self.images[id.0].image
+    }
+
+    pub fn view(&self, id: ImageId) -> vk::ImageView {
+        self.images[id.0].view
+    }
+}
+
+type RecordFn<'a> = Box<dyn FnOnce(&PassContext) + 'a>;
+
+struct Attachment {
+    image: ImageId,
+    load_op: LoadOp,
+}
+
+struct Pass<'a> {
+    name: String,
+    label_color: [f32; 4],
+    colors: Vec<Attachment>,
+    depth: Option<Attachment>,
+    accesses: Vec<(ImageId, Access)>,
+    /// Whether the pass has effects the graph cannot see, so is never culled.
+    keep: bool,
+    record: RecordFn<'a>,
+}
+
+impl Pass<'_> {
+    /// Every image the pass uses, attachments included, and whether it discards their contents.
+    fn uses(&self) -> impl Iterator<Item = (ImageId, Access, bool)> + '_ {
+        let attachment = |attachment: &Attachment, access| {
+            let discards = !matches!(attachment.load_op, LoadOp::Load);
+            (attachment.image, access, discards)
+        };
+
+        self.colors
+            .iter()
+            .map(move |color| attachment(color, Access::ColorAttachment))
+            .chain(
+                self.depth
+                    .iter()
+                    .map(move |depth| attachment(depth, Access::DepthAttachment)),
+            )
+            .chain(
+                self.accesses
+                    .iter()
+                    .map(|&(image, access)| (image, access, false)),
+            )
+    }
+
+    fn is_raster(&self) -> bool {
+        !self.colors.is_empty() || self.depth.is_some()
+    }
+}
+
+enum GraphImage {
+    Transient { name: String, desc: ImageDesc },
+    Imported(ImportedImage),
+}
+
+impl GraphImage {
+    fn format(&self) -> vk::Format {
+        match self {
+            GraphImage::Transient { desc, .. } => desc.format,
+            GraphImage::Imported(imported) => imported.format,
+        }
+    }
+
+    fn extent(&self) -> vk::Extent2D {
+        match self {
+            GraphImage::Transient { desc, .. } => desc.extent,
+            GraphImage::Imported(imported) => imported.extent,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ResolvedImage {
+    image: vk::Image,
+    view: vk::ImageView,
+    layer: u32,
+}
+
+/// Passes and the images they use, for one frame.
+pub struct RenderGraph<'a> {
+    images: Vec<GraphImage>,
+    passes: Vec<Pass<'a>>,
+}
+
+/// Adds a pass to a [`RenderGraph`] once its commands are given with [`PassBuilder::record`].
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    name: String,
+    label_color: [f32; 4],
+    colors: Vec<Attachment>,
+    depth: Option<Attachment>,
+    accesses: Vec<(ImageId, Access)>,
+    keep: bool,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    /// Color of the pass's debug label.
+    pub fn label_color(mut self, color: [f32; 4]) -> Self {
+        self.label_color = color;
+        self
+    }
+
+    pub fn color_attachment(mut self, image: ImageId, load_op: LoadOp) -> Self {
+        self.colors.push(Attachment { image, load_op });
+        self
+    }
+
+    pub fn depth_attachment(mut self, image: ImageId, load_op: LoadOp) -> Self {
+        self.depth = Some(Attachment { image, load_op });
+        self
+    }
+
+    /// Declares a use of `image` other than as an attachment.
+    pub fn access(mut self, image: ImageId, access: Access) -> Self {
+        self.accesses.push((image, access));
+        self
+    }
+
+    /// Keeps the pass even if nothing uses the images it writes, e.g. as it writes buffers.
+    pub fn keep(mut self) -> Self {
+        self.keep = true;
+        self
+    }
+
+    /// Adds the pass, which records its commands with `record`. A pass with attachments records
+    /// inside the render pass the graph begins for them.
+    pub fn record(self, record: impl FnOnce(&PassContext) + 'a) {
+        self.graph.passes.push(Pass {
+            name: self.name,
+            label_color: self.label_color,
+            colors: self.colors,
+            depth: self.depth,
+            accesses: self.accesses,
+            keep: self.keep,
+            record: Box::new(record),
+        });
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            images: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Declares an image for the graph to create, named `name` for debugging.
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ImageId {
+        self.images.push(GraphImage::Transient {
+            name: name.to_owned(),
+            desc,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Declares an image created elsewhere. Passes writing to it are never culled.
+    pub fn import_image(&mut self, image: ImportedImage) -> ImageId {
+        self.images.push(GraphImage::Imported(image));
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Starts declaring a pass, executed after those added before it.
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            name: name.to_owned(),
+            label_color: [0.5, 0.5, 0.5, 1.],
+            colors: Vec::new(),
+            depth: None,
+            accesses: Vec::new(),
+            keep: false,
+        }
+    }
+
+    /// Records the passes that contribute to an imported image into `command_buffer`, timing
+    /// each with `profiler` if there is one, barriers included. Objects of `cache` the graph no
+    /// longer uses are retired into `deletions`, which the frame's submission seals.
+    pub fn execute(
+        mut self,
+        cache: &mut RenderGraphCache,
+        deletions: &mut DeletionQueue,
+        marker: &DebugMarker,
+        mut profiler: Option<&mut GpuProfiler>,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let live = self.live_passes();
+        let lifetimes = self.lifetimes(&live);
+        let keys = self
+            .images
+            .iter()
+            .zip(&lifetimes)
+            .enumerate()
+            .map(|(id, (image, lifetime))| match (image, lifetime) {
+                (GraphImage::Transient { desc, .. }, Some(lifetime)) => {
+                    Some((*desc, self.usage(ImageId(id), &live), *lifetime))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let images = cache.resolve_images(&self.images, &keys, deletions, marker);
+        let device = Arc::clone(&cache.device);
+
+        let mut states = self
+            .images
+            .iter()
+            .map(|image| match image {
+                GraphImage::Transient { .. } => None,
+                GraphImage::Imported(imported) => Some(imported.initial),
+            })
+            .collect::<Vec<_>>();
+        // the memory of a transient image was last used by whatever aliased it before, or by the
+        // previous frame
+        let mut slot_states = HashMap::new();
+
+        let passes = std::mem::take(&mut self.passes);
+        for (index, pass) in passes.into_iter().enumerate() {
+            if !live[index] {
+                continue;
+            }
+
+            let mut barriers = Vec::new();
+            for (id, access, discards) in pass.uses() {
+                let next = access.state(self.images[id.0].format());
+                let previous = match states[id.0] {
+                    Some(state) => state,
+                    None => {
+                        let slot = cache.slot(id);
+                        let state = slot_states.get(&slot).copied().unwrap_or(ImageState {
+                            layout: vk::ImageLayout::UNDEFINED,
+                            stages: vk::PipelineStageFlags::ALL_COMMANDS,
+                            access: vk::AccessFlags::MEMORY_WRITE,
+                        });
+                        ImageState {
+                            layout: vk::ImageLayout::UNDEFINED,
+                            ..state
+                        }
+                    }
+                };
+
+                let written = previous.access.intersects(WRITE_ACCESS);
+                if previous.layout == next.layout && !written && !access.is_write() {
+                    // reads after reads only have to be waited for by the next write
+                    states[id.0] = Some(ImageState {
+                        stages: previous.stages | next.stages,
+                        access: previous.access | next.access,
+                        ..previous
+                    });
+                    continue;
+                }
+
+                let resolved = images[id.0];
+                barriers.push(Barrier {
+                    barrier: vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(previous.access & WRITE_ACCESS)
+                        .dst_access_mask(next.access)
+                        .old_layout(if discards {
+                            vk::ImageLayout::UNDEFINED
+                        } else {
+                            previous.layout
+                        })
+                        .new_layout(next.layout)
+                        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                        .image(resolved.image)
+                        .subresource_range(subresource_range(
+                            self.images[id.0].format(),
+                            resolved.layer,
+                        ))
+                        .build(),
+                    src_stages: previous.stages,
+                    dst_stages: next.stages,
+                });
+                states[id.0] = Some(next);
+            }
+
+            marker.begin_label(command_buffer, &pass.name, pass.label_color);
+            if let Some(profiler) = profiler.as_deref_mut() {
+                profiler.begin_pass(command_buffer, &pass.name);
+            }
+
+            record_barriers(&device, command_buffer, &barriers);
+
+            let extent = pass
+                .colors
+                .iter()
+                .chain(&pass.depth)
+                .map(|attachment| self.images[attachment.image.0].extent())
+                .next()
+                .unwrap_or_default();
+
+            let raster = pass.is_raster();
+            let render_pass = if raster {
+                let read_later = |id: ImageId| match &self.images[id.0] {
+                    GraphImage::Imported(_) => true,
+                    GraphImage::Transient { .. } => lifetimes[id.0]
+                        .map(|(_, last)| last > index)
+                        .unwrap_or(false),
+                };
+                let attachment_desc = |attachment: &Attachment| AttachmentDesc {
+                    format: self.images[attachment.image.0].format(),
+                    load_op: attachment.load_op.vk(),
+                    store_op: if read_later(attachment.image) {
+                        vk::AttachmentStoreOp::STORE
+                    } else {
+                        vk::AttachmentStoreOp::DONT_CARE
+                    },
+                };
+                let desc = RenderPassDesc {
+                    colors: pass.colors.iter().map(attachment_desc).collect(),
+                    depth: pass.depth.as_ref().map(attachment_desc),
+                };
+
+                let views = pass
+                    .colors
+                    .iter()
+                    .chain(&pass.depth)
+                    .map(|attachment| images[attachment.image.0].view)
+                    .collect::<Vec<_>>();
+                let clear_values = pass
+                    .colors
+                    .iter()
+                    .chain(&pass.depth)
+                    .map(|attachment| match attachment.load_op {
+                        LoadOp::Clear(value) => value,
+                        _ => vk::ClearValue::default(),
+                    })
+                    .collect::<Vec<_>>();
+
+                let render_area = vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                };
+
+                if let Some(dynamic_rendering) = device.dynamic_rendering() {
+                    let attachment_info =
+                        |attachment: &AttachmentDesc, view, clear_value, layout| {
+                            RenderingAttachmentInfoKHR::new(
+                                view,
+                                layout,
+                                attachment.load_op,
+                                attachment.store_op,
+                                clear_value,
+                            )
+                        };
+                    let colors = desc
+                        .colors
+                        .iter()
+                        .zip(&views)
+                        .zip(&clear_values)
+                        .map(|((color, &view), &clear_value)| {
+                            attachment_info(
+                                color,
+                                view,
+                                clear_value,
+                                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    let depth = desc.depth.as_ref().map(|depth| {
+                        attachment_info(
+                            depth,
+                            views[colors.len()],
+                            clear_values[colors.len()],
+                            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        )
+                    });
+
+                    let rendering_info =
+                        RenderingInfoKHR::new(render_area, &colors, depth.as_ref());
+                    unsafe {
+                        dynamic_rendering.cmd_begin_rendering(command_buffer, &rendering_info)
+                    };
+
+                    vk::RenderPass::null()
+                } else {
+                    let render_pass = cache.render_pass(&desc);
+                    let framebuffer = cache.framebuffer(render_pass, views, extent);
+
+                    let render_pass_info = vk::RenderPassBeginInfo::builder()
+                        .render_pass(render_pass)
+                        .framebuffer(framebuffer)
+                        .render_area(render_area)
+                        .clear_values(&clear_values);
+
+                    unsafe {
+                        device.cmd_begin_render_pass(
+                            command_buffer,
+                            &render_pass_info,
+                            vk::SubpassContents::INLINE,
+                        );
+                    }
+
+                    render_pass
+                }
+            } else {
+                vk::RenderPass::null()
+            };
+
+            (pass.record)(&PassContext {
+                device: &device,
+                command_buffer,
+                render_pass,
+                extent,
+                images: &images,
+            });
+
+            match device.dynamic_rendering() {
+                _ if !raster => {}
+                Some(dynamic_rendering) => unsafe {
+                    dynamic_rendering.cmd_end_rendering(command_buffer)
+                },
+                None => unsafe { device.cmd_end_render_pass(command_buffer) },
+            }
+
+            if let Some(profiler) = profiler.as_deref_mut() {
+                profiler.end_pass(command_buffer);
+            }
+            marker.end_label(command_buffer);
+
+            for (id, state) in states.iter().enumerate() {
+                if let (GraphImage::Transient { .. }, Some((_, last)), Some(state)) =
+                    (&self.images[id], lifetimes[id], state)
+                {
+                    if last == index {
+                        slot_states.insert(cache.slot(ImageId(id)), *state);
+                    }
+                }
+            }
+        }
+
+        // leave imported images as the code after the graph expects them
+        let mut barriers = Vec::new();
+        for (id, image) in self.images.iter().enumerate() {
+            if let (GraphImage::Imported(imported), Some(state)) = (image, states[id]) {
+                if state.layout != imported.final_layout {
+                    barriers.push(Barrier {
+                        barrier: vk::ImageMemoryBarrier::builder()
+                            .src_access_mask(state.access & WRITE_ACCESS)
+                            .dst_access_mask(vk::AccessFlags::empty())
+                            .old_layout(state.layout)
+                            .new_layout(imported.final_layout)
+                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .image(imported.image)
+                            .subresource_range(subresource_range(imported.format, imported.layer))
+                            .build(),
+                        src_stages: state.stages,
+                        dst_stages: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    });
+                }
+            }
+        }
+        record_barriers(&device, command_buffer, &barriers);
+    }
+
+    /// Which passes contribute to an imported image, directly or through the images they
+    /// write, or are kept.
+    fn live_passes(&self) -> Vec<bool> {
+        let mut needed = self
+            .images
+            .iter()
+            .map(|image| matches!(image, GraphImage::Imported(_)))
+            .collect::<Vec<_>>();
+        let mut live = vec![false; self.passes.len()];
+
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            live[index] = pass.keep
+                || pass
+                    .uses()
+                    .any(|(id, access, _)| access.is_write() && needed[id.0]);
+            if live[index] {
+                // what the pass discards is not needed from earlier passes, unless it reads it too
+                for (id, access, discards) in pass.uses() {
+                    if access.is_write() && discards {
+                        needed[id.0] = false;
+                    }
+                }
+                for (id, access, discards) in pass.uses() {
+                    if !access.is_write() || !discards {
+                        needed[id.0] = true;
+                    }
+                }
+            }
+        }
+
+        live
+    }
+
+    /// The first and last live pass using each transient image, or `None` for images no live
+    /// pass uses and imported images.
+    fn lifetimes(&self, live: &[bool]) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes = vec![None; self.images.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            if !live[index] {
+                continue;
+            }
+            for (id, _, _) in pass.uses() {
+                if let GraphImage::Transient { .. } = self.images[id.0] {
+                    let lifetime = lifetimes[id.0].get_or_insert((index, index));
+                    lifetime.1 = index;
+                }
+            }
+        }
+        lifetimes
+    }
+
+    /// How the live passes use an image.
+    fn usage(&self, id: ImageId, live: &[bool]) -> vk::ImageUsageFlags {
+        self.passes
+            .iter()
+            .zip(live)
+            .filter(|(_, &live)| live)
+            .flat_map(|(pass, _)| pass.uses())
+            .filter(|&(image, _, _)| image == id)
+            .fold(vk::ImageUsageFlags::empty(), |usage, (_, access, _)| {
+                usage | access.usage()
+            })
+    }
+}
+
+impl Default for RenderGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An image barrier and the stages it waits for and blocks.
+struct Barrier {
+    barrier: vk::ImageMemoryBarrier,
+    src_stages: vk::PipelineStageFlags,
+    dst_stages: vk::PipelineStageFlags,
+}
+
+/// Records `barriers` as one pipeline barrier. With synchronization2 each barrier keeps its own
+/// stages, otherwise the barrier waits for all of their source stages at once.
+fn record_barriers(device: &Device, command_buffer: vk::CommandBuffer, barriers: &[Barrier]) {
+    if barriers.is_empty() {
+        return;
+    }
+
+    if let Some(synchronization2) = device.synchronization2() {
+        let barriers = barriers
+            .iter()
+            .map(|b| ImageMemoryBarrier2KHR::new(&b.barrier, b.src_stages, b.dst_stages))
+            .collect::<Vec<_>>();
+        let dependency_info = DependencyInfoKHR::new(&barriers);
+        unsafe { synchronization2.cmd_pipeline_barrier2(command_buffer, &dependency_info) };
+        return;
+    }
+
+    let src_stages = barriers
+        .iter()
+        .fold(vk::PipelineStageFlags::empty(), |stages, b| {
+            stages | b.src_stages
+        });
+    let dst_stages = barriers
+        .iter()
+        .fold(vk::PipelineStageFlags::empty(), |stages, b| {
+            stages | b.dst_stages
+        });
+    let barriers = barriers.iter().map(|b| b.barrier).collect::<Vec<_>>();
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            if src_stages.is_empty() {
+                vk::PipelineStageFlags::TOP_OF_PIPE
+            } else {
+                src_stages
+            },
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barriers,
+        );
+    }
+}
+
+fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+fn subresource_range(format: vk::Format, layer: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: aspect_mask(format),
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: layer,
+        layer_count: 1,
+    }
+}
+
+/// A transient image's description, usage and the live passes it is used between, which decide
+/// what it can share memory with.
+type TransientKey = (ImageDesc, vk::ImageUsageFlags, (usize, usize));
+
+/// A render pass, the views of its attachments and their width and height.
+type FramebufferKey = (vk::RenderPass, Vec<vk::ImageView>, (u32, u32));
+
+/// Transient images and the memory they share, for one arrangement of the graph.
+struct TransientImages {
+    keys: Vec<TransientKey>,
+    views: Vec<ImageView>,
+    images: Vec<AliasedImage>,
+    /// Index in `memory` of each image's memory.
+    slots: Vec<usize>,
+    // dropped after the images bound to it
+    #[allow(dead_code)]
+    memory: Vec<DeviceMemory>,
+}
+
+impl TransientImages {
+    /// Creates the live transient images, those with a key in `keys`. Images share memory when
+    /// the passes they are used between do not overlap and their memory types allow it.
+    fn new(
+        device: &Arc<Device>,
+        images: &[GraphImage],
+        keys: &[Option<TransientKey>],
+        marker: &DebugMarker,
+    ) -> Self {
+        let mut live_keys = Vec::new();
+        let mut created = Vec::new();
+        for (image, key) in images.iter().zip(keys) {
+            let (name, key) = match (image, key) {
+                (GraphImage::Transient { name, .. }, Some(key)) => (name, *key),
+                _ => continue,
+            };
+            let (desc, usage, _) = key;
+
+            let image_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(desc.format)
+                .extent(vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED);
+
+            let image = unsafe {
+                device
+                    .create_image(&image_info, None)
+                    .expect("failed to create image!")
+            };
+            marker.set_object_name(image, name);
+
+            live_keys.push(key);
+            created.push(AliasedImage::from_raw(device, image));
+        }
+
+        let requirements = created
+            .iter()
+            .map(|image| unsafe { device.get_image_memory_requirements(image.handle()) })
+            .collect::<Vec<_>>();
+        let lifetimes = live_keys.iter().map(|key| key.2).collect::<Vec<_>>();
+        let (slots, slot_requirements) = assign_memory_slots(&lifetimes, &requirements);
+
+        let memory = slot_requirements
+            .iter()
+            .map(|requirements| {
+                let memory =
+                    device.allocate_memory(*requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+                DeviceMemory::from_raw(device, memory)
+            })
+            .collect::<Vec<_>>();
+
+        let views = created
+            .iter()
+            .zip(&live_keys)
+            .zip(&slots)
+            .map(|((image, (desc, _, _)), &slot)| {
+                unsafe {
+                    device
+                        .bind_image_memory(image.handle(), memory[slot].handle(), 0)
+                        .expect("failed to bind image memory!");
+                }
+
+                // depth stencil images are only ever viewed, and sampled, as depth
+                let mut range = subresource_range(desc.format, 0);
+                if range.aspect_mask.contains(vk::ImageAspectFlags::DEPTH) {
+                    range.aspect_mask = vk::ImageAspectFlags::DEPTH;
+                }
+                ImageView::new(
+                    device,
+                    image.handle(),
+                    vk::ImageViewType::TYPE_2D,
+                    desc.format,
+                    range,
+                )
+            })
+            .collect();
+
+        Self {
+            keys: live_keys,
+            views,
+            images: created,
+            slots,
+            memory,
+        }
+    }
+}
+
+/// Assigns images used between the passes of `lifetimes`, with `requirements`, to memory slots,
+/// first fit in the order they are first used. Images only share a slot if the passes they are
+/// used between do not overlap and their memory types allow it. Returns each image's slot and
+/// what each slot's memory has to satisfy.
+fn assign_memory_slots(
+    lifetimes: &[(usize, usize)],
+    requirements: &[vk::MemoryRequirements],
+) -> (Vec<usize>, Vec<vk::MemoryRequirements>) {
+    let mut order = (0..lifetimes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| lifetimes[index].0);
+
+    let mut slots = vec![0; lifetimes.len()];
+    let mut slot_memory: Vec<(vk::MemoryRequirements, usize)> = Vec::new();
+    for index in order {
+        let (first, last) = lifetimes[index];
+        let requirements = requirements[index];
+
+        let slot = slot_memory.iter().position(|(slot, slot_last)| {
+            *slot_last < first && slot.memory_type_bits & requirements.memory_type_bits != 0
+        });
+        slots[index] = match slot {
+            Some(slot) => {
+                let (slot_requirements, slot_last) = &mut slot_memory[slot];
+                slot_requirements.size = slot_requirements.size.max(requirements.size);
+                slot_requirements.alignment =
+                    slot_requirements.alignment.max(requirements.alignment);
+                slot_requirements.memory_type_bits &= requirements.memory_type_bits;
+                *slot_last = last;
+                slot
+            }
+            None => {
+                slot_memory.push((requirements, last));
+                slot_memory.len() - 1
+            }
+        };
+    }
+
+    let slot_requirements = slot_memory
+        .into_iter()
+        .map(|(requirements, _)| requirements)
+        .collect();
+    (slots, slot_requirements)
+}
+
+/// Objects a [`RenderGraph`] creates, kept from one frame to the next and recreated when the
+/// graph changes. Objects that are replaced are handed to the frame's [`DeletionQueue`], to be
+/// destroyed once the frames that may still use them have completed.
+pub struct RenderGraphCache {
+    transient: Option<TransientImages>,
+    // for each of the current graph's images, its index among the transient images, if it is a
+    // live transient one
+    indices: Vec<Option<usize>>,
+    framebuffers: HashMap<FramebufferKey, Framebuffer>,
+    render_passes: HashMap<RenderPassDesc, RenderPass>,
+    device: Arc<Device>,
+}
+
+impl RenderGraphCache {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            transient: None,
+            indices: Vec::new(),
+            framebuffers: HashMap::new(),
+            render_passes: HashMap::new(),
+            device: Arc::clone(device),
+        }
+    }
+
+    /// Resolves every image of the graph to a handle and view, creating the transient ones
+    /// unless the previous frame's can be reused, and retiring those into `deletions`
+    /// otherwise. `keys` has the key of each live transient image, in the graph's order.
+    fn resolve_images(
+        &mut self,
+        images: &[GraphImage],
+        keys: &[Option<TransientKey>],
+        deletions: &mut DeletionQueue,
+        marker: &DebugMarker,
+    ) -> Vec<ResolvedImage> {
+        let live_keys = keys.iter().flatten().copied().collect::<Vec<_>>();
+        if self.transient.as_ref().map(|transient| &transient.keys) != Some(&live_keys) {
+            // the framebuffers may refer to the old images' views
+            let framebuffers = self
+                .framebuffers
+                .drain()
+                .map(|(_, f)| f)
+                .collect::<Vec<_>>();
+            deletions.retire((self.transient.take(), framebuffers));
+            self.transient = Some(TransientImages::new(&self.device, images, keys, marker));
+        }
+        let transient = self.transient.as_ref().unwrap();
+
+        let mut next = 0;
+        self.indices = keys
+            .iter()
+            .map(|key| {
+                key.map(|_| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+
+        images
+            .iter()
+            .zip(&self.indices)
+            .map(|(image, index)| match (image, index) {
+                (GraphImage::Imported(imported), _) => ResolvedImage {
+                    image: imported.image,
+                    view: imported.view,
+                    layer: imported.layer,
+                },
+                (GraphImage::Transient { .. }, Some(index)) => ResolvedImage {
+                    image: transient.images[*index].handle(),
+                    view: transient.views[*index].handle(),
+                    layer: 0,
+                },
+                // not used by any live pass
+                (GraphImage::Transient { .. }, None) => ResolvedImage {
+                    image: vk::Image::null(),
+                    view: vk::ImageView::null(),
+                    layer: 0,
+                },
+            })
+            .collect()
+    }
+
+    /// The memory slot of a live transient image.
+    fn slot(&self, id: ImageId) -> usize {
+        let transient = self.transient.as_ref().expect("no transient images");
+        transient.slots[self.indices[id.0].expect("not a live transient image")]
+    }
+
+    fn render_pass(&mut self, desc: &RenderPassDesc) -> vk::RenderPass {
+        let device = &self.device;
+        self.render_passes
+            .entry(desc.clone())
+            .or_insert_with(|| desc.create(device))
+            .handle()
+    }
+
+    fn framebuffer(
+        &mut self,
+        render_pass: vk::RenderPass,
+        views: Vec<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> vk::Framebuffer {
+        let device = &self.device;
+        let key = (render_pass, views, (extent.width, extent.height));
+        self.framebuffers
+            .entry(key)
+            .or_insert_with_key(|(render_pass, views, _)| {
+                let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(*render_pass)
+                    .attachments(views)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+
+                let framebuffer = unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .expect("failed to create framebuffer!")
+                };
+
+                Framebuffer::from_raw(device, framebuffer)
+            })
+            .handle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D {
+        width: 64,
+        height: 64,
+    };
+
+    fn transient(graph: &mut RenderGraph, name: &str) -> ImageId {
+        graph.create_image(
+            name,
+            ImageDesc {
+                format: vk::Format::R8G8B8A8_UNORM,
+                extent: EXTENT,
+            },
+        )
+    }
+
+    fn imported(graph: &mut RenderGraph) -> ImageId {
+        let state = ImageState {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages: vk::PipelineStageFlags::TOP_OF_PIPE,
+            access: vk::AccessFlags::empty(),
+        };
+        graph.import_image(ImportedImage {
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            layer: 0,
+            format: vk::Format::B8G8R8A8_SRGB,
+            extent: EXTENT,
+            initial: state,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        })
+    }
+
+    #[test]
+    fn culls_passes_nothing_imported_depends_on() {
+        let mut graph = RenderGraph::new();
+        let scene = transient(&mut graph, "scene");
+        let unused = transient(&mut graph, "unused");
+        let swapchain = imported(&mut graph);
+
+        graph
+            .add_pass("scene")
+            .color_attachment(scene, LoadOp::DontCare)
+            .record(|_| {});
+        graph
+            .add_pass("unused")
+            .color_attachment(unused, LoadOp::DontCare)
+            .record(|_| {});
+        graph
+            .add_pass("compute")
+            .access(unused, Access::Sampled)
+            .keep()
+            .record(|_| {});
+        graph
+            .add_pass("present")
+            .color_attachment(swapchain, LoadOp::DontCare)
+            .access(scene, Access::Sampled)
+            .record(|_| {});
+
+        // kept passes keep what they read
+        assert_eq!(graph.live_passes(), [true, true, true, true]);
+    }
+
+    #[test]
+    fn culls_writes_that_are_overwritten() {
+        let mut graph = RenderGraph::new();
+        let scene = transient(&mut graph, "scene");
+        let unread = transient(&mut graph, "unread");
+        let swapchain = imported(&mut graph);
+
+        graph
+            .add_pass("cleared")
+            .color_attachment(scene, LoadOp::DontCare)
+            .record(|_| {});
+        // discards what the first pass wrote
+        graph
+            .add_pass("redrawn")
+            .color_attachment(scene, LoadOp::DontCare)
+            .record(|_| {});
+        graph
+            .add_pass("loaded")
+            .color_attachment(scene, LoadOp::Load)
+            .record(|_| {});
+        graph
+            .add_pass("unread")
+            .color_attachment(unread, LoadOp::DontCare)
+            .record(|_| {});
+        graph
+            .add_pass("present")
+            .color_attachment(swapchain, LoadOp::DontCare)
+            .access(scene, Access::Sampled)
+            .record(|_| {});
+
+        let live = graph.live_passes();
+        assert_eq!(live, [false, true, true, false, true]);
+        let lifetimes = graph.lifetimes(&live);
+        assert_eq!(lifetimes[scene.0], Some((1, 4)));
+        assert_eq!(lifetimes[unread.0], None);
+        assert_eq!(lifetimes[swapchain.0], None);
+    }
+
+    #[test]
+    fn collects_usage_of_live_passes() {
+        let mut graph = RenderGraph::new();
+        let scene = transient(&mut graph, "scene");
+        let swapchain = imported(&mut graph);
+
+        graph
+            .add_pass("scene")
+            .color_attachment(scene, LoadOp::DontCare)
+            .record(|_| {});
+        graph
+            .add_pass("present")
+            .access(scene, Access::TransferSrc)
+            .access(swapchain, Access::TransferDst)
+            .record(|_| {});
+
+        let live = graph.live_passes();
+        assert_eq!(
+            graph.usage(scene, &live),
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+        );
+    }
+
+    fn requirements(size: u64, memory_type_bits: u32) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits,
+        }
+    }
+
+    #[test]
+    fn aliases_images_whose_lifetimes_do_not_overlap() {
+        let lifetimes = [(0, 1), (1, 2), (2, 3), (3, 3)];
+        let requirements = [
+            requirements(100, 1),
+            requirements(200, 1),
+            requirements(300, 1),
+            requirements(50, 1),
+        ];
+        let (slots, memory) = assign_memory_slots(&lifetimes, &requirements);
+
+        // the third starts after the first ends, the fourth after the second
+        assert_eq!(slots, [0, 1, 0, 1]);
+        assert_eq!(memory.len(), 2);
+        assert_eq!(memory[0].size, 300);
+        assert_eq!(memory[1].size, 200);
+    }
+
+    #[test]
+    fn aliases_in_the_order_images_are_first_used() {
+        let lifetimes = [(2, 3), (0, 1)];
+        let requirements = [requirements(100, 1), requirements(100, 1)];
+        let (slots, memory) = assign_memory_slots(&lifetimes, &requirements);
+        assert_eq!(slots, [0, 0]);
+        assert_eq!(memory.len(), 1);
+    }
+
+    #[test]
+    fn only_aliases_compatible_memory_types() {
+        let lifetimes = [(0, 0), (1, 1), (2, 2)];
+        let requirements = [
+            requirements(100, 0b011),
+            requirements(100, 0b100),
+            requirements(100, 0b010),
+        ];
+        let (slots, memory) = assign_memory_slots(&lifetimes, &requirements);
+        assert_eq!(slots, [0, 1, 0]);
+        assert_eq!(memory[0].memory_type_bits, 0b010);
+        assert_eq!(memory[1].memory_type_bits, 0b100);
+    }
+}
@@ -0,0 +1,50 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+pub mod camera;
+pub mod culling;
+pub mod debug;
+pub mod draw;
+pub mod environment;
+pub mod ext;
+pub mod graph;
+pub mod light;
+pub mod loader;
+pub mod post;
+pub mod profiler;
+pub mod query;
+pub mod resources;
+pub mod scene;
+pub mod shadow;
+pub mod sprite;
+pub mod swapchain;
+pub mod sync;
+pub mod text;
+pub mod texture;
+pub mod ui;
+pub mod upload;
+
+pub fn clamp<T>(val: T, min: T, max: T) -> T
+where
+    T: PartialOrd<T>,
+{
+    assert!(min <= max, "min must not be greater than max");
+    if val < min {
+        min
+    } else if val > max {
+        max
+    } else {
+        val
+    }
+}
+
+pub fn vk_to_str(c: &[c_char]) -> &str {
+    unsafe { CStr::from_ptr(c.as_ptr()) }
+        .to_str()
+        .expect("failed to convert vulkan string")
+}
+
+/// Views `value` as raw bytes, e.g. to pass it as push constants.
+pub fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
+}
@@ -0,0 +1,145 @@
+//! Punctual lights, as in glTF's `KHR_lights_punctual`, and their layout in the light buffer
+//! read by the PBR shader.
+
+use glam::{Mat4, Vec3, Vec4};
+
+/// Lights beyond this many are not uploaded.
+pub const MAX_LIGHTS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Shines along the node's -Z axis from infinitely far away. Intensity is in lux.
+    Directional,
+    /// Shines in all directions from the node's origin. Intensity is in candela.
+    Point {
+        /// Distance at which the light has faded out completely, or `None` for the physical
+        /// inverse square falloff only.
+        range: Option<f32>,
+    },
+    /// Shines in a cone along the node's -Z axis from its origin. Intensity is in candela.
+    Spot {
+        range: Option<f32>,
+        /// Angle from the axis, in radians, at which the light starts to fade out.
+        inner_cone_angle: f32,
+        /// Angle from the axis, in radians, at which the light has faded out completely.
+        outer_cone_angle: f32,
+    },
+}
+
+/// A light attached to a scene node, which places and orients it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vec3,
+    pub intensity: f32,
+    /// Whether the light is blocked by the scene's meshes. Only directional and spot lights
+    /// cast shadows.
+    pub casts_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            color,
+            intensity,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn point(color: Vec3, intensity: f32, range: Option<f32>) -> Self {
+        Self {
+            kind: LightKind::Point { range },
+            color,
+            intensity,
+            casts_shadows: true,
+        }
+    }
+}
+
+/// A light as the shaders read it, placed by a node's world transform.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LightUniform {
+    /// World space position, and range in `w`, 0 if unlimited.
+    pub position: Vec4,
+    /// World space direction the light shines in, and kind in `w`: 0 for directional, 1 for
+    /// point and 2 for spot lights.
+    pub direction: Vec4,
+    /// Color, and intensity in `w`.
+    pub color: Vec4,
+    /// Cosines of a spot light's inner and outer cone angles in `x` and `y`, and the first layer
+    /// of its shadow map in `z`, or -1 if it casts no shadows. See
+    /// [`ShadowUniform::new`](crate::shadow::ShadowUniform::new).
+    pub cone: Vec4,
+}
+
+impl LightUniform {
+    pub fn new(light: &Light, transform: Mat4) -> Self {
+        let (kind, range, cone) = match light.kind {
+            LightKind::Directional => (0., None, Vec4::new(0., 0., -1., 0.)),
+            LightKind::Point { range } => (1., range, Vec4::new(0., 0., -1., 0.)),
+            LightKind::Spot {
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (
+                2.,
+                range,
+                Vec4::new(inner_cone_angle.cos(), outer_cone_angle.cos(), -1., 0.),
+            ),
+        };
+
+        Self {
+            position: transform.w_axis.truncate().extend(range.unwrap_or(0.)),
+            direction: transform
+                .transform_vector3(-Vec3::Z)
+                .normalize_or_zero()
+                .extend(kind),
+            color: light.color.extend(light.intensity),
+            cone,
+        }
+    }
+}
+
+/// Contents of the light storage buffer: a count followed by that many lights.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LightArray {
+    pub count: u32,
+    _padding: [u32; 3],
+    pub lights: [LightUniform; MAX_LIGHTS],
+}
+
+impl LightArray {
+    /// Collects up to [`MAX_LIGHTS`] lights, warning once about any that do not fit.
+    pub fn new(lights: impl IntoIterator<Item = LightUniform>) -> Self {
+        let mut array = Self {
+            count: 0,
+            _padding: [0; 3],
+            lights: [LightUniform::default(); MAX_LIGHTS],
+        };
+
+        for light in lights {
+            if array.count as usize == MAX_LIGHTS {
+                log_dropped_lights();
+                break;
+            }
+            array.lights[array.count as usize] = light;
+            array.count += 1;
+        }
+
+        array
+    }
+}
+
+fn log_dropped_lights() {
+    static LOGGED: std::sync::Once = std::sync::Once::new();
+    LOGGED.call_once(|| {
+        log::warn!(
+            "the scene has more than {} lights, the rest are ignored",
+            MAX_LIGHTS
+        )
+    });
+}
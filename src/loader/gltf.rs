@@ -0,0 +1,781 @@
+//! glTF 2.0 import, from `.gltf` files with external or embedded buffers and from `.glb` files,
+//! including lights from the `KHR_lights_punctual` extension.
+//!
+//! Files are read and checked completely by [`GltfAsset::read`] before anything is uploaded, so
+//! a malformed file is reported as a [`GltfError`] and leaves the scene untouched.
+
+use crate::light::{Light, LightKind};
+use crate::loader::{add_materials, MaterialData, TextureSource};
+use crate::scene::{Material, MeshData, NodeId, Scene, Transform, Vertex};
+use crate::texture::SamplerDesc;
+use crate::upload::UploadQueue;
+use ::gltf::image::Format;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::mesh::Mode;
+use ::gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use ash::vk;
+use glam::{Quat, Vec2, Vec3, Vec4};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum GltfError {
+    /// The file could not be read or is not valid glTF, or a buffer or image it refers to could
+    /// not be loaded.
+    Import(::gltf::Error),
+    /// A primitive has no readable `POSITION` attribute.
+    MissingPositions { mesh: String },
+    /// A vertex attribute has a different number of elements than `POSITION`.
+    AttributeCount {
+        mesh: String,
+        attribute: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// An index refers to a vertex past the end of the primitive's attributes.
+    IndexOutOfBounds {
+        mesh: String,
+        index: u32,
+        vertex_count: usize,
+    },
+    /// A decoded image is empty, has a side longer than the device supports, or holds fewer
+    /// pixels than its dimensions call for.
+    ImageSize { image: usize },
+    /// A node is its own ancestor, or has more than one parent.
+    NodeHierarchy { node: usize },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Import(err) => write!(f, "failed to import glTF: {}", err),
+            GltfError::MissingPositions { mesh } => {
+                write!(f, "a primitive of mesh {:?} has no positions", mesh)
+            }
+            GltfError::AttributeCount {
+                mesh,
+                attribute,
+                expected,
+                found,
+            } => write!(
+                f,
+                "mesh {:?} has {} {} values for {} positions",
+                mesh, found, attribute, expected
+            ),
+            GltfError::IndexOutOfBounds {
+                mesh,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh {:?} has index {} but only {} vertices",
+                mesh, index, vertex_count
+            ),
+            GltfError::ImageSize { image } => {
+                write!(
+                    f,
+                    "image {} is empty, too large or smaller than its dimensions",
+                    image
+                )
+            }
+            GltfError::NodeHierarchy { node } => {
+                write!(f, "node {} appears more than once in the hierarchy", node)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Import(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<::gltf::Error> for GltfError {
+    fn from(err: ::gltf::Error) -> Self {
+        GltfError::Import(err)
+    }
+}
+
+struct Primitive {
+    data: MeshData,
+    material: Option<usize>,
+}
+
+struct NodeData {
+    name: String,
+    transform: Transform,
+    mesh: Option<usize>,
+    light: Option<Light>,
+    children: Vec<usize>,
+}
+
+struct TextureRef {
+    image: usize,
+    sampler: SamplerDesc,
+}
+
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// The contents of a glTF file, read into memory and checked but not yet uploaded.
+pub struct GltfAsset {
+    meshes: Vec<(String, Vec<Primitive>)>,
+    materials: Vec<MaterialData>,
+    textures: Vec<TextureRef>,
+    images: Vec<Image>,
+    nodes: Vec<NodeData>,
+    roots: Vec<usize>,
+}
+
+impl GltfAsset {
+    /// Reads the `.gltf` or `.glb` file at `path` along with the buffers and images it refers to.
+    /// Images with a side longer than `max_image_dimension`, the device's `maxImageDimension2D`,
+    /// are rejected.
+    pub fn read(path: impl AsRef<Path>, max_image_dimension: u32) -> Result<Self, GltfError> {
+        let (document, buffers, images) = ::gltf::import(path)?;
+
+        let images = images
+            .iter()
+            .enumerate()
+            .map(|(i, image)| {
+                let sides = [image.width, image.height];
+                if sides.contains(&0) || sides.iter().any(|&side| side > max_image_dimension) {
+                    return Err(GltfError::ImageSize { image: i });
+                }
+                let pixels = to_rgba8(image).ok_or(GltfError::ImageSize { image: i })?;
+                Ok(Image {
+                    width: image.width,
+                    height: image.height,
+                    pixels,
+                })
+            })
+            .collect::<Result<Vec<_>, GltfError>>()?;
+
+        let textures = document
+            .textures()
+            .map(|texture| TextureRef {
+                image: texture.source().index(),
+                sampler: sampler_desc(&texture.sampler()),
+            })
+            .collect();
+
+        let materials = document.materials().map(read_material).collect();
+
+        let meshes = document
+            .meshes()
+            .map(|mesh| {
+                let name = mesh
+                    .name()
+                    .map_or_else(|| format!("mesh {}", mesh.index()), String::from);
+                let primitives = mesh
+                    .primitives()
+                    .filter_map(|primitive| read_primitive(&name, &primitive, &buffers).transpose())
+                    .collect::<Result<Vec<_>, GltfError>>()?;
+                Ok((name, primitives))
+            })
+            .collect::<Result<Vec<_>, GltfError>>()?;
+
+        let nodes = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                NodeData {
+                    name: node
+                        .name()
+                        .map_or_else(|| format!("node {}", node.index()), String::from),
+                    transform: Transform {
+                        translation: Vec3::from(translation),
+                        rotation: Quat::from_array(rotation),
+                        scale: Vec3::from(scale),
+                    },
+                    mesh: node.mesh().map(|mesh| mesh.index()),
+                    light: node.light().map(|light| read_light(&light)),
+                    children: node.children().map(|child| child.index()).collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let roots: Vec<usize> = match document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            // without scenes, show every node that is nobody's child
+            None => {
+                let mut is_child = vec![false; nodes.len()];
+                for child in nodes.iter().flat_map(|node| &node.children) {
+                    is_child[*child] = true;
+                }
+                (0..nodes.len()).filter(|&i| !is_child[i]).collect()
+            }
+        };
+
+        check_hierarchy(&nodes, &roots)?;
+
+        Ok(Self {
+            meshes,
+            materials,
+            textures,
+            images,
+            nodes,
+            roots,
+        })
+    }
+
+    /// Records uploading the asset's textures into `uploads` and adds its meshes and node
+    /// hierarchy to `scene`, under `parent` if given. Returns the added root nodes.
+    pub fn add_to_scene(
+        &self,
+        uploads: &mut UploadQueue,
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+    ) -> Vec<NodeId> {
+        let materials = add_materials(uploads, scene, &self.materials, |index| {
+            let texture = &self.textures[index];
+            let image = &self.images[texture.image];
+            TextureSource {
+                width: image.width,
+                height: image.height,
+                pixels: &image.pixels,
+                sampler: &texture.sampler,
+            }
+        });
+
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|(_, primitives)| {
+                primitives
+                    .iter()
+                    .map(|primitive| {
+                        let mesh = scene.add_mesh(primitive.data.clone());
+                        (mesh, primitive.material.map(|index| materials[index]))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut roots = Vec::new();
+        // depth first, so parents are always added before their children as the scene requires
+        let mut pending = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&index| (index, parent, true))
+            .collect::<Vec<_>>();
+
+        while let Some((index, parent, is_root)) = pending.pop() {
+            let node = &self.nodes[index];
+            let id = scene.add_node(parent, node.name.clone(), node.transform);
+            scene.node_mut(id).light = node.light;
+            if is_root {
+                roots.push(id);
+            }
+
+            if let Some(mesh) = node.mesh {
+                match meshes[mesh].as_slice() {
+                    [(mesh, material)] => {
+                        scene.node_mut(id).mesh = Some(*mesh);
+                        scene.node_mut(id).material = *material;
+                    }
+                    primitives => {
+                        for (i, (mesh, material)) in primitives.iter().enumerate() {
+                            let child = scene.add_node(
+                                Some(id),
+                                format!("{} primitive {}", node.name, i),
+                                Transform::IDENTITY,
+                            );
+                            scene.node_mut(child).mesh = Some(*mesh);
+                            scene.node_mut(child).material = *material;
+                        }
+                    }
+                }
+            }
+
+            pending.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, Some(id), false)),
+            );
+        }
+
+        scene.update_transforms();
+        roots
+    }
+}
+
+/// Reads the file at `path` and adds its contents to `scene`. Returns the added root nodes.
+pub fn load(
+    path: impl AsRef<Path>,
+    uploads: &mut UploadQueue,
+    scene: &mut Scene,
+) -> Result<Vec<NodeId>, GltfError> {
+    let asset = GltfAsset::read(path, uploads.device().limits().max_image_dimension2_d)?;
+    Ok(asset.add_to_scene(uploads, scene, None))
+}
+
+/// Reads a triangle list primitive. Returns `None` for other modes, which are skipped.
+fn read_primitive(
+    mesh: &str,
+    primitive: &::gltf::Primitive<'_>,
+    buffers: &[::gltf::buffer::Data],
+) -> Result<Option<Primitive>, GltfError> {
+    if primitive.mode() != Mode::Triangles {
+        log::warn!(
+            "skipping {:?} primitive of mesh {:?}, only triangles are supported",
+            primitive.mode(),
+            mesh
+        );
+        return Ok(None);
+    }
+
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+
+    let positions = reader
+        .read_positions()
+        .ok_or_else(|| GltfError::MissingPositions {
+            mesh: mesh.to_string(),
+        })?
+        .collect::<Vec<_>>();
+    let vertex_count = positions.len();
+
+    let check_count = |attribute, found| {
+        if found == vertex_count {
+            Ok(())
+        } else {
+            Err(GltfError::AttributeCount {
+                mesh: mesh.to_string(),
+                attribute,
+                expected: vertex_count,
+                found,
+            })
+        }
+    };
+
+    let normals = reader
+        .read_normals()
+        .map(|normals| normals.collect::<Vec<_>>());
+    if let Some(normals) = &normals {
+        check_count("NORMAL", normals.len())?;
+    }
+
+    let uvs = reader
+        .read_tex_coords(0)
+        .map(|uvs| uvs.into_f32().collect::<Vec<_>>());
+    if let Some(uvs) = &uvs {
+        check_count("TEXCOORD_0", uvs.len())?;
+    }
+
+    let mut data = MeshData {
+        vertices: positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| Vertex {
+                position: Vec3::from(position),
+                normal: normals.as_ref().map_or(Vec3::ZERO, |n| Vec3::from(n[i])),
+                uv: uvs.as_ref().map_or(Vec2::ZERO, |uv| Vec2::from(uv[i])),
+            })
+            .collect(),
+        indices: match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertex_count as u32).collect(),
+        },
+    };
+
+    // a trailing partial triangle is not drawn by the spec either
+    data.indices.truncate(data.indices.len() / 3 * 3);
+
+    if let Some(&index) = data
+        .indices
+        .iter()
+        .find(|&&index| index as usize >= vertex_count)
+    {
+        return Err(GltfError::IndexOutOfBounds {
+            mesh: mesh.to_string(),
+            index,
+            vertex_count,
+        });
+    }
+
+    if data.vertices.is_empty() || data.indices.is_empty() {
+        log::warn!("skipping empty primitive of mesh {:?}", mesh);
+        return Ok(None);
+    }
+
+    if normals.is_none() {
+        data.compute_normals();
+    }
+
+    Ok(Some(Primitive {
+        data,
+        material: primitive.material().index(),
+    }))
+}
+
+fn read_material(material: ::gltf::Material<'_>) -> MaterialData {
+    let pbr = material.pbr_metallic_roughness();
+
+    let texture_index = |info: Option<::gltf::texture::Info<'_>>| {
+        let info = info?;
+        if info.tex_coord() != 0 {
+            log::warn!("ignoring texture using TEXCOORD_{}", info.tex_coord());
+            return None;
+        }
+        Some(info.texture().index())
+    };
+
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+
+    MaterialData {
+        material: Material {
+            base_color: Vec4::from(pbr.base_color_factor()),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            normal_scale: normal.as_ref().map_or(1., |normal| normal.scale()),
+            occlusion_strength: occlusion
+                .as_ref()
+                .map_or(1., |occlusion| occlusion.strength()),
+            emissive: Vec3::from(material.emissive_factor()),
+            ..Material::default()
+        },
+        textures: [
+            texture_index(pbr.base_color_texture()),
+            texture_index(pbr.metallic_roughness_texture()),
+            normal.map(|normal| normal.texture().index()),
+            occlusion.map(|occlusion| occlusion.texture().index()),
+            texture_index(material.emissive_texture()),
+        ],
+    }
+}
+
+fn read_light(light: &::gltf::khr_lights_punctual::Light<'_>) -> Light {
+    let range = light.range();
+    Light {
+        kind: match light.kind() {
+            Kind::Directional => LightKind::Directional,
+            Kind::Point => LightKind::Point { range },
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        },
+        color: Vec3::from(light.color()),
+        intensity: light.intensity(),
+        // the extension has no say in this, so every light that can cast shadows does
+        casts_shadows: true,
+    }
+}
+
+fn sampler_desc(sampler: &::gltf::texture::Sampler<'_>) -> SamplerDesc {
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+    };
+
+    let default = SamplerDesc::default();
+
+    SamplerDesc {
+        mag_filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+            Some(MagFilter::Linear) => vk::Filter::LINEAR,
+            None => default.mag_filter,
+        },
+        min_filter: match sampler.min_filter() {
+            Some(MinFilter::Nearest)
+            | Some(MinFilter::NearestMipmapNearest)
+            | Some(MinFilter::NearestMipmapLinear) => vk::Filter::NEAREST,
+            Some(_) => vk::Filter::LINEAR,
+            None => default.min_filter,
+        },
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+    }
+}
+
+/// Converts a decoded image to 8 bit RGBA. Returns `None` if it has fewer pixels than its
+/// dimensions call for.
+fn to_rgba8(image: &::gltf::image::Data) -> Option<Vec<u8>> {
+    let (channels, bytes_per_channel) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+
+    let pixel_count = image.width as usize * image.height as usize;
+    let pixel_size = channels * bytes_per_channel;
+    let pixels = image.pixels.get(..pixel_count * pixel_size)?;
+
+    let channel = |bytes: &[u8]| -> u8 {
+        match *bytes {
+            [value] => value,
+            // 16 bit channels are in native byte order; keep the most significant byte
+            [a, b] => (u16::from_ne_bytes([a, b]) >> 8) as u8,
+            [a, b, c, d] => (f32::from_ne_bytes([a, b, c, d]).clamp(0., 1.) * 255.).round() as u8,
+            _ => unreachable!(),
+        }
+    };
+
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+    for pixel in pixels.chunks_exact(pixel_size) {
+        let mut values = pixel.chunks_exact(bytes_per_channel).map(channel);
+        let rgba_pixel = match channels {
+            // luminance, with or without alpha
+            1 | 2 => {
+                let luminance = values.next().unwrap();
+                [
+                    luminance,
+                    luminance,
+                    luminance,
+                    values.next().unwrap_or(255),
+                ]
+            }
+            _ => [
+                values.next().unwrap(),
+                values.next().unwrap(),
+                values.next().unwrap(),
+                values.next().unwrap_or(255),
+            ],
+        };
+        rgba.extend_from_slice(&rgba_pixel);
+    }
+
+    Some(rgba)
+}
+
+/// Checks that every node reachable from `roots` is reached exactly once, so the hierarchy is a
+/// forest and adding it to a scene terminates.
+fn check_hierarchy(nodes: &[NodeData], roots: &[usize]) -> Result<(), GltfError> {
+    let mut visited = vec![false; nodes.len()];
+    let mut pending = roots.to_vec();
+
+    while let Some(index) = pending.pop() {
+        if std::mem::replace(&mut visited[index], true) {
+            return Err(GltfError::NodeHierarchy { node: index });
+        }
+        pending.extend_from_slice(&nodes[index].children);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `maxImageDimension2D` no test image comes close to.
+    const MAX_IMAGE_DIMENSION: u32 = 4096;
+
+    /// Writes `files` into a new directory and reads the first as a glTF file.
+    fn read(name: &str, files: &[(&str, &[u8])]) -> Result<GltfAsset, GltfError> {
+        read_with_limit(name, files, MAX_IMAGE_DIMENSION)
+    }
+
+    fn read_with_limit(
+        name: &str,
+        files: &[(&str, &[u8])],
+        max_image_dimension: u32,
+    ) -> Result<GltfAsset, GltfError> {
+        let directory =
+            std::env::temp_dir().join(format!("vka-gltf-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            std::fs::write(directory.join(file), contents).unwrap();
+        }
+        let asset = GltfAsset::read(directory.join(files[0].0), max_image_dimension);
+        std::fs::remove_dir_all(&directory).unwrap();
+        asset
+    }
+
+    /// The positions and normals of a triangle, followed by `indices`.
+    fn triangle_buffer(indices: [u16; 3]) -> Vec<u8> {
+        let positions = [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]];
+        let normals = [[0., 0., 1.]; 3];
+        let mut buffer = Vec::new();
+        for value in positions.iter().chain(&normals).flatten() {
+            buffer.extend_from_slice(&f32::to_le_bytes(*value));
+        }
+        for index in indices {
+            buffer.extend_from_slice(&index.to_le_bytes());
+        }
+        buffer
+    }
+
+    /// A document drawing [`triangle_buffer`], from `buffer_uri` or the `.glb` chunk if `None`,
+    /// with `normal_count` normals and the nodes `nodes` of which the scene shows `roots`.
+    fn triangle_document(
+        buffer_uri: Option<&str>,
+        normal_count: usize,
+        nodes: &str,
+        roots: &str,
+    ) -> String {
+        let uri = buffer_uri.map_or(String::new(), |uri| format!("\"uri\":\"{}\",", uri));
+        format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{{}"byteLength": 78}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 72}},
+                    {{"buffer": 0, "byteOffset": 72, "byteLength": 6}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "byteOffset": 0, "componentType": 5126, "count": 3,
+                      "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": {},
+                      "type": "VEC3"}},
+                    {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+                ],
+                "meshes": [{{
+                    "name": "triangle",
+                    "primitives": [{{"attributes": {{"POSITION": 0, "NORMAL": 1}}, "indices": 2}}]
+                }}],
+                "nodes": {},
+                "scenes": [{{"nodes": {}}}],
+                "scene": 0
+            }}"#,
+            uri, normal_count, nodes, roots
+        )
+    }
+
+    const ONE_NODE: &str = r#"[{"name": "triangle", "mesh": 0}]"#;
+
+    fn read_triangle(
+        name: &str,
+        indices: [u16; 3],
+        normal_count: usize,
+        nodes: &str,
+    ) -> Result<GltfAsset, GltfError> {
+        let document = triangle_document(Some("triangle.bin"), normal_count, nodes, "[0]");
+        read(
+            name,
+            &[
+                ("triangle.gltf", document.as_bytes()),
+                ("triangle.bin", &triangle_buffer(indices)),
+            ],
+        )
+    }
+
+    /// Packs `document` and `buffer` into a `.glb` file.
+    fn glb(document: &str, buffer: &[u8]) -> Vec<u8> {
+        let chunk = |data: &[u8], kind: &[u8; 4], padding: u8| {
+            let mut chunk = data.to_vec();
+            chunk.resize(data.len().next_multiple_of(4), padding);
+            let mut header = (chunk.len() as u32).to_le_bytes().to_vec();
+            header.extend_from_slice(kind);
+            header.extend_from_slice(&chunk);
+            header
+        };
+        let json = chunk(document.as_bytes(), b"JSON", b' ');
+        let bin = chunk(buffer, b"BIN\0", 0);
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + json.len() + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    #[test]
+    fn reads_a_triangle() {
+        let asset = read_triangle("triangle", [0, 1, 2], 3, ONE_NODE).unwrap();
+        let (name, primitives) = &asset.meshes[0];
+        assert_eq!(name, "triangle");
+        assert_eq!(primitives[0].data.indices, [0, 1, 2]);
+        assert_eq!(primitives[0].data.vertices[1].position, Vec3::X);
+        assert_eq!(primitives[0].data.vertices[2].normal, Vec3::Z);
+        assert_eq!(asset.roots, [0]);
+    }
+
+    #[test]
+    fn rejects_indices_out_of_range() {
+        match read_triangle("indices", [0, 1, 3], 3, ONE_NODE) {
+            Err(GltfError::IndexOutOfBounds {
+                index: 3,
+                vertex_count: 3,
+                ..
+            }) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_attribute_count_mismatches() {
+        match read_triangle("attributes", [0, 1, 2], 2, ONE_NODE) {
+            Err(GltfError::AttributeCount {
+                attribute: "NORMAL",
+                expected: 3,
+                found: 2,
+                ..
+            }) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_cyclic_hierarchies() {
+        let nodes = r#"[{"mesh": 0, "children": [1]}, {"children": [0]}]"#;
+        match read_triangle("cycle", [0, 1, 2], 3, nodes) {
+            Err(GltfError::NodeHierarchy { .. }) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_glb_files() {
+        let document = triangle_document(None, 3, ONE_NODE, "[0]");
+        let glb = glb(&document, &triangle_buffer([0, 1, 2]));
+        assert!(read("glb", &[("triangle.glb", &glb)]).is_ok());
+
+        for length in [glb.len() - 8, glb.len() / 2, 20, 4] {
+            match read("truncated", &[("triangle.glb", &glb[..length])]) {
+                Err(GltfError::Import(_)) => {}
+                other => panic!("unexpected {:?} at {}", other.map(|_| ()), length),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_images_larger_than_the_device_supports() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbaImage::new(4, 2)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let document = r#"{
+            "asset": {"version": "2.0"},
+            "images": [{"uri": "image.png"}]
+        }"#;
+        let files: &[(&str, &[u8])] = &[
+            ("image.gltf", document.as_bytes()),
+            ("image.png", png.get_ref()),
+        ];
+
+        let asset = read_with_limit("image", files, 4).unwrap();
+        assert_eq!((asset.images[0].width, asset.images[0].height), (4, 2));
+        assert_eq!(asset.images[0].pixels.len(), 4 * 2 * 4);
+        match read_with_limit("large-image", files, 3) {
+            Err(GltfError::ImageSize { image: 0 }) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Importers adding the contents of model files to a [`Scene`](crate::scene::Scene).
+
+pub mod gltf;
+pub mod obj;
+
+use crate::scene::{Material, MaterialId, NodeId, Scene, TextureId};
+use crate::texture::{SamplerDesc, Texture, TextureData};
+use crate::upload::UploadQueue;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Gltf(gltf::GltfError),
+    Obj(obj::ObjError),
+    /// The file's extension is not one of `gltf`, `glb` or `obj`.
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Gltf(err) => err.fmt(f),
+            LoadError::Obj(err) => err.fmt(f),
+            LoadError::UnknownFormat(path) => {
+                write!(f, "unknown model format of {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Gltf(err) => Some(err),
+            LoadError::Obj(err) => Some(err),
+            LoadError::UnknownFormat(_) => None,
+        }
+    }
+}
+
+impl From<gltf::GltfError> for LoadError {
+    fn from(err: gltf::GltfError) -> Self {
+        LoadError::Gltf(err)
+    }
+}
+
+impl From<obj::ObjError> for LoadError {
+    fn from(err: obj::ObjError) -> Self {
+        LoadError::Obj(err)
+    }
+}
+
+/// Reads the model file at `path` with the importer for its extension and adds its contents to
+/// `scene`, recording the uploads of its textures into `uploads`. Returns the added root nodes.
+pub fn load(
+    path: impl AsRef<Path>,
+    uploads: &mut UploadQueue,
+    scene: &mut Scene,
+) -> Result<Vec<NodeId>, LoadError> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => Ok(gltf::load(path, uploads, scene)?),
+        Some("obj") => Ok(obj::load(path, uploads, scene)?),
+        _ => Err(LoadError::UnknownFormat(path.to_path_buf())),
+    }
+}
+
+/// A material read from a model file.
+struct MaterialData {
+    material: Material,
+    /// Indices of the file's textures, in the order of [`Material::textures`].
+    textures: [Option<usize>; 5],
+}
+
+/// Tightly packed RGBA8 pixels of a texture in a model file, and how it is sampled.
+struct TextureSource<'a> {
+    width: u32,
+    height: u32,
+    pixels: &'a [u8],
+    sampler: &'a SamplerDesc,
+}
+
+/// Records uploading the textures `materials` use, looked up by index with `texture`, and adds
+/// the materials to `scene`. Returns their ids, in the same order.
+fn add_materials<'a>(
+    uploads: &mut UploadQueue,
+    scene: &mut Scene,
+    materials: &[MaterialData],
+    texture: impl Fn(usize) -> TextureSource<'a>,
+) -> Vec<MaterialId> {
+    // the same image can be used both as color, which is sRGB, and as linear data
+    let mut textures = HashMap::<(usize, bool), TextureId>::new();
+    let mut texture = |scene: &mut Scene, index: usize, srgb: bool| {
+        *textures.entry((index, srgb)).or_insert_with(|| {
+            let source = texture(index);
+            let data = TextureData {
+                width: source.width,
+                height: source.height,
+                pixels: source.pixels.to_vec(),
+                srgb,
+            };
+            scene.add_texture(Texture::queue(uploads, &data, source.sampler))
+        })
+    };
+
+    materials
+        .iter()
+        .map(|data| {
+            let [base_color, metallic_roughness, normal, occlusion, emissive] = data.textures;
+            let mut material = data.material;
+            material.base_color_texture = base_color.map(|i| texture(scene, i, true));
+            material.metallic_roughness_texture =
+                metallic_roughness.map(|i| texture(scene, i, false));
+            material.normal_texture = normal.map(|i| texture(scene, i, false));
+            material.occlusion_texture = occlusion.map(|i| texture(scene, i, false));
+            material.emissive_texture = emissive.map(|i| texture(scene, i, true));
+            scene.add_material(material)
+        })
+        .collect()
+}
@@ -0,0 +1,698 @@
+//! Wavefront OBJ import, with materials from the MTL libraries the file refers to.
+//!
+//! Polygons are triangulated as fans and the `v/vt/vn` triples of their corners deduplicated
+//! into an index buffer. Each object or group becomes a node, split into one mesh per material
+//! it uses. Like glTF files, OBJ files are read and checked completely by [`ObjAsset::read`]
+//! before anything is uploaded.
+//!
+//! Material libraries and textures that cannot be found are skipped with a warning, as they
+//! are often missing from downloaded models.
+
+use crate::loader::{add_materials, MaterialData, TextureSource};
+use crate::scene::{Material, MeshData, NodeId, Scene, Transform, Vertex};
+use crate::texture::SamplerDesc;
+use crate::upload::UploadQueue;
+use ash::vk;
+use glam::{Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ObjError {
+    /// The OBJ file could not be read.
+    Io { path: PathBuf, err: io::Error },
+    /// A statement of the OBJ or an MTL file is malformed or refers to a missing element.
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, err } => write!(f, "failed to read {}: {}", path.display(), err),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+struct Primitive {
+    name: String,
+    data: MeshData,
+    material: Option<usize>,
+}
+
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    sampler: SamplerDesc,
+}
+
+/// The contents of an OBJ file and its materials, read into memory and checked but not yet
+/// uploaded.
+pub struct ObjAsset {
+    objects: Vec<(String, Vec<Primitive>)>,
+    materials: Vec<MaterialData>,
+    images: Vec<Image>,
+}
+
+impl ObjAsset {
+    /// Reads the `.obj` file at `path` along with the material libraries and textures it refers
+    /// to.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let source = read_to_string(path)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut libraries = MaterialLibraries::default();
+        let mut positions = Vec::<Vec3>::new();
+        let mut uvs = Vec::<Vec2>::new();
+        let mut normals = Vec::<Vec3>::new();
+
+        let default_name = path
+            .file_stem()
+            .map_or_else(|| "obj".to_string(), |stem| stem.to_string_lossy().into());
+        let mut objects = vec![ObjectBuilder::new(default_name)];
+        let mut material = None;
+
+        for (line, statement) in statements(&source) {
+            let error = |message: String| ObjError::Parse {
+                path: path.to_path_buf(),
+                line,
+                message,
+            };
+
+            let mut tokens = statement.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let rest = statement.trim_start()[keyword.len()..].trim();
+
+            match keyword {
+                "v" => positions.push(Vec3::from(parse_floats::<3>(tokens).map_err(error)?)),
+                "vt" => {
+                    let [u, v] =
+                        parse_floats::<2>(tokens.chain(std::iter::once("0"))).map_err(error)?;
+                    // OBJ puts the origin at the bottom left, Vulkan at the top left
+                    uvs.push(Vec2::new(u, 1. - v));
+                }
+                "vn" => normals.push(Vec3::from(parse_floats::<3>(tokens).map_err(error)?)),
+                "f" => {
+                    let corners = tokens
+                        .map(|corner| {
+                            parse_corner(corner, [positions.len(), uvs.len(), normals.len()])
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(error)?;
+                    if corners.len() < 3 {
+                        return Err(error(format!(
+                            "face has {} vertices, at least 3 are needed",
+                            corners.len()
+                        )));
+                    }
+                    let object = objects.last_mut().unwrap();
+                    object
+                        .primitive(material)
+                        .add_polygon(&corners, &positions, &uvs, &normals);
+                }
+                "o" | "g" => objects.push(ObjectBuilder::new(rest.to_string())),
+                "usemtl" => {
+                    material = libraries.find(rest);
+                    if material.is_none() {
+                        log::warn!("{}:{}: unknown material {:?}", path.display(), line, rest);
+                    }
+                }
+                "mtllib" => {
+                    // several libraries may be named, but their names cannot contain spaces
+                    for name in rest.split_whitespace() {
+                        libraries.read(&directory.join(name))?;
+                    }
+                }
+                "s" | "l" | "p" | "vp" => {}
+                _ => log::debug!("{}:{}: ignoring {:?}", path.display(), line, keyword),
+            }
+        }
+
+        let objects = objects
+            .into_iter()
+            .filter_map(|object| object.build(&libraries))
+            .collect();
+
+        Ok(Self {
+            objects,
+            materials: libraries.materials,
+            images: libraries.images,
+        })
+    }
+
+    /// Records uploading the asset's textures into `uploads` and adds its meshes and a node for
+    /// each of its objects to `scene`, under `parent` if given. Returns the added nodes.
+    pub fn add_to_scene(
+        &self,
+        uploads: &mut UploadQueue,
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+    ) -> Vec<NodeId> {
+        let materials = add_materials(uploads, scene, &self.materials, |index| {
+            let image = &self.images[index];
+            TextureSource {
+                width: image.width,
+                height: image.height,
+                pixels: &image.pixels,
+                sampler: &image.sampler,
+            }
+        });
+
+        let nodes = self
+            .objects
+            .iter()
+            .map(|(name, primitives)| {
+                let id = scene.add_node(parent, name.clone(), Transform::IDENTITY);
+                let add = |scene: &mut Scene, node, primitive: &Primitive| {
+                    let mesh = scene.add_mesh(primitive.data.clone());
+                    scene.node_mut(node).mesh = Some(mesh);
+                    scene.node_mut(node).material = primitive.material.map(|i| materials[i]);
+                };
+
+                match primitives.as_slice() {
+                    [primitive] => add(scene, id, primitive),
+                    primitives => {
+                        for primitive in primitives {
+                            let child = scene.add_node(
+                                Some(id),
+                                format!("{} {}", name, primitive.name),
+                                Transform::IDENTITY,
+                            );
+                            add(scene, child, primitive);
+                        }
+                    }
+                }
+                id
+            })
+            .collect();
+
+        scene.update_transforms();
+        nodes
+    }
+}
+
+/// Reads the file at `path` and adds its contents to `scene`. Returns the added nodes.
+pub fn load(
+    path: impl AsRef<Path>,
+    uploads: &mut UploadQueue,
+    scene: &mut Scene,
+) -> Result<Vec<NodeId>, ObjError> {
+    let asset = ObjAsset::read(path)?;
+    Ok(asset.add_to_scene(uploads, scene, None))
+}
+
+fn read_to_string(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|err| ObjError::Io {
+        path: path.to_path_buf(),
+        err,
+    })
+}
+
+/// Splits `source` into statements along with the line number each starts on, dropping
+/// comments and joining lines continued with a trailing backslash.
+fn statements(source: &str) -> Vec<(usize, String)> {
+    let mut statements = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let (line, continued) = match line.trim_end().strip_suffix('\\') {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+
+        let (_, statement) = current.get_or_insert_with(|| (i + 1, String::new()));
+        statement.push(' ');
+        statement.push_str(line.trim());
+
+        if !continued {
+            statements.extend(current.take());
+        }
+    }
+
+    statements.extend(current);
+    statements
+}
+
+/// Parses the first `N` of `tokens` as numbers, ignoring any after them.
+fn parse_floats<'a, const N: usize>(
+    mut tokens: impl Iterator<Item = &'a str>,
+) -> Result<[f32; N], String> {
+    let mut values = [0.; N];
+    for value in &mut values {
+        let token = tokens
+            .next()
+            .ok_or_else(|| format!("expected {} numbers", N))?;
+        *value = token
+            .parse()
+            .map_err(|_| format!("invalid number {:?}", token))?;
+    }
+    Ok(values)
+}
+
+/// Indices of a face corner's position, texture coordinates and normal.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner into zero based indices, given the
+/// number of positions, texture coordinates and normals read so far.
+fn parse_corner(corner: &str, counts: [usize; 3]) -> Result<Corner, String> {
+    let mut parts = corner.split('/');
+    let mut index = |count: usize, element: &str| -> Result<Option<usize>, String> {
+        let part = match parts.next() {
+            Some(part) if !part.is_empty() => part,
+            _ => return Ok(None),
+        };
+        let index = part
+            .parse::<i64>()
+            .map_err(|_| format!("invalid {} index {:?}", element, part))?;
+        // negative indices count back from the last element read
+        let resolved = match index {
+            1.. => index - 1,
+            0 => return Err(format!("{} indices start at 1", element)),
+            _ => count as i64 + index,
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(format!(
+                "{} index {} is out of bounds, only {} are defined",
+                element, index, count
+            ));
+        }
+        Ok(Some(resolved as usize))
+    };
+
+    let position = index(counts[0], "position")?
+        .ok_or_else(|| format!("face vertex {:?} has no position", corner))?;
+    let uv = index(counts[1], "texture coordinate")?;
+    let normal = index(counts[2], "normal")?;
+    Ok((position, uv, normal))
+}
+
+struct ObjectBuilder {
+    name: String,
+    /// Keyed by material, in the order they were first used.
+    primitives: Vec<(Option<usize>, PrimitiveBuilder)>,
+}
+
+impl ObjectBuilder {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            primitives: Vec::new(),
+        }
+    }
+
+    fn primitive(&mut self, material: Option<usize>) -> &mut PrimitiveBuilder {
+        let index = match self.primitives.iter().position(|(m, _)| *m == material) {
+            Some(index) => index,
+            None => {
+                self.primitives
+                    .push((material, PrimitiveBuilder::default()));
+                self.primitives.len() - 1
+            }
+        };
+        &mut self.primitives[index].1
+    }
+
+    /// Returns `None` if the object has no faces.
+    fn build(self, libraries: &MaterialLibraries) -> Option<(String, Vec<Primitive>)> {
+        let primitives = self
+            .primitives
+            .into_iter()
+            .map(|(material, builder)| {
+                let mut data = builder.data;
+                // a single corner without a normal leaves the whole mesh to be smoothed
+                if builder.missing_normals {
+                    data.compute_normals();
+                }
+                Primitive {
+                    name: material
+                        .map_or_else(|| "default".to_string(), |i| libraries.names[i].clone()),
+                    data,
+                    material,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if primitives.is_empty() {
+            None
+        } else {
+            Some((self.name, primitives))
+        }
+    }
+}
+
+#[derive(Default)]
+struct PrimitiveBuilder {
+    data: MeshData,
+    vertices: HashMap<Corner, u32>,
+    missing_normals: bool,
+}
+
+impl PrimitiveBuilder {
+    fn add_polygon(
+        &mut self,
+        corners: &[Corner],
+        positions: &[Vec3],
+        uvs: &[Vec2],
+        normals: &[Vec3],
+    ) {
+        let indices = corners
+            .iter()
+            .map(|&corner| {
+                let (position, uv, normal) = corner;
+                self.missing_normals |= normal.is_none();
+                let data = &mut self.data;
+                *self.vertices.entry(corner).or_insert_with(|| {
+                    data.vertices.push(Vertex {
+                        position: positions[position],
+                        normal: normal.map_or(Vec3::ZERO, |i| normals[i]),
+                        uv: uv.map_or(Vec2::ZERO, |i| uvs[i]),
+                    });
+                    data.vertices.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1..indices.len() - 1 {
+            self.data
+                .indices
+                .extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+        }
+    }
+}
+
+/// Materials of every MTL file read so far, and the images they use.
+#[derive(Default)]
+struct MaterialLibraries {
+    names: Vec<String>,
+    materials: Vec<MaterialData>,
+    images: Vec<Image>,
+    image_paths: HashMap<PathBuf, Option<usize>>,
+}
+
+impl MaterialLibraries {
+    /// Returns the most recently defined material called `name`.
+    fn find(&self, name: &str) -> Option<usize> {
+        self.names.iter().rposition(|n| n == name)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(), ObjError> {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                log::warn!("skipping material library {}: {}", path.display(), err);
+                return Ok(());
+            }
+        };
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        // whether a material has physically based parameters, so that Ns can be ignored
+        let mut has_roughness = false;
+
+        for (line, statement) in statements(&source) {
+            let error = |message: String| ObjError::Parse {
+                path: path.to_path_buf(),
+                line,
+                message,
+            };
+
+            let mut tokens = statement.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let rest = statement.trim_start()[keyword.len()..].trim();
+
+            if keyword == "newmtl" {
+                self.names.push(rest.to_string());
+                self.materials.push(MaterialData {
+                    material: Material::default(),
+                    textures: [None; 5],
+                });
+                has_roughness = false;
+                continue;
+            }
+
+            let data = match self.materials.last_mut() {
+                Some(data) => data,
+                None => return Err(error(format!("{:?} before any newmtl", keyword))),
+            };
+            let material = &mut data.material;
+
+            match keyword {
+                "Kd" => {
+                    let [r, g, b] = parse_floats::<3>(tokens).map_err(error)?;
+                    material.base_color = Vec4::new(r, g, b, material.base_color.w);
+                }
+                "d" => material.base_color.w = parse_floats::<1>(tokens).map_err(error)?[0],
+                "Tr" => material.base_color.w = 1. - parse_floats::<1>(tokens).map_err(error)?[0],
+                "Ke" => material.emissive = Vec3::from(parse_floats::<3>(tokens).map_err(error)?),
+                "Pm" => material.metallic = parse_floats::<1>(tokens).map_err(error)?[0],
+                "Pr" => {
+                    material.roughness = parse_floats::<1>(tokens).map_err(error)?[0];
+                    has_roughness = true;
+                }
+                "Ns" if !has_roughness => {
+                    // the usual mapping of a Blinn-Phong exponent to a roughness
+                    let exponent = parse_floats::<1>(tokens).map_err(error)?[0];
+                    material.roughness = (2. / (exponent.max(0.) + 2.)).sqrt();
+                }
+                "map_Kd" | "map_Ke" | "norm" | "map_Bump" | "bump" => {
+                    let map = parse_texture_map(rest).map_err(error)?;
+                    let slot = match keyword {
+                        "map_Kd" => 0,
+                        "map_Ke" => 4,
+                        // exporters write normal maps as bump maps, height maps are not supported
+                        _ => {
+                            material.normal_scale = map.bump_multiplier;
+                            2
+                        }
+                    };
+                    let image = self.image(&directory.join(&map.file), map.sampler);
+                    self.materials.last_mut().unwrap().textures[slot] = image;
+                }
+                _ => log::debug!("{}:{}: ignoring {:?}", path.display(), line, keyword),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the image at `path` unless it was loaded already. Returns `None` if it cannot be.
+    fn image(&mut self, path: &Path, sampler: SamplerDesc) -> Option<usize> {
+        let images = &mut self.images;
+        *self
+            .image_paths
+            .entry(path.to_path_buf())
+            .or_insert_with(|| match ::image::open(path) {
+                Ok(image) => {
+                    let image = image.into_rgba8();
+                    images.push(Image {
+                        width: image.width(),
+                        height: image.height(),
+                        pixels: image.into_raw(),
+                        sampler,
+                    });
+                    Some(images.len() - 1)
+                }
+                Err(err) => {
+                    log::warn!("skipping texture {}: {}", path.display(), err);
+                    None
+                }
+            })
+    }
+}
+
+struct TextureMap {
+    file: PathBuf,
+    sampler: SamplerDesc,
+    bump_multiplier: f32,
+}
+
+/// Parses the options and file name of a texture map statement, such as `-clamp on file.png`.
+fn parse_texture_map(statement: &str) -> Result<TextureMap, String> {
+    let mut map = TextureMap {
+        file: PathBuf::new(),
+        sampler: SamplerDesc::default(),
+        bump_multiplier: 1.,
+    };
+
+    let mut tokens = statement.split_whitespace().peekable();
+    while let Some(option) = tokens.next_if(|token| token.starts_with('-')) {
+        match option {
+            "-clamp" => {
+                if tokens.next() == Some("on") {
+                    map.sampler.address_mode_u = vk::SamplerAddressMode::CLAMP_TO_EDGE;
+                    map.sampler.address_mode_v = vk::SamplerAddressMode::CLAMP_TO_EDGE;
+                }
+            }
+            "-bm" => map.bump_multiplier = parse_floats::<1>(&mut tokens)?[0],
+            "-blendu" | "-blendv" | "-cc" | "-boost" | "-texres" | "-imfchan" => {
+                tokens.next();
+            }
+            "-mm" => {
+                tokens.nth(1);
+            }
+            // up to three numbers each
+            "-o" | "-s" | "-t" => {
+                for _ in 0..3 {
+                    tokens.next_if(|token| token.parse::<f32>().is_ok());
+                }
+            }
+            _ => return Err(format!("unknown texture option {:?}", option)),
+        }
+    }
+
+    // file names may contain spaces, and are often written with Windows separators
+    let file = tokens.collect::<Vec<_>>().join(" ");
+    if file.is_empty() {
+        return Err("texture map without a file name".to_string());
+    }
+    map.file = PathBuf::from(file.replace('\\', "/"));
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` into a new directory and reads the first as an OBJ file.
+    fn read(name: &str, files: &[(&str, &str)]) -> Result<ObjAsset, ObjError> {
+        let directory =
+            std::env::temp_dir().join(format!("vka-obj-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            std::fs::write(directory.join(file), contents).unwrap();
+        }
+        let asset = ObjAsset::read(directory.join(files[0].0));
+        std::fs::remove_dir_all(&directory).unwrap();
+        asset
+    }
+
+    #[test]
+    fn splits_statements() {
+        let source = "# comment\nv 1 2 3 # trailing\nf 1 \\\n  2 3\n\ng name";
+        assert_eq!(
+            statements(source),
+            [
+                (1, " ".to_string()),
+                (2, " v 1 2 3".to_string()),
+                (3, " f 1 2 3".to_string()),
+                (5, " ".to_string()),
+                (6, " g name".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_corners() {
+        let counts = [4, 2, 3];
+        assert_eq!(parse_corner("1", counts), Ok((0, None, None)));
+        assert_eq!(parse_corner("2/1", counts), Ok((1, Some(0), None)));
+        assert_eq!(parse_corner("3//3", counts), Ok((2, None, Some(2))));
+        assert_eq!(parse_corner("4/2/1", counts), Ok((3, Some(1), Some(0))));
+        // counting back from the last element
+        assert_eq!(parse_corner("-1/-2/-3", counts), Ok((3, Some(0), Some(0))));
+
+        assert!(parse_corner("0", counts).is_err());
+        assert!(parse_corner("5", counts).is_err());
+        assert!(parse_corner("-5", counts).is_err());
+        assert!(parse_corner("1/3", counts).is_err());
+        assert!(parse_corner("x", counts).is_err());
+        assert!(parse_corner("/1", counts).is_err());
+    }
+
+    #[test]
+    fn parses_texture_maps() {
+        let map = parse_texture_map("-clamp on -o 0.5 0.5 -bm 2 my texture.png").unwrap();
+        assert_eq!(map.file, PathBuf::from("my texture.png"));
+        assert_eq!(
+            map.sampler.address_mode_u,
+            vk::SamplerAddressMode::CLAMP_TO_EDGE
+        );
+        assert_eq!(map.bump_multiplier, 2.);
+
+        assert_eq!(
+            parse_texture_map("textures\\wood.png").unwrap().file,
+            PathBuf::from("textures/wood.png")
+        );
+        assert!(parse_texture_map("-clamp on").is_err());
+        assert!(parse_texture_map("-unknown file.png").is_err());
+    }
+
+    #[test]
+    fn triangulates_and_deduplicates_corners() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\n";
+        let asset = read("quad", &[("quad.obj", obj)]).unwrap();
+
+        assert_eq!(asset.objects.len(), 1);
+        let (name, primitives) = &asset.objects[0];
+        assert_eq!(name, "quad");
+        let data = &primitives[0].data;
+        assert_eq!(data.vertices.len(), 4);
+        assert_eq!(data.indices, [0, 1, 2, 0, 2, 3]);
+        assert!(data.vertices.iter().all(|vertex| vertex.normal == Vec3::Z));
+    }
+
+    #[test]
+    fn splits_objects_by_material() {
+        let obj = "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                   o first\nusemtl red\nf 1 2 3\nusemtl blue\nf 3 2 1\nusemtl red\nf 1 3 2\n\
+                   o empty\n";
+        let mtl = "newmtl red\nKd 1 0 0\nd 0.5\nnewmtl blue\nKd 0 0 1\nNs 0\n";
+        let asset = read("materials", &[("scene.obj", obj), ("scene.mtl", mtl)]).unwrap();
+
+        // the default object and the empty one have no faces
+        assert_eq!(asset.objects.len(), 1);
+        let (name, primitives) = &asset.objects[0];
+        assert_eq!(name, "first");
+        let materials = primitives
+            .iter()
+            .map(|primitive| (primitive.name.as_str(), primitive.data.indices.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(materials, [("red", 6), ("blue", 3)]);
+
+        let red = &asset.materials[0].material;
+        assert_eq!(red.base_color, Vec4::new(1., 0., 0., 0.5));
+        let blue = &asset.materials[1].material;
+        assert_eq!(blue.roughness, 1.);
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let err = read("error", &[("bad.obj", "v 0 0 0\n\nf 1 2 3\n")])
+            .err()
+            .unwrap();
+        match err {
+            ObjError::Parse { line, message, .. } => {
+                assert_eq!(line, 3);
+                assert!(message.contains("out of bounds"), "{}", message);
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
@@ -1,602 +1,2233 @@
+use ab_glyph::FontArc;
 use ash::extensions::{ext::DebugUtils, khr};
 use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0};
 use ash::vk;
-use std::ffi::{c_void, CStr, CString};
-use std::fs::File;
-use std::io::Read;
-use std::os::raw::c_char;
+use glam::{Mat4, Vec4};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+use vka::as_bytes;
+use vka::camera::{Camera, CameraController, CameraUniform};
+use vka::culling::{CullPipelines, CullUniform, DepthPyramid, GpuCulling, PYRAMID_FORMAT};
+use vka::debug::{
+    instance_extension_supported, DebugCallback, DebugMarker, DebugMessenger, ValidationConfig,
+    VALIDATION_LAYERS,
+};
+use vka::draw::{IndirectDraws, InstanceData};
+use vka::environment::{Environment, EnvironmentSource};
+use vka::ext::{ExtensionSupport, PipelineRenderingCreateInfoKHR};
+use vka::graph::{
+    Access, ImageDesc, ImageId, ImageState, ImportedImage, LoadOp, RenderGraph, RenderGraphCache,
+    RenderPassDesc,
+};
+use vka::light::{Light, LightArray, LightKind};
+use vka::loader::LoadError;
+use vka::post::{
+    is_srgb, ColorGrading, PostEffects, PostPushConstants, Tonemapper, BLOOM_KNEE, BLOOM_STRENGTH,
+    BLOOM_THRESHOLD, HDR_FORMAT,
+};
+use vka::profiler::{FrameTiming, GpuProfiler};
+use vka::query::OcclusionQueries;
+use vka::resources::{
+    Buffer, CommandPool, DescriptorPool, DescriptorSetLayout, Device, Fence, ImageView, Instance,
+    Pipeline, PipelineLayout, RenderPass, Sampler, Semaphore, ShaderModule, Surface, Swapchain,
+};
+use vka::scene::{Material, MaterialPushConstants, MeshData, Scene, Shading, Transform, Vertex};
+use vka::shadow::{ShadowMap, ShadowPushConstants, ShadowUniform, SHADOW_FORMAT, SHADOW_MAP_SIZE};
+use vka::sprite::{SpriteBatch, SpriteInstance, SpritePushConstants};
+use vka::swapchain::{find_depth_format, RenderScale, SwapchainSupportDetails};
+use vka::sync::{DeletionQueue, FrameSync, Submission, Timeline};
+use vka::text::{self, TextRenderer, TextVertex};
+use vka::texture::{SamplerDesc, Texture, TextureData};
+use vka::ui::{self, FrameTimes, UiLayer, UiPushConstants};
+use vka::upload::{UploadQueue, Uploader, UPLOAD_READ_STAGES};
+use vka::vk_to_str;
 use winit::{
     event::{ElementState, KeyboardInput, VirtualKeyCode},
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::Window,
     window::WindowBuilder,
+    window::{Window, WindowId},
 };
 
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 600;
 
 const DEVICE_EXTENSIONS: [&str; 1] = ["VK_KHR_swapchain"];
-const VALIDATION_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
 
 const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
-#[cfg(debug_assertions)]
-const ENABLE_VALIDATION_LAYERS: bool = true;
-#[cfg(not(debug_assertions))]
-const ENABLE_VALIDATION_LAYERS: bool = false;
+/// Size of the frame stats text in points, pixels per em at a scale factor of 1.
+const STATS_TEXT_SIZE: f32 = 15.;
 
-struct VkApp {
-    _entry: ash::Entry,
-    instance: ash::Instance,
-    debug_utils: DebugUtils,
-    debug_messenger: vk::DebugUtilsMessengerEXT,
-    device: ash::Device,
-    surface: vk::SurfaceKHR,
-    surface_loader: khr::Surface,
-    graphics_queue: vk::Queue,
-    present_queue: vk::Queue,
-    swapchain_loader: khr::Swapchain,
-    swapchain: vk::SwapchainKHR,
+/// Textures read by each material, see [`Material::textures`].
+const MATERIAL_TEXTURES: u32 = 5;
+
+/// Textures of the environment set, see [`Environment::textures`].
+const ENVIRONMENT_TEXTURES: u32 = 4;
+
+/// Direction towards the sun of the default scene, which lights it and is drawn in its sky.
+const SUN_DIRECTION: glam::Vec3 = glam::Vec3::new(0.4, 1., 0.3);
+
+/// Pipelines drawing the scene's meshes and the environment behind them into one kind of render
+/// target. Shared by every window whose targets have the same format.
+struct MeshPipeline {
+    simple_pipeline: Pipeline,
+    pbr_pipeline: Pipeline,
+    skybox_pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+}
+
+impl MeshPipeline {
+    fn for_shading(&self, shading: Shading) -> &Pipeline {
+        match shading {
+            Shading::Simple => &self.simple_pipeline,
+            Shading::Pbr => &self.pbr_pipeline,
+        }
+    }
+}
+
+/// Pipeline drawing the scene's depth into one layer of a [`ShadowMap`].
+struct ShadowPipeline {
+    pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+}
+
+/// Pipelines of the post-processing passes, which share a layout whose set has two input images
+/// and their samplers.
+struct PostPipelines {
+    bloom_threshold: Pipeline,
+    blur: Pipeline,
+    tonemap: Pipeline,
+    color_grading: Pipeline,
+    fxaa: Pipeline,
+    gamma: Pipeline,
+    pipeline_layout: PipelineLayout,
+    set_layout: DescriptorSetLayout,
+    sampler: Sampler,
+}
+
+/// Pipelines drawing a window's [`UiLayer`], [`TextRenderer`] and [`SpriteBatch`] over the
+/// finished frame, onto targets of one format. The UI and text share a layout: a set with one
+/// texture, and push constants of the same size. Sprites have the same set, but also push a
+/// projection.
+struct OverlayPipelines {
+    ui_pipeline: Pipeline,
+    text_pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+    sprite_pipeline: Pipeline,
+    sprite_layout: PipelineLayout,
+}
+
+/// Post-processing passes a frame can run at most, and so descriptor sets it needs.
+const POST_PASSES: usize = 7;
+
+/// Shaders and fixed function state that differ between the graphics pipelines.
+struct PipelineDesc<'a> {
+    vertex_shader: &'a str,
+    /// `None` for depth only pipelines, whose render pass has no color attachment.
+    fragment_shader: Option<&'a str>,
+    vertex_input: VertexInput,
+    cull_mode: vk::CullModeFlags,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    /// Whether depth is pushed back by a constant and slope scaled bias.
+    depth_bias: bool,
+    /// Whether colors are blended over the target with premultiplied alpha, rather than
+    /// replacing it.
+    blend: bool,
+}
+
+/// Where a pipeline's vertices come from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VertexInput {
+    /// Made up by the vertex shader from the vertex index.
+    None,
+    /// Read from a [`Vertex`] buffer, and an [`InstanceData`] buffer per instance.
+    Mesh,
+    /// Read from a buffer of egui's vertices.
+    Ui,
+    /// Read from a [`TextVertex`] buffer.
+    Text,
+    /// A quad made up from the vertex index, for each [`SpriteInstance`] of a buffer.
+    Sprite,
+}
+
+impl PipelineDesc<'_> {
+    const SIMPLE: Self = Self {
+        vertex_shader: "shaders/vert.spv",
+        fragment_shader: Some("shaders/frag.spv"),
+        vertex_input: VertexInput::Mesh,
+        cull_mode: vk::CullModeFlags::BACK,
+        depth_write: true,
+        depth_compare_op: vk::CompareOp::LESS,
+        depth_bias: false,
+        blend: false,
+    };
+
+    const PBR: Self = Self {
+        fragment_shader: Some("shaders/pbr_frag.spv"),
+        ..Self::SIMPLE
+    };
+
+    /// Drawn after the meshes at the far plane, so it only covers what they leave uncovered.
+    const SKYBOX: Self = Self {
+        vertex_shader: "shaders/skybox_vert.spv",
+        fragment_shader: Some("shaders/skybox_frag.spv"),
+        vertex_input: VertexInput::None,
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write: false,
+        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        depth_bias: false,
+        blend: false,
+    };
+
+    /// Covers the target with one triangle, see `fullscreen.vert`. Post-processing targets have
+    /// no depth.
+    const FULL_SCREEN: Self = Self {
+        vertex_shader: "shaders/fullscreen_vert.spv",
+        fragment_shader: None,
+        vertex_input: VertexInput::None,
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write: false,
+        depth_compare_op: vk::CompareOp::ALWAYS,
+        depth_bias: false,
+        blend: false,
+    };
+
+    /// egui's meshes, blended over the finished frame in the order they come.
+    const UI: Self = Self {
+        vertex_shader: "shaders/ui_vert.spv",
+        fragment_shader: Some("shaders/ui_frag.spv"),
+        vertex_input: VertexInput::Ui,
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write: false,
+        depth_compare_op: vk::CompareOp::ALWAYS,
+        depth_bias: false,
+        blend: true,
+    };
+
+    /// Glyph quads, blended over the finished frame.
+    const TEXT: Self = Self {
+        vertex_shader: "shaders/text_vert.spv",
+        fragment_shader: Some("shaders/text_frag.spv"),
+        vertex_input: VertexInput::Text,
+        ..Self::UI
+    };
+
+    /// 2D shapes, blended over the finished frame.
+    const SPRITE: Self = Self {
+        vertex_shader: "shaders/sprite_vert.spv",
+        fragment_shader: Some("shaders/sprite_frag.spv"),
+        vertex_input: VertexInput::Sprite,
+        ..Self::UI
+    };
+
+    /// Both sides of a mesh cast shadows, and the bias keeps its own surface out of them.
+    const SHADOW: Self = Self {
+        vertex_shader: "shaders/shadow_vert.spv",
+        fragment_shader: None,
+        vertex_input: VertexInput::Mesh,
+        cull_mode: vk::CullModeFlags::NONE,
+        depth_write: true,
+        depth_compare_op: vk::CompareOp::LESS,
+        depth_bias: true,
+        blend: false,
+    };
+}
+
+/// Everything that belongs to a single window: its surface, swapchain, framebuffers, command
+/// buffers and frame synchronization.
+struct WindowState {
+    name: String,
     swapchain_images: Vec<vk::Image>,
     swapchain_format: vk::Format,
     swapchain_extent: vk::Extent2D,
-    swapchain_image_views: Vec<vk::ImageView>,
-    // vert_shader_module: vk::ShaderModule,
-    // frag_shader_module: vk::ShaderModule,
-    render_pass: vk::RenderPass,
-    pipeline_layout: vk::PipelineLayout,
-    graphics_pipeline: vk::Pipeline,
-    swapchain_framebuffers: Vec<vk::Framebuffer>,
-    command_pool: vk::CommandPool,
+    depth_format: vk::Format,
+    render_extent: vk::Extent2D,
+    camera: Camera,
+    controller: CameraController,
+    last_frame: Instant,
+    // one of each per frame in flight
     command_buffers: Vec<vk::CommandBuffer>,
-    image_available_semaphores: Vec<vk::Semaphore>,
-    render_finished_semaphores: Vec<vk::Semaphore>,
-    in_flight_fences: Vec<vk::Fence>,
-    in_flight_images: Vec<vk::Fence>,
+    camera_buffers: Vec<Buffer>,
+    light_buffers: Vec<Buffer>,
+    shadow_buffers: Vec<Buffer>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    /// The scene's draw batches, with instance and indirect buffers per frame in flight.
+    draws: IndirectDraws,
     current_frame: usize,
+    image_available_semaphores: Vec<Semaphore>,
+    render_finished_semaphores: Vec<Semaphore>,
+    /// When each frame in flight, and the frame rendering to each swapchain image, completes.
+    frame_sync: FrameSync,
+    /// Resources the frames in flight may still use. What is retired keeps what it needs to be
+    /// destroyed alive, such as the UI's descriptor pool, so the fields can drop in any order
+    /// once the frames have completed.
+    deletions: DeletionQueue,
+    /// Shadow map layers rendered by the frame being recorded.
+    shadow_layers: u32,
+    post_effects: PostEffects,
+    // POST_PASSES per frame in flight
+    post_sets: Vec<vk::DescriptorSet>,
+    post_pool: DescriptorPool,
+    ui: UiLayer,
+    text: TextRenderer,
+    /// Whether the frame stats are drawn with `text`.
+    stats_visible: bool,
+    sprites: SpriteBatch,
+    /// Whether the lights are marked with `sprites`.
+    gizmos_visible: bool,
+    /// Whether the main pass only draws the instances `culling` finds visible.
+    culling_enabled: bool,
+    culling: GpuCulling,
+    /// What the depth in `depth_pyramid` was rendered with, or `None` if it was not built by the
+    /// previous frame.
+    previous_view_projection: Option<Mat4>,
+    // shared by the frames in flight, the pyramid pass waits for the previous frame's culling
+    depth_pyramid: DepthPyramid,
+    cull_pipelines: Rc<CullPipelines>,
+    frame_times: FrameTimes,
+    /// Times the passes of each frame, if the graphics queue supports timestamps.
+    profiler: Option<GpuProfiler>,
+    occlusion_queries: OcclusionQueries,
+    /// Samples of the scene's meshes that passed the depth test in the last frame whose results
+    /// came back.
+    scene_samples: Rc<Cell<Option<u64>>>,
+    descriptor_pool: DescriptorPool,
+    // the depth and scaled render targets, shared by the frames in flight like the shadow map
+    graph_cache: RenderGraphCache,
+    pipeline: Rc<MeshPipeline>,
+    post_pipelines: Rc<PostPipelines>,
+    overlay_pipelines: Rc<OverlayPipelines>,
+    // shared by the frames in flight, the shadow pass waits for the previous frame to read it
+    shadow_map: ShadowMap,
+    shadow_pipeline: Rc<ShadowPipeline>,
+    swapchain_image_views: Vec<ImageView>,
+    swapchain: Swapchain,
+    surface: Arc<Surface>,
+    // declared last so the surface is destroyed before the window it was created for
+    window: Window,
 }
 
-fn clamp<T>(val: T, min: T, max: T) -> T
-where
-    T: PartialOrd<T>,
-{
-    assert!(min < max, "min must be less than max");
-    if val < min {
-        min
-    } else if val > max {
-        max
-    } else {
-        val
+impl WindowState {
+    /// Gives the window's objects debug names. Does nothing unless debug utils is enabled.
+    fn name_objects(&self, marker: &DebugMarker) {
+        if !marker.is_enabled() {
+            return;
+        }
+
+        let name = |object: &str| format!("{} {}", self.name, object);
+
+        marker.set_object_name(self.surface.handle(), &name("surface"));
+        marker.set_object_name(self.swapchain.handle(), &name("swapchain"));
+        marker.set_object_names(
+            self.swapchain_images.iter().copied(),
+            &name("swapchain image"),
+        );
+        marker.set_object_names(
+            self.swapchain_image_views.iter().map(ImageView::handle),
+            &name("swapchain image view"),
+        );
+        marker.set_object_names(
+            self.camera_buffers.iter().map(Buffer::handle),
+            &name("camera buffer"),
+        );
+        marker.set_object_names(
+            self.light_buffers.iter().map(Buffer::handle),
+            &name("light buffer"),
+        );
+        marker.set_object_names(
+            self.shadow_buffers.iter().map(Buffer::handle),
+            &name("shadow buffer"),
+        );
+        marker.set_object_name(self.shadow_map.image.handle(), &name("shadow map"));
+        marker.set_object_name(self.shadow_map.view.handle(), &name("shadow map view"));
+        marker.set_object_names(
+            self.shadow_map.layer_views.iter().map(ImageView::handle),
+            &name("shadow map layer view"),
+        );
+        marker.set_object_name(self.descriptor_pool.handle(), &name("descriptor pool"));
+        marker.set_object_name(
+            self.post_pool.handle(),
+            &name("post-processing descriptor pool"),
+        );
+        marker.set_object_names(
+            self.post_sets.iter().copied(),
+            &name("post-processing descriptor set"),
+        );
+        marker.set_object_name(self.ui.pool().handle(), &name("UI descriptor pool"));
+        marker.set_object_name(self.text.pool().handle(), &name("text descriptor pool"));
+        marker.set_object_name(
+            self.sprites.pool().handle(),
+            &name("sprite descriptor pool"),
+        );
+        marker.set_object_names(
+            self.descriptor_sets.iter().copied(),
+            &name("frame descriptor set"),
+        );
+        marker.set_object_names(
+            self.command_buffers.iter().copied(),
+            &name("command buffer"),
+        );
+        marker.set_object_names(
+            self.image_available_semaphores
+                .iter()
+                .map(Semaphore::handle),
+            &name("image available semaphore"),
+        );
+        marker.set_object_names(
+            self.render_finished_semaphores
+                .iter()
+                .map(Semaphore::handle),
+            &name("render finished semaphore"),
+        );
+        marker.set_object_names(
+            self.frame_sync.fences().iter().map(Fence::handle),
+            &name("in flight fence"),
+        );
     }
-}
 
-pub fn vk_to_str(c: &[c_char]) -> &str {
-    unsafe { CStr::from_ptr(c.as_ptr()) }
-        .to_str()
-        .expect("failed to convert vulkan string")
-}
+    /// Adds the passes of the enabled post-processing effects, which start from `scene_color`.
+    /// Returns the image the last one leaves its result in.
+    fn add_post_passes<'a>(
+        &self,
+        graph: &mut RenderGraph<'a>,
+        post_pipelines: &'a PostPipelines,
+        scene_color: ImageId,
+    ) -> ImageId {
+        let effects = &self.post_effects;
+        let extent = self.render_extent;
+        let mut sets = self.post_sets[self.current_frame * POST_PASSES..][..POST_PASSES]
+            .iter()
+            .copied();
+        let mut pass = |graph: &mut RenderGraph<'a>, name, pipeline, inputs, extent, constants| {
+            let output = graph.create_image(
+                &format!("{} {}", self.name, name),
+                ImageDesc {
+                    format: HDR_FORMAT,
+                    extent,
+                },
+            );
+            let set = sets.next().expect("more post-processing passes than sets");
+            add_post_pass(
+                graph,
+                name,
+                post_pipelines,
+                pipeline,
+                set,
+                inputs,
+                output,
+                constants,
+            );
+            output
+        };
 
-struct QueueFamilyIndices {
-    graphics_family: Option<u32>,
-    present_family: Option<u32>,
-}
+        let mut color = scene_color;
 
-impl QueueFamilyIndices {
-    pub fn is_complete(&self) -> bool {
-        self.graphics_family.is_some() && self.present_family.is_some()
+        // thresholded, then blurred horizontally and vertically
+        let bloom = if effects.bloom {
+            let half = vk::Extent2D {
+                width: (extent.width / 2).max(1),
+                height: (extent.height / 2).max(1),
+            };
+            let constants =
+                PostPushConstants::new(extent, 0, Vec4::new(BLOOM_THRESHOLD, BLOOM_KNEE, 0., 0.));
+            let bright = pass(
+                graph,
+                "bloom threshold",
+                &post_pipelines.bloom_threshold,
+                [color, color],
+                half,
+                constants,
+            );
+            let constants = PostPushConstants::new(half, 0, Vec4::X);
+            let blurred = pass(
+                graph,
+                "bloom blur horizontal",
+                &post_pipelines.blur,
+                [bright, bright],
+                half,
+                constants,
+            );
+            let constants = PostPushConstants::new(half, 0, Vec4::Y);
+            Some(pass(
+                graph,
+                "bloom blur vertical",
+                &post_pipelines.blur,
+                [blurred, blurred],
+                half,
+                constants,
+            ))
+        } else {
+            None
+        };
+
+        if effects.tonemapper.is_some() || bloom.is_some() {
+            let strength = if bloom.is_some() { BLOOM_STRENGTH } else { 0. };
+            let constants = PostPushConstants::tonemap(extent, effects, strength);
+            color = pass(
+                graph,
+                "tonemap",
+                &post_pipelines.tonemap,
+                [color, bloom.unwrap_or(color)],
+                extent,
+                constants,
+            );
+        }
+
+        if let Some(grading) = &effects.color_grading {
+            let constants = PostPushConstants::color_grading(extent, grading);
+            color = pass(
+                graph,
+                "color grading",
+                &post_pipelines.color_grading,
+                [color, color],
+                extent,
+                constants,
+            );
+        }
+
+        if effects.fxaa {
+            let constants = PostPushConstants::new(extent, 0, Vec4::ZERO);
+            color = pass(
+                graph,
+                "FXAA",
+                &post_pipelines.fxaa,
+                [color, color],
+                extent,
+                constants,
+            );
+        }
+
+        // sRGB swapchains encode what is blitted to them themselves
+        if let Some(gamma) = effects.gamma.filter(|_| !is_srgb(self.swapchain_format)) {
+            let constants = PostPushConstants::new(extent, 0, Vec4::new(1. / gamma, 0., 0., 0.));
+            color = pass(
+                graph,
+                "gamma",
+                &post_pipelines.gamma,
+                [color, color],
+                extent,
+                constants,
+            );
+        }
+
+        color
     }
-}
 
-struct SwapchainSupportDetails {
-    capabilities: vk::SurfaceCapabilitiesKHR,
-    formats: Vec<vk::SurfaceFormatKHR>,
-    present_modes: Vec<vk::PresentModeKHR>,
-}
+    /// Records drawing `scene` into the current frame's command buffer, targeting the swapchain
+    /// image at `image_index`, after acquiring what `uploads` copied.
+    #[allow(clippy::too_many_arguments)]
+    fn record_command_buffer(
+        &mut self,
+        device: &ash::Device,
+        marker: &DebugMarker,
+        uploads: &mut UploadQueue,
+        scene: &Scene,
+        material_sets: &[vk::DescriptorSet],
+        environment_set: vk::DescriptorSet,
+        image_index: usize,
+    ) {
+        let command_buffer = self.command_buffers[self.current_frame];
+        let descriptor_set = self.descriptor_sets[self.current_frame];
+        let pipeline = &self.pipeline;
+        let shadow_pipeline = &self.shadow_pipeline;
+        let draws = &self.draws;
+        let culling = &self.culling;
+        let depth_pyramid = &self.depth_pyramid;
+        let cull_pipelines = &self.cull_pipelines;
+        let current_frame = self.current_frame;
+        let post_pipelines = Rc::clone(&self.post_pipelines);
 
-impl SwapchainSupportDetails {
-    pub fn query_swapchain_support(
-        device: vk::PhysicalDevice,
-        surface_loader: &khr::Surface,
-        surface: vk::SurfaceKHR,
-    ) -> Self {
-        let capabilities = unsafe {
-            surface_loader
-                .get_physical_device_surface_capabilities(device, surface)
-                .expect("could not get physical device surface capabilities!")
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            p_inheritance_info: std::ptr::null(),
         };
 
-        let formats = unsafe {
-            surface_loader
-                .get_physical_device_surface_formats(device, surface)
-                .expect("could not get physical device surface formats!")
+        unsafe {
+            device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .expect("failed to reset command buffer!");
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .expect("failed to begin recording command buffer!");
+        }
+        uploads.record_acquires(command_buffer);
+        self.shadow_map.record_initialize(device, command_buffer);
+
+        // queries are reset before the passes begin any
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin_frame(command_buffer, current_frame);
+        }
+        self.occlusion_queries
+            .begin_frame(command_buffer, current_frame);
+        let occlusion_queries = &self.occlusion_queries;
+        let scene_samples = Rc::clone(&self.scene_samples);
+
+        let clear_color = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0., 0., 0., 1.],
+            },
+        };
+        let clear_depth = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.,
+                stencil: 0,
+            },
         };
 
-        let present_modes = unsafe {
-            surface_loader
-                .get_physical_device_surface_present_modes(device, surface)
-                .expect("could not get physical device surface present modes!")
+        let mut graph = RenderGraph::new();
+
+        // the submit waits for the image to be acquired at the color attachment output stage
+        let swapchain_image = graph.import_image(ImportedImage {
+            image: self.swapchain_images[image_index],
+            view: self.swapchain_image_views[image_index].handle(),
+            layer: 0,
+            format: self.swapchain_format,
+            extent: self.swapchain_extent,
+            initial: ImageState {
+                layout: vk::ImageLayout::UNDEFINED,
+                stages: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags::empty(),
+            },
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        });
+
+        // layers no light renders into are left as they are, in the layout they are sampled in
+        let shadow_layers = self.shadow_map.layer_views[..self.shadow_layers as usize]
+            .iter()
+            .enumerate()
+            .map(|(layer, view)| {
+                graph.import_image(ImportedImage {
+                    image: self.shadow_map.image.handle(),
+                    view: view.handle(),
+                    layer: layer as u32,
+                    format: SHADOW_FORMAT,
+                    extent: vk::Extent2D {
+                        width: SHADOW_MAP_SIZE,
+                        height: SHADOW_MAP_SIZE,
+                    },
+                    initial: ImageState {
+                        layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                        stages: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        access: vk::AccessFlags::SHADER_READ,
+                    },
+                    final_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let depth = graph.create_image(
+            &format!("{} depth target", self.name),
+            ImageDesc {
+                format: self.depth_format,
+                extent: self.render_extent,
+            },
+        );
+
+        let scene_color = graph.create_image(
+            &format!("{} HDR color", self.name),
+            ImageDesc {
+                format: HDR_FORMAT,
+                extent: self.render_extent,
+            },
+        );
+
+        // built from the depth of the frame before, and left for the frame after
+        let pyramid = graph.import_image(ImportedImage {
+            image: depth_pyramid.image(),
+            view: depth_pyramid.view(),
+            layer: 0,
+            format: PYRAMID_FORMAT,
+            extent: depth_pyramid.extent(),
+            initial: ImageState {
+                layout: vk::ImageLayout::GENERAL,
+                stages: vk::PipelineStageFlags::COMPUTE_SHADER,
+                access: vk::AccessFlags::SHADER_WRITE,
+            },
+            final_layout: vk::ImageLayout::GENERAL,
+        });
+
+        // the shadow passes draw every instance, as what the camera cannot see may cast shadows
+        let all_instances = draws.buffers(current_frame);
+        let main_instances = if self.culling_enabled {
+            if let Some(all_instances) = all_instances {
+                graph
+                    .add_pass("cull")
+                    .label_color([0.6, 1., 0.4, 1.])
+                    .access(pyramid, Access::Sampled)
+                    .keep()
+                    .record(move |ctx| {
+                        culling.record(
+                            ctx.command_buffer,
+                            cull_pipelines,
+                            current_frame,
+                            all_instances,
+                            depth_pyramid,
+                        );
+                    });
+            }
+            culling.buffers(current_frame)
+        } else {
+            all_instances
         };
 
-        Self {
-            capabilities,
-            formats,
-            present_modes,
+        for (layer, &shadow_layer) in shadow_layers.iter().enumerate() {
+            graph
+                .add_pass(&format!("shadow layer {}", layer))
+                .depth_attachment(shadow_layer, LoadOp::Clear(clear_depth))
+                .record(move |ctx| {
+                    let device = ctx.device;
+                    let command_buffer = ctx.command_buffer;
+                    let (viewport, render_area) = viewport(ctx.extent);
+
+                    unsafe {
+                        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            shadow_pipeline.pipeline.handle(),
+                        );
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            shadow_pipeline.pipeline_layout.handle(),
+                            0,
+                            &[descriptor_set],
+                            &[],
+                        );
+                    }
+
+                    let constants = ShadowPushConstants::new(layer as u32);
+                    unsafe {
+                        device.cmd_push_constants(
+                            command_buffer,
+                            shadow_pipeline.pipeline_layout.handle(),
+                            vk::ShaderStageFlags::VERTEX,
+                            0,
+                            as_bytes(&constants),
+                        );
+                    }
+
+                    if let Some(buffers) = all_instances {
+                        for index in 0..draws.buckets().len() {
+                            draws.record_bucket(command_buffer, buffers, scene, index);
+                        }
+                    }
+                });
         }
-    }
 
-    pub fn choose_swap_surface_format(
-        available_formats: &[vk::SurfaceFormatKHR],
-    ) -> vk::SurfaceFormatKHR {
-        for fmt in available_formats {
-            if fmt.format == vk::Format::B8G8R8_SRGB
-                && fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            {
-                return fmt.to_owned();
+        let mut main_pass = graph
+            .add_pass("main pass")
+            .label_color([0.2, 0.6, 1., 1.])
+            .color_attachment(scene_color, LoadOp::Clear(clear_color))
+            .depth_attachment(depth, LoadOp::Clear(clear_depth));
+        for &shadow_layer in &shadow_layers {
+            main_pass = main_pass.access(shadow_layer, Access::Sampled);
+        }
+        main_pass.record(move |ctx| {
+            let device = ctx.device;
+            let command_buffer = ctx.command_buffer;
+            let (viewport, render_area) = viewport(ctx.extent);
+
+            unsafe {
+                device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+                // every pipeline shares the layout, so these stay bound across pipeline changes
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline_layout.handle(),
+                    0,
+                    &[descriptor_set],
+                    &[],
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline_layout.handle(),
+                    2,
+                    &[environment_set],
+                    &[],
+                );
+            }
+
+            let query = occlusion_queries.begin_occlusion(command_buffer, true);
+
+            // buckets are sorted by shading and then material, so each is bound once
+            let mut bound_shading = None;
+            let mut bound_material = None;
+            for (index, bucket) in draws.buckets().iter().enumerate() {
+                unsafe {
+                    if bound_shading != Some(bucket.shading) {
+                        bound_shading = Some(bucket.shading);
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline.for_shading(bucket.shading).handle(),
+                        );
+                    }
+
+                    if bound_material != Some(bucket.material_id) {
+                        bound_material = Some(bucket.material_id);
+                        let constants =
+                            MaterialPushConstants::new(scene.material(bucket.material_id));
+
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline.pipeline_layout.handle(),
+                            1,
+                            &[material_sets[bucket.material_id.index()]],
+                            &[],
+                        );
+
+                        device.cmd_push_constants(
+                            command_buffer,
+                            pipeline.pipeline_layout.handle(),
+                            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                            0,
+                            as_bytes(&constants),
+                        );
+                    }
+                }
+
+                if let Some(buffers) = main_instances {
+                    draws.record_bucket(command_buffer, buffers, scene, index);
+                }
+            }
+
+            if let Some(query) = query {
+                occlusion_queries.end(command_buffer, query, move |samples| {
+                    scene_samples.set(Some(samples))
+                });
+            }
+
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.skybox_pipeline.handle(),
+                );
+                device.cmd_draw(command_buffer, 3, 1, 0, 0);
             }
+        });
+
+        if self.culling_enabled {
+            let render_extent = self.render_extent;
+            graph
+                .add_pass("depth pyramid")
+                .label_color([0.6, 1., 0.4, 1.])
+                .access(depth, Access::Sampled)
+                .access(pyramid, Access::Storage)
+                .record(move |ctx| {
+                    depth_pyramid.record(
+                        ctx.command_buffer,
+                        cull_pipelines,
+                        current_frame,
+                        ctx.view(depth),
+                        render_extent,
+                    );
+                });
         }
 
-        available_formats[0]
-    }
+        let output = self.add_post_passes(&mut graph, &post_pipelines, scene_color);
+
+        // scales to the swapchain's resolution, and converts to its format
+        let (src_extent, dst_extent) = (self.render_extent, self.swapchain_extent);
+        graph
+            .add_pass("present")
+            .label_color([1., 0.6, 0.2, 1.])
+            .access(output, Access::TransferSrc)
+            .access(swapchain_image, Access::TransferDst)
+            .record(move |ctx| {
+                let subresource = vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                };
+                let corner = |extent: vk::Extent2D| vk::Offset3D {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: 1,
+                };
+
+                let blit = vk::ImageBlit {
+                    src_subresource: subresource,
+                    src_offsets: [vk::Offset3D::default(), corner(src_extent)],
+                    dst_subresource: subresource,
+                    dst_offsets: [vk::Offset3D::default(), corner(dst_extent)],
+                };
+
+                unsafe {
+                    ctx.device.cmd_blit_image(
+                        ctx.command_buffer,
+                        ctx.image(output),
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        ctx.image(swapchain_image),
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        &[blit],
+                        vk::Filter::LINEAR,
+                    );
+                }
+            });
 
-    pub fn choose_swap_present_mode(
-        available_present_modes: &[vk::PresentModeKHR],
-    ) -> vk::PresentModeKHR {
-        if available_present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
-            return vk::PresentModeKHR::MAILBOX;
+        let overlay_pipelines = &self.overlay_pipelines;
+        let srgb_target = is_srgb(self.swapchain_format);
+
+        if !self.sprites.is_empty() {
+            let sprites = &self.sprites;
+            graph
+                .add_pass("2D")
+                .label_color([0.8, 0.4, 0.9, 1.])
+                .color_attachment(swapchain_image, LoadOp::Load)
+                .record(move |ctx| {
+                    let (viewport, render_area) = viewport(ctx.extent);
+                    unsafe {
+                        ctx.device
+                            .cmd_set_viewport(ctx.command_buffer, 0, &[viewport]);
+                        ctx.device
+                            .cmd_set_scissor(ctx.command_buffer, 0, &[render_area]);
+                    }
+                    sprites.record(
+                        ctx.command_buffer,
+                        overlay_pipelines.sprite_pipeline.handle(),
+                        overlay_pipelines.sprite_layout.handle(),
+                        current_frame,
+                        ctx.extent,
+                        srgb_target,
+                    );
+                });
         }
-        vk::PresentModeKHR::FIFO
-    }
 
-    pub fn choose_swap_extent(
-        capabilities: vk::SurfaceCapabilitiesKHR,
-        window: &Window,
-    ) -> vk::Extent2D {
-        if capabilities.current_extent.width != u32::MAX {
-            return capabilities.current_extent;
+        if self.stats_visible {
+            let text = &self.text;
+            graph
+                .add_pass("text")
+                .label_color([0.9, 0.9, 0.9, 1.])
+                .color_attachment(swapchain_image, LoadOp::Load)
+                .record(move |ctx| {
+                    let (viewport, render_area) = viewport(ctx.extent);
+                    unsafe {
+                        ctx.device
+                            .cmd_set_viewport(ctx.command_buffer, 0, &[viewport]);
+                        ctx.device
+                            .cmd_set_scissor(ctx.command_buffer, 0, &[render_area]);
+                    }
+                    text.record(
+                        ctx.command_buffer,
+                        overlay_pipelines.text_pipeline.handle(),
+                        overlay_pipelines.pipeline_layout.handle(),
+                        current_frame,
+                        ctx.extent,
+                        srgb_target,
+                    );
+                });
         }
 
-        let (phys_height, phys_width) = (window.inner_size().height, window.inner_size().width);
-        let scale_factor = window.scale_factor();
+        if self.ui.visible {
+            let ui = &self.ui;
+            graph
+                .add_pass("UI")
+                .label_color([0.2, 0.8, 0.8, 1.])
+                .color_attachment(swapchain_image, LoadOp::Load)
+                .record(move |ctx| {
+                    let (viewport, _) = viewport(ctx.extent);
+                    unsafe {
+                        ctx.device
+                            .cmd_set_viewport(ctx.command_buffer, 0, &[viewport]);
+                    }
+                    ui.record(
+                        ctx.command_buffer,
+                        overlay_pipelines.ui_pipeline.handle(),
+                        overlay_pipelines.pipeline_layout.handle(),
+                        current_frame,
+                        ctx.extent,
+                        srgb_target,
+                    );
+                });
+        }
 
-        // logical size = physical size / scale factor
-        let actual_height = clamp(
-            (phys_height as f64 / scale_factor) as u32,
-            capabilities.min_image_extent.height,
-            capabilities.max_image_extent.height,
-        );
-        let actual_width = clamp(
-            (phys_width as f64 / scale_factor) as u32,
-            capabilities.min_image_extent.width,
-            capabilities.max_image_extent.width,
+        graph.execute(
+            &mut self.graph_cache,
+            &mut self.deletions,
+            marker,
+            self.profiler.as_mut(),
+            command_buffer,
         );
 
-        vk::Extent2D {
-            height: actual_height,
-            width: actual_width,
+        unsafe {
+            device
+                .end_command_buffer(command_buffer)
+                .expect("failed to record command buffer!");
         }
     }
 }
 
-unsafe extern "system" fn debug_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut c_void,
-) -> vk::Bool32 {
-    let message = CStr::from_ptr((*p_callback_data).p_message);
-
-    println!(
-        "[DEBUG] [{:?}] [{:?}] {:?}",
-        message_severity, message_type, message
-    );
+/// Adds a pass drawing `pipeline` over all of `output`, reading `inputs` through `set`.
+#[allow(clippy::too_many_arguments)]
+fn add_post_pass<'a>(
+    graph: &mut RenderGraph<'a>,
+    name: &str,
+    post_pipelines: &'a PostPipelines,
+    pipeline: &'a Pipeline,
+    set: vk::DescriptorSet,
+    inputs: [ImageId; 2],
+    output: ImageId,
+    constants: PostPushConstants,
+) {
+    let mut pass = graph
+        .add_pass(name)
+        .label_color([0.8, 0.4, 1., 1.])
+        .color_attachment(output, LoadOp::DontCare)
+        .access(inputs[0], Access::Sampled);
+    // passes with a single input bind it twice
+    if inputs[1] != inputs[0] {
+        pass = pass.access(inputs[1], Access::Sampled);
+    }
+    pass.record(move |ctx| {
+        let device = ctx.device;
+        let command_buffer = ctx.command_buffer;
+        let (viewport, render_area) = viewport(ctx.extent);
+
+        // the set was last used by this frame's previous submission, which has completed
+        let image_infos = inputs.map(|input| vk::DescriptorImageInfo {
+            sampler: post_pipelines.sampler.handle(),
+            image_view: ctx.view(input),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        });
+        VkApp::write_texture_set(device, set, &image_infos);
 
-    ash::vk::FALSE
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.handle(),
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                post_pipelines.pipeline_layout.handle(),
+                0,
+                &[set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                post_pipelines.pipeline_layout.handle(),
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                as_bytes(&constants),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    });
+}
+
+/// A viewport and scissor covering all of `extent`.
+fn viewport(extent: vk::Extent2D) -> (vk::Viewport, vk::Rect2D) {
+    let viewport = vk::Viewport {
+        x: 0.,
+        y: 0.,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.,
+        max_depth: 1.,
+    };
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+    (viewport, render_area)
+}
+
+struct VkApp {
+    debug_marker: DebugMarker,
+    queue_families: QueueFamilyIndices,
+    graphics_queue: vk::Queue,
+    present_queue: vk::Queue,
+    /// The queue `uploads` submits to, the graphics queue unless it has its own.
+    upload_queue: vk::Queue,
+    render_scale: RenderScale,
+    post_effects: PostEffects,
+    main_window: WindowId,
+    windows_created: usize,
+    started: Instant,
+    depth_format: vk::Format,
+    device_info: Vec<(&'static str, String)>,
+    font: FontArc,
+    // everything below is destroyed on drop; each wrapper keeps the objects it was created from
+    // alive, so the device outlives all of its children and the instance outlives the device
+    windows: HashMap<WindowId, WindowState>,
+    pipelines: HashMap<vk::Format, Rc<MeshPipeline>>,
+    shadow_pipeline: Rc<ShadowPipeline>,
+    cull_pipelines: Rc<CullPipelines>,
+    post_pipelines: Rc<PostPipelines>,
+    overlay_pipelines: HashMap<vk::Format, Rc<OverlayPipelines>>,
+    scene: Scene,
+    /// A loaded scene whose uploads have yet to complete, which replaces `scene` once they have.
+    loading_scene: Option<Scene>,
+    // one per scene material, indexed by its id
+    material_sets: Vec<vk::DescriptorSet>,
+    material_pool: DescriptorPool,
+    // bound in place of the textures a material does not have
+    fallback_textures: FallbackTextures,
+    environment_set: vk::DescriptorSet,
+    environment_pool: DescriptorPool,
+    environment: Environment,
+    frame_set_layout: DescriptorSetLayout,
+    material_set_layout: DescriptorSetLayout,
+    environment_set_layout: DescriptorSetLayout,
+    overlay_set_layout: DescriptorSetLayout,
+    /// The graphics queue's timeline, which frames signal, if the device supports timeline
+    /// semaphores.
+    timeline: Option<Rc<Timeline>>,
+    /// Copies recorded while drawing frames, submitted ahead of them, to a queue of their own
+    /// with a timeline of its own where the device has a transfer only queue family or a second
+    /// graphics queue.
+    uploads: UploadQueue,
+    uploader: Uploader,
+    command_pool: CommandPool,
+    device: Arc<Device>,
+    _debug_messenger: Option<DebugMessenger>,
+    instance: Arc<Instance>,
+}
+
+struct FallbackTextures {
+    white: Texture,
+    flat_normal: Texture,
+}
+
+impl FallbackTextures {
+    fn new(uploads: &mut UploadQueue) -> Self {
+        // frames submitted after the copies can sample them, so the handles are not needed
+        let mut texture = |color, srgb| {
+            Texture::queue(
+                uploads,
+                &TextureData::solid(color, srgb),
+                &SamplerDesc::default(),
+            )
+            .0
+        };
+
+        Self {
+            white: texture([255; 4], true),
+            flat_normal: texture([128, 128, 255, 255], false),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct QueueFamilyIndices {
+    graphics_family: Option<u32>,
+    /// How many queues the graphics family has.
+    graphics_queue_count: u32,
+    present_family: Option<u32>,
+    /// A family with transfers but neither graphics nor compute, which is usually backed by
+    /// dedicated copy engines.
+    transfer_family: Option<u32>,
 }
 
-fn populate_debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
-    vk::DebugUtilsMessengerCreateInfoEXT {
-        s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
-        p_next: std::ptr::null(),
-        flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-        | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-        // | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-        | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-        message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-        pfn_user_callback: Some(debug_callback),
-        p_user_data: std::ptr::null_mut(),
+impl QueueFamilyIndices {
+    pub fn is_complete(&self) -> bool {
+        self.graphics_family.is_some() && self.present_family.is_some()
     }
 }
 
 impl VkApp {
-    pub fn init_vulkan(window: &Window) -> Self {
+    pub fn init_vulkan(
+        window: Window,
+        render_scale: RenderScale,
+        post_effects: PostEffects,
+        validation: ValidationConfig,
+        debug_callback: DebugCallback,
+    ) -> Self {
         let entry = unsafe { ash::Entry::new().unwrap() };
-        let instance = Self::create_instance(&entry, window);
-        let (debug_utils, debug_messenger) = Self::setup_debug_messenger(&entry, &instance);
-        let (surface, surface_loader) = Self::create_surface(&entry, &instance, &window);
-        let physical_device = Self::pick_physical_device(&instance, &surface_loader, &surface);
-        let (logical_device, graphics_queue, present_queue) =
-            Self::create_logical_device(&instance, physical_device, &surface_loader, &surface);
-        let (swapchain_loader, swapchain, swapchain_images, swapchain_format, swapchain_extent) =
-            Self::create_swapchain(
-                &instance,
-                &logical_device,
-                physical_device,
-                &surface_loader,
-                &surface,
-                &window,
-            );
-        let swapchain_image_views =
-            Self::create_image_views(&swapchain_images, swapchain_format, &logical_device);
+        let validation = validation.resolve(&entry);
+        // names and labels show up in RenderDoc and Nsight with or without validation
+        let debug_utils =
+            validation.enabled || instance_extension_supported(&entry, None, DebugUtils::name());
+        let debug_callback = Arc::new(debug_callback);
+        let instance =
+            Self::create_instance(entry, &window, validation, debug_utils, &debug_callback);
+        let debug_messenger = Self::setup_debug_messenger(&instance, validation, &debug_callback);
+        // the device has to be able to present to the first window; later windows are checked
+        // against the queue families picked here
+        let surface = Self::create_surface(&instance, &window);
+        let physical_device =
+            Self::pick_physical_device(&instance, surface.loader(), &surface.handle());
+        let queue_families = Self::find_queue_family(
+            &instance,
+            physical_device,
+            surface.loader(),
+            &surface.handle(),
+        );
+        let (device, graphics_queue, present_queue, upload_queue) = Self::create_logical_device(
+            &instance,
+            physical_device,
+            surface.loader(),
+            &surface.handle(),
+            validation,
+        );
+        let debug_marker = DebugMarker::new(
+            debug_utils.then(|| DebugUtils::new(instance.entry(), &**instance)),
+            device.handle(),
+        );
 
-        let render_pass = Self::create_render_pass(swapchain_format, &logical_device);
+        let command_pool = Self::create_command_pool(&device, surface.loader(), &surface.handle());
+        let depth_format = find_depth_format(&instance, physical_device);
+        let frame_set_layout = Self::create_frame_set_layout(&device);
+        let material_set_layout = Self::create_texture_set_layout(&device, MATERIAL_TEXTURES);
+        let environment_set_layout = Self::create_texture_set_layout(&device, ENVIRONMENT_TEXTURES);
+        let overlay_set_layout = Self::create_texture_set_layout(&device, 1);
+        let device_info = describe_device(&device);
+        let font = font_from_env();
+        let shadow_pipeline = Rc::new(Self::create_shadow_pipeline(&device, &frame_set_layout));
+        let cull_pipelines = Rc::new(CullPipelines::new(&device));
+        let post_pipelines = Rc::new(Self::create_post_pipelines(&device));
+        let uploader = Uploader::new(
+            &device,
+            queue_families.graphics_family.unwrap(),
+            graphics_queue,
+        );
+        let timeline = (device.timeline_semaphore() && timeline_semaphores_from_env())
+            .then(|| Rc::new(Timeline::new(&device)));
+        // uploads only get a queue of their own with timelines, which frames wait for them on
+        let graphics_family = queue_families.graphics_family.unwrap();
+        let (upload_family, upload_queue, upload_timeline) = match (&timeline, upload_queue) {
+            (Some(_), Some((family, queue))) => {
+                (family, queue, Some(Rc::new(Timeline::new(&device))))
+            }
+            _ => (graphics_family, graphics_queue, timeline.clone()),
+        };
+        let mut uploads = UploadQueue::new(
+            &device,
+            upload_family,
+            upload_queue,
+            upload_timeline,
+            graphics_family,
+        );
+        let fallback_textures = FallbackTextures::new(&mut uploads);
+        let environment = Environment::new(&uploader, &environment_source_from_env());
+        let (environment_pool, environment_set) =
+            Self::create_environment_descriptor_set(&device, &environment_set_layout, &environment);
+        // the first frames draw the default scene, which they are submitted after the copies of
+        let scene = Self::create_default_scene(&mut uploads);
+        let (material_pool, material_sets) = Self::create_material_descriptor_sets(
+            &device,
+            &material_set_layout,
+            &scene,
+            &fallback_textures,
+        );
 
-        let (pipeline_layout, graphics_pipeline) =
-            Self::create_graphics_pipeline(&logical_device, swapchain_extent, render_pass);
+        let mut app = VkApp {
+            debug_marker,
+            queue_families,
+            graphics_queue,
+            present_queue,
+            upload_queue,
+            render_scale,
+            post_effects,
+            main_window: window.id(),
+            windows_created: 0,
+            started: Instant::now(),
+            depth_format,
+            device_info,
+            font,
+            windows: HashMap::new(),
+            pipelines: HashMap::new(),
+            shadow_pipeline,
+            cull_pipelines,
+            post_pipelines,
+            overlay_pipelines: HashMap::new(),
+            scene,
+            loading_scene: None,
+            material_sets,
+            material_pool,
+            fallback_textures,
+            environment_set,
+            environment_pool,
+            environment,
+            frame_set_layout,
+            material_set_layout,
+            environment_set_layout,
+            overlay_set_layout,
+            timeline,
+            uploads,
+            uploader,
+            command_pool,
+            device,
+            _debug_messenger: debug_messenger,
+            instance,
+        };
 
-        let swapchain_framebuffers = Self::create_framebuffers(
-            &logical_device,
-            &swapchain_image_views,
-            render_pass,
-            swapchain_extent,
+        app.name_device_objects();
+        app.add_window_with_surface(window, surface);
+
+        app
+    }
+
+    /// Starts rendering to another window with the same device, queues and pipelines.
+    pub fn add_window(&mut self, window: Window) -> WindowId {
+        let surface = Self::create_surface(&self.instance, &window);
+
+        let present_supported = unsafe {
+            surface
+                .loader()
+                .get_physical_device_surface_support(
+                    self.device.physical_device(),
+                    self.queue_families.present_family.unwrap(),
+                    surface.handle(),
+                )
+                .expect("failed to get physical device surface support!")
+        };
+        if !present_supported {
+            panic!("the present queue cannot present to the new window!");
+        }
+
+        self.add_window_with_surface(window, surface)
+    }
+
+    fn add_window_with_surface(&mut self, window: Window, surface: Arc<Surface>) -> WindowId {
+        let (swapchain, swapchain_images, swapchain_format, swapchain_extent) =
+            Self::create_swapchain(&self.device, &surface, &window, self.queue_families);
+        let swapchain_image_views =
+            Self::create_image_views(&swapchain_images, swapchain_format, &self.device);
+
+        let max_dimension = self.device.limits().max_image_dimension2_d;
+        let render_extent = self.render_scale.apply(swapchain_extent, max_dimension);
+
+        let pipeline = self.pipeline_for(HDR_FORMAT);
+        let overlay_pipelines = self.overlay_pipelines_for(swapchain_format);
+        let ui = UiLayer::new(
+            &self.device,
+            &self.overlay_set_layout,
+            MAX_FRAMES_IN_FLIGHT,
+            window.scale_factor(),
+            max_dimension,
+        );
+        let text = TextRenderer::new(
+            &self.device,
+            &self.overlay_set_layout,
+            self.font.clone(),
+            MAX_FRAMES_IN_FLIGHT,
+        );
+        let sprites = SpriteBatch::new(
+            &mut self.uploads,
+            &self.overlay_set_layout,
+            MAX_FRAMES_IN_FLIGHT,
         );
 
-        let command_pool = Self::create_command_pool(
-            &instance,
-            &logical_device,
-            physical_device,
-            &surface_loader,
-            &surface,
+        let command_buffers = Self::create_command_buffers(&self.command_pool, &self.device);
+
+        let camera_buffers = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Buffer::new(
+                    &self.device,
+                    std::mem::size_of::<CameraUniform>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let light_buffers = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Buffer::new(
+                    &self.device,
+                    std::mem::size_of::<LightArray>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let shadow_buffers = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                Buffer::new(
+                    &self.device,
+                    std::mem::size_of::<ShadowUniform>() as vk::DeviceSize,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let shadow_map = ShadowMap::new(&self.device);
+
+        let (descriptor_pool, descriptor_sets) = Self::create_frame_descriptor_sets(
+            &self.device,
+            &self.frame_set_layout,
+            &camera_buffers,
+            &light_buffers,
+            &shadow_buffers,
+            &shadow_map,
         );
 
-        let command_buffers = Self::create_command_buffers(
-            command_pool,
-            &swapchain_framebuffers,
-            &logical_device,
-            render_pass,
-            swapchain_extent,
-            graphics_pipeline,
+        let post_pipelines = Rc::clone(&self.post_pipelines);
+        let (post_pool, post_sets) = Self::allocate_texture_sets(
+            &self.device,
+            &post_pipelines.set_layout,
+            2,
+            POST_PASSES * MAX_FRAMES_IN_FLIGHT,
         );
 
-        let (
-            image_available_semaphores,
-            render_finished_semaphores,
-            in_flight_fences,
-            in_flight_images,
-        ) = Self::create_sync_objects(&logical_device, &swapchain_images);
+        let (image_available_semaphores, render_finished_semaphores, frame_sync) =
+            Self::create_sync_objects(&self.device, &swapchain_images, self.timeline.clone());
 
-        VkApp {
-            _entry: entry,
-            instance,
-            debug_utils,
-            debug_messenger,
-            device: logical_device,
-            surface,
-            surface_loader,
-            graphics_queue,
-            present_queue,
-            swapchain_loader,
-            swapchain,
+        let camera = Camera::default();
+        let controller = CameraController::orbit(glam::Vec3::ZERO, camera.position.length());
+
+        let name = if self.windows_created == 0 {
+            String::from("main window")
+        } else {
+            format!("window {}", self.windows_created)
+        };
+        self.windows_created += 1;
+
+        let state = WindowState {
+            name,
             swapchain_images,
             swapchain_format,
             swapchain_extent,
-            swapchain_image_views,
-            render_pass,
-            pipeline_layout,
-            graphics_pipeline,
-            swapchain_framebuffers,
-            command_pool,
+            depth_format: self.depth_format,
+            render_extent,
+            camera,
+            controller,
+            last_frame: Instant::now(),
             command_buffers,
+            camera_buffers,
+            light_buffers,
+            shadow_buffers,
+            descriptor_sets,
+            draws: IndirectDraws::new(&self.device, MAX_FRAMES_IN_FLIGHT),
+            current_frame: 0,
             image_available_semaphores,
             render_finished_semaphores,
-            in_flight_fences,
-            in_flight_images,
-            current_frame: 0,
+            frame_sync,
+            deletions: DeletionQueue::new(),
+            shadow_layers: 0,
+            post_effects: self.post_effects,
+            post_sets,
+            post_pool,
+            ui,
+            text,
+            stats_visible: true,
+            sprites,
+            gizmos_visible: false,
+            profiler: GpuProfiler::new(
+                &self.device,
+                self.queue_families.graphics_family.unwrap(),
+                MAX_FRAMES_IN_FLIGHT,
+            ),
+            occlusion_queries: OcclusionQueries::new(&self.device, 1, MAX_FRAMES_IN_FLIGHT),
+            scene_samples: Rc::new(Cell::new(None)),
+            culling_enabled: true,
+            culling: GpuCulling::new(&self.device, &self.cull_pipelines, MAX_FRAMES_IN_FLIGHT),
+            previous_view_projection: None,
+            depth_pyramid: DepthPyramid::new(
+                &self.uploader,
+                &self.cull_pipelines,
+                render_extent,
+                MAX_FRAMES_IN_FLIGHT,
+            ),
+            cull_pipelines: Rc::clone(&self.cull_pipelines),
+            frame_times: FrameTimes::new(),
+            descriptor_pool,
+            graph_cache: RenderGraphCache::new(&self.device),
+            pipeline,
+            post_pipelines,
+            overlay_pipelines,
+            shadow_map,
+            shadow_pipeline: Rc::clone(&self.shadow_pipeline),
+            swapchain_image_views,
+            swapchain,
+            surface,
+            window,
+        };
+
+        state.name_objects(&self.debug_marker);
+
+        let id = state.window.id();
+        self.windows.insert(id, state);
+        id
+    }
+
+    /// Stops rendering to a window once the work already submitted for it has completed.
+    pub fn remove_window(&mut self, window_id: WindowId) {
+        let state = match self.windows.remove(&window_id) {
+            Some(state) => state,
+            None => return,
+        };
+
+        state.frame_sync.wait_all();
+        unsafe {
+            self.device
+                .free_command_buffers(self.command_pool.handle(), &state.command_buffers);
         }
     }
 
-    pub fn init_window(event_loop: &EventLoop<()>) -> Window {
-        WindowBuilder::new()
-            .with_inner_size(winit::dpi::LogicalSize::new(WIDTH, HEIGHT))
-            .with_title("Vulkan")
-            .build(event_loop)
-            .expect("failed to create window")
+    /// Returns the pipeline for targets with `format`, creating it the first time it is needed.
+    fn pipeline_for(&mut self, format: vk::Format) -> Rc<MeshPipeline> {
+        let device = &self.device;
+        let debug_marker = &self.debug_marker;
+        let depth_format = self.depth_format;
+        let set_layouts = [
+            self.frame_set_layout.handle(),
+            self.material_set_layout.handle(),
+            self.environment_set_layout.handle(),
+        ];
+
+        let pipeline = self.pipelines.entry(format).or_insert_with(|| {
+            let targets = RenderPassDesc::compatible(&[format], Some(depth_format));
+            let pipeline_layout = Self::create_pipeline_layout::<MaterialPushConstants>(
+                device,
+                &set_layouts,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            );
+            let pipeline =
+                |desc| Self::create_graphics_pipeline(device, &targets, &pipeline_layout, desc);
+            let simple_pipeline = pipeline(&PipelineDesc::SIMPLE);
+            let pbr_pipeline = pipeline(&PipelineDesc::PBR);
+            let skybox_pipeline = pipeline(&PipelineDesc::SKYBOX);
+
+            debug_marker.set_object_name(pipeline_layout.handle(), "mesh pipeline layout");
+            debug_marker.set_object_name(
+                simple_pipeline.handle(),
+                &format!("simple mesh pipeline ({:?})", format),
+            );
+            debug_marker.set_object_name(
+                pbr_pipeline.handle(),
+                &format!("PBR mesh pipeline ({:?})", format),
+            );
+            debug_marker.set_object_name(
+                skybox_pipeline.handle(),
+                &format!("skybox pipeline ({:?})", format),
+            );
+
+            Rc::new(MeshPipeline {
+                simple_pipeline,
+                pbr_pipeline,
+                skybox_pipeline,
+                pipeline_layout,
+            })
+        });
+
+        Rc::clone(pipeline)
     }
 
-    pub fn create_render_pass(
-        swapchain_format: vk::Format,
-        device: &ash::Device,
-    ) -> vk::RenderPass {
-        let color_attachment = vk::AttachmentDescription {
-            flags: vk::AttachmentDescriptionFlags::empty(),
-            format: swapchain_format,
-            samples: vk::SampleCountFlags::TYPE_1,
-            load_op: vk::AttachmentLoadOp::CLEAR,
-            store_op: vk::AttachmentStoreOp::STORE,
-            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-            initial_layout: vk::ImageLayout::UNDEFINED,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-        };
+    /// Returns the overlay pipelines for targets with `format`, creating them the first time
+    /// they are needed.
+    fn overlay_pipelines_for(&mut self, format: vk::Format) -> Rc<OverlayPipelines> {
+        let device = &self.device;
+        let debug_marker = &self.debug_marker;
+        let set_layout = self.overlay_set_layout.handle();
+
+        let pipelines = self.overlay_pipelines.entry(format).or_insert_with(|| {
+            let targets = RenderPassDesc::compatible(&[format], None);
+            let pipeline_layout = Self::create_pipeline_layout::<UiPushConstants>(
+                device,
+                &[set_layout],
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            );
+            let pipeline =
+                |desc| Self::create_graphics_pipeline(device, &targets, &pipeline_layout, desc);
+            let ui_pipeline = pipeline(&PipelineDesc::UI);
+            let text_pipeline = pipeline(&PipelineDesc::TEXT);
+            let sprite_layout = Self::create_pipeline_layout::<SpritePushConstants>(
+                device,
+                &[set_layout],
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            );
+            let sprite_pipeline = Self::create_graphics_pipeline(
+                device,
+                &targets,
+                &sprite_layout,
+                &PipelineDesc::SPRITE,
+            );
 
-        let color_attachment_ref = vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        };
+            debug_marker.set_object_name(pipeline_layout.handle(), "overlay pipeline layout");
+            debug_marker
+                .set_object_name(ui_pipeline.handle(), &format!("UI pipeline ({:?})", format));
+            debug_marker.set_object_name(
+                text_pipeline.handle(),
+                &format!("text pipeline ({:?})", format),
+            );
+            debug_marker.set_object_name(sprite_layout.handle(), "sprite pipeline layout");
+            debug_marker.set_object_name(
+                sprite_pipeline.handle(),
+                &format!("sprite pipeline ({:?})", format),
+            );
 
-        let subpass = vk::SubpassDescription {
-            flags: vk::SubpassDescriptionFlags::empty(),
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-            input_attachment_count: 0,
-            p_input_attachments: std::ptr::null(),
-            color_attachment_count: 1,
-            p_color_attachments: &color_attachment_ref,
-            p_resolve_attachments: std::ptr::null(),
-            p_depth_stencil_attachment: std::ptr::null(),
-            preserve_attachment_count: 0,
-            p_preserve_attachments: std::ptr::null(),
-        };
+            Rc::new(OverlayPipelines {
+                ui_pipeline,
+                text_pipeline,
+                pipeline_layout,
+                sprite_pipeline,
+                sprite_layout,
+            })
+        });
 
-        let dependency = vk::SubpassDependency {
-            src_subpass: vk::SUBPASS_EXTERNAL,
-            dst_subpass: 0,
-            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-            dependency_flags: vk::DependencyFlags::empty(),
-        };
+        Rc::clone(pipelines)
+    }
 
-        let render_pass_info = vk::RenderPassCreateInfo {
-            s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::RenderPassCreateFlags::empty(),
-            attachment_count: 1,
-            p_attachments: &color_attachment,
-            subpass_count: 1,
-            p_subpasses: &subpass,
-            dependency_count: 1,
-            p_dependencies: &dependency,
-        };
+    /// Names the objects shared by all windows. Does nothing unless debug utils is enabled.
+    fn name_device_objects(&self) {
+        let marker = &self.debug_marker;
+        if !marker.is_enabled() {
+            return;
+        }
 
-        unsafe {
-            device
-                .create_render_pass(&render_pass_info, None)
-                .expect("failed to create render pass!")
+        marker.set_object_name(self.device.handle(), "device");
+        marker.set_object_name(self.graphics_queue, "graphics queue");
+        if self.present_queue != self.graphics_queue {
+            marker.set_object_name(self.present_queue, "present queue");
+        }
+        if let Some(timeline) = &self.timeline {
+            marker.set_object_name(timeline.semaphore().handle(), "graphics timeline");
         }
+        if self.upload_queue != self.graphics_queue {
+            marker.set_object_name(self.upload_queue, "upload queue");
+            if let Some(timeline) = self.uploads.timeline() {
+                marker.set_object_name(timeline.semaphore().handle(), "upload timeline");
+            }
+        }
+        marker.set_object_name(self.command_pool.handle(), "graphics command pool");
+        marker.set_object_name(
+            self.shadow_pipeline.pipeline_layout.handle(),
+            "shadow pipeline layout",
+        );
+        marker.set_object_name(self.shadow_pipeline.pipeline.handle(), "shadow pipeline");
+        let post = &self.post_pipelines;
+        marker.set_object_name(
+            post.pipeline_layout.handle(),
+            "post-processing pipeline layout",
+        );
+        marker.set_object_name(post.set_layout.handle(), "post-processing set layout");
+        marker.set_object_name(post.sampler.handle(), "post-processing sampler");
+        for (pipeline, name) in [
+            (&post.bloom_threshold, "bloom threshold"),
+            (&post.blur, "blur"),
+            (&post.tonemap, "tonemap"),
+            (&post.color_grading, "color grading"),
+            (&post.fxaa, "FXAA"),
+            (&post.gamma, "gamma"),
+        ] {
+            marker.set_object_name(pipeline.handle(), &format!("{} pipeline", name));
+        }
+        marker.set_object_name(self.frame_set_layout.handle(), "frame set layout");
+        marker.set_object_name(self.material_set_layout.handle(), "material set layout");
+        marker.set_object_name(self.overlay_set_layout.handle(), "overlay set layout");
+        marker.set_object_name(
+            self.environment_set_layout.handle(),
+            "environment set layout",
+        );
+        marker.set_object_name(self.uploader.command_pool().handle(), "upload command pool");
+        marker.set_object_name(
+            self.fallback_textures.white.image.handle(),
+            "white fallback texture",
+        );
+        marker.set_object_name(
+            self.fallback_textures.flat_normal.image.handle(),
+            "flat normal fallback texture",
+        );
+        let environment = &self.environment;
+        marker.set_object_name(environment.cube.image.handle(), "environment cube map");
+        marker.set_object_name(environment.irradiance.image.handle(), "irradiance map");
+        marker.set_object_name(environment.prefiltered.image.handle(), "prefiltered map");
+        marker.set_object_name(environment.brdf_lut.image.handle(), "BRDF lookup table");
+        marker.set_object_name(
+            self.environment_pool.handle(),
+            "environment descriptor pool",
+        );
+        marker.set_object_name(self.environment_set, "environment descriptor set");
+        self.name_scene_objects();
     }
 
-    pub fn setup_debug_messenger(
-        entry: &ash::Entry,
-        instance: &ash::Instance,
-    ) -> (DebugUtils, vk::DebugUtilsMessengerEXT) {
-        let debug_utils = DebugUtils::new(entry, instance);
-
-        if !ENABLE_VALIDATION_LAYERS {
-            return (debug_utils, vk::DebugUtilsMessengerEXT::null());
+    /// Names the scene's buffers, textures and material descriptor sets.
+    fn name_scene_objects(&self) {
+        let marker = &self.debug_marker;
+        if !marker.is_enabled() {
+            return;
         }
 
-        let messenger_create_info = populate_debug_messenger_create_info();
+        marker.set_object_name(self.material_pool.handle(), "material descriptor pool");
+        marker.set_object_names(
+            self.material_sets.iter().copied(),
+            "material descriptor set",
+        );
+        marker.set_object_names(
+            self.scene
+                .textures()
+                .iter()
+                .map(|texture| texture.image.handle()),
+            "scene texture",
+        );
+        if let Some(geometry) = self.scene.geometry() {
+            marker.set_object_name(geometry.vertex_buffer().handle(), "scene vertex buffer");
+            marker.set_object_name(geometry.index_buffer().handle(), "scene index buffer");
+        }
+    }
 
-        let debug_utils_messenger = unsafe {
-            debug_utils
-                .create_debug_utils_messenger(&messenger_create_info, None)
-                .expect("could not create debug messenger")
-        };
+    /// Replaces the scene with the contents of the glTF or OBJ file at `path`, once its uploads
+    /// have completed. The current scene is kept until then, or if the file cannot be loaded.
+    pub fn load_model(&mut self, path: &Path) -> Result<(), LoadError> {
+        let mut scene = Scene::new();
+        vka::loader::load(path, &mut self.uploads, &mut scene)?;
+        scene.merge_meshes(&mut self.uploads);
+        // a model still loading is dropped once its copies, which the next frames wait for, are
+        // done with it
+        if let Some(loading) = self.loading_scene.replace(scene) {
+            let loading = Rc::new(loading);
+            for state in self.windows.values_mut() {
+                state.deletions.retire(Rc::clone(&loading));
+            }
+        }
+        Ok(())
+    }
 
-        (debug_utils, debug_utils_messenger)
+    /// Replaces the scene with the one loading, if its uploads have completed.
+    fn finish_loading_scene(&mut self) {
+        self.uploads.poll();
+        if self.loading_scene.as_ref().is_some_and(Scene::is_resident) {
+            let scene = self.loading_scene.take().unwrap();
+            self.set_scene(scene);
+        }
     }
 
-    pub fn read_spv(fname: &str) -> Vec<u8> {
-        let file = File::open(fname).expect("could not read file!");
-        file.bytes().filter_map(|b| b.ok()).collect()
+    fn set_scene(&mut self, scene: Scene) {
+        let (material_pool, material_sets) = Self::create_material_descriptor_sets(
+            &self.device,
+            &self.material_set_layout,
+            &scene,
+            &self.fallback_textures,
+        );
+
+        let old_scene = std::mem::replace(&mut self.scene, scene);
+        let old_pool = std::mem::replace(&mut self.material_pool, material_pool);
+        self.material_sets = material_sets;
+        self.name_scene_objects();
+
+        // the frames in flight of every window may still draw the old scene, which goes once
+        // the last of them is done with it
+        let old = Rc::new((old_scene, old_pool));
+        for state in self.windows.values_mut() {
+            state.deletions.retire(Rc::clone(&old));
+        }
     }
 
-    pub fn check_validation_layer_support(entry: &ash::Entry) -> bool {
-        let available_layers = entry
-            .enumerate_instance_layer_properties()
-            .expect("could not enumerate instance layer properties");
+    /// A spinning cube with two smaller cubes orbiting it, above a ground plane, lit by the sun
+    /// and a lamp.
+    fn create_default_scene(uploads: &mut UploadQueue) -> Scene {
+        let mut scene = Scene::new();
 
-        let available_layers = available_layers
-            .iter()
-            .map(|x| vk_to_str(&x.layer_name))
-            .collect::<Vec<_>>();
+        let cube = scene.add_mesh(MeshData::cube(1.));
+        let plane = scene.add_mesh(MeshData::plane(10.));
 
-        println!("Available layers");
-        for l in &available_layers {
-            println!("\t{}", l);
+        let material = |scene: &mut Scene, r, g, b| {
+            scene.add_material(Material {
+                base_color: glam::Vec4::new(r, g, b, 1.),
+                ..Material::default()
+            })
+        };
+        let grey = material(&mut scene, 0.6, 0.6, 0.6);
+        let red = material(&mut scene, 1., 0.2, 0.2);
+        let green = material(&mut scene, 0.2, 1., 0.2);
+        let blue = material(&mut scene, 0.2, 0.4, 1.);
+
+        let ground = scene.add_node(
+            None,
+            "ground",
+            Transform::from_translation(glam::Vec3::new(0., -1., 0.)),
+        );
+        scene.node_mut(ground).mesh = Some(plane);
+        scene.node_mut(ground).material = Some(grey);
+
+        let spinner = scene.add_node(None, "spinner", Transform::IDENTITY);
+        scene.node_mut(spinner).mesh = Some(cube);
+        scene.node_mut(spinner).material = Some(red);
+
+        for (i, (x, material)) in [(2., green), (-2., blue)].iter().enumerate() {
+            let moon = scene.add_node(
+                Some(spinner),
+                format!("moon {}", i),
+                Transform {
+                    translation: glam::Vec3::new(*x, 0., 0.),
+                    scale: glam::Vec3::splat(0.5),
+                    ..Transform::IDENTITY
+                },
+            );
+            scene.node_mut(moon).mesh = Some(cube);
+            scene.node_mut(moon).material = Some(*material);
         }
 
-        for val_layer in VALIDATION_LAYERS {
-            if !available_layers.contains(&val_layer) {
-                return false;
-            }
+        // lights shine along their node's -Z axis
+        let sun = scene.add_node(
+            None,
+            "sun",
+            Transform {
+                rotation: glam::Quat::from_rotation_arc(-glam::Vec3::Z, -SUN_DIRECTION.normalize()),
+                ..Transform::IDENTITY
+            },
+        );
+        scene.node_mut(sun).light = Some(Light::directional(glam::Vec3::new(1., 0.95, 0.85), 3.));
+
+        let lamp = scene.add_node(
+            None,
+            "lamp",
+            Transform::from_translation(glam::Vec3::new(-1.5, 1., 1.5)),
+        );
+        scene.node_mut(lamp).light =
+            Some(Light::point(glam::Vec3::new(1., 0.6, 0.3), 4., Some(8.)));
+
+        scene.merge_meshes(uploads);
+        scene
+    }
+
+    /// Moves the scene's animated nodes to where they are at the current time.
+    fn animate_scene(&mut self) {
+        let time = self.started.elapsed().as_secs_f32();
+
+        if let Some(spinner) = self.scene.find_node("spinner") {
+            self.scene.node_mut(spinner).transform.rotation = glam::Quat::from_rotation_y(time);
         }
 
-        true
+        self.scene.update_transforms();
     }
 
-    pub fn create_framebuffers(
-        device: &ash::Device,
-        swapchain_image_views: &[vk::ImageView],
-        render_pass: vk::RenderPass,
-        swapchain_extent: vk::Extent2D,
-    ) -> Vec<vk::Framebuffer> {
-        swapchain_image_views
-            .iter()
-            .map(|iv| {
-                let framebuffer_info = vk::FramebufferCreateInfo {
-                    s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
-                    p_next: std::ptr::null(),
-                    flags: vk::FramebufferCreateFlags::empty(),
-                    render_pass,
-                    attachment_count: 1,
-                    p_attachments: iv,
-                    width: swapchain_extent.width,
-                    height: swapchain_extent.height,
-                    layers: 1,
-                };
+    /// Layout of the per frame set: the camera uniform buffer, the light storage buffer, the
+    /// shadow uniform buffer, and the shadow map and the sampler it is compared with.
+    fn create_frame_set_layout(device: &Arc<Device>) -> DescriptorSetLayout {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(3)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(4)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
 
-                unsafe {
-                    device
-                        .create_framebuffer(&framebuffer_info, None)
-                        .expect("failed to create framebuffer!")
-                }
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+        let layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .expect("failed to create descriptor set layout!")
+        };
+
+        DescriptorSetLayout::from_raw(device, layout)
+    }
+
+    /// Layout of a set of `textures` sampled images followed by a sampler for each of them, such
+    /// as the per material set with the base color, metallic-roughness, normal, occlusion and
+    /// emissive textures, in that order.
+    fn create_texture_set_layout(device: &Arc<Device>, textures: u32) -> DescriptorSetLayout {
+        let bindings = (0..textures * 2)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(texture_descriptor_type(binding, textures))
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build()
             })
-            .collect()
+            .collect::<Vec<_>>();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+
+        let layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .expect("failed to create descriptor set layout!")
+        };
+
+        DescriptorSetLayout::from_raw(device, layout)
     }
 
-    pub fn create_sync_objects(
+    /// Points the bindings of a set with [`Self::create_texture_set_layout`]'s layout at the
+    /// images and samplers of `image_infos`.
+    fn write_texture_set(
         device: &ash::Device,
-        swapchain_images: &[vk::Image],
-    ) -> (
-        Vec<vk::Semaphore>,
-        Vec<vk::Semaphore>,
-        Vec<vk::Fence>,
-        Vec<vk::Fence>,
+        set: vk::DescriptorSet,
+        image_infos: &[vk::DescriptorImageInfo],
     ) {
-        let semaphore_info = vk::SemaphoreCreateInfo {
-            s_type: vk::StructureType::SEMAPHORE_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::SemaphoreCreateFlags::empty(),
+        let count = image_infos.len() as u32;
+
+        // the image view is ignored by sampler writes and the sampler by image writes, so the
+        // same info serves for both bindings of a texture
+        let writes = (0..count * 2)
+            .map(|binding| {
+                let image_info = &image_infos[(binding % count) as usize];
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .descriptor_type(texture_descriptor_type(binding, count))
+                    .image_info(std::slice::from_ref(image_info))
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// Allocates `count` sets of `textures` textures each, with `layout`.
+    fn allocate_texture_sets(
+        device: &Arc<Device>,
+        layout: &DescriptorSetLayout,
+        textures: u32,
+        count: usize,
+    ) -> (DescriptorPool, Vec<vk::DescriptorSet>) {
+        let pool_sizes = [
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::SAMPLER,
+        ]
+        .map(|ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: count as u32 * textures,
+        });
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(count as u32)
+            .pool_sizes(&pool_sizes);
+
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .expect("failed to create descriptor pool!")
         };
+        let pool = DescriptorPool::from_raw(device, pool);
 
-        let fence_info = vk::FenceCreateInfo {
-            s_type: vk::StructureType::FENCE_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::FenceCreateFlags::SIGNALED,
+        let layouts = vec![layout.handle(); count];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool.handle())
+            .set_layouts(&layouts);
+
+        let descriptor_sets = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .expect("failed to allocate descriptor sets!")
         };
 
-        let mut image_available_semaphores = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
-        let mut render_finished_semaphores = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
-        let mut in_flight_fences = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
-        let in_flight_images = vec![vk::Fence::null(); swapchain_images.len()];
+        (pool, descriptor_sets)
+    }
 
-        for i in 0..MAX_FRAMES_IN_FLIGHT {
-            image_available_semaphores.push(unsafe {
-                device
-                    .create_semaphore(&semaphore_info, None)
-                    .expect("failed to create semaphores for a frame!")
-            });
-            render_finished_semaphores.push(unsafe {
-                device
-                    .create_semaphore(&semaphore_info, None)
-                    .expect("failed to create semaphores for a frame!")
-            });
-            in_flight_fences.push(unsafe {
-                device
-                    .create_fence(&fence_info, None)
-                    .expect("failed to create fences for a frame!")
-            });
+    fn create_environment_descriptor_set(
+        device: &Arc<Device>,
+        layout: &DescriptorSetLayout,
+        environment: &Environment,
+    ) -> (DescriptorPool, vk::DescriptorSet) {
+        let (pool, sets) = Self::allocate_texture_sets(device, layout, ENVIRONMENT_TEXTURES, 1);
+        let image_infos = environment.textures().map(Texture::descriptor);
+        Self::write_texture_set(device, sets[0], &image_infos);
+        (pool, sets[0])
+    }
+
+    /// Allocates a descriptor set for each of the scene's materials, in the order of their ids.
+    fn create_material_descriptor_sets(
+        device: &Arc<Device>,
+        layout: &DescriptorSetLayout,
+        scene: &Scene,
+        fallback: &FallbackTextures,
+    ) -> (DescriptorPool, Vec<vk::DescriptorSet>) {
+        let materials = scene.materials();
+        let (pool, descriptor_sets) =
+            Self::allocate_texture_sets(device, layout, MATERIAL_TEXTURES, materials.len());
+
+        for (&set, material) in descriptor_sets.iter().zip(materials) {
+            let textures = material
+                .textures()
+                .iter()
+                .enumerate()
+                .map(|(binding, texture)| match texture {
+                    Some(texture) => scene.texture(*texture),
+                    None if binding == 2 => &fallback.flat_normal,
+                    None => &fallback.white,
+                })
+                .map(Texture::descriptor)
+                .collect::<Vec<_>>();
+
+            Self::write_texture_set(device, set, &textures);
         }
 
-        (
-            image_available_semaphores,
-            render_finished_semaphores,
-            in_flight_fences,
-            in_flight_images,
-        )
+        (pool, descriptor_sets)
     }
 
-    pub fn create_command_buffers(
-        command_pool: vk::CommandPool,
-        swapchain_framebuffers: &[vk::Framebuffer],
-        device: &ash::Device,
-        render_pass: vk::RenderPass,
-        swapchain_extent: vk::Extent2D,
-        graphics_pipeline: vk::Pipeline,
-    ) -> Vec<vk::CommandBuffer> {
-        let alloc_info = vk::CommandBufferAllocateInfo {
-            s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
-            p_next: std::ptr::null(),
-            command_pool,
-            level: vk::CommandBufferLevel::PRIMARY,
-            command_buffer_count: swapchain_framebuffers.len() as u32,
-        };
+    /// Allocates a descriptor set pointing at each triple of `camera_buffers`, `light_buffers`
+    /// and `shadow_buffers`, and at `shadow_map`.
+    fn create_frame_descriptor_sets(
+        device: &Arc<Device>,
+        layout: &DescriptorSetLayout,
+        camera_buffers: &[Buffer],
+        light_buffers: &[Buffer],
+        shadow_buffers: &[Buffer],
+        shadow_map: &ShadowMap,
+    ) -> (DescriptorPool, Vec<vk::DescriptorSet>) {
+        let count = camera_buffers.len() as u32;
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: count * 2,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: count,
+            },
+        ];
 
-        let command_buffers = unsafe {
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(count)
+            .pool_sizes(&pool_sizes);
+
+        let pool = unsafe {
             device
-                .allocate_command_buffers(&alloc_info)
-                .expect("failed to allocate command buffers!")
+                .create_descriptor_pool(&pool_info, None)
+                .expect("failed to create descriptor pool!")
         };
+        let pool = DescriptorPool::from_raw(device, pool);
 
-        let begin_info = vk::CommandBufferBeginInfo {
-            s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::CommandBufferUsageFlags::empty(),
-            p_inheritance_info: std::ptr::null(),
-        };
+        let layouts = vec![layout.handle(); count as usize];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool.handle())
+            .set_layouts(&layouts);
 
-        let clear_color = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0., 0., 0., 1.],
-            },
+        let descriptor_sets = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .expect("failed to allocate descriptor sets!")
         };
 
-        let render_area = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain_extent,
+        let shadow_map_info = shadow_map.descriptor();
+        for (i, &set) in descriptor_sets.iter().enumerate() {
+            let buffer_info = |buffer: &Buffer| vk::DescriptorBufferInfo {
+                buffer: buffer.handle(),
+                offset: 0,
+                range: buffer.size(),
+            };
+            let camera_info = buffer_info(&camera_buffers[i]);
+            let light_info = buffer_info(&light_buffers[i]);
+            let shadow_info = buffer_info(&shadow_buffers[i]);
+
+            let writes = [
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(std::slice::from_ref(&camera_info))
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(std::slice::from_ref(&light_info))
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(2)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .buffer_info(std::slice::from_ref(&shadow_info))
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(3)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(std::slice::from_ref(&shadow_map_info))
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(4)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(std::slice::from_ref(&shadow_map_info))
+                    .build(),
+            ];
+
+            unsafe { device.update_descriptor_sets(&writes, &[]) };
+        }
+
+        (pool, descriptor_sets)
+    }
+
+    pub fn init_window(event_loop: &EventLoop<()>, title: &str) -> Window {
+        WindowBuilder::new()
+            .with_inner_size(winit::dpi::LogicalSize::new(WIDTH, HEIGHT))
+            .with_title(title)
+            .build(event_loop)
+            .expect("failed to create window")
+    }
+
+    fn create_shadow_pipeline(
+        device: &Arc<Device>,
+        frame_set_layout: &DescriptorSetLayout,
+    ) -> ShadowPipeline {
+        let targets = RenderPassDesc::compatible(&[], Some(SHADOW_FORMAT));
+        let pipeline_layout = Self::create_pipeline_layout::<ShadowPushConstants>(
+            device,
+            &[frame_set_layout.handle()],
+            vk::ShaderStageFlags::VERTEX,
+        );
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            &targets,
+            &pipeline_layout,
+            &PipelineDesc::SHADOW,
+        );
+
+        ShadowPipeline {
+            pipeline,
+            pipeline_layout,
+        }
+    }
+
+    fn create_post_pipelines(device: &Arc<Device>) -> PostPipelines {
+        let set_layout = Self::create_texture_set_layout(device, 2);
+        let pipeline_layout = Self::create_pipeline_layout::<PostPushConstants>(
+            device,
+            &[set_layout.handle()],
+            vk::ShaderStageFlags::FRAGMENT,
+        );
+        let targets = RenderPassDesc::compatible(&[HDR_FORMAT], None);
+        let pipeline = |fragment_shader| {
+            let desc = PipelineDesc {
+                fragment_shader: Some(fragment_shader),
+                ..PipelineDesc::FULL_SCREEN
+            };
+            Self::create_graphics_pipeline(device, &targets, &pipeline_layout, &desc)
         };
 
-        for i in 0..command_buffers.len() {
-            unsafe {
-                device
-                    .begin_command_buffer(command_buffers[i], &begin_info)
-                    .expect("failed to begin recording command buffer!");
+        PostPipelines {
+            bloom_threshold: pipeline("shaders/bloom_threshold_frag.spv"),
+            blur: pipeline("shaders/blur_frag.spv"),
+            tonemap: pipeline("shaders/tonemap_frag.spv"),
+            color_grading: pipeline("shaders/grading_frag.spv"),
+            fxaa: pipeline("shaders/fxaa_frag.spv"),
+            gamma: pipeline("shaders/gamma_frag.spv"),
+            sampler: SamplerDesc {
+                mag_filter: vk::Filter::LINEAR,
+                min_filter: vk::Filter::LINEAR,
+                address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
             }
+            .create(device),
+            pipeline_layout,
+            set_layout,
+        }
+    }
 
-            let render_pass_info = vk::RenderPassBeginInfo {
-                s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
-                p_next: std::ptr::null(),
-                render_pass,
-                framebuffer: swapchain_framebuffers[i],
-                render_area,
-                clear_value_count: 1,
-                p_clear_values: &clear_color,
-            };
+    pub fn setup_debug_messenger(
+        instance: &Arc<Instance>,
+        validation: ValidationConfig,
+        debug_callback: &Arc<DebugCallback>,
+    ) -> Option<DebugMessenger> {
+        // only the validation layers have anything to say
+        if !validation.enabled {
+            return None;
+        }
 
-            unsafe {
-                device.cmd_begin_render_pass(
-                    command_buffers[i],
-                    &render_pass_info,
-                    vk::SubpassContents::INLINE,
-                );
+        Some(DebugMessenger::new(instance, Arc::clone(debug_callback)))
+    }
 
-                device.cmd_bind_pipeline(
-                    command_buffers[i],
-                    vk::PipelineBindPoint::GRAPHICS,
-                    graphics_pipeline,
-                );
+    /// Creates the binary semaphores acquiring and presenting swapchain images wait for, which
+    /// presentation requires, and tracks the frames in flight with `timeline` if given, or with
+    /// fences otherwise.
+    pub fn create_sync_objects(
+        device: &Arc<Device>,
+        swapchain_images: &[vk::Image],
+        timeline: Option<Rc<Timeline>>,
+    ) -> (Vec<Semaphore>, Vec<Semaphore>, FrameSync) {
+        let image_available_semaphores = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| Semaphore::new(device))
+            .collect();
+        let render_finished_semaphores = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| Semaphore::new(device))
+            .collect();
+        let frame_sync = FrameSync::new(
+            device,
+            MAX_FRAMES_IN_FLIGHT,
+            swapchain_images.len(),
+            timeline,
+        );
 
-                device.cmd_draw(command_buffers[i], 3, 1, 0, 0);
+        (
+            image_available_semaphores,
+            render_finished_semaphores,
+            frame_sync,
+        )
+    }
 
-                device.cmd_end_render_pass(command_buffers[i]);
+    /// Allocates one command buffer per frame in flight, recorded anew every frame.
+    pub fn create_command_buffers(
+        command_pool: &CommandPool,
+        device: &Device,
+    ) -> Vec<vk::CommandBuffer> {
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            p_next: std::ptr::null(),
+            command_pool: command_pool.handle(),
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: MAX_FRAMES_IN_FLIGHT as u32,
+        };
 
-                device
-                    .end_command_buffer(command_buffers[i])
-                    .expect("failed to record command buffer!");
-            }
+        unsafe {
+            device
+                .allocate_command_buffers(&alloc_info)
+                .expect("failed to allocate command buffers!")
         }
-
-        command_buffers
     }
 
-    pub fn create_surface(
-        entry: &ash::Entry,
-        instance: &ash::Instance,
-        window: &Window,
-    ) -> (vk::SurfaceKHR, khr::Surface) {
+    pub fn create_surface(instance: &Arc<Instance>, window: &Window) -> Arc<Surface> {
+        let entry = instance.entry();
+
         let surface = unsafe {
-            ash_window::create_surface(entry, instance, window, None)
+            ash_window::create_surface(entry, &***instance, window, None)
                 .expect("failed to create window surface!")
         };
 
-        let surface_loader = khr::Surface::new(entry, instance);
+        let surface_loader = khr::Surface::new(entry, &***instance);
 
-        (surface, surface_loader)
+        Arc::new(Surface::new(instance, surface_loader, surface))
     }
 
     pub fn pick_physical_device(
@@ -624,19 +2255,21 @@ impl VkApp {
     }
 
     pub fn create_command_pool(
-        instance: &ash::Instance,
-        device: &ash::Device,
-        physical_device: vk::PhysicalDevice,
+        device: &Arc<Device>,
         surface_loader: &khr::Surface,
         surface: &vk::SurfaceKHR,
-    ) -> vk::CommandPool {
-        let queue_family_indices =
-            Self::find_queue_family(instance, physical_device, surface_loader, surface);
+    ) -> CommandPool {
+        let queue_family_indices = Self::find_queue_family(
+            device.instance(),
+            device.physical_device(),
+            surface_loader,
+            surface,
+        );
 
         let pool_info = vk::CommandPoolCreateInfo {
             s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
             p_next: std::ptr::null(),
-            flags: vk::CommandPoolCreateFlags::empty(),
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
             queue_family_index: queue_family_indices.graphics_family.unwrap(),
         };
 
@@ -646,7 +2279,7 @@ impl VkApp {
                 .expect("failed to create command_pool!")
         };
 
-        command_pool
+        CommandPool::from_raw(device, command_pool)
     }
 
     pub fn is_device_suitable(
@@ -705,16 +2338,27 @@ impl VkApp {
     ) -> QueueFamilyIndices {
         let mut indices = QueueFamilyIndices {
             graphics_family: None,
+            graphics_queue_count: 0,
             present_family: None,
+            transfer_family: None,
         };
 
         let queue_families_properties =
             unsafe { instance.get_physical_device_queue_family_properties(device) };
+        indices.transfer_family = (0u32..)
+            .zip(queue_families_properties.iter())
+            .find(|(_, qf)| {
+                qf.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !qf
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|(i, _)| i);
 
-        let mut i = 0;
-        for qf in queue_families_properties.iter() {
+        for (i, qf) in (0u32..).zip(queue_families_properties.iter()) {
             if qf.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                indices.graphics_family = Some(i as u32);
+                indices.graphics_family = Some(i);
+                indices.graphics_queue_count = qf.queue_count;
             }
 
             if unsafe {
@@ -728,26 +2372,33 @@ impl VkApp {
             if indices.is_complete() {
                 break;
             }
-
-            i += 1;
         }
 
         indices
     }
 
     pub fn create_logical_device(
-        instance: &ash::Instance,
+        instance: &Arc<Instance>,
         physical_device: vk::PhysicalDevice,
         surface_loader: &khr::Surface,
         surface: &vk::SurfaceKHR,
-    ) -> (ash::Device, vk::Queue, vk::Queue) {
+        validation: ValidationConfig,
+    ) -> (Arc<Device>, vk::Queue, vk::Queue, Option<(u32, vk::Queue)>) {
         let indices = Self::find_queue_family(instance, physical_device, surface_loader, surface);
 
         let mut unique_queue_families = std::collections::HashSet::new();
         unique_queue_families.insert(indices.graphics_family.unwrap());
         unique_queue_families.insert(indices.present_family.unwrap());
+        unique_queue_families.extend(indices.transfer_family);
 
-        let queue_priority = &1_f32 as *const f32;
+        let queue_priorities = [1_f32; 2];
+        // a transfer only queue takes the uploads, or else a second graphics queue where there
+        // is one
+        let graphics_queue_count = if indices.transfer_family.is_some() {
+            1
+        } else {
+            indices.graphics_queue_count.min(2)
+        };
 
         let queue_create_infos = unique_queue_families
             .iter()
@@ -756,12 +2407,34 @@ impl VkApp {
                 p_next: std::ptr::null(),
                 flags: vk::DeviceQueueCreateFlags::empty(),
                 queue_family_index: *qf,
-                queue_count: 1,
-                p_queue_priorities: queue_priority,
+                queue_count: if Some(*qf) == indices.graphics_family {
+                    graphics_queue_count
+                } else {
+                    1
+                },
+                p_queue_priorities: queue_priorities.as_ptr(),
             })
             .collect::<Vec<_>>();
 
-        let device_features = vk::PhysicalDeviceFeatures::default(); // defaults to all 0 (false)
+        // defaults to all 0 (false)
+        let mut device_features = vk::PhysicalDeviceFeatures::default();
+
+        // gpu-assisted validation instruments shaders with buffer writes
+        if validation.enabled && validation.gpu_assisted {
+            let supported = unsafe { instance.get_physical_device_features(physical_device) };
+            device_features.vertex_pipeline_stores_and_atomics =
+                supported.vertex_pipeline_stores_and_atomics;
+            device_features.fragment_stores_and_atomics = supported.fragment_stores_and_atomics;
+        }
+
+        // the profiler counts shader invocations per pass, and occlusion queries samples exactly,
+        // where they can
+        let supported = unsafe { instance.get_physical_device_features(physical_device) };
+        device_features.pipeline_statistics_query = supported.pipeline_statistics_query;
+        device_features.occlusion_query_precise = supported.occlusion_query_precise;
+        // indirect draws draw every batch of a material with one command each
+        device_features.multi_draw_indirect = supported.multi_draw_indirect;
+        device_features.draw_indirect_first_instance = supported.draw_indirect_first_instance;
 
         // let layer_names = get_validation_layer_names_as_ptrs();
 
@@ -771,41 +2444,50 @@ impl VkApp {
             .collect::<Vec<_>>();
         let layer_names = layer_names.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
 
+        // dynamic rendering and synchronization2 replace render passes and pipeline barriers
+        // where the device supports them
+        let extensions = ExtensionSupport::query(instance, physical_device);
+        log::info!("optional device extensions: {:?}", extensions);
+
         let device_extensions = DEVICE_EXTENSIONS
             .iter()
-            .map(|x| CString::new(*x).unwrap())
+            .copied()
+            .chain(extensions.extension_names())
+            .map(|x| CString::new(x).unwrap())
             .collect::<Vec<_>>();
         let device_extensions = device_extensions
             .iter()
             .map(|x| x.as_ptr())
             .collect::<Vec<*const i8>>();
 
-        let create_info = vk::DeviceCreateInfo {
-            s_type: vk::StructureType::DEVICE_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::DeviceCreateFlags::empty(),
-            queue_create_info_count: queue_create_infos.len() as u32,
-            p_queue_create_infos: queue_create_infos.as_ptr(),
-            enabled_layer_count: if ENABLE_VALIDATION_LAYERS {
-                layer_names.len() as u32
-            } else {
-                0
-            },
-            pp_enabled_layer_names: if ENABLE_VALIDATION_LAYERS {
-                layer_names.as_ptr()
-            } else {
-                std::ptr::null()
-            },
-            enabled_extension_count: device_extensions.len() as u32,
-            pp_enabled_extension_names: device_extensions.as_ptr(),
-            p_enabled_features: &device_features,
-        };
+        let logical_device = extensions.with_features(|features| {
+            let create_info = vk::DeviceCreateInfo {
+                s_type: vk::StructureType::DEVICE_CREATE_INFO,
+                p_next: features,
+                flags: vk::DeviceCreateFlags::empty(),
+                queue_create_info_count: queue_create_infos.len() as u32,
+                p_queue_create_infos: queue_create_infos.as_ptr(),
+                enabled_layer_count: if validation.enabled {
+                    layer_names.len() as u32
+                } else {
+                    0
+                },
+                pp_enabled_layer_names: if validation.enabled {
+                    layer_names.as_ptr()
+                } else {
+                    std::ptr::null()
+                },
+                enabled_extension_count: device_extensions.len() as u32,
+                pp_enabled_extension_names: device_extensions.as_ptr(),
+                p_enabled_features: &device_features,
+            };
 
-        let logical_device = unsafe {
-            instance
-                .create_device(physical_device, &create_info, None)
-                .expect("failed to create logical device!")
-        };
+            unsafe {
+                instance
+                    .create_device(physical_device, &create_info, None)
+                    .expect("failed to create logical device!")
+            }
+        });
 
         let graphics_queue =
             unsafe { logical_device.get_device_queue(indices.graphics_family.unwrap(), 0) };
@@ -813,27 +2495,46 @@ impl VkApp {
         let present_queue =
             unsafe { logical_device.get_device_queue(indices.present_family.unwrap(), 0) };
 
-        (logical_device, graphics_queue, present_queue)
+        let upload_queue = match indices.transfer_family {
+            Some(family) => Some((family, unsafe {
+                logical_device.get_device_queue(family, 0)
+            })),
+            None => (graphics_queue_count > 1).then(|| {
+                let family = indices.graphics_family.unwrap();
+                (family, unsafe {
+                    logical_device.get_device_queue(family, 1)
+                })
+            }),
+        };
+
+        (
+            Device::new(
+                instance,
+                physical_device,
+                logical_device,
+                extensions,
+                device_features,
+            ),
+            graphics_queue,
+            present_queue,
+            upload_queue,
+        )
     }
 
     pub fn create_swapchain(
-        instance: &ash::Instance,
-        device: &ash::Device,
-        physical_device: vk::PhysicalDevice,
-        surface_loader: &khr::Surface,
-        surface: &vk::SurfaceKHR,
+        device: &Arc<Device>,
+        surface: &Arc<Surface>,
         window: &Window,
-    ) -> (
-        khr::Swapchain,
-        vk::SwapchainKHR,
-        Vec<vk::Image>,
-        vk::Format,
-        vk::Extent2D,
-    ) {
+        indices: QueueFamilyIndices,
+    ) -> (Swapchain, Vec<vk::Image>, vk::Format, vk::Extent2D) {
+        let instance = device.instance();
+        let physical_device = device.physical_device();
+        let surface_loader = surface.loader();
+
         let swapchain_support = SwapchainSupportDetails::query_swapchain_support(
             physical_device,
             surface_loader,
-            *surface,
+            surface.handle(),
         );
         let surface_format =
             SwapchainSupportDetails::choose_swap_surface_format(&swapchain_support.formats);
@@ -848,21 +2549,24 @@ impl VkApp {
             image_count = swapchain_support.capabilities.max_image_count;
         }
 
+        // the post-processed HDR target is blitted onto the swapchain image; color attachment
+        // usage is the one every surface supports, and lets the image views be created
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST;
+
         let mut create_info = vk::SwapchainCreateInfoKHR::builder()
-            .surface(*surface)
+            .surface(surface.handle())
             .min_image_count(image_count)
             .image_format(surface_format.format)
             .image_color_space(surface_format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(image_usage)
             .pre_transform(swapchain_support.capabilities.current_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(present_mode)
             .clipped(true)
             .old_swapchain(vk::SwapchainKHR::null());
 
-        let indices = Self::find_queue_family(instance, physical_device, surface_loader, surface);
         let queue_family_indices = [
             indices.graphics_family.unwrap(),
             indices.present_family.unwrap(),
@@ -876,7 +2580,7 @@ impl VkApp {
             create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE)
         };
 
-        let swapchain_loader = khr::Swapchain::new(instance, device);
+        let swapchain_loader = khr::Swapchain::new(&***instance, &***device);
         let swapchain = unsafe {
             swapchain_loader
                 .create_swapchain(&create_info, None)
@@ -890,72 +2594,70 @@ impl VkApp {
         };
 
         (
-            swapchain_loader,
-            swapchain,
+            Swapchain::from_raw(device, surface, swapchain_loader, swapchain),
             swapchain_images,
             surface_format.format,
             extent,
         )
     }
 
-    pub fn get_required_extensions(window: &Window) -> Vec<*const i8> {
+    /// The extensions the window's surface needs, plus debug utils if `debug_utils` and the
+    /// validation features if any are enabled.
+    pub fn get_required_extensions(
+        window: &Window,
+        validation: ValidationConfig,
+        debug_utils: bool,
+    ) -> Vec<*const i8> {
         let mut extension_names = ash_window::enumerate_required_extensions(window).unwrap();
 
-        if ENABLE_VALIDATION_LAYERS {
+        if debug_utils {
             extension_names.push(DebugUtils::name());
         }
 
-        let extension_names_ptrs = extension_names
-            .iter()
-            .map(|x| {
-                println!("\t{}", x.to_str().unwrap());
-                x.as_ptr()
-            })
-            .collect::<Vec<*const i8>>();
+        if validation.has_features() {
+            extension_names.push(vk::ExtValidationFeaturesFn::name());
+        }
 
-        extension_names_ptrs
+        log::debug!("required instance extensions: {:?}", extension_names);
+
+        extension_names
+            .iter()
+            .map(|x| x.as_ptr())
+            .collect::<Vec<*const i8>>()
     }
 
     pub fn create_image_views(
         swapchain_images: &[vk::Image],
         format: vk::Format,
-        device: &ash::Device,
-    ) -> Vec<vk::ImageView> {
+        device: &Arc<Device>,
+    ) -> Vec<ImageView> {
         swapchain_images
             .iter()
             .map(|image| {
-                let create_info = vk::ImageViewCreateInfo::builder()
-                    .image(*image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(format)
-                    .components(vk::ComponentMapping {
-                        r: vk::ComponentSwizzle::IDENTITY,
-                        g: vk::ComponentSwizzle::IDENTITY,
-                        b: vk::ComponentSwizzle::IDENTITY,
-                        a: vk::ComponentSwizzle::IDENTITY,
-                    })
-                    .subresource_range(vk::ImageSubresourceRange {
+                ImageView::new(
+                    device,
+                    *image,
+                    vk::ImageViewType::TYPE_2D,
+                    format,
+                    vk::ImageSubresourceRange {
                         aspect_mask: vk::ImageAspectFlags::COLOR,
                         base_mip_level: 0,
                         level_count: 1,
                         base_array_layer: 0,
                         layer_count: 1,
-                    });
-
-                unsafe {
-                    device
-                        .create_image_view(&create_info, None)
-                        .expect("failed to create image view!")
-                }
+                    },
+                )
             })
             .collect::<Vec<_>>()
     }
 
-    pub fn create_instance(entry: &ash::Entry, window: &Window) -> ash::Instance {
-        if ENABLE_VALIDATION_LAYERS && !Self::check_validation_layer_support(entry) {
-            panic!("validation layers requested but not available!");
-        }
-
+    pub fn create_instance(
+        entry: ash::Entry,
+        window: &Window,
+        validation: ValidationConfig,
+        debug_utils: bool,
+        debug_callback: &Arc<DebugCallback>,
+    ) -> Arc<Instance> {
         let appname = CString::new("Hello triangle!").unwrap();
         let enginename = CString::new("No Engine.").unwrap();
         let appinfo = vk::ApplicationInfo {
@@ -968,7 +2670,23 @@ impl VkApp {
             api_version: vk::API_VERSION_1_2,
         };
 
-        let debug_utils_create_info = populate_debug_messenger_create_info();
+        let enabled_validation_features = validation.enabled_features();
+        let validation_features = vk::ValidationFeaturesEXT {
+            s_type: vk::StructureType::VALIDATION_FEATURES_EXT,
+            p_next: std::ptr::null(),
+            enabled_validation_feature_count: enabled_validation_features.len() as u32,
+            p_enabled_validation_features: enabled_validation_features.as_ptr(),
+            disabled_validation_feature_count: 0,
+            p_disabled_validation_features: std::ptr::null(),
+        };
+
+        // chained into the instance create info so that messages from vkCreateInstance and
+        // vkDestroyInstance, which happen without a messenger, are reported too
+        let mut debug_utils_create_info = debug_callback.messenger_create_info();
+        if validation.has_features() {
+            debug_utils_create_info.p_next =
+                &validation_features as *const vk::ValidationFeaturesEXT as *const c_void;
+        }
 
         let layer_names = VALIDATION_LAYERS
             .iter()
@@ -976,12 +2694,11 @@ impl VkApp {
             .collect::<Vec<_>>();
         let layer_names = layer_names.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
 
-        println!("Available extensions");
-        let extension_names = Self::get_required_extensions(window);
+        let extension_names = Self::get_required_extensions(window, validation, debug_utils);
 
         let createinfo = vk::InstanceCreateInfo {
             s_type: vk::StructureType::INSTANCE_CREATE_INFO,
-            p_next: if ENABLE_VALIDATION_LAYERS {
+            p_next: if validation.enabled {
                 &debug_utils_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT
                     as *const c_void
             } else {
@@ -989,12 +2706,12 @@ impl VkApp {
             },
             flags: vk::InstanceCreateFlags::empty(),
             p_application_info: &appinfo,
-            enabled_layer_count: if ENABLE_VALIDATION_LAYERS {
+            enabled_layer_count: if validation.enabled {
                 layer_names.len() as u32
             } else {
                 0
             },
-            pp_enabled_layer_names: if ENABLE_VALIDATION_LAYERS {
+            pp_enabled_layer_names: if validation.enabled {
                 layer_names.as_ptr()
             } else {
                 std::ptr::null()
@@ -1009,35 +2726,55 @@ impl VkApp {
                 .expect("failed to create instance!")
         };
 
-        instance
+        Instance::new(
+            entry,
+            instance,
+            validation.enabled.then(|| Arc::clone(debug_callback)),
+        )
     }
 
-    pub fn create_shader_module(code: Vec<u8>, device: &ash::Device) -> vk::ShaderModule {
-        let create_info = vk::ShaderModuleCreateInfo {
-            s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+    /// Layout with `set_layouts` and a push constant range holding a `T`, used by `stages`. The
+    /// mesh pipelines all share one, so descriptor sets stay bound when switching between them.
+    pub fn create_pipeline_layout<T>(
+        device: &Arc<Device>,
+        set_layouts: &[vk::DescriptorSetLayout],
+        stages: vk::ShaderStageFlags,
+    ) -> PipelineLayout {
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: stages,
+            offset: 0,
+            size: std::mem::size_of::<T>() as u32,
+        };
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
             p_next: std::ptr::null(),
-            flags: vk::ShaderModuleCreateFlags::empty(),
-            code_size: code.len(),
-            p_code: code.as_ptr() as *const u32,
+            flags: vk::PipelineLayoutCreateFlags::empty(),
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: 1,
+            p_push_constant_ranges: &push_constant_range,
         };
 
-        unsafe {
+        let pipeline_layout = unsafe {
             device
-                .create_shader_module(&create_info, None)
-                .expect("failed to create shader module!")
-        }
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .expect("failed to create pipeline layout!")
+        };
+        PipelineLayout::from_raw(device, pipeline_layout)
     }
 
+    /// Creates a pipeline drawing into attachments of the formats in `targets`.
     pub fn create_graphics_pipeline(
-        device: &ash::Device,
-        swapchain_extent: vk::Extent2D,
-        render_pass: vk::RenderPass,
-    ) -> (vk::PipelineLayout, vk::Pipeline) {
-        let vert_shader_code = Self::read_spv("shaders/vert.spv");
-        let frag_shader_code = Self::read_spv("shaders/frag.spv");
-
-        let vert_shader_module = Self::create_shader_module(vert_shader_code, device);
-        let frag_shader_module = Self::create_shader_module(frag_shader_code, device);
+        device: &Arc<Device>,
+        targets: &RenderPassDesc,
+        pipeline_layout: &PipelineLayout,
+        desc: &PipelineDesc,
+    ) -> Pipeline {
+        let vert_shader_module = ShaderModule::load(device, desc.vertex_shader);
+        let frag_shader_module = desc
+            .fragment_shader
+            .map(|path| ShaderModule::load(device, path));
 
         let p_name = CString::new("main").unwrap();
 
@@ -1046,31 +2783,63 @@ impl VkApp {
             p_next: std::ptr::null(),
             flags: vk::PipelineShaderStageCreateFlags::empty(),
             stage: vk::ShaderStageFlags::VERTEX,
-            module: vert_shader_module,
+            module: vert_shader_module.handle(),
             p_name: p_name.as_ptr(),
             p_specialization_info: std::ptr::null(),
         };
 
-        let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo {
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            stage: vk::ShaderStageFlags::FRAGMENT,
-            module: frag_shader_module,
-            p_name: p_name.as_ptr(),
-            p_specialization_info: std::ptr::null(),
-        };
+        let frag_shader_stage_info =
+            frag_shader_module
+                .as_ref()
+                .map(|module| vk::PipelineShaderStageCreateInfo {
+                    s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                    p_next: std::ptr::null(),
+                    flags: vk::PipelineShaderStageCreateFlags::empty(),
+                    stage: vk::ShaderStageFlags::FRAGMENT,
+                    module: module.handle(),
+                    p_name: p_name.as_ptr(),
+                    p_specialization_info: std::ptr::null(),
+                });
+
+        let shader_stages = std::iter::once(vert_shader_stage_info)
+            .chain(frag_shader_stage_info)
+            .collect::<Vec<_>>();
 
-        let shader_stages = [vert_shader_stage_info, frag_shader_stage_info];
+        let (binding_descriptions, attribute_descriptions) = match desc.vertex_input {
+            VertexInput::None => (vec![], vec![]),
+            VertexInput::Mesh => (
+                vec![
+                    Vertex::binding_description(),
+                    InstanceData::binding_description(),
+                ],
+                Vertex::attribute_descriptions()
+                    .iter()
+                    .chain(&InstanceData::attribute_descriptions())
+                    .copied()
+                    .collect(),
+            ),
+            VertexInput::Ui => (
+                vec![ui::vertex_binding_description()],
+                ui::vertex_attribute_descriptions().to_vec(),
+            ),
+            VertexInput::Text => (
+                vec![TextVertex::binding_description()],
+                TextVertex::attribute_descriptions().to_vec(),
+            ),
+            VertexInput::Sprite => (
+                vec![SpriteInstance::binding_description()],
+                SpriteInstance::attribute_descriptions().to_vec(),
+            ),
+        };
 
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineVertexInputStateCreateFlags::empty(),
-            vertex_binding_description_count: 0,
-            p_vertex_binding_descriptions: std::ptr::null(),
-            vertex_attribute_description_count: 0,
-            p_vertex_attribute_descriptions: std::ptr::null(),
+            vertex_binding_description_count: binding_descriptions.len() as u32,
+            p_vertex_binding_descriptions: binding_descriptions.as_ptr(),
+            vertex_attribute_description_count: attribute_descriptions.len() as u32,
+            p_vertex_attribute_descriptions: attribute_descriptions.as_ptr(),
         };
 
         let input_assembly = vk::PipelineInputAssemblyStateCreateInfo {
@@ -1081,28 +2850,15 @@ impl VkApp {
             primitive_restart_enable: vk::FALSE,
         };
 
-        let viewport = vk::Viewport {
-            x: 0.,
-            y: 0.,
-            width: swapchain_extent.width as f32,
-            height: swapchain_extent.height as f32,
-            min_depth: 0.,
-            max_depth: 1.,
-        };
-
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: swapchain_extent,
-        };
-
+        // the viewport and scissor are dynamic so windows of any size can share the pipeline
         let viewport_state = vk::PipelineViewportStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
             p_next: std::ptr::null(),
             flags: vk::PipelineViewportStateCreateFlags::empty(),
             viewport_count: 1,
-            p_viewports: &viewport,
+            p_viewports: std::ptr::null(),
             scissor_count: 1,
-            p_scissors: &scissor,
+            p_scissors: std::ptr::null(),
         };
 
         let rasterizer = vk::PipelineRasterizationStateCreateInfo {
@@ -1112,12 +2868,13 @@ impl VkApp {
             depth_clamp_enable: vk::TRUE,
             rasterizer_discard_enable: vk::FALSE,
             polygon_mode: vk::PolygonMode::FILL,
-            cull_mode: vk::CullModeFlags::BACK,
-            front_face: vk::FrontFace::CLOCKWISE,
-            depth_bias_enable: vk::FALSE,
-            depth_bias_constant_factor: 0.,
+            cull_mode: desc.cull_mode,
+            // meshes are wound counter-clockwise; the projection flips Y, which keeps it that way
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_bias_enable: desc.depth_bias as vk::Bool32,
+            depth_bias_constant_factor: 1.25,
             depth_bias_clamp: 0.,
-            depth_bias_slope_factor: 0.,
+            depth_bias_slope_factor: 1.75,
             line_width: 1.,
         };
 
@@ -1133,15 +2890,35 @@ impl VkApp {
             alpha_to_one_enable: vk::FALSE,
         };
 
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
-            blend_enable: vk::FALSE,
-            src_color_blend_factor: vk::BlendFactor::ONE,
-            dst_color_blend_factor: vk::BlendFactor::ZERO,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ONE,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::all(),
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(desc.depth_write)
+            .depth_compare_op(desc.depth_compare_op)
+            .depth_bounds_test_enable(false)
+            .stencil_test_enable(false);
+
+        let color_blend_attachment = if desc.blend {
+            vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::TRUE,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_DST_ALPHA,
+                dst_alpha_blend_factor: vk::BlendFactor::ONE,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::all(),
+            }
+        } else {
+            vk::PipelineColorBlendAttachmentState {
+                blend_enable: vk::FALSE,
+                src_color_blend_factor: vk::BlendFactor::ONE,
+                dst_color_blend_factor: vk::BlendFactor::ZERO,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ONE,
+                dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::all(),
+            }
         };
 
         let color_blending = vk::PipelineColorBlendStateCreateInfo {
@@ -1150,12 +2927,12 @@ impl VkApp {
             flags: vk::PipelineColorBlendStateCreateFlags::empty(),
             logic_op_enable: vk::FALSE,
             logic_op: vk::LogicOp::COPY,
-            attachment_count: 1,
+            attachment_count: desc.fragment_shader.is_some() as u32,
             p_attachments: &color_blend_attachment,
             blend_constants: [0., 0., 0., 0.],
         };
 
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::LINE_WIDTH];
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
 
         let dynamic_state = vk::PipelineDynamicStateCreateInfo {
             s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
@@ -1165,27 +2942,31 @@ impl VkApp {
             p_dynamic_states: dynamic_states.as_ptr(),
         };
 
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
-            s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
-            p_next: std::ptr::null(),
-            flags: vk::PipelineLayoutCreateFlags::empty(),
-            set_layout_count: 0,
-            p_set_layouts: std::ptr::null(),
-            push_constant_range_count: 0,
-            p_push_constant_ranges: std::ptr::null(),
-        };
-
-        let pipeline_layout = unsafe {
-            device
-                .create_pipeline_layout(&pipeline_layout_info, None)
-                .expect("failed to create pipeline layout!")
+        // with dynamic rendering the attachment formats are given directly, otherwise by a render
+        // pass compatible with those the render graph begins, which is only needed until the
+        // pipeline is created
+        let color_formats = targets
+            .colors
+            .iter()
+            .map(|color| color.format)
+            .collect::<Vec<_>>();
+        let rendering_info = PipelineRenderingCreateInfoKHR::new(
+            &color_formats,
+            targets.depth.map(|depth| depth.format),
+        );
+        let render_pass = match device.dynamic_rendering() {
+            Some(_) => None,
+            None => Some(targets.create(device)),
         };
 
         let pipeline_info = vk::GraphicsPipelineCreateInfo {
             s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
-            p_next: std::ptr::null(),
+            p_next: match render_pass {
+                Some(_) => std::ptr::null(),
+                None => &rendering_info as *const _ as *const c_void,
+            },
             flags: vk::PipelineCreateFlags::empty(),
-            stage_count: 2,
+            stage_count: shader_stages.len() as u32,
             p_stages: shader_stages.as_ptr(),
             p_vertex_input_state: &vertex_input_info,
             p_input_assembly_state: &input_assembly,
@@ -1193,90 +2974,235 @@ impl VkApp {
             p_viewport_state: &viewport_state,
             p_rasterization_state: &rasterizer,
             p_multisample_state: &multisampling,
-            p_depth_stencil_state: std::ptr::null(),
+            p_depth_stencil_state: &*depth_stencil,
             p_color_blend_state: &color_blending,
-            p_dynamic_state: std::ptr::null(),
-            layout: pipeline_layout,
-            render_pass,
+            p_dynamic_state: &dynamic_state,
+            layout: pipeline_layout.handle(),
+            render_pass: render_pass
+                .as_ref()
+                .map_or(vk::RenderPass::null(), RenderPass::handle),
             subpass: 0,
             base_pipeline_handle: vk::Pipeline::null(),
             base_pipeline_index: -1,
         };
 
+        // the shader modules are dropped, and destroyed, once this returns or unwinds
         let graphics_pipeline = unsafe {
             device
                 .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
                 .expect("failed to create graphics pipeline!")
         };
 
-        unsafe {
-            device.destroy_shader_module(vert_shader_module, None);
-            device.destroy_shader_module(frag_shader_module, None);
-        }
-
-        (pipeline_layout, graphics_pipeline[0])
+        Pipeline::from_raw(device, graphics_pipeline[0])
     }
 
-    pub fn draw_frame(&mut self) {
-        unsafe {
-            self.device
-                .wait_for_fences(&[self.in_flight_fences[self.current_frame]], true, u64::MAX)
-                .expect("failed to wait for fences!");
+    pub fn draw_frame(&mut self, window_id: WindowId) {
+        if !self.windows.contains_key(&window_id) {
+            return;
         }
 
+        self.finish_loading_scene();
+        self.animate_scene();
+
+        let state = match self.windows.get_mut(&window_id) {
+            Some(state) => state,
+            None => return,
+        };
+
+        state.frame_sync.wait(state.current_frame);
+        state.deletions.flush(&state.frame_sync);
+        state.occlusion_queries.poll(&state.frame_sync);
+
         let (image_index, _) = unsafe {
-            self.swapchain_loader
+            state
+                .swapchain
+                .loader()
                 .acquire_next_image(
-                    self.swapchain,
+                    state.swapchain.handle(),
                     u64::MAX,
-                    self.image_available_semaphores[self.current_frame],
+                    state.image_available_semaphores[state.current_frame].handle(),
                     vk::Fence::null(),
                 )
                 .expect("failed to acquire next image!")
         };
         let image_index = image_index as usize;
 
-        if self.in_flight_images[image_index] != vk::Fence::null() {
-            unsafe {
-                self.device
-                    .wait_for_fences(&[self.in_flight_images[image_index]], true, u64::MAX)
-                    .expect("failed to wait for fences!");
+        state
+            .frame_sync
+            .acquire_image(image_index, state.current_frame);
+
+        let now = Instant::now();
+        let dt = now.duration_since(state.last_frame).as_secs_f32();
+        state.last_frame = now;
+        state.controller.update(&mut state.camera, dt);
+        state.frame_times.push(dt);
+
+        let device_info = &self.device_info;
+        let targets = [
+            ("Swapchain", state.swapchain_extent, state.swapchain_format),
+            ("Render target", state.render_extent, HDR_FORMAT),
+        ];
+        let frame_times = &state.frame_times;
+        let gpu_frame = state.profiler.as_ref().and_then(GpuProfiler::last_frame);
+        let post_effects = &mut state.post_effects;
+        let mut export_trace = false;
+        state.ui.run(
+            state.swapchain_extent,
+            self.started.elapsed().as_secs_f64(),
+            |ctx| {
+                debug_ui(
+                    ctx,
+                    device_info,
+                    frame_times,
+                    gpu_frame,
+                    &mut export_trace,
+                    &targets,
+                    post_effects,
+                )
+            },
+        );
+        if let (true, Some(profiler)) = (export_trace, &state.profiler) {
+            let path = format!("{}-gpu-trace.json", state.name.replace(' ', "-"));
+            match profiler.write_chrome_trace(&path) {
+                Ok(()) => log::info!("wrote the GPU trace of {} to {}", state.name, path),
+                Err(err) => log::error!("failed to write {}: {}", path, err),
             }
         }
 
-        self.in_flight_images[image_index] = self.in_flight_fences[self.current_frame];
-
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
-        let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+        if state.stats_visible {
+            let average = state.frame_times.average();
+            let mut stats = format!(
+                "{}: {:.2} ms ({:.0} fps), {}x{} rendered to {}x{}",
+                state.name,
+                average * 1000.,
+                1. / average.max(f32::EPSILON),
+                state.render_extent.width,
+                state.render_extent.height,
+                state.swapchain_extent.width,
+                state.swapchain_extent.height,
+            );
+            if let Some(frame) = state.profiler.as_ref().and_then(GpuProfiler::last_frame) {
+                stats += &format!(", {:.2} ms on the GPU", frame.duration());
+            }
+            if let Some(samples) = state.scene_samples.get() {
+                stats += &format!(", {} scene samples", samples);
+            }
+            let stats = if state.culling_enabled {
+                let culled = state.culling.stats();
+                format!(
+                    "{}\n{} instances drawn, {} outside the view, {} occluded",
+                    stats, culled.visible, culled.frustum_culled, culled.occluded,
+                )
+            } else {
+                stats
+            };
+            let scale = state.window.scale_factor() as f32;
+            let size = STATS_TEXT_SIZE * scale;
+            let margin = 8. * scale;
+            let width = state.swapchain_extent.width as f32 - 2. * margin;
+            // in the bottom left corner
+            let layout = text::layout(state.text.font(), &stats, size, Some(width));
+            let height = state.swapchain_extent.height as f32;
+            let position = glam::Vec2::new(margin, height - margin - layout.size.y);
+            state
+                .text
+                .queue_layout(&layout, position, [1., 1., 1., 0.9]);
+        }
 
-        let submit_info = vk::SubmitInfo {
-            s_type: vk::StructureType::SUBMIT_INFO,
-            p_next: std::ptr::null(),
-            wait_semaphore_count: 1,
-            p_wait_semaphores: wait_semaphores.as_ptr(),
-            p_wait_dst_stage_mask: wait_stages.as_ptr(),
-            command_buffer_count: 1,
-            p_command_buffers: &self.command_buffers[image_index],
-            signal_semaphore_count: 1,
-            p_signal_semaphores: signal_semaphores.as_ptr(),
-        };
+        // replaced textures go once this frame, and so every one before it, has completed
+        state
+            .ui
+            .upload(&mut self.uploads, &mut state.deletions, state.current_frame);
+        state
+            .text
+            .upload(&mut self.uploads, &mut state.deletions, state.current_frame);
+
+        if state.gizmos_visible {
+            let aspect =
+                state.render_extent.width as f32 / state.render_extent.height.max(1) as f32;
+            let view_projection =
+                state.camera.projection_matrix(aspect) * state.camera.view_matrix();
+            queue_light_gizmos(
+                &mut state.sprites,
+                &self.scene,
+                view_projection,
+                state.swapchain_extent,
+                state.window.scale_factor() as f32,
+            );
+        }
+        state.sprites.upload(state.current_frame);
+
+        let aspect = state.render_extent.width as f32 / state.render_extent.height.max(1) as f32;
+        state.camera_buffers[state.current_frame].write(&[state.camera.uniform(aspect)]);
+        let mut lights = self
+            .scene
+            .light_items()
+            .map(|item| (item.light, item.uniform()))
+            .collect::<Vec<_>>();
+        let shadows = ShadowUniform::new(
+            &state.camera,
+            aspect,
+            lights.iter_mut().map(|(light, uniform)| (*light, uniform)),
+        );
+        let lights = LightArray::new(lights.into_iter().map(|(_, uniform)| uniform));
+        state.light_buffers[state.current_frame].write(std::slice::from_ref(&lights));
+        state.shadow_buffers[state.current_frame].write(std::slice::from_ref(&shadows));
+        state.shadow_layers = shadows.layer_count;
+        state.draws.upload(state.current_frame, &self.scene);
+        if state.culling_enabled {
+            let view_projection =
+                state.camera.projection_matrix(aspect) * state.camera.view_matrix();
+            // occlusion needs a pyramid, which the first frame culling has no previous one for
+            let previous = state.previous_view_projection;
+            let uniform = CullUniform::new(
+                view_projection,
+                previous.unwrap_or(view_projection),
+                previous.map(|_| &state.depth_pyramid),
+            );
+            state
+                .culling
+                .upload(state.current_frame, &state.draws, &self.scene, &uniform);
+            state.previous_view_projection = Some(view_projection);
+        } else {
+            state.previous_view_projection = None;
+        }
 
-        unsafe {
-            self.device
-                .reset_fences(&[self.in_flight_fences[self.current_frame]])
-                .expect("failed to reset fences!");
+        state.record_command_buffer(
+            &self.device,
+            &self.debug_marker,
+            &mut self.uploads,
+            &self.scene,
+            &self.material_sets,
+            self.environment_set,
+            image_index,
+        );
 
-            self.device
-                .queue_submit(
-                    self.graphics_queue,
-                    &[submit_info],
-                    self.in_flight_fences[self.current_frame],
-                )
-                .expect("failed to submit draw command buffer!");
+        // before the frame, which may use what they copy
+        self.uploads.submit();
+
+        let mut submission = Submission::default()
+            .wait(
+                &state.image_available_semaphores[state.current_frame],
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .signal(&state.render_finished_semaphores[state.current_frame]);
+        // on a queue of their own, the uploads are only ordered before the frame by its timeline
+        if self.upload_queue != self.graphics_queue {
+            if let Some(timeline) = self.uploads.timeline() {
+                submission =
+                    submission.wait_timeline(timeline, timeline.last_value(), UPLOAD_READ_STAGES);
+            }
         }
+        let after = state.frame_sync.submit(
+            state.current_frame,
+            submission,
+            self.graphics_queue,
+            std::slice::from_ref(&state.command_buffers[state.current_frame]),
+        );
+        state.deletions.seal(after);
+        let signal_semaphores = [state.render_finished_semaphores[state.current_frame].handle()];
 
-        let swapchains = [self.swapchain];
+        let swapchains = [state.swapchain.handle()];
 
         let present_info = vk::PresentInfoKHR {
             s_type: vk::StructureType::PRESENT_INFO_KHR,
@@ -1290,40 +3216,104 @@ impl VkApp {
         };
 
         unsafe {
-            self.swapchain_loader
+            state
+                .swapchain
+                .loader()
                 .queue_present(self.present_queue, &present_info)
                 .expect("failed to present image to swapchain!");
-
-            self.device
-                .queue_wait_idle(self.present_queue)
-                .expect("failed to queue wait idle!");
         }
 
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        state.current_frame = (state.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
     }
 
     pub fn main_loop(mut self, event_loop: EventLoop<()>) {
         event_loop.run(move |event, _, control_flow| {
-            *control_flow = ControlFlow::Wait;
+            // redraw continuously, the scene is animated
+            *control_flow = ControlFlow::Poll;
 
             match event {
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            virtual_keycode,
-                            state,
-                            ..
-                        } => match (virtual_keycode, state) {
-                            (Some(VirtualKeyCode::Escape), ElementState::Pressed) => {
-                                *control_flow = ControlFlow::Exit
+                Event::WindowEvent { window_id, event } => match event {
+                    // closing the main window quits, closing any other one just stops drawing it
+                    WindowEvent::CloseRequested if window_id == self.main_window => {
+                        *control_flow = ControlFlow::Exit
+                    }
+                    WindowEvent::CloseRequested => self.remove_window(window_id),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::F1),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Some(state) = self.windows.get_mut(&window_id) {
+                            state.ui.visible ^= true;
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::F2),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Some(state) = self.windows.get_mut(&window_id) {
+                            state.stats_visible ^= true;
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::F3),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Some(state) = self.windows.get_mut(&window_id) {
+                            state.gizmos_visible ^= true;
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                virtual_keycode: Some(VirtualKeyCode::F4),
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Some(state) = self.windows.get_mut(&window_id) {
+                            state.culling_enabled ^= true;
+                        }
+                    }
+                    // the camera only gets what the UI does not use
+                    event => {
+                        if let Some(state) = self.windows.get_mut(&window_id) {
+                            if !state.ui.handle_event(&event) {
+                                state.controller.handle_event(&event);
                             }
-                            _ => {}
-                        },
-                    },
-                    _ => {}
+                        }
+                    }
                 },
-                Event::RedrawRequested(_) => self.draw_frame(),
+                Event::MainEventsCleared => {
+                    for state in self.windows.values() {
+                        state.window.request_redraw();
+                    }
+                }
+                Event::RedrawRequested(window_id) => self.draw_frame(window_id),
                 Event::LoopDestroyed => unsafe {
                     self.device
                         .device_wait_idle()
@@ -1337,44 +3327,340 @@ impl VkApp {
 
 impl Drop for VkApp {
     fn drop(&mut self) {
+        // nothing may still be executing when the fields are dropped and destroy their handles
         unsafe {
-            for i in 0..MAX_FRAMES_IN_FLIGHT {
-                self.device
-                    .destroy_semaphore(self.render_finished_semaphores[i], None);
-                self.device
-                    .destroy_semaphore(self.image_available_semaphores[i], None);
-                self.device.destroy_fence(self.in_flight_fences[i], None);
-            }
-            self.device.destroy_command_pool(self.command_pool, None);
-            for i in 0..self.swapchain_framebuffers.len() {
-                self.device
-                    .destroy_framebuffer(self.swapchain_framebuffers[i], None);
-            }
-            self.device.destroy_pipeline(self.graphics_pipeline, None);
             self.device
-                .destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.destroy_render_pass(self.render_pass, None);
-            for i in 0..self.swapchain_image_views.len() {
-                self.device
-                    .destroy_image_view(self.swapchain_image_views[i], None);
-            }
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
-            self.device.destroy_device(None);
-            // this doesn't work??? doesn't complain when disabled.
-            if ENABLE_VALIDATION_LAYERS {
-                self.debug_utils
-                    .destroy_debug_utils_messenger(self.debug_messenger, None);
-            }
-            self.surface_loader.destroy_surface(self.surface, None);
-            self.instance.destroy_instance(None);
+                .device_wait_idle()
+                .expect("failed to device wait idle!");
         }
     }
 }
 
 fn main() {
+    // validation warnings are logged unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("error,vulkan=warn"),
+    )
+    .init();
+
     let el = EventLoop::new();
-    let win = VkApp::init_window(&el);
-    let app = VkApp::init_vulkan(&win);
+    let win = VkApp::init_window(&el, "Vulkan");
+    let mut app = VkApp::init_vulkan(
+        win,
+        RenderScale::from_env(),
+        PostEffects::from_env(),
+        ValidationConfig::from_env(),
+        DebugCallback::default(),
+    );
+
+    // a glTF file to show instead of the built-in scene
+    if let Some(path) = std::env::args_os().nth(1) {
+        let path = Path::new(&path);
+        if let Err(err) = app.load_model(path) {
+            log::error!("failed to load {}: {}", path.display(), err);
+        }
+    }
+
+    for i in 1..=inspector_windows_from_env() {
+        app.add_window(VkApp::init_window(&el, &format!("Inspector {}", i)));
+    }
+
     app.main_loop(el);
 }
+
+/// The panorama at the path in `VKA_ENVIRONMENT`, or a sky with the default scene's sun if it is
+/// not set or cannot be read.
+fn environment_source_from_env() -> EnvironmentSource {
+    let sky = EnvironmentSource::Sky {
+        sun_direction: SUN_DIRECTION.normalize(),
+    };
+
+    match std::env::var_os("VKA_ENVIRONMENT") {
+        Some(path) => EnvironmentSource::load_panorama(&path).unwrap_or_else(|err| {
+            log::error!(
+                "failed to load environment {}: {}",
+                Path::new(&path).display(),
+                err
+            );
+            sky
+        }),
+        None => sky,
+    }
+}
+
+/// The font to draw text with, read from the TTF or OTF file at `VKA_FONT`. Unset, or if it
+/// fails to load, it is [`text::default_font`].
+fn font_from_env() -> FontArc {
+    match std::env::var_os("VKA_FONT") {
+        Some(path) => text::load_font(&path).unwrap_or_else(|err| {
+            log::error!(
+                "failed to load font {}: {}",
+                Path::new(&path).display(),
+                err
+            );
+            text::default_font()
+        }),
+        None => text::default_font(),
+    }
+}
+
+/// Rows of the debug overlay's device section, which do not change.
+fn describe_device(device: &Device) -> Vec<(&'static str, String)> {
+    let properties = unsafe {
+        device
+            .instance()
+            .get_physical_device_properties(device.physical_device())
+    };
+    let version = |version| {
+        format!(
+            "{}.{}.{}",
+            vk::version_major(version),
+            vk::version_minor(version),
+            vk::version_patch(version)
+        )
+    };
+    let supported = |supported: bool| String::from(if supported { "yes" } else { "no" });
+
+    vec![
+        ("Name", vk_to_str(&properties.device_name).to_owned()),
+        ("Type", format!("{:?}", properties.device_type)),
+        ("API version", version(properties.api_version)),
+        ("Driver version", version(properties.driver_version)),
+        (
+            "Dynamic rendering",
+            supported(device.dynamic_rendering().is_some()),
+        ),
+        (
+            "Synchronization2",
+            supported(device.synchronization2().is_some()),
+        ),
+        (
+            "Draw indirect count",
+            supported(device.draw_indirect_count()),
+        ),
+        (
+            "Timeline semaphores",
+            supported(device.timeline_semaphore()),
+        ),
+        (
+            "Pipeline statistics",
+            supported(device.features().pipeline_statistics_query == vk::TRUE),
+        ),
+    ]
+}
+
+/// Marks the lights of `scene` on a target of `extent`, seen through `view_projection`: a
+/// circle in each light's color where it is, and a line along where it shines unless it shines
+/// in all directions. Directional lights are infinitely far away, so only their direction is
+/// drawn, from the center of the target.
+fn queue_light_gizmos(
+    batch: &mut SpriteBatch,
+    scene: &Scene,
+    view_projection: glam::Mat4,
+    extent: vk::Extent2D,
+    scale: f32,
+) {
+    let size = glam::Vec2::new(extent.width as f32, extent.height as f32);
+    // clip space to pixels, or `None` behind the camera
+    let to_pixels = |clip: Vec4| {
+        if clip.w <= 0. {
+            return None;
+        }
+        let ndc = clip.truncate().truncate() / clip.w;
+        Some((ndc + glam::Vec2::ONE) / 2. * size)
+    };
+
+    batch.set_layer(0);
+    for item in scene.light_items() {
+        let light = item.light;
+        let color = light.color / light.color.max_element().max(f32::EPSILON);
+        let color = [color.x, color.y, color.z, 0.9];
+        let position = item.transform.w_axis;
+        let direction = -item.transform.z_axis;
+
+        match light.kind {
+            LightKind::Directional => {
+                // a short step from the point in the middle of the view shows which way it goes
+                let middle = view_projection.inverse() * Vec4::new(0., 0., 0.5, 1.);
+                let middle = middle / middle.w;
+                let step = to_pixels(view_projection * (middle + direction * 0.01));
+                if let Some(step) = step {
+                    let center = size / 2.;
+                    let end = center + (step - center).normalize_or_zero() * 48. * scale;
+                    batch.line(center, end, 2. * scale, color);
+                    batch.circle(end, 4. * scale, color);
+                }
+            }
+            LightKind::Point { .. } | LightKind::Spot { .. } => {
+                let center = match to_pixels(view_projection * position) {
+                    Some(center) => center,
+                    None => continue,
+                };
+                if matches!(light.kind, LightKind::Spot { .. }) {
+                    if let Some(end) = to_pixels(view_projection * (position + direction)) {
+                        batch.line(center, end, 2. * scale, color);
+                    }
+                }
+                // an outline, so dim lights stand out from what they light
+                batch.circle(center, 8. * scale, [0., 0., 0., 0.6]);
+                batch.circle(center, 6. * scale, color);
+            }
+        }
+    }
+}
+
+/// Builds a window's debug overlay: its frame times, what it renders with and its
+/// post-processing effects, which can be changed from there.
+fn debug_ui(
+    ctx: &egui::Context,
+    device_info: &[(&'static str, String)],
+    frame_times: &FrameTimes,
+    gpu_frame: Option<&FrameTiming>,
+    export_trace: &mut bool,
+    targets: &[(&str, vk::Extent2D, vk::Format)],
+    post_effects: &mut PostEffects,
+) {
+    egui::Window::new("Debug")
+        .default_width(280.)
+        .show(ctx, |ui| {
+            let average = frame_times.average();
+            ui.label(format!(
+                "{:.2} ms ({:.0} fps)",
+                average * 1000.,
+                1. / average.max(f32::EPSILON)
+            ));
+            frame_times.graph(ui);
+
+            if let Some(frame) = gpu_frame {
+                egui::CollapsingHeader::new(format!("GPU: {:.2} ms", frame.duration())).show(
+                    ui,
+                    |ui| {
+                        egui::Grid::new("gpu passes").num_columns(2).show(ui, |ui| {
+                            for pass in &frame.passes {
+                                let label = ui.label(&pass.name);
+                                if let Some(statistics) = pass.statistics {
+                                    label.on_hover_text(format!(
+                                        "{} vertices, {} vertex / {} fragment / {} compute \
+                                         invocations, {} primitives clipped",
+                                        statistics.input_assembly_vertices,
+                                        statistics.vertex_shader_invocations,
+                                        statistics.fragment_shader_invocations,
+                                        statistics.compute_shader_invocations,
+                                        statistics.clipping_primitives,
+                                    ));
+                                }
+                                ui.label(format!("{:.3} ms", pass.duration));
+                                ui.end_row();
+                            }
+                        });
+                        *export_trace = ui.button("Export Chrome trace").clicked();
+                    },
+                );
+            }
+
+            egui::CollapsingHeader::new("Device").show(ui, |ui| {
+                egui::Grid::new("device").num_columns(2).show(ui, |ui| {
+                    for (label, value) in device_info {
+                        ui.label(*label);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                    for (label, extent, format) in targets {
+                        ui.label(*label);
+                        ui.label(format!("{}x{} {:?}", extent.width, extent.height, format));
+                        ui.end_row();
+                    }
+                });
+            });
+
+            egui::CollapsingHeader::new("Post-processing")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ui.checkbox(&mut post_effects.bloom, "Bloom");
+
+                    ui.horizontal(|ui| {
+                        let tonemapper = &mut post_effects.tonemapper;
+                        ui.label("Tone mapping");
+                        ui.radio_value(tonemapper, None, "None");
+                        ui.radio_value(tonemapper, Some(Tonemapper::Reinhard), "Reinhard");
+                        ui.radio_value(tonemapper, Some(Tonemapper::Aces), "ACES");
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut post_effects.exposure, 0.1..=8.)
+                            .logarithmic(true)
+                            .text("Exposure"),
+                    );
+
+                    let mut grading = post_effects.color_grading.is_some();
+                    if ui.checkbox(&mut grading, "Color grading").changed() {
+                        post_effects.color_grading = grading.then(ColorGrading::default);
+                    }
+                    if let Some(grading) = &mut post_effects.color_grading {
+                        ui.add(egui::Slider::new(&mut grading.contrast, 0.5..=2.).text("Contrast"));
+                        ui.add(
+                            egui::Slider::new(&mut grading.saturation, 0.0..=2.).text("Saturation"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut grading.temperature, -1.0..=1.)
+                                .text("Temperature"),
+                        );
+                    }
+
+                    ui.checkbox(&mut post_effects.fxaa, "FXAA");
+
+                    let mut gamma = post_effects.gamma.is_some();
+                    if ui
+                        .checkbox(&mut gamma, "Gamma (non-sRGB swapchains)")
+                        .changed()
+                    {
+                        post_effects.gamma = if gamma {
+                            PostEffects::default().gamma
+                        } else {
+                            None
+                        };
+                    }
+                    if let Some(gamma) = &mut post_effects.gamma {
+                        ui.add(egui::Slider::new(gamma, 1.0..=3.).text("Gamma"));
+                    }
+                });
+        });
+}
+
+/// Whether to synchronize with a timeline semaphore where the device supports them, rather than
+/// with fences, read from `VKA_TIMELINE_SEMAPHORES`.
+fn timeline_semaphores_from_env() -> bool {
+    match std::env::var("VKA_TIMELINE_SEMAPHORES")
+        .map(|v| v.trim().to_ascii_lowercase())
+        .as_deref()
+    {
+        Ok("1") | Ok("on") | Ok("true") | Err(_) => true,
+        Ok("0") | Ok("off") | Ok("false") => false,
+        Ok(other) => {
+            log::warn!(
+                "ignoring unrecognized VKA_TIMELINE_SEMAPHORES value {:?}",
+                other
+            );
+            true
+        }
+    }
+}
+
+/// Number of extra windows to open next to the main one, read from `VKA_INSPECTOR_WINDOWS`.
+fn inspector_windows_from_env() -> usize {
+    match std::env::var("VKA_INSPECTOR_WINDOWS") {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            log::warn!("ignoring invalid VKA_INSPECTOR_WINDOWS value {:?}", value);
+            0
+        }),
+        Err(_) => 0,
+    }
+}
+
+/// Texture set bindings hold the textures first and then their samplers.
+fn texture_descriptor_type(binding: u32, textures: u32) -> vk::DescriptorType {
+    if binding < textures {
+        vk::DescriptorType::SAMPLED_IMAGE
+    } else {
+        vk::DescriptorType::SAMPLER
+    }
+}
@@ -0,0 +1,180 @@
+//! Post-processing: the scene is rendered into an HDR target, which a chain of full-screen passes
+//! turns into the image that is blitted onto the swapchain. Each effect is its own pass and can be
+//! turned off, in which case the render graph never sees it.
+//!
+//! The passes run in this order: bloom (a threshold and a two pass blur at half resolution),
+//! tone mapping, which adds the bloom back, color grading, FXAA and gamma encoding.
+
+use ash::vk;
+use glam::{Vec2, Vec4};
+
+/// Format of the scene color and of every image between post-processing passes.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Maps HDR colors into the range the display shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    Reinhard,
+}
+
+/// A simple grade applied after tone mapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorGrading {
+    /// Scales the distance from middle grey.
+    pub contrast: f32,
+    /// 0 is greyscale, 1 leaves colors as they are.
+    pub saturation: f32,
+    /// Shifts the white balance towards orange when positive and blue when negative.
+    pub temperature: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            contrast: 1.1,
+            saturation: 1.1,
+            temperature: 0.2,
+        }
+    }
+}
+
+/// Which post-processing passes run, and their settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostEffects {
+    pub bloom: bool,
+    /// `None` clamps the HDR colors instead.
+    pub tonemapper: Option<Tonemapper>,
+    pub color_grading: Option<ColorGrading>,
+    pub fxaa: bool,
+    /// Encodes colors with this gamma. Only done for swapchains whose format is not sRGB, the
+    /// others encode when written to.
+    pub gamma: Option<f32>,
+    /// Multiplies the scene's colors before tone mapping.
+    pub exposure: f32,
+}
+
+impl Default for PostEffects {
+    fn default() -> Self {
+        Self {
+            bloom: true,
+            tonemapper: Some(Tonemapper::Aces),
+            color_grading: None,
+            fxaa: true,
+            gamma: Some(2.2),
+            exposure: 1.,
+        }
+    }
+}
+
+impl PostEffects {
+    /// Reads the effects from `VKA_POST_EFFECTS`, a comma separated list of `bloom`, `aces` or
+    /// `reinhard`, `grading`, `fxaa` and `gamma`, or `none`. Unset, the default effects run.
+    pub fn from_env() -> Self {
+        let list = match std::env::var("VKA_POST_EFFECTS") {
+            Ok(list) => list,
+            Err(_) => return Self::default(),
+        };
+
+        let mut effects = Self {
+            bloom: false,
+            tonemapper: None,
+            color_grading: None,
+            fxaa: false,
+            gamma: None,
+            ..Self::default()
+        };
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            match name.to_ascii_lowercase().as_str() {
+                "bloom" => effects.bloom = true,
+                "aces" => effects.tonemapper = Some(Tonemapper::Aces),
+                "reinhard" => effects.tonemapper = Some(Tonemapper::Reinhard),
+                "grading" => effects.color_grading = Some(ColorGrading::default()),
+                "fxaa" => effects.fxaa = true,
+                "gamma" => effects.gamma = Self::default().gamma,
+                "none" => {}
+                other => log::warn!("ignoring unknown post-processing effect {:?}", other),
+            }
+        }
+        effects
+    }
+}
+
+/// Bloom only spreads what is brighter than this.
+pub const BLOOM_THRESHOLD: f32 = 1.;
+/// Width of the range below the threshold that blooms partially.
+pub const BLOOM_KNEE: f32 = 0.5;
+/// How much of the blurred highlights is added back to the scene.
+pub const BLOOM_STRENGTH: f32 = 0.3;
+
+/// Push constants shared by the post-processing shaders. What `mode` and `params` mean depends on
+/// the pass.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PostPushConstants {
+    /// Size of a texel of the pass's first input, in texture coordinates.
+    pub texel_size: Vec2,
+    pub mode: u32,
+    _padding: u32,
+    pub params: Vec4,
+}
+
+impl PostPushConstants {
+    /// Constants for a pass reading an input of `input_extent`.
+    pub fn new(input_extent: vk::Extent2D, mode: u32, params: Vec4) -> Self {
+        Self {
+            texel_size: Vec2::new(
+                1. / input_extent.width as f32,
+                1. / input_extent.height as f32,
+            ),
+            mode,
+            _padding: 0,
+            params,
+        }
+    }
+
+    /// Constants of the tone mapping pass, which adds `bloom_strength` times the bloom image. The
+    /// pass runs without a tone mapper too when there is bloom to add.
+    pub fn tonemap(input_extent: vk::Extent2D, effects: &PostEffects, bloom_strength: f32) -> Self {
+        let mode = match effects.tonemapper {
+            None => 0,
+            Some(Tonemapper::Reinhard) => 1,
+            Some(Tonemapper::Aces) => 2,
+        };
+        Self::new(
+            input_extent,
+            mode,
+            Vec4::new(effects.exposure, bloom_strength, 0., 0.),
+        )
+    }
+
+    pub fn color_grading(input_extent: vk::Extent2D, grading: &ColorGrading) -> Self {
+        Self::new(
+            input_extent,
+            0,
+            Vec4::new(
+                grading.contrast,
+                grading.saturation,
+                grading.temperature,
+                0.,
+            ),
+        )
+    }
+}
+
+/// Whether writes to images of `format` are encoded to sRGB by the hardware.
+pub fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+    )
+}
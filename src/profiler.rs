@@ -0,0 +1,492 @@
+//! GPU timings of render graph passes.
+//!
+//! Timestamps are written before and after each pass, along with pipeline statistics where the
+//! device supports them. Each frame in flight has its own range of queries, read back once the
+//! frame has completed and it is about to be recorded again, so reading never waits for the
+//! GPU. Recent frames are kept for exporting as a Chrome trace, which `chrome://tracing` and
+//! Perfetto open.
+
+use crate::query::{PipelineStatistics, QueryKind, PIPELINE_STATISTICS};
+use crate::resources::{Device, QueryPool};
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Passes timed per frame; those after are recorded but not timed.
+pub const MAX_PASSES: u32 = 64;
+/// Frames kept for [`GpuProfiler::chrome_trace`].
+const TRACE_FRAMES: usize = 240;
+
+/// How long a pass took on the GPU.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassTiming {
+    pub name: String,
+    /// Milliseconds from the start of the frame's first pass.
+    pub start: f64,
+    /// Milliseconds.
+    pub duration: f64,
+    pub statistics: Option<PipelineStatistics>,
+}
+
+/// The timed passes of a frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameTiming {
+    /// Counts the frames the profiler has recorded.
+    pub frame: u64,
+    /// Nanoseconds from an arbitrary point to the start of the first pass, to order frames by.
+    pub gpu_start: f64,
+    pub passes: Vec<PassTiming>,
+}
+
+impl FrameTiming {
+    /// Milliseconds from the start of the first pass to the end of the last.
+    pub fn duration(&self) -> f64 {
+        self.passes
+            .iter()
+            .map(|pass| pass.start + pass.duration)
+            .fold(0., f64::max)
+    }
+}
+
+/// The passes a frame in flight recorded queries for.
+struct RecordedFrame {
+    frame: u64,
+    names: Vec<String>,
+    /// Whether a pass is between [`GpuProfiler::begin_pass`] and [`GpuProfiler::end_pass`],
+    /// and has queries.
+    open: bool,
+}
+
+/// Times the passes of a window's frames. Every frame in flight uses [`MAX_PASSES`] pairs of
+/// timestamp queries, and as many pipeline statistics queries if the device supports them.
+pub struct GpuProfiler {
+    frames: Vec<RecordedFrame>,
+    current: usize,
+    frame_count: u64,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f64,
+    /// The bits of a timestamp that are valid.
+    timestamp_mask: u64,
+    history: VecDeque<FrameTiming>,
+    statistics: Option<QueryPool>,
+    timestamps: QueryPool,
+    device: Arc<Device>,
+}
+
+impl GpuProfiler {
+    /// Creates a profiler for command buffers submitted to queues of family
+    /// `queue_family_index`, or returns `None` if they do not support timestamps.
+    pub fn new(
+        device: &Arc<Device>,
+        queue_family_index: u32,
+        frames_in_flight: usize,
+    ) -> Option<Self> {
+        let instance = device.instance();
+        let physical_device = device.physical_device();
+        let (properties, queue_families) = unsafe {
+            (
+                instance.get_physical_device_properties(physical_device),
+                instance.get_physical_device_queue_family_properties(physical_device),
+            )
+        };
+        let valid_bits = queue_families[queue_family_index as usize].timestamp_valid_bits;
+        if valid_bits == 0 {
+            return None;
+        }
+
+        let create_pool = |query_type, count, statistics| {
+            let pool_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(query_type)
+                .query_count(count)
+                .pipeline_statistics(statistics);
+            let pool = unsafe {
+                device
+                    .create_query_pool(&pool_info, None)
+                    .expect("failed to create query pool!")
+            };
+            QueryPool::from_raw(device, pool)
+        };
+        let frames = frames_in_flight as u32;
+        let timestamps = create_pool(
+            vk::QueryType::TIMESTAMP,
+            2 * MAX_PASSES * frames,
+            vk::QueryPipelineStatisticFlags::empty(),
+        );
+        let statistics = (device.features().pipeline_statistics_query == vk::TRUE).then(|| {
+            create_pool(
+                vk::QueryType::PIPELINE_STATISTICS,
+                MAX_PASSES * frames,
+                PIPELINE_STATISTICS,
+            )
+        });
+
+        Some(Self {
+            frames: (0..frames_in_flight)
+                .map(|_| RecordedFrame {
+                    frame: 0,
+                    names: Vec::new(),
+                    open: false,
+                })
+                .collect(),
+            current: 0,
+            frame_count: 0,
+            timestamp_period: properties.limits.timestamp_period as f64,
+            timestamp_mask: if valid_bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << valid_bits) - 1
+            },
+            history: VecDeque::with_capacity(TRACE_FRAMES),
+            statistics,
+            timestamps,
+            device: Arc::clone(device),
+        })
+    }
+
+    /// Whether passes are timed with pipeline statistics as well.
+    pub fn has_statistics(&self) -> bool {
+        self.statistics.is_some()
+    }
+
+    /// Reads back what frame in flight `frame` timed last, which must have completed, and
+    /// records resetting its queries for the passes `command_buffer` is about to record. Has to
+    /// be recorded outside of any render pass.
+    pub fn begin_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        self.read_back(frame);
+
+        self.current = frame;
+        self.frame_count += 1;
+        let recorded = &mut self.frames[frame];
+        recorded.frame = self.frame_count;
+        recorded.names.clear();
+        recorded.open = false;
+
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                command_buffer,
+                self.timestamps.handle(),
+                2 * first_query(frame),
+                2 * MAX_PASSES,
+            );
+            if let Some(statistics) = &self.statistics {
+                self.device.cmd_reset_query_pool(
+                    command_buffer,
+                    statistics.handle(),
+                    first_query(frame),
+                    MAX_PASSES,
+                );
+            }
+        }
+    }
+
+    /// Records starting to time the pass `name`. Has to be recorded outside of any render pass,
+    /// and be followed by [`Self::end_pass`] once the pass has ended.
+    pub fn begin_pass(&mut self, command_buffer: vk::CommandBuffer, name: &str) {
+        let recorded = &mut self.frames[self.current];
+        let index = recorded.names.len() as u32;
+        if index == MAX_PASSES {
+            return;
+        }
+        recorded.names.push(name.to_owned());
+        recorded.open = true;
+
+        let first = first_query(self.current);
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.timestamps.handle(),
+                2 * (first + index),
+            );
+            if let Some(statistics) = &self.statistics {
+                self.device.cmd_begin_query(
+                    command_buffer,
+                    statistics.handle(),
+                    first + index,
+                    vk::QueryControlFlags::empty(),
+                );
+            }
+        }
+    }
+
+    /// Records the end of the pass last begun.
+    pub fn end_pass(&mut self, command_buffer: vk::CommandBuffer) {
+        let recorded = &mut self.frames[self.current];
+        if !recorded.open {
+            return;
+        }
+        recorded.open = false;
+
+        let first = first_query(self.current);
+        let index = recorded.names.len() as u32 - 1;
+        unsafe {
+            if let Some(statistics) = &self.statistics {
+                self.device
+                    .cmd_end_query(command_buffer, statistics.handle(), first + index);
+            }
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.timestamps.handle(),
+                2 * (first + index) + 1,
+            );
+        }
+    }
+
+    /// Adds the timings of frame in flight `frame` to the history, if it recorded any and they
+    /// are available.
+    fn read_back(&mut self, frame: usize) {
+        let recorded = &self.frames[frame];
+        let count = recorded.names.len() as u32;
+        if count == 0 {
+            return;
+        }
+
+        // without WAIT, a query that is not available yet fails the read rather than blocking
+        let mut timestamps = vec![0u64; 2 * count as usize];
+        let read = unsafe {
+            self.device.get_query_pool_results(
+                self.timestamps.handle(),
+                2 * first_query(frame),
+                2 * count,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        if read.is_err() {
+            return;
+        }
+
+        let mut statistics = vec![[0u64; 5]; count as usize];
+        let statistics = self.statistics.as_ref().and_then(|pool| {
+            let read = unsafe {
+                self.device.get_query_pool_results(
+                    pool.handle(),
+                    first_query(frame),
+                    count,
+                    &mut statistics,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            read.ok().map(|_| statistics)
+        });
+
+        let timing = frame_timing(
+            recorded,
+            &timestamps,
+            statistics.as_deref(),
+            self.timestamp_mask,
+            self.timestamp_period,
+        );
+        if self.history.len() == TRACE_FRAMES {
+            self.history.pop_front();
+        }
+        self.history.push_back(timing);
+    }
+
+    /// The timings of the last frame read back.
+    pub fn last_frame(&self) -> Option<&FrameTiming> {
+        self.history.back()
+    }
+
+    /// The timings of recent frames, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &FrameTiming> {
+        self.history.iter()
+    }
+
+    /// The recent frames' passes in the Chrome trace event format, each frame on a row of its own
+    /// followed by its passes.
+    pub fn chrome_trace(&self) -> String {
+        let origin = self
+            .history
+            .front()
+            .map(|frame| frame.gpu_start)
+            .unwrap_or_default();
+        let mut events = Vec::new();
+        for frame in &self.history {
+            // microseconds
+            let frame_start = (frame.gpu_start - origin) / 1e3;
+            events.push(trace_event(
+                &format!("frame {}", frame.frame),
+                frame_start,
+                frame.duration() * 1e3,
+                0,
+                "",
+            ));
+            for pass in &frame.passes {
+                let args = match pass.statistics {
+                    Some(statistics) => format!(
+                        ",\"args\":{{\"input assembly vertices\":{},\
+                         \"vertex shader invocations\":{},\
+                         \"clipping primitives\":{},\
+                         \"fragment shader invocations\":{},\
+                         \"compute shader invocations\":{}}}",
+                        statistics.input_assembly_vertices,
+                        statistics.vertex_shader_invocations,
+                        statistics.clipping_primitives,
+                        statistics.fragment_shader_invocations,
+                        statistics.compute_shader_invocations,
+                    ),
+                    None => String::new(),
+                };
+                events.push(trace_event(
+                    &pass.name,
+                    frame_start + pass.start * 1e3,
+                    pass.duration * 1e3,
+                    1,
+                    &args,
+                ));
+            }
+        }
+
+        format!(
+            "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n{}\n]}}\n",
+            events.join(",\n")
+        )
+    }
+
+    /// Writes [`Self::chrome_trace`] to `path`.
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_trace())
+    }
+}
+
+/// The first of the queries of frame in flight `frame`: a pass's pipeline statistics query, or
+/// half of its begin timestamp query.
+fn first_query(frame: usize) -> u32 {
+    frame as u32 * MAX_PASSES
+}
+
+/// The timings of `recorded` from its passes' begin and end `timestamps`, interleaved, which
+/// count ticks of `period` nanoseconds with the bits of `mask` valid.
+fn frame_timing(
+    recorded: &RecordedFrame,
+    timestamps: &[u64],
+    statistics: Option<&[[u64; 5]]>,
+    mask: u64,
+    period: f64,
+) -> FrameTiming {
+    let frame_start = timestamps[0];
+    // timestamps may wrap around their valid bits, so only differences are meaningful
+    let milliseconds = |from: u64, to: u64| (to.wrapping_sub(from) & mask) as f64 * period / 1e6;
+    let passes = recorded
+        .names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| PassTiming {
+            name: name.clone(),
+            start: milliseconds(frame_start, timestamps[2 * i]),
+            duration: milliseconds(timestamps[2 * i], timestamps[2 * i + 1]),
+            statistics: statistics.map(|statistics| PipelineStatistics::output(&statistics[i])),
+        })
+        .collect();
+
+    FrameTiming {
+        frame: recorded.frame,
+        gpu_start: (frame_start & mask) as f64 * period,
+        passes,
+    }
+}
+
+/// A complete event named `name` on thread `thread`, with timestamps in microseconds and `args`
+/// being empty or a leading comma and the args member.
+fn trace_event(name: &str, start: f64, duration: f64, thread: u32, args: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    format!(
+        "{{\"name\":\"{}\",\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\
+         \"pid\":0,\"tid\":{}{}}}",
+        escaped, start, duration, thread, args
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(names: &[&str]) -> RecordedFrame {
+        RecordedFrame {
+            frame: 7,
+            names: names.iter().map(|&name| name.to_owned()).collect(),
+            open: false,
+        }
+    }
+
+    #[test]
+    fn frames_in_flight_use_their_own_queries() {
+        for frame in 0..3 {
+            let first = first_query(frame);
+            let last = first + MAX_PASSES - 1;
+            assert!(frame == 0 || first > first_query(frame - 1) + MAX_PASSES - 1);
+            assert!(2 * last + 1 < 2 * MAX_PASSES * 3);
+            assert_eq!(2 * first, 2 * MAX_PASSES * frame as u32);
+        }
+    }
+
+    #[test]
+    fn times_passes_from_the_first_timestamp() {
+        let timestamps = [1_000, 3_000, 3_000, 7_000];
+        let timing = frame_timing(
+            &recorded(&["shadow", "main"]),
+            &timestamps,
+            None,
+            u64::MAX,
+            500.,
+        );
+        assert_eq!(timing.frame, 7);
+        assert_eq!(timing.gpu_start, 500_000.);
+        let times: Vec<_> = timing
+            .passes
+            .iter()
+            .map(|pass| (pass.name.as_str(), pass.start, pass.duration))
+            .collect();
+        assert_eq!(times, [("shadow", 0., 1.), ("main", 1., 2.)]);
+        assert_eq!(timing.duration(), 3.);
+    }
+
+    #[test]
+    fn times_passes_across_wrapping_timestamps() {
+        let mask = (1 << 16) - 1;
+        let timestamps = [0xfff0, 0x0010];
+        let timing = frame_timing(&recorded(&["main"]), &timestamps, None, mask, 1e6);
+        assert_eq!(timing.passes[0].start, 0.);
+        assert_eq!(timing.passes[0].duration, 32.);
+    }
+
+    #[test]
+    fn reads_statistics_per_pass() {
+        let statistics = [[1, 2, 3, 4, 5], [6, 7, 8, 9, 10]];
+        let timing = frame_timing(
+            &recorded(&["shadow", "main"]),
+            &[0, 1, 1, 2],
+            Some(&statistics),
+            u64::MAX,
+            1.,
+        );
+        let main = timing.passes[1].statistics.unwrap();
+        assert_eq!(main.input_assembly_vertices, 6);
+        assert_eq!(main.compute_shader_invocations, 10);
+    }
+
+    #[test]
+    fn escapes_trace_event_names() {
+        assert_eq!(
+            trace_event("a \"b\"\\\n", 1., 2.5, 1, ""),
+            "{\"name\":\"a \\\"b\\\"\\\\\\u000a\",\"cat\":\"gpu\",\"ph\":\"X\",\
+             \"ts\":1.000,\"dur\":2.500,\"pid\":0,\"tid\":1}"
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Occlusion and pipeline statistics queries, typed by what they count.
+//!
+//! Each frame in flight has its own range of a [`Queries`] pool. A query's result is handed to
+//! the callback it was ended with once the frame that recorded it has completed,
+//! found either by [`Queries::poll`] or when the frame in flight is recorded again, so results
+//! are never waited for.
+
+use crate::resources::{Device, QueryPool};
+use crate::sync::FrameSync;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The statistics [`PipelineStatistics`] queries count, which are written in the order of their
+/// bits.
+pub const PIPELINE_STATISTICS: vk::QueryPipelineStatisticFlags =
+    vk::QueryPipelineStatisticFlags::from_raw(
+        vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+            | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+    );
+
+/// A type of query, and how its results are read.
+pub trait QueryKind {
+    type Output;
+    const TYPE: vk::QueryType;
+    /// Values written per query.
+    const VALUES: usize;
+
+    /// Statistics counted, for pipeline statistics queries.
+    fn statistics() -> vk::QueryPipelineStatisticFlags {
+        vk::QueryPipelineStatisticFlags::empty()
+    }
+
+    /// Reads the [`Self::VALUES`] values of a query.
+    fn output(values: &[u64]) -> Self::Output;
+}
+
+/// Counts the samples passing the depth and stencil tests. Unless the query is precise, any
+/// non-zero count only means some did.
+pub enum Occlusion {}
+
+impl QueryKind for Occlusion {
+    type Output = u64;
+    const TYPE: vk::QueryType = vk::QueryType::OCCLUSION;
+    const VALUES: usize = 1;
+
+    fn output(values: &[u64]) -> u64 {
+        values[0]
+    }
+}
+
+/// What the commands between the start and end of a query made the GPU do.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub vertex_shader_invocations: u64,
+    /// Primitives that reached clipping, so were not culled before it.
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+impl QueryKind for PipelineStatistics {
+    type Output = Self;
+    const TYPE: vk::QueryType = vk::QueryType::PIPELINE_STATISTICS;
+    const VALUES: usize = 5;
+
+    fn statistics() -> vk::QueryPipelineStatisticFlags {
+        PIPELINE_STATISTICS
+    }
+
+    fn output(values: &[u64]) -> Self {
+        Self {
+            input_assembly_vertices: values[0],
+            vertex_shader_invocations: values[1],
+            clipping_primitives: values[2],
+            fragment_shader_invocations: values[3],
+            compute_shader_invocations: values[4],
+        }
+    }
+}
+
+/// A query begun with [`Queries::begin`], to be ended by the same [`Queries`].
+#[must_use]
+pub struct ActiveQuery<K> {
+    index: u32,
+    kind: PhantomData<K>,
+}
+
+type Callback<K> = Box<dyn FnOnce(<K as QueryKind>::Output)>;
+
+/// The queries of one frame in flight.
+struct FrameQueries<K: QueryKind> {
+    /// Queries begun since the range was last reset.
+    used: u32,
+    /// Ended queries, by index, waiting for the frame to complete.
+    pending: Vec<(u32, Callback<K>)>,
+}
+
+/// A pool of queries of kind `K`, with `capacity` of them per frame in flight. Queries are begun
+/// and ended through a shared reference, so render graph passes can record them.
+pub struct Queries<K: QueryKind> {
+    frames: RefCell<Vec<FrameQueries<K>>>,
+    current: usize,
+    capacity: u32,
+    pool: QueryPool,
+    device: Arc<Device>,
+}
+
+/// Counts samples, e.g. to find out whether something was visible.
+pub type OcclusionQueries = Queries<Occlusion>;
+/// Counts vertices, primitives and shader invocations. The device needs the
+/// `pipelineStatisticsQuery` feature.
+pub type PipelineStatisticsQueries = Queries<PipelineStatistics>;
+
+impl<K: QueryKind> Queries<K> {
+    pub fn new(device: &Arc<Device>, capacity: u32, frames_in_flight: usize) -> Self {
+        let pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(K::TYPE)
+            .query_count(capacity * frames_in_flight as u32)
+            .pipeline_statistics(K::statistics());
+        let pool = unsafe {
+            device
+                .create_query_pool(&pool_info, None)
+                .expect("failed to create query pool!")
+        };
+
+        Self {
+            frames: (0..frames_in_flight)
+                .map(|_| FrameQueries {
+                    used: 0,
+                    pending: Vec::new(),
+                })
+                .collect::<Vec<_>>()
+                .into(),
+            current: 0,
+            capacity,
+            pool: QueryPool::from_raw(device, pool),
+            device: Arc::clone(device),
+        }
+    }
+
+    /// Hands the results of frame in flight `frame`, which must have completed, to their
+    /// callbacks, and records resetting its queries before `command_buffer` begins any. Has to
+    /// be recorded outside of any render pass.
+    pub fn begin_frame(&mut self, command_buffer: vk::CommandBuffer, frame: usize) {
+        self.resolve(frame);
+        self.current = frame;
+        self.frames.get_mut()[frame].used = 0;
+
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                command_buffer,
+                self.pool.handle(),
+                frame as u32 * self.capacity,
+                self.capacity,
+            );
+        }
+    }
+
+    /// Hands the results of the frames in flight that `sync` finds have completed to their
+    /// callbacks, without waiting for the others.
+    pub fn poll(&mut self, sync: &FrameSync) {
+        for frame in 0..self.frames.get_mut().len() {
+            if !self.frames.get_mut()[frame].pending.is_empty() && sync.is_complete(frame) {
+                self.resolve(frame);
+            }
+        }
+    }
+
+    /// Records beginning a query in `command_buffer`, or returns `None` if the current frame has
+    /// used all of its queries. A query begun inside a render pass has to end in the same
+    /// subpass.
+    pub fn begin(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        flags: vk::QueryControlFlags,
+    ) -> Option<ActiveQuery<K>> {
+        let mut frames = self.frames.borrow_mut();
+        let frame = &mut frames[self.current];
+        if frame.used == self.capacity {
+            return None;
+        }
+        let index = self.current as u32 * self.capacity + frame.used;
+        frame.used += 1;
+
+        unsafe {
+            self.device
+                .cmd_begin_query(command_buffer, self.pool.handle(), index, flags)
+        };
+        Some(ActiveQuery {
+            index,
+            kind: PhantomData,
+        })
+    }
+
+    /// Records ending `query`, whose result is handed to `callback` once the frame has completed.
+    pub fn end(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        query: ActiveQuery<K>,
+        callback: impl FnOnce(K::Output) + 'static,
+    ) {
+        unsafe {
+            self.device
+                .cmd_end_query(command_buffer, self.pool.handle(), query.index)
+        };
+        self.frames.borrow_mut()[self.current]
+            .pending
+            .push((query.index, Box::new(callback)));
+    }
+
+    /// Reads the results of frame in flight `frame`, which must have completed, and calls their
+    /// callbacks.
+    fn resolve(&mut self, frame: usize) {
+        let frames = self.frames.get_mut();
+        let pending = std::mem::take(&mut frames[frame].pending);
+        let used = frames[frame].used;
+        if pending.is_empty() {
+            return;
+        }
+
+        let first = frame as u32 * self.capacity;
+        let mut values = vec![0u64; used as usize * K::VALUES];
+        // the frame has completed, so every ended query is available; the stride depends on the
+        // kind, which ash's typed read cannot express
+        let stride = (K::VALUES * std::mem::size_of::<u64>()) as vk::DeviceSize;
+        let read: ash::prelude::VkResult<()> = unsafe {
+            self.device
+                .fp_v1_0()
+                .get_query_pool_results(
+                    self.device.handle(),
+                    self.pool.handle(),
+                    first,
+                    used,
+                    std::mem::size_of_val(values.as_slice()),
+                    values.as_mut_ptr().cast(),
+                    stride,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+                .into()
+        };
+        if let Err(err) = read {
+            log::warn!("dropping {} query results: {}", pending.len(), err);
+            return;
+        }
+
+        for (index, callback) in pending {
+            let start = (index - first) as usize * K::VALUES;
+            callback(K::output(&values[start..start + K::VALUES]));
+        }
+    }
+}
+
+impl Queries<Occlusion> {
+    /// Records beginning an occlusion query, counting samples exactly if `precise` and the
+    /// device supports it.
+    pub fn begin_occlusion(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        precise: bool,
+    ) -> Option<ActiveQuery<Occlusion>> {
+        let flags = if precise && self.device.features().occlusion_query_precise == vk::TRUE {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        };
+        self.begin(command_buffer, flags)
+    }
+}
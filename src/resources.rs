@@ -0,0 +1,639 @@
+//! Owned wrappers around Vulkan handles.
+//!
+//! Every wrapper destroys its handle on drop and keeps whatever it was created from alive through
+//! an `Arc`, so children always go before their parents: objects before the [`Device`], the
+//! device and [`Surface`] before the [`Instance`], and so on, regardless of the order they are
+//! stored or dropped in.
+
+use crate::debug::DebugCallback;
+use crate::ext::{DynamicRendering, ExtensionSupport, Synchronization2};
+use crate::swapchain::find_memory_type;
+use ash::extensions::khr;
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+pub struct Instance {
+    entry: ash::Entry,
+    instance: ash::Instance,
+    // referenced by the layers through the create info chained into vkCreateInstance, so it has
+    // to stay alive until vkDestroyInstance has returned
+    _debug_callback: Option<Arc<DebugCallback>>,
+}
+
+impl Instance {
+    pub fn new(
+        entry: ash::Entry,
+        instance: ash::Instance,
+        debug_callback: Option<Arc<DebugCallback>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            entry,
+            instance,
+            _debug_callback: debug_callback,
+        })
+    }
+
+    pub fn entry(&self) -> &ash::Entry {
+        &self.entry
+    }
+}
+
+impl Deref for Instance {
+    type Target = ash::Instance;
+
+    fn deref(&self) -> &ash::Instance {
+        &self.instance
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy_instance(None) };
+    }
+}
+
+pub struct Surface {
+    loader: khr::Surface,
+    handle: vk::SurfaceKHR,
+    _instance: Arc<Instance>,
+}
+
+impl Surface {
+    pub fn new(instance: &Arc<Instance>, loader: khr::Surface, handle: vk::SurfaceKHR) -> Self {
+        Self {
+            loader,
+            handle,
+            _instance: Arc::clone(instance),
+        }
+    }
+
+    pub fn loader(&self) -> &khr::Surface {
+        &self.loader
+    }
+
+    pub fn handle(&self) -> vk::SurfaceKHR {
+        self.handle
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_surface(self.handle, None) };
+    }
+}
+
+pub struct Device {
+    device: ash::Device,
+    physical_device: vk::PhysicalDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    limits: vk::PhysicalDeviceLimits,
+    dynamic_rendering: Option<DynamicRendering>,
+    synchronization2: Option<Synchronization2>,
+    draw_indirect_count: bool,
+    timeline_semaphore: bool,
+    features: vk::PhysicalDeviceFeatures,
+    instance: Arc<Instance>,
+}
+
+impl Device {
+    /// Wraps `device`, which was created with `features` and the extensions and features of
+    /// `extensions`.
+    pub fn new(
+        instance: &Arc<Instance>,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        extensions: ExtensionSupport,
+        features: vk::PhysicalDeviceFeatures,
+    ) -> Arc<Self> {
+        let (memory_properties, properties) = unsafe {
+            (
+                instance.get_physical_device_memory_properties(physical_device),
+                instance.get_physical_device_properties(physical_device),
+            )
+        };
+        let dynamic_rendering = extensions
+            .dynamic_rendering
+            .then(|| DynamicRendering::new(instance, &device));
+        let synchronization2 = extensions
+            .synchronization2
+            .then(|| Synchronization2::new(instance, &device));
+
+        Arc::new(Self {
+            device,
+            physical_device,
+            memory_properties,
+            limits: properties.limits,
+            dynamic_rendering,
+            synchronization2,
+            draw_indirect_count: extensions.draw_indirect_count,
+            timeline_semaphore: extensions.timeline_semaphore,
+            features,
+            instance: Arc::clone(instance),
+        })
+    }
+
+    /// `VK_KHR_dynamic_rendering`, if the device supports it. Rendering then begins without
+    /// render pass and framebuffer objects.
+    pub fn dynamic_rendering(&self) -> Option<&DynamicRendering> {
+        self.dynamic_rendering.as_ref()
+    }
+
+    /// `VK_KHR_synchronization2`, if the device supports it.
+    pub fn synchronization2(&self) -> Option<&Synchronization2> {
+        self.synchronization2.as_ref()
+    }
+
+    /// Whether indirect draws can read their count from a buffer, with
+    /// `cmd_draw_indexed_indirect_count`.
+    pub fn draw_indirect_count(&self) -> bool {
+        self.draw_indirect_count
+    }
+
+    /// Whether one indirect draw can draw several commands, each with its own first instance.
+    pub fn multi_draw_indirect(&self) -> bool {
+        self.features.multi_draw_indirect == vk::TRUE
+            && self.features.draw_indirect_first_instance == vk::TRUE
+    }
+
+    /// Whether semaphores can be created as timelines, see [`Semaphore::timeline`].
+    pub fn timeline_semaphore(&self) -> bool {
+        self.timeline_semaphore
+    }
+
+    /// The core features the device was created with.
+    pub fn features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.features
+    }
+
+    pub fn instance(&self) -> &Arc<Instance> {
+        &self.instance
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    pub fn limits(&self) -> &vk::PhysicalDeviceLimits {
+        &self.limits
+    }
+
+    /// Allocates memory satisfying `requirements` from the first memory type with `properties`.
+    pub fn allocate_memory(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> vk::DeviceMemory {
+        let memory_type_index = find_memory_type(
+            &self.memory_properties,
+            requirements.memory_type_bits,
+            properties,
+        )
+        .expect("failed to find a suitable memory type!");
+
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+
+        unsafe {
+            self.device
+                .allocate_memory(&alloc_info, None)
+                .expect("failed to allocate device memory!")
+        }
+    }
+}
+
+impl Deref for Device {
+    type Target = ash::Device;
+
+    fn deref(&self) -> &ash::Device {
+        &self.device
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_device(None) };
+    }
+}
+
+/// Defines a wrapper owning a handle created from a [`Device`] and destroyed with `$destroy`.
+macro_rules! device_handle {
+    ($(#[$meta:meta])* $name:ident, $handle:ty, $destroy:ident) => {
+        $(#[$meta])*
+        pub struct $name {
+            device: Arc<Device>,
+            handle: $handle,
+        }
+
+        impl $name {
+            /// Takes ownership of `handle`, which must have been created from `device`.
+            pub fn from_raw(device: &Arc<Device>, handle: $handle) -> Self {
+                Self {
+                    device: Arc::clone(device),
+                    handle,
+                }
+            }
+
+            pub fn handle(&self) -> $handle {
+                self.handle
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                unsafe { self.device.$destroy(self.handle, None) };
+            }
+        }
+    };
+}
+
+device_handle!(ImageView, vk::ImageView, destroy_image_view);
+device_handle!(Framebuffer, vk::Framebuffer, destroy_framebuffer);
+device_handle!(RenderPass, vk::RenderPass, destroy_render_pass);
+device_handle!(PipelineLayout, vk::PipelineLayout, destroy_pipeline_layout);
+device_handle!(Pipeline, vk::Pipeline, destroy_pipeline);
+device_handle!(ShaderModule, vk::ShaderModule, destroy_shader_module);
+device_handle!(
+    /// Command buffers allocated from the pool are freed along with it.
+    CommandPool,
+    vk::CommandPool,
+    destroy_command_pool
+);
+device_handle!(Semaphore, vk::Semaphore, destroy_semaphore);
+device_handle!(Fence, vk::Fence, destroy_fence);
+device_handle!(Sampler, vk::Sampler, destroy_sampler);
+device_handle!(QueryPool, vk::QueryPool, destroy_query_pool);
+device_handle!(DeviceMemory, vk::DeviceMemory, free_memory);
+device_handle!(
+    /// An image bound to memory it does not own, such as memory shared with other images that are
+    /// never in use at the same time.
+    AliasedImage,
+    vk::Image,
+    destroy_image
+);
+device_handle!(
+    DescriptorSetLayout,
+    vk::DescriptorSetLayout,
+    destroy_descriptor_set_layout
+);
+device_handle!(
+    /// Descriptor sets allocated from the pool are freed along with it.
+    DescriptorPool,
+    vk::DescriptorPool,
+    destroy_descriptor_pool
+);
+
+impl ShaderModule {
+    /// Creates a module from the SPIR-V file at `path`.
+    pub fn load(device: &Arc<Device>, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let code = File::open(path)
+            .and_then(|mut file| ash::util::read_spv(&mut file))
+            .unwrap_or_else(|err| panic!("could not read {}: {}", path.display(), err));
+        let create_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+
+        let handle = unsafe {
+            device
+                .create_shader_module(&create_info, None)
+                .expect("failed to create shader module!")
+        };
+
+        Self::from_raw(device, handle)
+    }
+}
+
+impl Semaphore {
+    pub fn new(device: &Arc<Device>) -> Self {
+        let semaphore_info = vk::SemaphoreCreateInfo {
+            s_type: vk::StructureType::SEMAPHORE_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: vk::SemaphoreCreateFlags::empty(),
+        };
+
+        let handle = unsafe {
+            device
+                .create_semaphore(&semaphore_info, None)
+                .expect("failed to create semaphore!")
+        };
+
+        Self::from_raw(device, handle)
+    }
+
+    /// A timeline semaphore starting at `initial_value`. The device needs the `timelineSemaphore`
+    /// feature, see [`Device::timeline_semaphore`].
+    pub fn timeline(device: &Arc<Device>, initial_value: u64) -> Self {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+
+        let handle = unsafe {
+            device
+                .create_semaphore(&semaphore_info, None)
+                .expect("failed to create semaphore!")
+        };
+
+        Self::from_raw(device, handle)
+    }
+}
+
+impl Fence {
+    pub fn new(device: &Arc<Device>, signaled: bool) -> Self {
+        let fence_info = vk::FenceCreateInfo {
+            s_type: vk::StructureType::FENCE_CREATE_INFO,
+            p_next: std::ptr::null(),
+            flags: if signaled {
+                vk::FenceCreateFlags::SIGNALED
+            } else {
+                vk::FenceCreateFlags::empty()
+            },
+        };
+
+        let handle = unsafe {
+            device
+                .create_fence(&fence_info, None)
+                .expect("failed to create fence!")
+        };
+
+        Self::from_raw(device, handle)
+    }
+}
+
+/// A buffer along with the memory bound to it.
+pub struct Buffer {
+    device: Arc<Device>,
+    handle: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+}
+
+impl Buffer {
+    pub fn new(
+        device: &Arc<Device>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Self {
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let handle = unsafe {
+            device
+                .create_buffer(&buffer_info, None)
+                .expect("failed to create buffer!")
+        };
+
+        let requirements = unsafe { device.get_buffer_memory_requirements(handle) };
+        let memory = device.allocate_memory(requirements, properties);
+
+        unsafe {
+            device
+                .bind_buffer_memory(handle, memory, 0)
+                .expect("failed to bind buffer memory!");
+        }
+
+        Self {
+            device: Arc::clone(device),
+            handle,
+            memory,
+            size,
+        }
+    }
+
+    pub fn handle(&self) -> vk::Buffer {
+        self.handle
+    }
+
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Copies `data` to the start of the buffer, which must be host visible and coherent.
+    pub fn write<T: Copy>(&self, data: &[T]) {
+        self.write_at(0, data);
+    }
+
+    /// Replaces `buffer` with a host visible and coherent one of at least `size` bytes if it is
+    /// smaller. Sizes are rounded up to a power of two, so data that grows a little each frame
+    /// does not mean a new buffer each frame. The old buffer must not be in use.
+    pub fn grow(
+        device: &Arc<Device>,
+        buffer: &mut Option<Buffer>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) {
+        if !matches!(buffer, Some(buffer) if buffer.size() >= size) {
+            *buffer = Some(Self::new(
+                device,
+                size.max(4 * 1024).next_power_of_two(),
+                usage,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ));
+        }
+    }
+
+    /// Writes `data` to the start of `buffer`, first growing it with [`Self::grow`] if it does
+    /// not fit.
+    pub fn write_growing<T: Copy>(
+        device: &Arc<Device>,
+        buffer: &mut Option<Buffer>,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        Self::grow(device, buffer, size, usage);
+        if let Some(buffer) = buffer {
+            buffer.write(data);
+        }
+    }
+
+    /// Copies `data` to `offset` bytes into the buffer, which must be host visible and coherent.
+    pub fn write_at<T: Copy>(&self, offset: vk::DeviceSize, data: &[T]) {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if size == 0 {
+            return;
+        }
+        assert!(offset + size <= self.size, "write out of buffer bounds");
+
+        unsafe {
+            let ptr = self
+                .device
+                .map_memory(self.memory, offset, size, vk::MemoryMapFlags::empty())
+                .expect("failed to map buffer memory!");
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                ptr as *mut u8,
+                size as usize,
+            );
+            self.device.unmap_memory(self.memory);
+        }
+    }
+
+    /// Copies `count` values from the start of the buffer, which must be host visible and
+    /// coherent.
+    pub fn read<T: Copy + Default>(&self, count: usize) -> Vec<T> {
+        let mut data = vec![T::default(); count];
+        let size = std::mem::size_of_val(data.as_slice()) as vk::DeviceSize;
+        if size == 0 {
+            return data;
+        }
+        assert!(size <= self.size, "read out of buffer bounds");
+
+        unsafe {
+            let ptr = self
+                .device
+                .map_memory(self.memory, 0, size, vk::MemoryMapFlags::empty())
+                .expect("failed to map buffer memory!");
+            std::ptr::copy_nonoverlapping(
+                ptr as *const u8,
+                data.as_mut_ptr() as *mut u8,
+                size as usize,
+            );
+            self.device.unmap_memory(self.memory);
+        }
+        data
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer(self.handle, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// An image along with the memory bound to it.
+pub struct Image {
+    device: Arc<Device>,
+    handle: vk::Image,
+    memory: vk::DeviceMemory,
+}
+
+impl Image {
+    pub fn new(
+        device: &Arc<Device>,
+        image_info: &vk::ImageCreateInfo,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Self {
+        let handle = unsafe {
+            device
+                .create_image(image_info, None)
+                .expect("failed to create image!")
+        };
+
+        let requirements = unsafe { device.get_image_memory_requirements(handle) };
+        let memory = device.allocate_memory(requirements, properties);
+
+        unsafe {
+            device
+                .bind_image_memory(handle, memory, 0)
+                .expect("failed to bind image memory!");
+        }
+
+        Self {
+            device: Arc::clone(device),
+            handle,
+            memory,
+        }
+    }
+
+    pub fn handle(&self) -> vk::Image {
+        self.handle
+    }
+
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image(self.handle, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+impl ImageView {
+    pub fn new(
+        device: &Arc<Device>,
+        image: vk::Image,
+        view_type: vk::ImageViewType,
+        format: vk::Format,
+        subresource_range: vk::ImageSubresourceRange,
+    ) -> Self {
+        let create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(view_type)
+            .format(format)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY,
+            })
+            .subresource_range(subresource_range);
+
+        let handle = unsafe {
+            device
+                .create_image_view(&create_info, None)
+                .expect("failed to create image view!")
+        };
+
+        Self::from_raw(device, handle)
+    }
+}
+
+pub struct Swapchain {
+    loader: khr::Swapchain,
+    handle: vk::SwapchainKHR,
+    _device: Arc<Device>,
+    _surface: Arc<Surface>,
+}
+
+impl Swapchain {
+    pub fn from_raw(
+        device: &Arc<Device>,
+        surface: &Arc<Surface>,
+        loader: khr::Swapchain,
+        handle: vk::SwapchainKHR,
+    ) -> Self {
+        Self {
+            loader,
+            handle,
+            _device: Arc::clone(device),
+            _surface: Arc::clone(surface),
+        }
+    }
+
+    pub fn loader(&self) -> &khr::Swapchain {
+        &self.loader
+    }
+
+    pub fn handle(&self) -> vk::SwapchainKHR {
+        self.handle
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_swapchain(self.handle, None) };
+    }
+}
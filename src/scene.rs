@@ -0,0 +1,655 @@
+//! A minimal scene graph: a hierarchy of nodes with transforms, the meshes they draw and the
+//! materials those are drawn with.
+
+use crate::light::{Light, LightUniform};
+use crate::resources::Buffer;
+use crate::texture::Texture;
+use crate::upload::{UploadHandle, UploadQueue};
+use ash::vk;
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+
+/// Vertex as read by the vertex shaders, at binding 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub uv: Vec2,
+}
+
+impl Vertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let attribute = |location, format, offset| vk::VertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset,
+        };
+        [
+            attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32B32_SFLOAT, 12),
+            attribute(2, vk::Format::R32G32_SFLOAT, 24),
+        ]
+    }
+}
+
+/// Vertices and indices of a triangle list, before they are uploaded.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// A cube with sides of length `size` centered on the origin.
+    pub fn cube(size: f32) -> Self {
+        let mut data = Self::default();
+        let half = size / 2.;
+
+        for &normal in &[Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z] {
+            // two axes spanning the face, wound counter-clockwise seen from outside
+            let tangent = if normal.y.abs() > 0.5 {
+                Vec3::X
+            } else {
+                Vec3::Y.cross(normal)
+            };
+            let bitangent = normal.cross(tangent);
+
+            let base = data.vertices.len() as u32;
+            for &(u, v) in &[(0., 0.), (1., 0.), (1., 1.), (0., 1.)] {
+                let position =
+                    (normal + tangent * (u * 2. - 1.) + bitangent * (v * 2. - 1.)) * half;
+                data.vertices.push(Vertex {
+                    position,
+                    normal,
+                    uv: Vec2::new(u, 1. - v),
+                });
+            }
+            data.indices
+                .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        data
+    }
+
+    /// Replaces the normals with smooth ones, the area weighted average of the normals of the
+    /// triangles sharing each vertex.
+    pub fn compute_normals(&mut self) {
+        for vertex in &mut self.vertices {
+            vertex.normal = Vec3::ZERO;
+        }
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+            let ab = self.vertices[b].position - self.vertices[a].position;
+            let ac = self.vertices[c].position - self.vertices[a].position;
+            // the cross product's length is twice the triangle's area
+            let normal = ab.cross(ac);
+            for i in [a, b, c] {
+                self.vertices[i].normal += normal;
+            }
+        }
+
+        for vertex in &mut self.vertices {
+            vertex.normal = vertex.normal.normalize_or_zero();
+        }
+    }
+
+    /// A square in the XZ plane with sides of length `size`, facing +Y.
+    pub fn plane(size: f32) -> Self {
+        let half = size / 2.;
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Vec3::new(x * half, 0., z * half),
+            normal: Vec3::Y,
+            uv: Vec2::new((x + 1.) / 2., (z + 1.) / 2.),
+        };
+
+        Self {
+            vertices: vec![
+                vertex(-1., -1.),
+                vertex(-1., 1.),
+                vertex(1., 1.),
+                vertex(1., -1.),
+            ],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+}
+
+/// The sizes and bounds of a triangle list added to a [`Scene`], whose vertices and indices are
+/// in the scene's [`Geometry`].
+pub struct Mesh {
+    vertex_count: u32,
+    index_count: u32,
+    bounding_sphere: Vec4,
+}
+
+impl Mesh {
+    fn new(data: &MeshData) -> Self {
+        assert!(
+            !data.vertices.is_empty() && !data.indices.is_empty(),
+            "mesh must not be empty"
+        );
+
+        // around the center of the bounding box, which is close enough to the smallest sphere
+        let (min, max) = data.vertices.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), vertex| (min.min(vertex.position), max.max(vertex.position)),
+        );
+        let center = (min + max) / 2.;
+        let radius = data
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position.distance(center))
+            .fold(0., f32::max);
+
+        Self {
+            vertex_count: data.vertices.len() as u32,
+            index_count: data.indices.len() as u32,
+            bounding_sphere: center.extend(radius),
+        }
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    /// Center and radius of a sphere around the mesh's vertices.
+    pub fn bounding_sphere(&self) -> Vec4 {
+        self.bounding_sphere
+    }
+}
+
+/// Where a mesh's vertices and indices are in a [`Geometry`], as `vk::DrawIndexedIndirectCommand`
+/// wants them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshRange {
+    pub first_index: u32,
+    pub vertex_offset: i32,
+}
+
+/// The vertices and indices of every mesh of a scene, one after the other in a buffer each, so
+/// a single indirect draw can draw different meshes.
+pub struct Geometry {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    ranges: Vec<MeshRange>,
+}
+
+impl Geometry {
+    /// Records uploading the vertices and indices of `meshes` into new device local buffers
+    /// into `uploads`. The buffers have to be kept until the returned handle resolves.
+    pub fn new(uploads: &mut UploadQueue, meshes: &[MeshData]) -> (Self, UploadHandle) {
+        let mut ranges = Vec::with_capacity(meshes.len());
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for mesh in meshes {
+            ranges.push(MeshRange {
+                first_index: indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+        // an empty scene still gets buffers, which nothing draws from
+        if meshes.is_empty() {
+            vertices.push(Vertex::default());
+            indices.push(0);
+        }
+
+        let (vertex_buffer, _) =
+            uploads.create_buffer(&vertices, vk::BufferUsageFlags::VERTEX_BUFFER);
+        // both copies are in the same batch, so the second resolves with the first
+        let (index_buffer, upload) =
+            uploads.create_buffer(&indices, vk::BufferUsageFlags::INDEX_BUFFER);
+        let geometry = Self {
+            vertex_buffer,
+            index_buffer,
+            ranges,
+        };
+        (geometry, upload)
+    }
+
+    pub fn vertex_buffer(&self) -> &Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &Buffer {
+        &self.index_buffer
+    }
+
+    /// Where the mesh `id` is in the buffers.
+    pub fn range(&self, id: MeshId) -> MeshRange {
+        self.ranges[id.0]
+    }
+}
+
+/// Which pipeline a material is drawn with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Shading {
+    /// A fixed directional light with Lambertian diffuse only, ignoring the scene's lights and
+    /// the metallic, roughness and normal parameters.
+    Simple,
+    /// Physically based shading of the metallic-roughness parameters, lit by the scene's lights
+    /// and the environment.
+    Pbr,
+}
+
+/// A metallic-roughness material, as in glTF. Each factor is multiplied with its texture, if it
+/// has one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    pub shading: Shading,
+    /// Linear RGBA.
+    pub base_color: Vec4,
+    pub base_color_texture: Option<TextureId>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Metalness is read from the blue channel and roughness from the green one.
+    pub metallic_roughness_texture: Option<TextureId>,
+    pub normal_texture: Option<TextureId>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureId>,
+    pub occlusion_strength: f32,
+    /// Linear RGB.
+    pub emissive: Vec3,
+    pub emissive_texture: Option<TextureId>,
+}
+
+impl Material {
+    pub fn textures(&self) -> [Option<TextureId>; 5] {
+        [
+            self.base_color_texture,
+            self.metallic_roughness_texture,
+            self.normal_texture,
+            self.occlusion_texture,
+            self.emissive_texture,
+        ]
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            shading: Shading::Pbr,
+            base_color: Vec4::ONE,
+            base_color_texture: None,
+            metallic: 0.,
+            roughness: 1.,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.,
+            occlusion_texture: None,
+            occlusion_strength: 1.,
+            emissive: Vec3::ZERO,
+            emissive_texture: None,
+        }
+    }
+}
+
+/// Material factors pushed to the shaders as push constants. The model matrix of each instance
+/// comes from the instance buffer, see [`InstanceData`](crate::draw::InstanceData).
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialPushConstants {
+    pub base_color: Vec4,
+    pub emissive: Vec4,
+    /// Metallic, roughness, normal scale and occlusion strength.
+    pub material: Vec4,
+}
+
+impl MaterialPushConstants {
+    pub fn new(material: &Material) -> Self {
+        Self {
+            base_color: material.base_color,
+            emissive: material.emissive.extend(0.),
+            material: Vec4::new(
+                material.metallic,
+                material.roughness,
+                material.normal_scale,
+                material.occlusion_strength,
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl From<Mat4> for Transform {
+    fn from(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshId(usize);
+
+impl MeshId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+impl MaterialId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureId(usize);
+
+impl TextureId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    /// Transform relative to the parent node.
+    pub transform: Transform,
+    pub mesh: Option<MeshId>,
+    pub material: Option<MaterialId>,
+    pub light: Option<Light>,
+    world: Mat4,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    /// Transform relative to the scene, as of the last [`Scene::update_transforms`].
+    pub fn world_transform(&self) -> Mat4 {
+        self.world
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// A mesh to draw, with the world transform and material to draw it with.
+pub struct DrawItem<'a> {
+    pub node: NodeId,
+    pub mesh_id: MeshId,
+    pub mesh: &'a Mesh,
+    pub material_id: MaterialId,
+    pub material: &'a Material,
+    pub transform: Mat4,
+}
+
+/// A light placed by its node's world transform.
+pub struct LightItem<'a> {
+    pub node: NodeId,
+    pub light: &'a Light,
+    pub transform: Mat4,
+}
+
+impl LightItem<'_> {
+    pub fn uniform(&self) -> LightUniform {
+        LightUniform::new(self.light, self.transform)
+    }
+}
+
+/// Nodes are only ever added after their parent, so parents always come before their children
+/// and world transforms can be updated in a single pass.
+pub struct Scene {
+    nodes: Vec<Node>,
+    meshes: Vec<Mesh>,
+    /// The vertices and indices of the meshes, kept on the host until [`Scene::merge_meshes`].
+    mesh_data: Vec<MeshData>,
+    geometry: Option<Geometry>,
+    materials: Vec<Material>,
+    textures: Vec<Texture>,
+    /// The copies of the textures and the geometry.
+    uploads: Vec<UploadHandle>,
+}
+
+impl Scene {
+    /// Used by nodes that have a mesh but no material: plain white and fully rough.
+    pub const DEFAULT_MATERIAL: MaterialId = MaterialId(0);
+
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            meshes: Vec::new(),
+            mesh_data: Vec::new(),
+            geometry: None,
+            materials: vec![Material::default()],
+            textures: Vec::new(),
+            uploads: Vec::new(),
+        }
+    }
+
+    /// Adds a mesh, which is uploaded by [`Scene::merge_meshes`]. Meshes cannot be added once they
+    /// have been merged.
+    pub fn add_mesh(&mut self, data: MeshData) -> MeshId {
+        assert!(
+            self.geometry.is_none(),
+            "meshes have to be added before they are merged"
+        );
+        self.meshes.push(Mesh::new(&data));
+        self.mesh_data.push(data);
+        MeshId(self.meshes.len() - 1)
+    }
+
+    /// Records uploading every mesh into one [`Geometry`], which indirect draws are drawn from,
+    /// and frees their vertices and indices on the host.
+    pub fn merge_meshes(&mut self, uploads: &mut UploadQueue) {
+        let (geometry, upload) = Geometry::new(uploads, &self.mesh_data);
+        self.geometry = Some(geometry);
+        self.uploads.push(upload);
+        self.mesh_data = Vec::new();
+    }
+
+    /// Whether the copies of the textures and the geometry have completed, see
+    /// [`UploadHandle::is_resident`].
+    pub fn is_resident(&self) -> bool {
+        self.uploads.iter().all(UploadHandle::is_resident)
+    }
+
+    /// The meshes merged by [`Scene::merge_meshes`], once it has been called.
+    pub fn geometry(&self) -> Option<&Geometry> {
+        self.geometry.as_ref()
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        MaterialId(self.materials.len() - 1)
+    }
+
+    /// Adds a texture, whose copy `upload` is, see [`Texture::queue`].
+    pub fn add_texture(&mut self, (texture, upload): (Texture, UploadHandle)) -> TextureId {
+        self.textures.push(texture);
+        self.uploads.push(upload);
+        TextureId(self.textures.len() - 1)
+    }
+
+    pub fn add_node(
+        &mut self,
+        parent: Option<NodeId>,
+        name: impl Into<String>,
+        transform: Transform,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        let world = match parent {
+            Some(parent) => {
+                let parent = &mut self.nodes[parent.0];
+                parent.children.push(id);
+                parent.world * transform.matrix()
+            }
+            None => transform.matrix(),
+        };
+
+        self.nodes.push(Node {
+            name: name.into(),
+            transform,
+            mesh: None,
+            material: None,
+            light: None,
+            world,
+            parent,
+            children: Vec::new(),
+        });
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId(i), node))
+    }
+
+    /// Nodes without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| id)
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<NodeId> {
+        self.nodes()
+            .find(|(_, node)| node.name == name)
+            .map(|(id, _)| id)
+    }
+
+    pub fn mesh(&self, id: MeshId) -> &Mesh {
+        &self.meshes[id.0]
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn material(&self, id: MaterialId) -> &Material {
+        &self.materials[id.0]
+    }
+
+    pub fn material_mut(&mut self, id: MaterialId) -> &mut Material {
+        &mut self.materials[id.0]
+    }
+
+    /// All materials, indexed by [`MaterialId::index`].
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn texture(&self, id: TextureId) -> &Texture {
+        &self.textures[id.0]
+    }
+
+    /// All textures, indexed by [`TextureId::index`].
+    pub fn textures(&self) -> &[Texture] {
+        &self.textures
+    }
+
+    /// Recomputes every node's world transform from the local transforms.
+    pub fn update_transforms(&mut self) {
+        for i in 0..self.nodes.len() {
+            let local = self.nodes[i].transform.matrix();
+            self.nodes[i].world = match self.nodes[i].parent {
+                Some(parent) => self.nodes[parent.0].world * local,
+                None => local,
+            };
+        }
+    }
+
+    /// Everything there is to draw, in node order.
+    pub fn draw_items(&self) -> impl Iterator<Item = DrawItem<'_>> {
+        self.nodes().filter_map(move |(id, node)| {
+            let mesh = node.mesh?;
+            let material_id = node.material.unwrap_or(Self::DEFAULT_MATERIAL);
+            Some(DrawItem {
+                node: id,
+                mesh_id: mesh,
+                mesh: &self.meshes[mesh.0],
+                material_id,
+                material: &self.materials[material_id.0],
+                transform: node.world,
+            })
+        })
+    }
+
+    /// Every light in the scene, in node order.
+    pub fn light_items(&self) -> impl Iterator<Item = LightItem<'_>> {
+        self.nodes().filter_map(|(id, node)| {
+            Some(LightItem {
+                node: id,
+                light: node.light.as_ref()?,
+                transform: node.world,
+            })
+        })
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,299 @@
+//! Shadow maps: depth rendered from each shadow casting light into a layer of one array image,
+//! which the PBR shader compares against with percentage closer filtering. Directional lights get
+//! a cascade of layers, each covering a slice of the camera's view, and spot lights one layer.
+
+use crate::camera::Camera;
+use crate::light::{Light, LightKind, LightUniform};
+use crate::resources::{Device, Image, ImageView, Sampler};
+use ash::version::DeviceV1_0;
+use ash::vk;
+use glam::{Mat4, Vec3, Vec4};
+use std::sync::Arc;
+
+/// The one depth format every device can both render to and sample.
+pub const SHADOW_FORMAT: vk::Format = vk::Format::D16_UNORM;
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+pub const CASCADE_COUNT: usize = 4;
+/// Layers of the shadow map. Shadow casting lights beyond what fits cast no shadows.
+pub const MAX_SHADOW_LAYERS: usize = 8;
+
+/// Cascades cover the view up to this distance, or up to the camera's far plane if it is closer.
+const SHADOW_DISTANCE: f32 = 40.;
+/// Blend between uniformly (0) and logarithmically (1) spaced cascade splits.
+const SPLIT_BLEND: f32 = 0.8;
+/// How far beyond a cascade, towards the light, meshes still cast shadows into it.
+const CASTER_DISTANCE: f32 = 50.;
+const SPOT_NEAR: f32 = 0.05;
+/// Far plane of spot lights without a range.
+const SPOT_FAR: f32 = 50.;
+
+/// Shadow data as laid out in the `Shadows` uniform block of the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowUniform {
+    /// World to shadow map clip space of each layer.
+    pub matrices: [Mat4; MAX_SHADOW_LAYERS],
+    /// View space distance at which each cascade ends.
+    pub cascade_splits: Vec4,
+    /// Layers in use, which are the ones rendered.
+    pub layer_count: u32,
+    _padding: [u32; 3],
+}
+
+impl ShadowUniform {
+    /// Gives shadow casting `lights` layers of the shadow map in order, until it is full, and
+    /// records the first layer of each in its uniform. Cascades are fit to the view of `camera`.
+    pub fn new<'a>(
+        camera: &Camera,
+        aspect: f32,
+        lights: impl IntoIterator<Item = (&'a Light, &'a mut LightUniform)>,
+    ) -> Self {
+        let near = camera.projection.near();
+        let far = camera.projection.far().min(SHADOW_DISTANCE);
+        let splits = cascade_splits(near, far);
+
+        let mut shadows = Self {
+            matrices: [Mat4::IDENTITY; MAX_SHADOW_LAYERS],
+            cascade_splits: Vec4::from(splits),
+            layer_count: 0,
+            _padding: [0; 3],
+        };
+
+        for (light, uniform) in lights {
+            if !light.casts_shadows {
+                continue;
+            }
+
+            let first = shadows.layer_count as usize;
+            let direction = uniform.direction.truncate();
+            match light.kind {
+                LightKind::Directional if first + CASCADE_COUNT <= MAX_SHADOW_LAYERS => {
+                    let mut cascade_near = near;
+                    for (i, &split) in splits.iter().enumerate() {
+                        let corners = camera.frustum_corners(aspect, cascade_near, split);
+                        shadows.matrices[first + i] = cascade_matrix(&corners, direction);
+                        cascade_near = split;
+                    }
+                    shadows.layer_count += CASCADE_COUNT as u32;
+                }
+                LightKind::Spot {
+                    range,
+                    outer_cone_angle,
+                    ..
+                } if first < MAX_SHADOW_LAYERS => {
+                    // the cone is at most a hemisphere, which no perspective projection covers
+                    let fov = (2. * outer_cone_angle).min(170f32.to_radians());
+                    let projection =
+                        Mat4::perspective_rh(fov, 1., SPOT_NEAR, range.unwrap_or(SPOT_FAR));
+                    let view = Mat4::look_to_rh(
+                        uniform.position.truncate(),
+                        direction,
+                        up_vector(direction),
+                    );
+                    shadows.matrices[first] = projection * view;
+                    shadows.layer_count += 1;
+                }
+                _ => continue,
+            }
+            uniform.cone.z = first as f32;
+        }
+
+        shadows
+    }
+}
+
+/// Where each cascade ends, between `near` and `far`.
+fn cascade_splits(near: f32, far: f32) -> [f32; CASCADE_COUNT] {
+    let mut splits = [far; CASCADE_COUNT];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let fraction = (i + 1) as f32 / CASCADE_COUNT as f32;
+        let uniform = near + (far - near) * fraction;
+        let logarithmic = near * (far / near).powf(fraction);
+        *split = uniform + (logarithmic - uniform) * SPLIT_BLEND;
+    }
+    splits
+}
+
+/// An orthographic projection along `direction` that covers the bounding sphere of `corners`.
+/// The sphere keeps the projection's size from changing as the camera turns, and its position
+/// is snapped to whole texels, so shadow edges do not shimmer as the camera moves.
+fn cascade_matrix(corners: &[Vec3; 8], direction: Vec3) -> Mat4 {
+    let center = corners.iter().fold(Vec3::ZERO, |sum, &corner| sum + corner) / 8.;
+    let radius = corners
+        .iter()
+        .map(|corner| corner.distance(center))
+        .fold(0., f32::max);
+    let radius = (radius * 16.).ceil() / 16.;
+
+    let view = Mat4::look_to_rh(Vec3::ZERO, direction, up_vector(direction));
+    let center = view.transform_point3(center);
+    let texel = 2. * radius / SHADOW_MAP_SIZE as f32;
+    let x = (center.x / texel).floor() * texel;
+    let y = (center.y / texel).floor() * texel;
+
+    // view space looks down -Z, so the center is -center.z in front of the light
+    let projection = Mat4::orthographic_rh(
+        x - radius,
+        x + radius,
+        y - radius,
+        y + radius,
+        -center.z - radius - CASTER_DISTANCE,
+        -center.z + radius,
+    );
+    projection * view
+}
+
+/// An up vector for looking along `direction` that is not parallel to it.
+fn up_vector(direction: Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 {
+        Vec3::Z
+    } else {
+        Vec3::Y
+    }
+}
+
+/// The depth aspect of `layer_count` layers of the shadow map from `base_array_layer` on.
+fn layer_range(base_array_layer: u32, layer_count: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::DEPTH,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer,
+        layer_count,
+    }
+}
+
+/// Push constants of the shadow pipeline, as laid out in `shadow.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowPushConstants {
+    /// Layer of the shadow map rendered to. The model matrices come from the instance buffer.
+    pub layer: u32,
+}
+
+impl ShadowPushConstants {
+    pub fn new(layer: u32) -> Self {
+        Self { layer }
+    }
+}
+
+/// The layered depth image lights render their shadows into, with a view per layer for rendering
+/// and a view of all layers for sampling. Layers are in `DEPTH_STENCIL_READ_ONLY_OPTIMAL` layout
+/// outside of the shadow passes, once [`ShadowMap::record_initialize`] has been recorded.
+pub struct ShadowMap {
+    pub sampler: Sampler,
+    pub layer_views: Vec<ImageView>,
+    pub view: ImageView,
+    pub image: Image,
+    initialized: bool,
+}
+
+impl ShadowMap {
+    pub fn new(device: &Arc<Device>) -> Self {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(SHADOW_FORMAT)
+            .extent(vk::Extent3D {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(MAX_SHADOW_LAYERS as u32)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = Image::new(device, &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+
+        let view = ImageView::new(
+            device,
+            image.handle(),
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            SHADOW_FORMAT,
+            layer_range(0, MAX_SHADOW_LAYERS as u32),
+        );
+        let layer_views = (0..MAX_SHADOW_LAYERS as u32)
+            .map(|layer| {
+                ImageView::new(
+                    device,
+                    image.handle(),
+                    vk::ImageViewType::TYPE_2D,
+                    SHADOW_FORMAT,
+                    layer_range(layer, 1),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // compares against the reference depth and filters the results of the four texels
+        // around it; outside the map everything is lit
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+            .compare_enable(true)
+            .compare_op(vk::CompareOp::LESS_OR_EQUAL);
+
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .expect("failed to create sampler!")
+        };
+
+        Self {
+            sampler: Sampler::from_raw(device, sampler),
+            layer_views,
+            view,
+            image,
+            initialized: false,
+        }
+    }
+
+    /// Records moving every layer into the layout it is sampled in, the first time it is called,
+    /// which has to be before the map is first used.
+    pub fn record_initialize(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if std::mem::replace(&mut self.initialized, true) {
+            return;
+        }
+
+        // layers no light renders into are still sampled through the array view, so they must
+        // be in the same layout as those that are
+        let to_read_only = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image.handle())
+            .subresource_range(layer_range(0, MAX_SHADOW_LAYERS as u32))
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_read_only],
+            );
+        }
+    }
+
+    /// Descriptor for binding all layers, as a sampled image or as a comparison sampler.
+    pub fn descriptor(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler.handle(),
+            image_view: self.view.handle(),
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        }
+    }
+}
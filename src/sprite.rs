@@ -0,0 +1,454 @@
+//! Batched 2D drawing of rectangles, lines, circles and textured sprites, in the pixel
+//! coordinates of the target.
+//!
+//! Every shape is one instance of a quad the vertex shader expands, read from a per-frame
+//! instance buffer. Shapes are drawn in layers; within a layer they are grouped by texture, so a
+//! frame binds each texture at most once per layer.
+
+use crate::resources::{Buffer, DescriptorPool, DescriptorSetLayout, Device};
+use crate::texture::{Texture, TextureData};
+use crate::upload::UploadQueue;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use glam::{Mat4, Vec2};
+use std::sync::Arc;
+
+/// Textures a [`SpriteBatch`] can have, including its plain white one.
+pub const MAX_SPRITE_TEXTURES: u32 = 16;
+
+/// What the fragment shader makes of an instance's quad.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    /// The whole quad, for rectangles, lines and sprites.
+    Quad = 0,
+    /// The circle inscribed in the quad.
+    Circle = 1,
+}
+
+/// Per-instance data, at binding 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpriteInstance {
+    /// Center in pixels.
+    pub center: Vec2,
+    pub half_size: Vec2,
+    /// Corner of the texture at the quad's top left, before rotation.
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+    /// Gamma encoded, not premultiplied. Multiplies the texture.
+    pub color: [f32; 4],
+    /// Clockwise around the center, in radians.
+    pub rotation: f32,
+    pub shape: Shape,
+    _padding: [u32; 2],
+}
+
+impl SpriteInstance {
+    pub fn new(center: Vec2, size: Vec2, color: [f32; 4], shape: Shape) -> Self {
+        Self {
+            center,
+            half_size: size / 2.,
+            uv_min: Vec2::ZERO,
+            uv_max: Vec2::ONE,
+            color,
+            rotation: 0.,
+            shape,
+            _padding: [0; 2],
+        }
+    }
+
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<SpriteInstance>() as u32,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 7] {
+        let attribute = |location, format, offset| vk::VertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset,
+        };
+        [
+            attribute(0, vk::Format::R32G32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32_SFLOAT, 8),
+            attribute(2, vk::Format::R32G32_SFLOAT, 16),
+            attribute(3, vk::Format::R32G32_SFLOAT, 24),
+            attribute(4, vk::Format::R32G32B32A32_SFLOAT, 32),
+            attribute(5, vk::Format::R32_SFLOAT, 48),
+            attribute(6, vk::Format::R32_UINT, 52),
+        ]
+    }
+}
+
+/// Push constants of the sprite pipeline.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SpritePushConstants {
+    pub projection: Mat4,
+    /// Whether the target encodes to sRGB when written to, so the shader has to decode colors
+    /// first.
+    pub srgb_target: u32,
+    _padding: [u32; 3],
+}
+
+impl SpritePushConstants {
+    /// Constants for a target of `extent`, whose pixel coordinates go from the top left corner
+    /// to the bottom right one.
+    pub fn new(extent: vk::Extent2D, srgb_target: bool) -> Self {
+        Self {
+            projection: pixel_projection(extent),
+            srgb_target: srgb_target as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Orthographic projection mapping pixel coordinates of a target of `extent` onto it, with the
+/// origin in the top left corner and y pointing down like Vulkan's clip space.
+pub fn pixel_projection(extent: vk::Extent2D) -> Mat4 {
+    Mat4::orthographic_rh(0., extent.width as f32, 0., extent.height as f32, -1., 1.)
+}
+
+/// A texture added to a [`SpriteBatch`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpriteTexture(u32);
+
+/// A run of instances drawn with the same texture.
+#[derive(Debug, PartialEq)]
+struct DrawCall {
+    texture: SpriteTexture,
+    first_instance: u32,
+    instance_count: u32,
+}
+
+/// Sorts `queued` instances, with their layer and texture, by layer and then texture, returning
+/// the runs to draw them in and the instances in that order.
+fn batch(
+    mut queued: Vec<(i32, SpriteTexture, SpriteInstance)>,
+) -> (Vec<DrawCall>, Vec<SpriteInstance>) {
+    // stable, so shapes with the same layer and texture keep their order
+    queued.sort_by_key(|&(layer, texture, _)| (layer, texture));
+
+    let mut draw_calls = Vec::<DrawCall>::new();
+    for (i, &(_, texture, _)) in queued.iter().enumerate() {
+        match draw_calls.last_mut() {
+            Some(draw) if draw.texture == texture => draw.instance_count += 1,
+            _ => draw_calls.push(DrawCall {
+                texture,
+                first_instance: i as u32,
+                instance_count: 1,
+            }),
+        }
+    }
+
+    let instances = queued
+        .into_iter()
+        .map(|(_, _, instance)| instance)
+        .collect();
+    (draw_calls, instances)
+}
+
+/// Collects the shapes of a frame, and draws them in as few batches as their layers and textures
+/// allow.
+pub struct SpriteBatch {
+    /// Instances queued since the last upload, with their layer and texture.
+    queued: Vec<(i32, SpriteTexture, SpriteInstance)>,
+    layer: i32,
+    draw_calls: Vec<DrawCall>,
+    // one per frame in flight, grown when a frame's shapes no longer fit
+    instance_buffers: Vec<Option<Buffer>>,
+    /// The set each texture is bound with, indexed by [`SpriteTexture`].
+    sets: Vec<vk::DescriptorSet>,
+    white: Texture,
+    pool: DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    device: Arc<Device>,
+}
+
+impl SpriteBatch {
+    /// Creates a batch whose textures are bound with sets of `set_layout`, which has one sampled
+    /// image and one sampler.
+    pub fn new(
+        uploads: &mut UploadQueue,
+        set_layout: &DescriptorSetLayout,
+        frames_in_flight: usize,
+    ) -> Self {
+        let device = Arc::clone(uploads.device());
+        let pool_sizes = [
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::SAMPLER,
+        ]
+        .map(|ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: MAX_SPRITE_TEXTURES,
+        });
+
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(MAX_SPRITE_TEXTURES)
+            .pool_sizes(&pool_sizes);
+
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .expect("failed to create descriptor pool!")
+        };
+
+        // frames submitted after the copy can sample it, so the handle is not needed
+        let (white, _) = Texture::queue(
+            uploads,
+            &TextureData::solid([255; 4], true),
+            &Default::default(),
+        );
+
+        let mut batch = Self {
+            queued: Vec::new(),
+            layer: 0,
+            draw_calls: Vec::new(),
+            instance_buffers: (0..frames_in_flight).map(|_| None).collect(),
+            sets: Vec::new(),
+            pool: DescriptorPool::from_raw(&device, pool),
+            set_layout: set_layout.handle(),
+            device,
+            white,
+        };
+        let white = batch.white.descriptor();
+        batch.add_texture(white);
+        batch
+    }
+
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Makes the texture of `image_info` available to sprites. It has to outlive the batch, or
+    /// at least every frame drawing it.
+    pub fn add_texture(&mut self, image_info: vk::DescriptorImageInfo) -> SpriteTexture {
+        assert!(
+            (self.sets.len() as u32) < MAX_SPRITE_TEXTURES,
+            "too many sprite textures"
+        );
+
+        let layouts = [self.set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool.handle())
+            .set_layouts(&layouts);
+
+        let set = unsafe {
+            self.device
+                .allocate_descriptor_sets(&alloc_info)
+                .expect("failed to allocate descriptor sets!")[0]
+        };
+
+        // the image view is ignored by the sampler write and the sampler by the image write
+        let descriptor_types = [
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::SAMPLER,
+        ];
+        let writes = [0, 1].map(|binding| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(descriptor_types[binding as usize])
+                .image_info(std::slice::from_ref(&image_info))
+                .build()
+        });
+        unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+
+        self.sets.push(set);
+        SpriteTexture(self.sets.len() as u32 - 1)
+    }
+
+    /// Shapes added from now on are drawn over those of lower layers. Within a layer, the
+    /// order of shapes with different textures is not kept.
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
+
+    /// Adds an instance drawn with `texture`, for shapes the other methods do not cover.
+    pub fn push(&mut self, texture: SpriteTexture, instance: SpriteInstance) {
+        self.queued.push((self.layer, texture, instance));
+    }
+
+    /// Adds a filled rectangle from `min` to `max`.
+    pub fn rect(&mut self, min: Vec2, max: Vec2, color: [f32; 4]) {
+        let instance = SpriteInstance::new((min + max) / 2., max - min, color, Shape::Quad);
+        self.push(SpriteTexture(0), instance);
+    }
+
+    /// Adds a line `width` pixels wide from `from` to `to`.
+    pub fn line(&mut self, from: Vec2, to: Vec2, width: f32, color: [f32; 4]) {
+        let direction = to - from;
+        let mut instance = SpriteInstance::new(
+            (from + to) / 2.,
+            Vec2::new(direction.length(), width),
+            color,
+            Shape::Quad,
+        );
+        instance.rotation = direction.y.atan2(direction.x);
+        self.push(SpriteTexture(0), instance);
+    }
+
+    /// Adds a filled circle.
+    pub fn circle(&mut self, center: Vec2, radius: f32, color: [f32; 4]) {
+        let instance = SpriteInstance::new(center, Vec2::splat(radius * 2.), color, Shape::Circle);
+        self.push(SpriteTexture(0), instance);
+    }
+
+    /// Adds `texture` stretched over `size` pixels around `center`, rotated by `rotation`
+    /// radians and tinted with `color`.
+    pub fn sprite(
+        &mut self,
+        texture: SpriteTexture,
+        center: Vec2,
+        size: Vec2,
+        rotation: f32,
+        color: [f32; 4],
+    ) {
+        let mut instance = SpriteInstance::new(center, size, color, Shape::Quad);
+        instance.rotation = rotation;
+        self.push(texture, instance);
+    }
+
+    /// Sorts the shapes queued since the last upload into batches, and copies them into the
+    /// instance buffer of frame in flight `frame`.
+    pub fn upload(&mut self, frame: usize) {
+        let (draw_calls, instances) = batch(std::mem::take(&mut self.queued));
+        self.draw_calls = draw_calls;
+        if instances.is_empty() {
+            return;
+        }
+
+        Buffer::write_growing(
+            &self.device,
+            &mut self.instance_buffers[frame],
+            &instances,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+    }
+
+    /// Whether the last upload had no shapes, so there is nothing to record.
+    pub fn is_empty(&self) -> bool {
+        self.draw_calls.is_empty()
+    }
+
+    /// Records drawing the shapes of the last upload, which went into the buffer of frame in
+    /// flight `frame`, with `pipeline` onto a target of `extent`. The viewport and scissor have to
+    /// cover the target already.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        frame: usize,
+        extent: vk::Extent2D,
+        srgb_target: bool,
+    ) {
+        let buffer = match &self.instance_buffers[frame] {
+            Some(buffer) if !self.draw_calls.is_empty() => buffer,
+            _ => return,
+        };
+
+        let constants = SpritePushConstants::new(extent, srgb_target);
+        let device = &self.device;
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer.handle()], &[0]);
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                crate::as_bytes(&constants),
+            );
+
+            for draw in &self.draw_calls {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[self.sets[draw.texture.0 as usize]],
+                    &[],
+                );
+                device.cmd_draw(
+                    command_buffer,
+                    6,
+                    draw.instance_count,
+                    0,
+                    draw.first_instance,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A queued instance told apart from the others by its center's x.
+    fn queued(layer: i32, texture: u32, x: f32) -> (i32, SpriteTexture, SpriteInstance) {
+        let instance = SpriteInstance::new(Vec2::new(x, 0.), Vec2::ONE, [1.; 4], Shape::Quad);
+        (layer, SpriteTexture(texture), instance)
+    }
+
+    fn draw(texture: u32, first_instance: u32, instance_count: u32) -> DrawCall {
+        DrawCall {
+            texture: SpriteTexture(texture),
+            first_instance,
+            instance_count,
+        }
+    }
+
+    #[test]
+    fn draws_each_texture_of_a_layer_once() {
+        let (draw_calls, instances) = batch(vec![
+            queued(0, 1, 0.),
+            queued(0, 2, 1.),
+            queued(0, 1, 2.),
+            queued(0, 2, 3.),
+            queued(0, 1, 4.),
+        ]);
+
+        assert_eq!(draw_calls, vec![draw(1, 0, 3), draw(2, 3, 2)]);
+        let order = instances.iter().map(|i| i.center.x).collect::<Vec<_>>();
+        assert_eq!(order, vec![0., 2., 4., 1., 3.]);
+    }
+
+    #[test]
+    fn draws_higher_layers_over_lower_ones() {
+        let (draw_calls, instances) = batch(vec![
+            queued(1, 0, 0.),
+            queued(0, 1, 1.),
+            queued(1, 1, 2.),
+            queued(0, 0, 3.),
+            queued(-1, 1, 4.),
+        ]);
+
+        assert_eq!(
+            draw_calls,
+            vec![
+                draw(1, 0, 1),
+                draw(0, 1, 1),
+                draw(1, 2, 1),
+                draw(0, 3, 1),
+                draw(1, 4, 1),
+            ]
+        );
+        let order = instances.iter().map(|i| i.center.x).collect::<Vec<_>>();
+        assert_eq!(order, vec![4., 3., 1., 0., 2.]);
+    }
+
+    #[test]
+    fn batches_nothing_without_shapes() {
+        let (draw_calls, instances) = batch(Vec::new());
+        assert!(draw_calls.is_empty());
+        assert!(instances.is_empty());
+    }
+}
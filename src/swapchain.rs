@@ -0,0 +1,277 @@
+use crate::clamp;
+use ash::extensions::khr;
+use ash::version::InstanceV1_0;
+use ash::vk;
+use winit::window::Window;
+
+pub struct SwapchainSupportDetails {
+    pub capabilities: vk::SurfaceCapabilitiesKHR,
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+}
+
+impl SwapchainSupportDetails {
+    pub fn query_swapchain_support(
+        device: vk::PhysicalDevice,
+        surface_loader: &khr::Surface,
+        surface: vk::SurfaceKHR,
+    ) -> Self {
+        let capabilities = unsafe {
+            surface_loader
+                .get_physical_device_surface_capabilities(device, surface)
+                .expect("could not get physical device surface capabilities!")
+        };
+
+        let formats = unsafe {
+            surface_loader
+                .get_physical_device_surface_formats(device, surface)
+                .expect("could not get physical device surface formats!")
+        };
+
+        let present_modes = unsafe {
+            surface_loader
+                .get_physical_device_surface_present_modes(device, surface)
+                .expect("could not get physical device surface present modes!")
+        };
+
+        Self {
+            capabilities,
+            formats,
+            present_modes,
+        }
+    }
+
+    pub fn choose_swap_surface_format(
+        available_formats: &[vk::SurfaceFormatKHR],
+    ) -> vk::SurfaceFormatKHR {
+        for fmt in available_formats {
+            if fmt.format == vk::Format::B8G8R8A8_SRGB
+                && fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            {
+                return fmt.to_owned();
+            }
+        }
+
+        available_formats[0]
+    }
+
+    pub fn choose_swap_present_mode(
+        available_present_modes: &[vk::PresentModeKHR],
+    ) -> vk::PresentModeKHR {
+        if available_present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
+            return vk::PresentModeKHR::MAILBOX;
+        }
+        vk::PresentModeKHR::FIFO
+    }
+
+    pub fn choose_swap_extent(
+        capabilities: vk::SurfaceCapabilitiesKHR,
+        window: &Window,
+    ) -> vk::Extent2D {
+        if capabilities.current_extent.width != u32::MAX {
+            return capabilities.current_extent;
+        }
+
+        // the surface is measured in physical pixels, so the window's scale factor must not be
+        // applied here; doing so would give a swapchain smaller than the framebuffer on HiDPI
+        // displays
+        let (phys_width, phys_height) = (window.inner_size().width, window.inner_size().height);
+
+        let actual_width = clamp(
+            phys_width,
+            capabilities.min_image_extent.width,
+            capabilities.max_image_extent.width,
+        );
+        let actual_height = clamp(
+            phys_height,
+            capabilities.min_image_extent.height,
+            capabilities.max_image_extent.height,
+        );
+
+        vk::Extent2D {
+            width: actual_width,
+            height: actual_height,
+        }
+    }
+}
+
+/// Ratio between the resolution the scene is rendered at and the native (swapchain) resolution.
+/// Anything other than 1 renders into an offscreen target which is then blitted onto the
+/// swapchain image, so 0.5 renders at half resolution and 2 supersamples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderScale(f32);
+
+impl RenderScale {
+    pub const NATIVE: RenderScale = RenderScale(1.);
+
+    pub fn new(scale: f32) -> Self {
+        assert!(
+            scale.is_finite() && scale > 0.,
+            "render scale must be a positive number"
+        );
+        RenderScale(scale)
+    }
+
+    /// Reads the scale from `VKA_RENDER_SCALE`, falling back to native resolution when the
+    /// variable is unset or not a positive number.
+    pub fn from_env() -> Self {
+        Self::parse(std::env::var("VKA_RENDER_SCALE").ok().as_deref())
+    }
+
+    fn parse(value: Option<&str>) -> Self {
+        value
+            .and_then(|s| s.trim().parse::<f32>().ok())
+            .filter(|s| s.is_finite() && *s > 0.)
+            .map(RenderScale::new)
+            .unwrap_or(Self::NATIVE)
+    }
+
+    pub fn is_native(&self) -> bool {
+        (self.0 - 1.).abs() < f32::EPSILON
+    }
+
+    /// Extent to render at for a swapchain of extent `native`, kept within `max_dimension`
+    /// (`maxImageDimension2D` of the device).
+    pub fn apply(&self, native: vk::Extent2D, max_dimension: u32) -> vk::Extent2D {
+        if self.is_native() {
+            return native;
+        }
+
+        let scale = |x: u32| clamp((x as f32 * self.0).round() as u32, 1, max_dimension);
+
+        vk::Extent2D {
+            width: scale(native.width),
+            height: scale(native.height),
+        }
+    }
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        Self::NATIVE
+    }
+}
+
+/// Depth formats in order of preference.
+const DEPTH_FORMATS: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+];
+
+/// Returns the first depth format `physical_device` can use as an optimally tiled attachment.
+pub fn find_depth_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::Format {
+    DEPTH_FORMATS
+        .iter()
+        .copied()
+        .find(|&format| {
+            let properties =
+                unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .expect("failed to find a supported depth format!")
+}
+
+pub fn color_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+pub fn depth_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::DEPTH,
+        ..color_subresource_range()
+    }
+}
+
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_filter: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    (0..memory_properties.memory_type_count).find(|&i| {
+        type_filter & (1 << i) != 0
+            && memory_properties.memory_types[i as usize]
+                .property_flags
+                .contains(properties)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_positive_scales() {
+        assert_eq!(RenderScale::parse(Some("0.5")), RenderScale(0.5));
+        assert_eq!(RenderScale::parse(Some(" 2 ")), RenderScale(2.));
+    }
+
+    #[test]
+    fn falls_back_to_native() {
+        for value in [
+            None,
+            Some(""),
+            Some("half"),
+            Some("0"),
+            Some("-1"),
+            Some("inf"),
+            Some("NaN"),
+        ] {
+            assert_eq!(
+                RenderScale::parse(value),
+                RenderScale::NATIVE,
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn applies_to_the_native_extent() {
+        let native = vk::Extent2D {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(RenderScale::NATIVE.apply(native, 16384), native);
+        assert_eq!(
+            RenderScale::new(0.5).apply(native, 16384),
+            vk::Extent2D {
+                width: 960,
+                height: 540,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_the_extent_within_limits() {
+        let native = vk::Extent2D {
+            width: 3000,
+            height: 1,
+        };
+        assert_eq!(
+            RenderScale::new(2.).apply(native, 4096),
+            vk::Extent2D {
+                width: 4096,
+                height: 2,
+            }
+        );
+        assert_eq!(
+            RenderScale::new(0.1).apply(native, 4096),
+            vk::Extent2D {
+                width: 300,
+                height: 1,
+            }
+        );
+    }
+}
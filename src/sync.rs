@@ -0,0 +1,512 @@
+//! Synchronizing queue submissions with each other and with the host.
+//!
+//! Where the device supports timeline semaphores, each queue has a [`Timeline`] whose value every
+//! submission to it raises, so the host can wait for, or check on, any earlier submission by its
+//! value, and submissions to other queues can wait for it with a [`Submission`]. Otherwise frames
+//! fall back to binary semaphores and a fence per frame in flight, behind the same [`FrameSync`].
+//!
+//! Resources that submitted work may still use are handed to a [`DeletionQueue`], which destroys
+//! them once that work has completed instead of waiting for the device to idle.
+
+use crate::resources::{Device, Fence, Semaphore};
+use ash::version::{DeviceV1_0, DeviceV1_2};
+use ash::vk;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A timeline semaphore counting the submissions to one queue. Values are handed out in
+/// submission order, so the semaphore reaching one means every submission to the queue up to it
+/// has completed.
+pub struct Timeline {
+    semaphore: Semaphore,
+    /// The value most recently handed out.
+    last: Cell<u64>,
+    device: Arc<Device>,
+}
+
+impl Timeline {
+    /// The device needs the `timelineSemaphore` feature, see [`Device::timeline_semaphore`].
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            semaphore: Semaphore::timeline(device, 0),
+            last: Cell::new(0),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn semaphore(&self) -> &Semaphore {
+        &self.semaphore
+    }
+
+    /// Hands out the value for the next submission to the queue to signal. Submissions have to
+    /// be made in the order their values were handed out.
+    pub fn next_value(&self) -> u64 {
+        let value = self.last.get() + 1;
+        self.last.set(value);
+        value
+    }
+
+    /// The value of the last submission handed one, which the semaphore reaches once the queue
+    /// has finished all of its work so far.
+    pub fn last_value(&self) -> u64 {
+        self.last.get()
+    }
+
+    /// The value the semaphore has reached.
+    pub fn completed_value(&self) -> u64 {
+        unsafe {
+            self.device
+                .get_semaphore_counter_value(self.semaphore.handle())
+                .expect("failed to get semaphore counter value!")
+        }
+    }
+
+    pub fn is_complete(&self, value: u64) -> bool {
+        value <= self.completed_value()
+    }
+
+    /// Blocks until the semaphore has reached `value`.
+    pub fn wait(&self, value: u64) {
+        let semaphores = [self.semaphore.handle()];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+
+        unsafe {
+            self.device
+                .wait_semaphores(&wait_info, u64::MAX)
+                .expect("failed to wait for semaphores!");
+        }
+    }
+}
+
+/// The semaphores one queue submission waits for and signals, binary or timeline.
+#[derive(Default)]
+pub struct Submission {
+    wait_semaphores: Vec<vk::Semaphore>,
+    wait_values: Vec<u64>,
+    wait_stages: Vec<vk::PipelineStageFlags>,
+    signal_semaphores: Vec<vk::Semaphore>,
+    signal_values: Vec<u64>,
+}
+
+impl Submission {
+    /// Waits for the binary `semaphore` before `stages`.
+    pub fn wait(mut self, semaphore: &Semaphore, stages: vk::PipelineStageFlags) -> Self {
+        self.wait_semaphores.push(semaphore.handle());
+        // ignored for binary semaphores
+        self.wait_values.push(0);
+        self.wait_stages.push(stages);
+        self
+    }
+
+    /// Waits for `timeline`, which may be another queue's, to reach `value` before `stages`.
+    pub fn wait_timeline(
+        mut self,
+        timeline: &Timeline,
+        value: u64,
+        stages: vk::PipelineStageFlags,
+    ) -> Self {
+        self.wait_semaphores.push(timeline.semaphore.handle());
+        self.wait_values.push(value);
+        self.wait_stages.push(stages);
+        self
+    }
+
+    /// Signals the binary `semaphore` once the submission completes.
+    pub fn signal(mut self, semaphore: &Semaphore) -> Self {
+        self.signal_semaphores.push(semaphore.handle());
+        self.signal_values.push(0);
+        self
+    }
+
+    /// Signals `timeline`, the submitting queue's, with `value` once the submission completes.
+    pub fn signal_timeline(mut self, timeline: &Timeline, value: u64) -> Self {
+        self.signal_semaphores.push(timeline.semaphore.handle());
+        self.signal_values.push(value);
+        self
+    }
+
+    /// Submits `command_buffers` to `queue`, signaling `fence`, which may be null, on completion.
+    pub fn submit(
+        &self,
+        device: &Device,
+        queue: vk::Queue,
+        command_buffers: &[vk::CommandBuffer],
+        fence: vk::Fence,
+    ) {
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+            .wait_semaphore_values(&self.wait_values)
+            .signal_semaphore_values(&self.signal_values);
+        let mut submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&self.wait_semaphores)
+            .wait_dst_stage_mask(&self.wait_stages)
+            .command_buffers(command_buffers)
+            .signal_semaphores(&self.signal_semaphores);
+        // only valid to chain where the device has timeline semaphores
+        if device.timeline_semaphore() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+
+        unsafe {
+            device
+                .queue_submit(queue, &[submit_info.build()], fence)
+                .expect("failed to submit command buffer!");
+        }
+    }
+}
+
+/// How the host finds out that a frame in flight has completed.
+enum FrameCompletion {
+    Fences(Vec<Fence>),
+    /// The value each frame's last submission signals on the queue's timeline.
+    Timeline {
+        timeline: Rc<Timeline>,
+        values: Vec<u64>,
+    },
+}
+
+/// Tracks the frames in flight of a swapchain, and which of them last rendered to each of its
+/// images, either with the queue's timeline or with a fence per frame.
+pub struct FrameSync {
+    completion: FrameCompletion,
+    /// Frames submitted so far, which are numbered from one.
+    submitted: u64,
+    /// The number of each frame in flight's last submission, zero before its first.
+    numbers: Vec<u64>,
+    /// The frame in flight that last rendered to each swapchain image.
+    image_frames: Vec<Option<usize>>,
+    device: Arc<Device>,
+}
+
+impl FrameSync {
+    /// Tracks `frames_in_flight` frames rendering to `image_count` swapchain images, with
+    /// `timeline` if given, which has to be the timeline of the queue frames are submitted to.
+    pub fn new(
+        device: &Arc<Device>,
+        frames_in_flight: usize,
+        image_count: usize,
+        timeline: Option<Rc<Timeline>>,
+    ) -> Self {
+        let completion = match timeline {
+            Some(timeline) => FrameCompletion::Timeline {
+                timeline,
+                values: vec![0; frames_in_flight],
+            },
+            // signaled, so the first wait for each frame returns at once
+            None => FrameCompletion::Fences(
+                (0..frames_in_flight)
+                    .map(|_| Fence::new(device, true))
+                    .collect(),
+            ),
+        };
+
+        Self {
+            completion,
+            submitted: 0,
+            numbers: vec![0; frames_in_flight],
+            image_frames: vec![None; image_count],
+            device: Arc::clone(device),
+        }
+    }
+
+    /// The fences of the frames in flight, if there is no timeline.
+    pub fn fences(&self) -> &[Fence] {
+        match &self.completion {
+            FrameCompletion::Fences(fences) => fences,
+            FrameCompletion::Timeline { .. } => &[],
+        }
+    }
+
+    pub fn timeline(&self) -> Option<&Rc<Timeline>> {
+        match &self.completion {
+            FrameCompletion::Timeline { timeline, .. } => Some(timeline),
+            FrameCompletion::Fences(_) => None,
+        }
+    }
+
+    /// The timeline value frame in flight `frame` signals with its last submission, if there is a
+    /// timeline.
+    pub fn frame_value(&self, frame: usize) -> Option<u64> {
+        match &self.completion {
+            FrameCompletion::Timeline { values, .. } => Some(values[frame]),
+            FrameCompletion::Fences(_) => None,
+        }
+    }
+
+    /// The number the next frame submitted gets.
+    pub fn next_frame_number(&self) -> u64 {
+        self.submitted + 1
+    }
+
+    /// The number of the latest frame that has completed, along with every frame before it, or
+    /// zero if none has. Does not wait.
+    pub fn completed_frame_number(&self) -> u64 {
+        // frames complete in submission order, as far as anyone waiting on them can tell
+        (0..self.numbers.len())
+            .filter(|&frame| self.numbers[frame] != 0 && self.is_complete(frame))
+            .map(|frame| self.numbers[frame])
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether the last submission of frame in flight `frame` has completed, without waiting.
+    pub fn is_complete(&self, frame: usize) -> bool {
+        match &self.completion {
+            FrameCompletion::Fences(fences) => unsafe {
+                self.device.get_fence_status(fences[frame].handle()) == Ok(true)
+            },
+            FrameCompletion::Timeline { timeline, values } => timeline.is_complete(values[frame]),
+        }
+    }
+
+    /// Blocks until the last submission of frame in flight `frame` has completed.
+    pub fn wait(&self, frame: usize) {
+        match &self.completion {
+            FrameCompletion::Fences(fences) => unsafe {
+                self.device
+                    .wait_for_fences(&[fences[frame].handle()], true, u64::MAX)
+                    .expect("failed to wait for fences!");
+            },
+            FrameCompletion::Timeline { timeline, values } => timeline.wait(values[frame]),
+        }
+    }
+
+    /// Blocks until the last submissions of all frames in flight have completed.
+    pub fn wait_all(&self) {
+        match &self.completion {
+            FrameCompletion::Fences(fences) => {
+                let fences = fences.iter().map(Fence::handle).collect::<Vec<_>>();
+                unsafe {
+                    self.device
+                        .wait_for_fences(&fences, true, u64::MAX)
+                        .expect("failed to wait for fences!");
+                }
+            }
+            // frames are submitted in order, so the latest one completes last
+            FrameCompletion::Timeline { timeline, values } => {
+                timeline.wait(values.iter().copied().max().unwrap_or(0))
+            }
+        }
+    }
+
+    /// Blocks until the frame that last rendered to swapchain image `image` has completed, and
+    /// records that frame in flight `frame` renders to it next.
+    pub fn acquire_image(&mut self, image: usize, frame: usize) {
+        if let Some(previous) = self.image_frames[image] {
+            if previous != frame {
+                self.wait(previous);
+            }
+        }
+        self.image_frames[image] = Some(frame);
+    }
+
+    /// Submits `command_buffers` as frame in flight `frame`, which has to have completed, to
+    /// `queue`, signaling the frame's fence or the next timeline value on completion. Returns
+    /// when what the frame, and every one before it, used can go.
+    pub fn submit(
+        &mut self,
+        frame: usize,
+        submission: Submission,
+        queue: vk::Queue,
+        command_buffers: &[vk::CommandBuffer],
+    ) -> RetireAfter {
+        self.submitted += 1;
+        self.numbers[frame] = self.submitted;
+
+        match &mut self.completion {
+            FrameCompletion::Fences(fences) => {
+                let fence = fences[frame].handle();
+                unsafe {
+                    self.device
+                        .reset_fences(&[fence])
+                        .expect("failed to reset fences!");
+                }
+                submission.submit(&self.device, queue, command_buffers, fence);
+                RetireAfter::Frame(self.submitted)
+            }
+            FrameCompletion::Timeline { timeline, values } => {
+                let value = timeline.next_value();
+                values[frame] = value;
+                submission.signal_timeline(timeline, value).submit(
+                    &self.device,
+                    queue,
+                    command_buffers,
+                    vk::Fence::null(),
+                );
+                RetireAfter::Timeline(value)
+            }
+        }
+    }
+}
+
+/// When a resource handed to a [`DeletionQueue`] is no longer used, as returned by
+/// [`FrameSync::submit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetireAfter {
+    /// Once the frame of the number, see [`FrameSync::next_frame_number`], has completed.
+    Frame(u64),
+    /// Once the queue's timeline has reached the value.
+    Timeline(u64),
+}
+
+/// Resources retired while submitted work may still use them, destroyed once it has completed.
+///
+/// Resources retired while a frame is recorded wait for [`Self::seal`] with what the frame's
+/// submission returned, as the frame may still use them.
+/// Dropping the queue destroys whatever is left, so the work has to have completed by then.
+#[derive(Default)]
+pub struct DeletionQueue {
+    /// Retired since the last seal.
+    pending: Vec<Box<dyn FnOnce()>>,
+    entries: Vec<(RetireAfter, Box<dyn FnOnce()>)>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.entries.is_empty()
+    }
+
+    /// Drops `resource` once the next submission sealed, and every one before it, has completed.
+    pub fn retire<T: 'static>(&mut self, resource: T) {
+        self.defer(move || drop(resource));
+    }
+
+    /// Calls `destroy` once the next submission sealed, and every one before it, has completed,
+    /// for handles that are not dropped on their own, such as descriptor sets.
+    pub fn defer(&mut self, destroy: impl FnOnce() + 'static) {
+        self.pending.push(Box::new(destroy));
+    }
+
+    /// Destroys what was retired since the last seal once `after`.
+    pub fn seal(&mut self, after: RetireAfter) {
+        self.entries
+            .extend(self.pending.drain(..).map(|destroy| (after, destroy)));
+    }
+
+    /// Destroys the resources whose frames, or timeline values, `sync` finds have completed,
+    /// without waiting for the others.
+    pub fn flush(&mut self, sync: &FrameSync) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let value = sync
+            .timeline()
+            .map_or(0, |timeline| timeline.completed_value());
+        self.flush_completed(sync.completed_frame_number(), value);
+    }
+
+    /// Destroys, in the order they were retired, the resources whose frames have completed up to
+    /// number `frame` or whose timeline values are up to `value`.
+    fn flush_completed(&mut self, frame: u64, value: u64) {
+        let (done, pending) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(after, _)| match *after {
+                RetireAfter::Frame(number) => number <= frame,
+                RetireAfter::Timeline(target) => target <= value,
+            });
+        self.entries = pending;
+        for (_, destroy) in done {
+            destroy();
+        }
+    }
+}
+
+impl Drop for DeletionQueue {
+    fn drop(&mut self) {
+        for (_, destroy) in self.entries.drain(..) {
+            destroy();
+        }
+        for destroy in self.pending.drain(..) {
+            destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A queue whose resources log their names as they are destroyed.
+    fn logged() -> (DeletionQueue, Rc<RefCell<Vec<&'static str>>>) {
+        (DeletionQueue::new(), Rc::default())
+    }
+
+    fn defer_logged(
+        queue: &mut DeletionQueue,
+        log: &Rc<RefCell<Vec<&'static str>>>,
+        name: &'static str,
+    ) {
+        let log = Rc::clone(log);
+        queue.defer(move || log.borrow_mut().push(name));
+    }
+
+    #[test]
+    fn keeps_pending_resources_until_sealed() {
+        let (mut queue, log) = logged();
+        defer_logged(&mut queue, &log, "a");
+        queue.flush_completed(u64::MAX, u64::MAX);
+        assert!(log.borrow().is_empty());
+        assert!(!queue.is_empty());
+
+        queue.seal(RetireAfter::Frame(1));
+        queue.flush_completed(1, 0);
+        assert_eq!(*log.borrow(), ["a"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn destroys_completed_resources_in_retire_order() {
+        let (mut queue, log) = logged();
+        defer_logged(&mut queue, &log, "frame 1");
+        queue.seal(RetireAfter::Frame(1));
+        defer_logged(&mut queue, &log, "value 3");
+        queue.seal(RetireAfter::Timeline(3));
+        defer_logged(&mut queue, &log, "frame 2");
+        defer_logged(&mut queue, &log, "frame 2 too");
+        queue.seal(RetireAfter::Frame(2));
+        defer_logged(&mut queue, &log, "value 2");
+        queue.seal(RetireAfter::Timeline(2));
+
+        queue.flush_completed(0, 0);
+        assert!(log.borrow().is_empty());
+        queue.flush_completed(2, 2);
+        assert_eq!(
+            *log.borrow(),
+            ["frame 1", "frame 2", "frame 2 too", "value 2"]
+        );
+        queue.flush_completed(2, 3);
+        assert_eq!(log.borrow().last(), Some(&"value 3"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn drops_resources_once() {
+        let resource = Rc::new(());
+        let mut queue = DeletionQueue::new();
+        queue.retire(Rc::clone(&resource));
+        queue.seal(RetireAfter::Frame(1));
+        assert_eq!(Rc::strong_count(&resource), 2);
+        queue.flush_completed(1, 0);
+        assert_eq!(Rc::strong_count(&resource), 1);
+        queue.flush_completed(1, 0);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn destroys_everything_left_when_dropped() {
+        let (mut queue, log) = logged();
+        defer_logged(&mut queue, &log, "sealed");
+        queue.seal(RetireAfter::Timeline(5));
+        defer_logged(&mut queue, &log, "pending");
+        drop(queue);
+        assert_eq!(*log.borrow(), ["sealed", "pending"]);
+    }
+}
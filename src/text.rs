@@ -0,0 +1,650 @@
+//! Text drawn straight from a TTF or OTF font, for stats and labels that do not need the egui
+//! overlay.
+//!
+//! Glyphs are rasterized with `ab_glyph` the first time they are drawn at a size, and packed
+//! into an atlas that is uploaded as a single channel texture. Strings are laid out with the
+//! font's kerning and wrapped at word boundaries, and every glyph becomes a textured quad; a
+//! frame's quads are drawn in one batch with an alpha blended pipeline.
+//!
+//! Kerning only comes from the legacy `kern` table, which is all `ab_glyph` reads: fonts that
+//! keep their pairs in `GPOS`, as most recent ones do, are not kerned, and neither is the
+//! bundled Hack, which is monospaced. A proportional font with a `kern` table is kerned when set
+//! through `VKA_FONT`.
+
+use crate::resources::{Buffer, DescriptorPool, DescriptorSetLayout, Device};
+use crate::sync::DeletionQueue;
+use crate::texture::{SamplerDesc, Texture};
+use crate::upload::UploadQueue;
+use ab_glyph::{Font, FontArc, Glyph, GlyphId, PxScale, ScaleFont};
+use ash::version::DeviceV1_0;
+use ash::vk;
+use glam::Vec2;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Width and height of the glyph atlas, in pixels.
+pub const ATLAS_SIZE: u32 = 1024;
+
+/// Format of the glyph atlas, which only holds coverage.
+pub const ATLAS_FORMAT: vk::Format = vk::Format::R8_UNORM;
+
+/// Empty pixels around each glyph in the atlas, so filtering never reads a neighbour.
+const ATLAS_PADDING: u32 = 1;
+
+/// Why a font could not be loaded.
+#[derive(Debug)]
+pub enum FontError {
+    Io(std::io::Error),
+    /// The file is not a font `ab_glyph` can read.
+    Invalid(ab_glyph::InvalidFont),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::Io(err) => err.fmt(f),
+            FontError::Invalid(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FontError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FontError::Io(err) => Some(err),
+            FontError::Invalid(err) => Some(err),
+        }
+    }
+}
+
+/// Loads the TTF or OTF font at `path`.
+pub fn load_font(path: impl AsRef<Path>) -> Result<FontArc, FontError> {
+    let data = std::fs::read(path).map_err(FontError::Io)?;
+    FontArc::try_from_vec(data).map_err(FontError::Invalid)
+}
+
+/// Hack, the monospace font egui comes with.
+pub fn default_font() -> FontArc {
+    FontArc::try_from_slice(epaint_default_fonts::HACK_REGULAR)
+        .expect("the default font is a valid font")
+}
+
+/// Glyphs positioned relative to the top left corner of the text.
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+    pub glyphs: Vec<Glyph>,
+    /// Width of the widest line, and height of all lines.
+    pub size: Vec2,
+}
+
+/// Lays out `text` at `size` pixels per em, with the first line's ascent at the top. Lines break
+/// at `\n`, and with a `max_width` also before words that would reach past it; words wider than
+/// that on their own break between characters.
+pub fn layout(font: &FontArc, text: &str, size: f32, max_width: Option<f32>) -> TextLayout {
+    let scale = PxScale::from(size);
+    let font = font.as_scaled(scale);
+    let line_height = font.height() + font.line_gap();
+    let max_width = max_width.unwrap_or(f32::INFINITY);
+
+    let mut glyphs = Vec::new();
+    let mut width = 0f32;
+    let mut caret = ab_glyph::point(0., font.ascent());
+    for line in text.lines() {
+        let mut previous: Option<GlyphId> = None;
+
+        for word in line.split_inclusive(' ') {
+            let ids = word.chars().map(|c| font.glyph_id(c)).collect::<Vec<_>>();
+
+            // the trailing space may hang past the edge
+            let visible = word.trim_end().chars().count();
+            let word_width = ids[..visible]
+                .iter()
+                .enumerate()
+                .map(|(i, &id)| {
+                    let kern = match i {
+                        0 => 0.,
+                        _ => font.kern(ids[i - 1], id),
+                    };
+                    kern + font.h_advance(id)
+                })
+                .sum::<f32>();
+            if caret.x > 0. && caret.x + word_width > max_width {
+                caret.x = 0.;
+                caret.y += line_height;
+                previous = None;
+            }
+
+            for (i, &id) in ids.iter().enumerate() {
+                if let Some(previous) = previous {
+                    caret.x += font.kern(previous, id);
+                }
+                let advance = font.h_advance(id);
+                if i < visible && caret.x > 0. && caret.x + advance > max_width {
+                    caret.x = 0.;
+                    caret.y += line_height;
+                }
+
+                glyphs.push(id.with_scale_and_position(scale, caret));
+                caret.x += advance;
+                if i < visible {
+                    width = width.max(caret.x);
+                }
+                previous = Some(id);
+            }
+        }
+
+        caret.x = 0.;
+        caret.y += line_height;
+    }
+
+    TextLayout {
+        glyphs,
+        size: Vec2::new(width, caret.y - font.ascent()),
+    }
+}
+
+/// Where a glyph is in the atlas, and where its pixels go relative to its position.
+#[derive(Clone, Copy, Debug)]
+struct AtlasEntry {
+    uv_min: Vec2,
+    uv_max: Vec2,
+    /// Offset of the top left corner of the glyph's pixels from its position on the baseline.
+    offset: Vec2,
+    size: Vec2,
+}
+
+/// Glyphs rasterized into rows of an [`ATLAS_SIZE`] square, filled left to right and top to
+/// bottom. Nothing is ever evicted; once full, further glyphs are not drawn.
+pub struct GlyphAtlas {
+    pixels: Vec<u8>,
+    /// `None` for glyphs without an outline, such as spaces.
+    entries: HashMap<(GlyphId, u32), Option<AtlasEntry>>,
+    cursor: (u32, u32),
+    row_height: u32,
+    /// Whether glyphs were added since the atlas was last uploaded.
+    dirty: bool,
+    full: bool,
+}
+
+impl Default for GlyphAtlas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GlyphAtlas {
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            entries: HashMap::new(),
+            cursor: (ATLAS_PADDING, ATLAS_PADDING),
+            row_height: 0,
+            dirty: false,
+            full: false,
+        }
+    }
+
+    /// Coverage of every pixel, a byte each.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Returns where `glyph` is, rasterizing it first if it is new at its size.
+    fn entry(&mut self, font: &FontArc, glyph: &Glyph) -> Option<AtlasEntry> {
+        let key = (glyph.id, glyph.scale.y.to_bits());
+        if let Some(entry) = self.entries.get(&key) {
+            return *entry;
+        }
+
+        let entry = self.rasterize(font, glyph);
+        self.entries.insert(key, entry);
+        entry
+    }
+
+    fn rasterize(&mut self, font: &FontArc, glyph: &Glyph) -> Option<AtlasEntry> {
+        // rasterized at the origin, quads are snapped to whole pixels when drawn
+        let mut glyph = glyph.clone();
+        glyph.position = ab_glyph::point(0., 0.);
+        let outlined = font.outline_glyph(glyph)?;
+        let bounds = outlined.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let (x, y) = self.allocate(width, height)?;
+        outlined.draw(|dx, dy, coverage| {
+            let index = (y + dy) * ATLAS_SIZE + x + dx;
+            self.pixels[index as usize] = (coverage.clamp(0., 1.) * 255.).round() as u8;
+        });
+        self.dirty = true;
+
+        let atlas_size = ATLAS_SIZE as f32;
+        let size = Vec2::new(width as f32, height as f32);
+        let uv_min = Vec2::new(x as f32, y as f32) / atlas_size;
+        Some(AtlasEntry {
+            uv_min,
+            uv_max: uv_min + size / atlas_size,
+            offset: Vec2::new(bounds.min.x, bounds.min.y),
+            size,
+        })
+    }
+
+    /// Finds room for a `width` by `height` glyph, starting a new row when the current one is
+    /// full.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if self.cursor.0 + width + ATLAS_PADDING > ATLAS_SIZE {
+            self.cursor = (
+                ATLAS_PADDING,
+                self.cursor.1 + self.row_height + ATLAS_PADDING,
+            );
+            self.row_height = 0;
+        }
+        if self.cursor.0 + width + ATLAS_PADDING > ATLAS_SIZE
+            || self.cursor.1 + height + ATLAS_PADDING > ATLAS_SIZE
+        {
+            if !self.full {
+                log::warn!("the glyph atlas is full, new glyphs are not drawn");
+                self.full = true;
+            }
+            return None;
+        }
+
+        let position = self.cursor;
+        self.cursor.0 += width + ATLAS_PADDING;
+        self.row_height = self.row_height.max(height);
+        Some(position)
+    }
+}
+
+/// Vertex of a glyph quad, at binding 0. Positions are in pixels from the top left corner of the
+/// target.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextVertex {
+    pub position: Vec2,
+    pub uv: Vec2,
+    /// Gamma encoded, not premultiplied.
+    pub color: [f32; 4],
+}
+
+impl TextVertex {
+    pub fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<TextVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    pub fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let attribute = |location, format, offset| vk::VertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset,
+        };
+        [
+            attribute(0, vk::Format::R32G32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32_SFLOAT, 8),
+            attribute(2, vk::Format::R32G32B32A32_SFLOAT, 16),
+        ]
+    }
+}
+
+/// Push constants of the text pipeline.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TextPushConstants {
+    /// Size of the target in pixels.
+    pub screen_size: Vec2,
+    /// Whether the target encodes to sRGB when written to, so the shader has to decode first.
+    pub srgb_target: u32,
+    _padding: u32,
+}
+
+impl TextPushConstants {
+    pub fn new(extent: vk::Extent2D, srgb_target: bool) -> Self {
+        Self {
+            screen_size: Vec2::new(extent.width as f32, extent.height as f32),
+            srgb_target: srgb_target as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Batches the text of a frame into quads over a glyph atlas, and draws them.
+pub struct TextRenderer {
+    font: FontArc,
+    atlas: GlyphAtlas,
+    /// Queued since the last upload.
+    vertices: Vec<TextVertex>,
+    /// Vertices of the last upload.
+    vertex_count: u32,
+    // one per frame in flight, grown when a frame's text no longer fits
+    vertex_buffers: Vec<Option<Buffer>>,
+    texture: Option<Texture>,
+    /// Binds `texture`. Each atlas upload gets a new one, so frames in flight keep the old one.
+    set: vk::DescriptorSet,
+    /// Shared with the frees of retired sets.
+    pool: Rc<DescriptorPool>,
+    set_layout: vk::DescriptorSetLayout,
+    device: Arc<Device>,
+}
+
+impl TextRenderer {
+    /// Creates a renderer drawing with `font`. The atlas is bound with a set of `set_layout`,
+    /// which has one sampled image and one sampler.
+    pub fn new(
+        device: &Arc<Device>,
+        set_layout: &DescriptorSetLayout,
+        font: FontArc,
+        frames_in_flight: usize,
+    ) -> Self {
+        let pool_sizes = [
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::SAMPLER,
+        ]
+        .map(|ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: frames_in_flight as u32 + 1,
+        });
+
+        // the current set, and those of the atlases frames in flight may still draw with
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(frames_in_flight as u32 + 1)
+            .pool_sizes(&pool_sizes);
+
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .expect("failed to create descriptor pool!")
+        };
+
+        Self {
+            font,
+            atlas: GlyphAtlas::new(),
+            vertices: Vec::new(),
+            vertex_count: 0,
+            vertex_buffers: (0..frames_in_flight).map(|_| None).collect(),
+            texture: None,
+            set: vk::DescriptorSet::null(),
+            pool: Rc::new(DescriptorPool::from_raw(device, pool)),
+            set_layout: set_layout.handle(),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn font(&self) -> &FontArc {
+        &self.font
+    }
+
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Queues `text` to be drawn at `size` pixels per em, with the top left corner of its first
+    /// line at `position`. See [`layout`] for how it is wrapped.
+    pub fn queue(
+        &mut self,
+        text: &str,
+        position: Vec2,
+        size: f32,
+        color: [f32; 4],
+        max_width: Option<f32>,
+    ) {
+        let layout = layout(&self.font, text, size, max_width);
+        self.queue_layout(&layout, position, color);
+    }
+
+    /// Queues text laid out with [`layout`], with its top left corner at `position`.
+    pub fn queue_layout(&mut self, layout: &TextLayout, position: Vec2, color: [f32; 4]) {
+        for glyph in &layout.glyphs {
+            let origin = Vec2::new(glyph.position.x, glyph.position.y);
+            let entry = match self.atlas.entry(&self.font, glyph) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            let min = (position + origin + entry.offset).round();
+            let max = min + entry.size;
+            let vertex = |x: f32, y: f32, u: f32, v: f32| TextVertex {
+                position: Vec2::new(x, y),
+                uv: Vec2::new(u, v),
+                color,
+            };
+            let (uv_min, uv_max) = (entry.uv_min, entry.uv_max);
+            self.vertices.extend_from_slice(&[
+                vertex(min.x, min.y, uv_min.x, uv_min.y),
+                vertex(min.x, max.y, uv_min.x, uv_max.y),
+                vertex(max.x, max.y, uv_max.x, uv_max.y),
+                vertex(min.x, min.y, uv_min.x, uv_min.y),
+                vertex(max.x, max.y, uv_max.x, uv_max.y),
+                vertex(max.x, min.y, uv_max.x, uv_min.y),
+            ]);
+        }
+    }
+
+    /// Records uploading the atlas into `uploads` if it changed, and copies the text queued since
+    /// the last upload into the vertex buffer of frame in flight `frame`. The previous atlas is
+    /// handed to `deletions`, to go once the frames still drawing with it are done.
+    pub fn upload(
+        &mut self,
+        uploads: &mut UploadQueue,
+        deletions: &mut DeletionQueue,
+        frame: usize,
+    ) {
+        if self.atlas.dirty {
+            let extent = vk::Extent2D {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+            };
+            let sampler = SamplerDesc {
+                mag_filter: vk::Filter::LINEAR,
+                min_filter: vk::Filter::LINEAR,
+                address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            };
+            // frames submitted after the copy can sample it, so the handle is not needed
+            let (texture, _) =
+                Texture::queue_pixels(uploads, extent, ATLAS_FORMAT, self.atlas.pixels(), &sampler);
+            if let Some(old) = self.texture.take() {
+                let (device, pool, set) =
+                    (Arc::clone(&self.device), Rc::clone(&self.pool), self.set);
+                deletions.defer(move || {
+                    unsafe {
+                        device
+                            .free_descriptor_sets(pool.handle(), &[set])
+                            .expect("failed to free descriptor set!");
+                    }
+                    drop(old);
+                });
+            }
+
+            let layouts = [self.set_layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.pool.handle())
+                .set_layouts(&layouts);
+            self.set = unsafe {
+                self.device
+                    .allocate_descriptor_sets(&alloc_info)
+                    .expect("failed to allocate descriptor sets!")[0]
+            };
+
+            // the image view is ignored by the sampler write and the sampler by the image write
+            let image_info = texture.descriptor();
+            let descriptor_types = [
+                vk::DescriptorType::SAMPLED_IMAGE,
+                vk::DescriptorType::SAMPLER,
+            ];
+            let writes = [0, 1].map(|binding| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(self.set)
+                    .dst_binding(binding)
+                    .descriptor_type(descriptor_types[binding as usize])
+                    .image_info(std::slice::from_ref(&image_info))
+                    .build()
+            });
+            unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+
+            self.texture = Some(texture);
+            self.atlas.dirty = false;
+        }
+
+        let vertices = std::mem::take(&mut self.vertices);
+        self.vertex_count = vertices.len() as u32;
+        if vertices.is_empty() {
+            return;
+        }
+
+        Buffer::write_growing(
+            &self.device,
+            &mut self.vertex_buffers[frame],
+            &vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+    }
+
+    /// Records drawing the text of the last upload, which went into the buffer of frame in
+    /// flight `frame`, with `pipeline` onto a target of `extent`. The viewport and scissor have to
+    /// cover the target already.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        frame: usize,
+        extent: vk::Extent2D,
+        srgb_target: bool,
+    ) {
+        let buffer = match &self.vertex_buffers[frame] {
+            Some(buffer) if self.vertex_count > 0 && self.texture.is_some() => buffer,
+            _ => return,
+        };
+
+        let constants = TextPushConstants::new(extent, srgb_target);
+        let device = &self.device;
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[buffer.handle()], &[0]);
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                crate::as_bytes(&constants),
+            );
+            device.cmd_draw(command_buffer, self.vertex_count, 1, 0, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: f32 = 20.;
+
+    /// Advance of every character of the monospace default font, and the height of a line.
+    fn metrics(font: &FontArc) -> (f32, f32) {
+        let font = font.as_scaled(PxScale::from(SIZE));
+        (
+            font.h_advance(font.glyph_id('a')),
+            font.height() + font.line_gap(),
+        )
+    }
+
+    /// The line of each glyph, counted from zero, and its column in advances.
+    fn positions(font: &FontArc, layout: &TextLayout) -> Vec<(usize, usize)> {
+        let (advance, line_height) = metrics(font);
+        let ascent = font.as_scaled(PxScale::from(SIZE)).ascent();
+        layout
+            .glyphs
+            .iter()
+            .map(|glyph| {
+                let line = (glyph.position.y - ascent) / line_height;
+                let column = glyph.position.x / advance;
+                (line.round() as usize, column.round() as usize)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lays_out_lines() {
+        let font = default_font();
+        let (advance, line_height) = metrics(&font);
+        let text = layout(&font, "abc\nde", SIZE, None);
+
+        assert_eq!(
+            positions(&font, &text),
+            [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1)]
+        );
+        assert_eq!(text.size, Vec2::new(3. * advance, 2. * line_height));
+    }
+
+    #[test]
+    fn wraps_before_words_reaching_past_the_width() {
+        let font = default_font();
+        let (advance, _) = metrics(&font);
+        let text = layout(&font, "ab cd ef", SIZE, Some(5.5 * advance));
+
+        // the space after "cd" hangs past the edge rather than wrapping
+        assert_eq!(
+            positions(&font, &text),
+            [
+                (0, 0),
+                (0, 1),
+                (0, 2),
+                (0, 3),
+                (0, 4),
+                (0, 5),
+                (1, 0),
+                (1, 1)
+            ]
+        );
+        assert_eq!(text.size.x, 5. * advance);
+    }
+
+    #[test]
+    fn breaks_long_words_between_characters() {
+        let font = default_font();
+        let (advance, _) = metrics(&font);
+        let text = layout(&font, "abcde", SIZE, Some(2.5 * advance));
+
+        assert_eq!(
+            positions(&font, &text),
+            [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0)]
+        );
+        assert_eq!(text.size.x, 2. * advance);
+    }
+
+    #[test]
+    fn allocates_glyphs_in_rows() {
+        let mut atlas = GlyphAtlas::new();
+        let width = (ATLAS_SIZE - 3 * ATLAS_PADDING) / 2;
+
+        let first = atlas.allocate(width, 10).unwrap();
+        let second = atlas.allocate(width, 20).unwrap();
+        let third = atlas.allocate(width, 10).unwrap();
+        assert_eq!(first, (ATLAS_PADDING, ATLAS_PADDING));
+        assert_eq!(second, (2 * ATLAS_PADDING + width, ATLAS_PADDING));
+        // below the tallest glyph of the row
+        assert_eq!(third, (ATLAS_PADDING, 2 * ATLAS_PADDING + 20));
+
+        assert_eq!(atlas.allocate(ATLAS_SIZE, 1), None);
+        assert!(atlas.full);
+    }
+}
@@ -0,0 +1,197 @@
+//! Sampled images and the samplers they are read with.
+
+use crate::resources::{Device, Image, ImageView, Sampler};
+use crate::swapchain::color_subresource_range;
+use crate::upload::{record_image_copy, UploadHandle, UploadQueue, Uploader};
+use ash::version::DeviceV1_0;
+use ash::vk;
+use std::sync::Arc;
+
+/// Tightly packed 8 bit RGBA pixels, before they are uploaded.
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// Whether the color channels are sRGB encoded, as for base color and emissive textures, or
+    /// linear, as for normal and other data textures.
+    pub srgb: bool,
+}
+
+impl TextureData {
+    /// A 1x1 texture of `color`.
+    pub fn solid(color: [u8; 4], srgb: bool) -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            pixels: color.to_vec(),
+            srgb,
+        }
+    }
+
+    pub fn format(&self) -> vk::Format {
+        if self.srgb {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+        }
+    }
+}
+
+impl SamplerDesc {
+    pub fn create(&self, device: &Arc<Device>) -> Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(self.address_mode_u)
+            .address_mode_v(self.address_mode_v)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .expect("failed to create sampler!")
+        };
+
+        Sampler::from_raw(device, sampler)
+    }
+}
+
+/// An image in `SHADER_READ_ONLY_OPTIMAL` layout along with the view and sampler to read it. Most
+/// are 2D, the environment's are cube maps.
+pub struct Texture {
+    pub sampler: Sampler,
+    pub view: ImageView,
+    pub image: Image,
+    pub extent: vk::Extent2D,
+}
+
+impl Texture {
+    /// Records uploading `data` into `uploads`, like [`Self::queue_pixels`].
+    pub fn queue(
+        uploads: &mut UploadQueue,
+        data: &TextureData,
+        sampler: &SamplerDesc,
+    ) -> (Self, UploadHandle) {
+        assert_eq!(
+            data.pixels.len(),
+            data.width as usize * data.height as usize * 4,
+            "texture data must be tightly packed RGBA"
+        );
+
+        let extent = vk::Extent2D {
+            width: data.width,
+            height: data.height,
+        };
+        Self::queue_pixels(uploads, extent, data.format(), &data.pixels, sampler)
+    }
+
+    /// Uploads tightly packed `pixels` of `format`, for formats other than 8 bit RGBA.
+    pub fn from_pixels<T: Copy>(
+        uploader: &Uploader,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        pixels: &[T],
+        sampler: &SamplerDesc,
+    ) -> Self {
+        let texture = Self::uninitialized(uploader.device(), extent, format, sampler);
+        let staging = uploader.create_staging_buffer(pixels);
+        uploader.submit(|command_buffer| {
+            record_image_copy(
+                uploader.device(),
+                command_buffer,
+                staging.handle(),
+                0,
+                texture.image.handle(),
+                extent,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            );
+        });
+        texture
+    }
+
+    /// Like [`Self::from_pixels`], but records the copy into `uploads` rather than waiting for
+    /// it. The texture can be drawn with once `uploads` is submitted, and has to be kept until
+    /// the returned handle resolves.
+    pub fn queue_pixels<T: Copy>(
+        uploads: &mut UploadQueue,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        pixels: &[T],
+        sampler: &SamplerDesc,
+    ) -> (Self, UploadHandle) {
+        let texture = Self::uninitialized(uploads.device(), extent, format, sampler);
+        let handle = uploads.copy_to_image(texture.image.handle(), extent, pixels);
+        (texture, handle)
+    }
+
+    /// A texture whose image has yet to be written and transitioned.
+    fn uninitialized(
+        device: &Arc<Device>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        sampler: &SamplerDesc,
+    ) -> Self {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let image = Image::new(device, &image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+        let view = ImageView::new(
+            device,
+            image.handle(),
+            vk::ImageViewType::TYPE_2D,
+            format,
+            color_subresource_range(),
+        );
+
+        Self {
+            sampler: sampler.create(device),
+            view,
+            image,
+            extent,
+        }
+    }
+
+    /// Descriptor for binding the texture, as a sampled image, a sampler or both combined.
+    pub fn descriptor(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler.handle(),
+            image_view: self.view.handle(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+}
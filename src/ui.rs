@@ -0,0 +1,718 @@
+//! Debug overlay drawn with [`egui`] on top of the finished frame.
+//!
+//! Each window runs its own egui context, which gets the window's winit events forwarded to it.
+//! The meshes a frame's UI tessellates into are copied into vertex and index buffers of that frame
+//! in flight, and drawn by the last pass, straight onto the swapchain image.
+
+use crate::resources::{Buffer, DescriptorPool, DescriptorSetLayout, Device};
+use crate::sync::DeletionQueue;
+use crate::texture::{SamplerDesc, Texture, TextureData};
+use crate::upload::UploadQueue;
+use ash::version::DeviceV1_0;
+use ash::vk;
+use glam::Vec2;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::sync::Arc;
+use winit::event::{ElementState, ModifiersState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event::{KeyboardInput, VirtualKeyCode};
+
+/// Textures a window's UI can have at once. egui only needs its font atlas unless images are
+/// shown.
+pub const MAX_UI_TEXTURES: u32 = 8;
+
+/// Push constants of the UI pipeline.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct UiPushConstants {
+    /// Size of the target in points, egui's logical pixels.
+    pub screen_size: Vec2,
+    /// Whether the target encodes to sRGB when written to, so the shader has to decode first.
+    pub srgb_target: u32,
+    _padding: u32,
+}
+
+impl UiPushConstants {
+    pub fn new(screen_size: Vec2, srgb_target: bool) -> Self {
+        Self {
+            screen_size,
+            srgb_target: srgb_target as u32,
+            _padding: 0,
+        }
+    }
+}
+
+/// Binding of [`egui::epaint::Vertex`] buffers.
+pub fn vertex_binding_description() -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: std::mem::size_of::<egui::epaint::Vertex>() as u32,
+        input_rate: vk::VertexInputRate::VERTEX,
+    }
+}
+
+/// Attributes of [`egui::epaint::Vertex`], which is `repr(C)`: the position and texture
+/// coordinates in floats, then the color in bytes.
+pub fn vertex_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+    let attribute = |location, format, offset| vk::VertexInputAttributeDescription {
+        location,
+        binding: 0,
+        format,
+        offset,
+    };
+
+    [
+        attribute(0, vk::Format::R32G32_SFLOAT, 0),
+        attribute(1, vk::Format::R32G32_SFLOAT, 8),
+        attribute(2, vk::Format::R8G8B8A8_UNORM, 16),
+    ]
+}
+
+/// A texture egui asked for, along with the set it is bound with.
+struct UiTexture {
+    // kept alive while the set points at it
+    _texture: Texture,
+    /// Kept to apply partial updates to, which replace the whole texture.
+    image: egui::ColorImage,
+    set: vk::DescriptorSet,
+}
+
+/// Vertex and index buffers of one frame in flight, grown when a frame's UI no longer fits.
+#[derive(Default)]
+struct FrameBuffers {
+    vertices: Option<Buffer>,
+    indices: Option<Buffer>,
+}
+
+/// One mesh of the UI, drawn with its own scissor and texture.
+struct DrawCall {
+    scissor: vk::Rect2D,
+    texture: egui::TextureId,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// One window's UI: its egui context, the input collected for its next frame and the GPU
+/// resources it is drawn with.
+pub struct UiLayer {
+    pub context: egui::Context,
+    /// Hidden UIs neither draw nor take input.
+    pub visible: bool,
+    input: egui::RawInput,
+    pixels_per_point: f32,
+    modifiers: egui::Modifiers,
+    /// Where the cursor is, in points.
+    pointer: Option<egui::Pos2>,
+    /// Accumulated over the frames that ran until the textures were updated.
+    textures_delta: egui::TexturesDelta,
+    primitives: Vec<egui::ClippedPrimitive>,
+    draw_calls: Vec<DrawCall>,
+    // one per frame in flight
+    frames: Vec<FrameBuffers>,
+    textures: HashMap<egui::TextureId, UiTexture>,
+    /// Shared with the frees of retired sets.
+    pool: Rc<DescriptorPool>,
+    set_layout: vk::DescriptorSetLayout,
+    device: Arc<Device>,
+}
+
+impl UiLayer {
+    /// Creates the UI of a window with `scale_factor` physical pixels per point. Its textures
+    /// are bound with sets of `set_layout`, which has one sampled image and one sampler.
+    pub fn new(
+        device: &Arc<Device>,
+        set_layout: &DescriptorSetLayout,
+        frames_in_flight: usize,
+        scale_factor: f64,
+        max_texture_side: u32,
+    ) -> Self {
+        let pool_sizes = [
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::SAMPLER,
+        ]
+        .map(|ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: MAX_UI_TEXTURES,
+        });
+
+        // textures come and go as egui asks, so their sets are freed individually
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(MAX_UI_TEXTURES)
+            .pool_sizes(&pool_sizes);
+
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .expect("failed to create descriptor pool!")
+        };
+
+        let input = egui::RawInput {
+            max_texture_side: Some(max_texture_side as usize),
+            ..Default::default()
+        };
+
+        Self {
+            context: egui::Context::default(),
+            visible: true,
+            input,
+            pixels_per_point: scale_factor as f32,
+            modifiers: egui::Modifiers::default(),
+            pointer: None,
+            textures_delta: egui::TexturesDelta::default(),
+            primitives: Vec::new(),
+            draw_calls: Vec::new(),
+            frames: (0..frames_in_flight)
+                .map(|_| FrameBuffers::default())
+                .collect(),
+            textures: HashMap::new(),
+            pool: Rc::new(DescriptorPool::from_raw(device, pool)),
+            set_layout: set_layout.handle(),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Forwards a window event to egui. Returns whether the UI uses it, in which case the rest of
+    /// the application should ignore it.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+
+        let ppp = self.pixels_per_point;
+        let modifiers = self.modifiers;
+        let wants_pointer =
+            self.context.is_pointer_over_area() || self.context.wants_pointer_input();
+        let wants_keyboard = self.context.wants_keyboard_input();
+
+        match *event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let pos = egui::pos2(position.x as f32 / ppp, position.y as f32 / ppp);
+                self.pointer = Some(pos);
+                self.input.events.push(egui::Event::PointerMoved(pos));
+                // the cursor is still tracked elsewhere, or drags would jump when they leave the UI
+                false
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer = None;
+                self.input.events.push(egui::Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => egui::PointerButton::Primary,
+                    MouseButton::Right => egui::PointerButton::Secondary,
+                    MouseButton::Middle => egui::PointerButton::Middle,
+                    MouseButton::Other(_) => return false,
+                };
+                let pressed = state == ElementState::Pressed;
+                if let Some(pos) = self.pointer {
+                    self.input.events.push(egui::Event::PointerButton {
+                        pos,
+                        button,
+                        pressed,
+                        modifiers,
+                    });
+                }
+                // releases go everywhere, so nothing is left thinking the button is still held
+                pressed && wants_pointer
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        (egui::MouseWheelUnit::Line, egui::vec2(x, y))
+                    }
+                    MouseScrollDelta::PixelDelta(delta) => (
+                        egui::MouseWheelUnit::Point,
+                        egui::vec2(delta.x as f32, delta.y as f32) / ppp,
+                    ),
+                };
+                self.input.events.push(egui::Event::MouseWheel {
+                    unit,
+                    delta,
+                    modifiers,
+                });
+                wants_pointer
+            }
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                self.input.events.push(egui::Event::Text(c.to_string()));
+                wants_keyboard
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                if let Some(key) = egui_key(key) {
+                    self.input.events.push(egui::Event::Key {
+                        key,
+                        physical_key: None,
+                        pressed,
+                        repeat: false,
+                        modifiers,
+                    });
+                }
+                pressed && wants_keyboard
+            }
+            WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = egui_modifiers(state);
+                self.input.modifiers = self.modifiers;
+                false
+            }
+            WindowEvent::Focused(focused) => {
+                self.input.focused = focused;
+                self.input.events.push(egui::Event::WindowFocused(focused));
+                false
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.pixels_per_point = scale_factor as f32;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs a frame of the UI, built by `build`, on a target of `extent` pixels. `time` is in
+    /// seconds since any fixed point.
+    pub fn run(&mut self, extent: vk::Extent2D, time: f64, build: impl FnMut(&egui::Context)) {
+        let ppp = self.pixels_per_point;
+        let mut input = std::mem::take(&mut self.input);
+        input.screen_rect = Some(egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(extent.width as f32, extent.height as f32) / ppp,
+        ));
+        input.time = Some(time);
+        input
+            .viewports
+            .entry(egui::ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(ppp);
+
+        // what is left of the input is carried over to the next frame
+        self.input = egui::RawInput {
+            max_texture_side: input.max_texture_side,
+            modifiers: input.modifiers,
+            focused: input.focused,
+            ..Default::default()
+        };
+
+        let output = self.context.run(input, build);
+        self.textures_delta.append(output.textures_delta);
+        self.primitives = if self.visible {
+            self.context
+                .tessellate(output.shapes, output.pixels_per_point)
+        } else {
+            Vec::new()
+        };
+        self.pixels_per_point = output.pixels_per_point;
+    }
+
+    /// Applies the texture updates of the frames that ran since the last upload, recording their
+    /// copies into `uploads`, and copies the last frame's meshes into the buffers of frame in
+    /// flight `frame`. Replaced and freed textures are handed to `deletions`, to go once the
+    /// frames still drawing with them are done.
+    pub fn upload(
+        &mut self,
+        uploads: &mut UploadQueue,
+        deletions: &mut DeletionQueue,
+        frame: usize,
+    ) {
+        let delta = std::mem::take(&mut self.textures_delta);
+        for (id, image_delta) in delta.set {
+            self.update_texture(uploads, deletions, id, image_delta);
+        }
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        self.draw_calls.clear();
+        for primitive in &self.primitives {
+            let mesh = match &primitive.primitive {
+                egui::epaint::Primitive::Mesh(mesh) => mesh,
+                // no paint callbacks are registered
+                egui::epaint::Primitive::Callback(_) => continue,
+            };
+            let scissor = match self.scissor(primitive.clip_rect) {
+                Some(scissor) => scissor,
+                None => continue,
+            };
+            if mesh.indices.is_empty() {
+                continue;
+            }
+
+            self.draw_calls.push(DrawCall {
+                scissor,
+                texture: mesh.texture_id,
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        let device = &self.device;
+        let buffers = &mut self.frames[frame];
+        Buffer::write_growing(
+            device,
+            &mut buffers.vertices,
+            &vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        Buffer::write_growing(
+            device,
+            &mut buffers.indices,
+            &indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        );
+
+        // freed after the frame that last drew with them, like egui expects
+        for id in delta.free {
+            if let Some(texture) = self.textures.remove(&id) {
+                self.retire_texture(deletions, texture);
+            }
+        }
+    }
+
+    /// Records drawing the meshes of the last upload, which went into the buffers of frame in
+    /// flight `frame`, with `pipeline` onto a target of `extent`. The viewport has to cover the
+    /// target already.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        frame: usize,
+        extent: vk::Extent2D,
+        srgb_target: bool,
+    ) {
+        let buffers = &self.frames[frame];
+        let (vertices, indices) = match (&buffers.vertices, &buffers.indices) {
+            (Some(vertices), Some(indices)) if !self.draw_calls.is_empty() => (vertices, indices),
+            _ => return,
+        };
+
+        let screen_size =
+            Vec2::new(extent.width as f32, extent.height as f32) / self.pixels_per_point;
+        let constants = UiPushConstants::new(screen_size, srgb_target);
+        let device = &self.device;
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertices.handle()], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                indices.handle(),
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                crate::as_bytes(&constants),
+            );
+
+            for draw in &self.draw_calls {
+                let texture = match self.textures.get(&draw.texture) {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+                device.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[texture.set],
+                    &[],
+                );
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    draw.index_count,
+                    1,
+                    draw.first_index,
+                    draw.vertex_offset,
+                    0,
+                );
+            }
+        }
+    }
+
+    /// Creates texture `id`, or replaces it with one that has `delta` applied.
+    fn update_texture(
+        &mut self,
+        uploads: &mut UploadQueue,
+        deletions: &mut DeletionQueue,
+        id: egui::TextureId,
+        delta: egui::epaint::ImageDelta,
+    ) {
+        let patch = match &delta.image {
+            egui::ImageData::Color(image) => egui::ColorImage::clone(image),
+            egui::ImageData::Font(image) => egui::ColorImage {
+                size: image.size,
+                pixels: image.srgba_pixels(None).collect(),
+            },
+        };
+
+        let existing = self.textures.remove(&id);
+        let image = match (delta.pos, existing.as_ref()) {
+            (Some([x, y]), Some(existing)) => {
+                let mut image = existing.image.clone();
+                let [width, height] = patch.size;
+                for row in 0..height {
+                    let src = row * width;
+                    let dst = (y + row) * image.size[0] + x;
+                    image.pixels[dst..dst + width].copy_from_slice(&patch.pixels[src..src + width]);
+                }
+                image
+            }
+            (Some(_), None) => {
+                log::warn!("ignoring partial update of unknown UI texture {:?}", id);
+                return;
+            }
+            (None, _) => patch,
+        };
+
+        let data = TextureData {
+            width: image.size[0] as u32,
+            height: image.size[1] as u32,
+            pixels: image
+                .pixels
+                .iter()
+                .flat_map(|color| color.to_array())
+                .collect(),
+            // decoded by the shader when the target needs it, egui blends in gamma space
+            srgb: false,
+        };
+        let filter = |filter| match filter {
+            egui::TextureFilter::Nearest => vk::Filter::NEAREST,
+            egui::TextureFilter::Linear => vk::Filter::LINEAR,
+        };
+        let address_mode = match delta.options.wrap_mode {
+            egui::TextureWrapMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            egui::TextureWrapMode::Repeat => vk::SamplerAddressMode::REPEAT,
+            egui::TextureWrapMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        };
+        let sampler = SamplerDesc {
+            mag_filter: filter(delta.options.magnification),
+            min_filter: filter(delta.options.minification),
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+        };
+        let extent = vk::Extent2D {
+            width: data.width,
+            height: data.height,
+        };
+        // frames submitted after the copy can sample it, so the handle is not needed
+        let (texture, _) =
+            Texture::queue_pixels(uploads, extent, data.format(), &data.pixels, &sampler);
+
+        // frames in flight may still draw with the existing set, so it is replaced rather than
+        // written
+        if let Some(existing) = existing {
+            self.retire_texture(deletions, existing);
+        }
+        let set = self.allocate_set();
+        let image_info = texture.descriptor();
+        // the image view is ignored by the sampler write and the sampler by the image write
+        let descriptor_types = [
+            vk::DescriptorType::SAMPLED_IMAGE,
+            vk::DescriptorType::SAMPLER,
+        ];
+        let writes = [0, 1].map(|binding| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(binding)
+                .descriptor_type(descriptor_types[binding as usize])
+                .image_info(std::slice::from_ref(&image_info))
+                .build()
+        });
+        unsafe { self.device.update_descriptor_sets(&writes, &[]) };
+
+        self.textures.insert(
+            id,
+            UiTexture {
+                _texture: texture,
+                image,
+                set,
+            },
+        );
+    }
+
+    /// Frees `texture` and its set once the frames drawing with them are done.
+    fn retire_texture(&self, deletions: &mut DeletionQueue, texture: UiTexture) {
+        let device = Arc::clone(&self.device);
+        let pool = Rc::clone(&self.pool);
+        deletions.defer(move || {
+            unsafe {
+                device
+                    .free_descriptor_sets(pool.handle(), &[texture.set])
+                    .expect("failed to free descriptor set!");
+            }
+            drop(texture);
+        });
+    }
+
+    fn allocate_set(&self) -> vk::DescriptorSet {
+        let layouts = [self.set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.pool.handle())
+            .set_layouts(&layouts);
+
+        unsafe {
+            self.device
+                .allocate_descriptor_sets(&alloc_info)
+                .expect("failed to allocate UI descriptor set, too many UI textures!")[0]
+        }
+    }
+
+    /// Converts a clip rectangle in points into a scissor in pixels, `None` if nothing of it is
+    /// on the screen.
+    fn scissor(&self, clip_rect: egui::Rect) -> Option<vk::Rect2D> {
+        let screen = self.context.screen_rect();
+        let clip_rect = clip_rect.intersect(screen);
+        let min = (clip_rect.min.to_vec2() * self.pixels_per_point).round();
+        let max = (clip_rect.max.to_vec2() * self.pixels_per_point).round();
+        let size = max - min;
+        if size.x < 1. || size.y < 1. {
+            return None;
+        }
+
+        Some(vk::Rect2D {
+            offset: vk::Offset2D {
+                x: min.x as i32,
+                y: min.y as i32,
+            },
+            extent: vk::Extent2D {
+                width: size.x as u32,
+                height: size.y as u32,
+            },
+        })
+    }
+}
+
+fn egui_modifiers(state: ModifiersState) -> egui::Modifiers {
+    egui::Modifiers {
+        alt: state.alt(),
+        ctrl: state.ctrl(),
+        shift: state.shift(),
+        mac_cmd: cfg!(target_os = "macos") && state.logo(),
+        command: if cfg!(target_os = "macos") {
+            state.logo()
+        } else {
+            state.ctrl()
+        },
+    }
+}
+
+/// The egui key for `key`, if it is one egui's widgets react to.
+fn egui_key(key: VirtualKeyCode) -> Option<egui::Key> {
+    use egui::Key;
+    use VirtualKeyCode as V;
+
+    Some(match key {
+        V::Down => Key::ArrowDown,
+        V::Left => Key::ArrowLeft,
+        V::Right => Key::ArrowRight,
+        V::Up => Key::ArrowUp,
+        V::Escape => Key::Escape,
+        V::Tab => Key::Tab,
+        V::Back => Key::Backspace,
+        V::Return | V::NumpadEnter => Key::Enter,
+        V::Space => Key::Space,
+        V::Insert => Key::Insert,
+        V::Delete => Key::Delete,
+        V::Home => Key::Home,
+        V::End => Key::End,
+        V::PageUp => Key::PageUp,
+        V::PageDown => Key::PageDown,
+        V::A => Key::A,
+        V::C => Key::C,
+        V::V => Key::V,
+        V::X => Key::X,
+        V::Y => Key::Y,
+        V::Z => Key::Z,
+        _ => return None,
+    })
+}
+
+/// Frame times of the last few seconds, to graph.
+pub struct FrameTimes {
+    times: VecDeque<f32>,
+}
+
+impl Default for FrameTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameTimes {
+    /// Frames the graph shows.
+    const CAPACITY: usize = 240;
+
+    pub fn new() -> Self {
+        Self {
+            times: VecDeque::with_capacity(Self::CAPACITY),
+        }
+    }
+
+    /// Records a frame that took `seconds`.
+    pub fn push(&mut self, seconds: f32) {
+        if self.times.len() == Self::CAPACITY {
+            self.times.pop_front();
+        }
+        self.times.push_back(seconds);
+    }
+
+    /// Average over the recorded frames, in seconds.
+    pub fn average(&self) -> f32 {
+        self.times.iter().sum::<f32>() / self.times.len().max(1) as f32
+    }
+
+    /// Adds a graph of the frame times to `ui`, scaled so the slowest frame fits, with lines at
+    /// the times of 60 and 30 frames per second where they are in range.
+    pub fn graph(&self, ui: &mut egui::Ui) {
+        let size = egui::vec2(ui.available_width(), 60.);
+        let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+        painter.rect_filled(rect, 2., visuals.extreme_bg_color);
+
+        let max = self.times.iter().copied().fold(1. / 60., f32::max) * 1.1;
+        let y = |seconds: f32| rect.bottom() - seconds / max * rect.height();
+        for (fps, color) in [
+            (60., egui::Color32::DARK_GREEN),
+            (30., egui::Color32::DARK_RED),
+        ] {
+            if 1. / fps < max {
+                let y = y(1. / fps);
+                painter.hline(rect.x_range(), y, egui::Stroke::new(1., color));
+            }
+        }
+
+        let step = rect.width() / (Self::CAPACITY - 1) as f32;
+        let offset = Self::CAPACITY - self.times.len();
+        let points = self
+            .times
+            .iter()
+            .enumerate()
+            .map(|(i, &seconds)| egui::pos2(rect.left() + (offset + i) as f32 * step, y(seconds)))
+            .collect::<Vec<_>>();
+        painter.add(egui::Shape::line(
+            points,
+            egui::Stroke::new(1., visuals.text_color()),
+        ));
+    }
+}
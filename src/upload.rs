@@ -0,0 +1,826 @@
+//! Copying data from the host into device local buffers and images, either waiting for the
+//! copies with an [`Uploader`] or not with an [`UploadQueue`].
+
+use crate::resources::{Buffer, CommandPool, Device, Fence};
+use crate::swapchain::color_subresource_range;
+use crate::sync::{Submission, Timeline};
+use ash::version::DeviceV1_0;
+use ash::vk;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Records transfer commands into one-off command buffers and waits for them to complete.
+pub struct Uploader {
+    queue: vk::Queue,
+    command_pool: CommandPool,
+    device: Arc<Device>,
+}
+
+impl Uploader {
+    /// Submits to `queue`, which must be from the family at `queue_family_index` and support
+    /// transfers.
+    pub fn new(device: &Arc<Device>, queue_family_index: u32, queue: vk::Queue) -> Self {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family_index);
+
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&pool_info, None)
+                .expect("failed to create command pool!")
+        };
+
+        Self {
+            queue,
+            command_pool: CommandPool::from_raw(device, command_pool),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    pub fn command_pool(&self) -> &CommandPool {
+        &self.command_pool
+    }
+
+    /// Records commands with `record`, submits them and waits until they have executed.
+    pub fn submit<F: FnOnce(vk::CommandBuffer)>(&self, record: F) {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool.handle())
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+
+        let command_buffer = unsafe {
+            self.device
+                .allocate_command_buffers(&alloc_info)
+                .expect("failed to allocate command buffers!")[0]
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        unsafe {
+            self.device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .expect("failed to begin recording command buffer!");
+        }
+
+        record(command_buffer);
+
+        let fence = Fence::new(&self.device, false);
+        let submit_info =
+            vk::SubmitInfo::builder().command_buffers(std::slice::from_ref(&command_buffer));
+
+        unsafe {
+            self.device
+                .end_command_buffer(command_buffer)
+                .expect("failed to record command buffer!");
+            self.device
+                .queue_submit(self.queue, &[submit_info.build()], fence.handle())
+                .expect("failed to submit upload command buffer!");
+            self.device
+                .wait_for_fences(&[fence.handle()], true, u64::MAX)
+                .expect("failed to wait for fences!");
+            self.device
+                .free_command_buffers(self.command_pool.handle(), &[command_buffer]);
+        }
+    }
+
+    /// Returns a host visible buffer holding `data`, to copy from.
+    pub fn create_staging_buffer<T: Copy>(&self, data: &[T]) -> Buffer {
+        let buffer = Buffer::new(
+            &self.device,
+            std::mem::size_of_val(data) as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+        buffer.write(data);
+        buffer
+    }
+}
+
+/// Size of [`UploadQueue`]'s staging ring. Copies that do not fit get a staging buffer of their
+/// own.
+pub const STAGING_RING_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
+/// Alignment of copies in the staging ring, enough for the offsets of buffer to image copies of
+/// any uncompressed format.
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+/// Stages that read what [`UploadQueue`] copies, which wait for the copies.
+pub const UPLOAD_READ_STAGES: vk::PipelineStageFlags = vk::PipelineStageFlags::from_raw(
+    vk::PipelineStageFlags::VERTEX_INPUT.as_raw()
+        | vk::PipelineStageFlags::VERTEX_SHADER.as_raw()
+        | vk::PipelineStageFlags::FRAGMENT_SHADER.as_raw()
+        | vk::PipelineStageFlags::COMPUTE_SHADER.as_raw(),
+);
+/// Accesses of [`UPLOAD_READ_STAGES`] to buffers [`UploadQueue`] copies to.
+const UPLOAD_BUFFER_READS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::VERTEX_ATTRIBUTE_READ.as_raw()
+        | vk::AccessFlags::INDEX_READ.as_raw()
+        | vk::AccessFlags::UNIFORM_READ.as_raw()
+        | vk::AccessFlags::SHADER_READ.as_raw(),
+);
+
+/// Resolves once the copies it was handed out for have completed, and the data is resident.
+#[derive(Clone, Debug)]
+pub struct UploadHandle {
+    /// Value of the batch the copies were recorded into, zero until it is submitted.
+    value: Rc<Cell<u64>>,
+    completed: Rc<Cell<u64>>,
+}
+
+impl UploadHandle {
+    /// Whether the copies have completed. Commands submitted to the upload queue's queue after
+    /// [`UploadQueue::submit`] can use the data before then, the copies' barriers order them, as
+    /// can those of [`UploadQueue::record_acquires`] waiting for the copies' timeline value.
+    pub fn is_resident(&self) -> bool {
+        let value = self.value.get();
+        value != 0 && self.completed.get() >= value
+    }
+
+    /// The value the queue's timeline reaches once the copies have completed, or `None` until
+    /// they are submitted. Without a timeline, values only order the upload queue's batches.
+    pub fn value(&self) -> Option<u64> {
+        Some(self.value.get()).filter(|&value| value != 0)
+    }
+}
+
+/// Copies recorded into a command buffer, and the staging memory they read.
+struct UploadBatch {
+    /// Shared with the batch's handles, set once it is submitted.
+    value: Rc<Cell<u64>>,
+    command_buffer: vk::CommandBuffer,
+    /// Where the batch's copies end in the staging ring, which is free up to there once it has
+    /// completed.
+    ring_end: vk::DeviceSize,
+    /// Staging buffers of copies that did not fit the ring.
+    staging: Vec<Buffer>,
+    /// Signaled on completion where there are no timeline semaphores.
+    fence: Option<Fence>,
+}
+
+/// Copies data from the host into buffers and images without waiting for them. Copies are staged
+/// in a persistent host visible ring buffer and batched into one command buffer until
+/// [`Self::submit`], which signals the queue's [`Timeline`] with the batch's value on completion,
+/// where there is one, and a fence otherwise. Each copy hands out an [`UploadHandle`]
+/// resolving once its batch completes, found by [`Self::poll`].
+///
+/// When the queue is of another family than the queues using the copies, such as a transfer
+/// only one, the copies release their destinations to that family, and the command buffers
+/// using them first have to acquire them with [`Self::record_acquires`].
+///
+/// The destinations have to outlive the copies, until their handles resolve.
+pub struct UploadQueue {
+    ring: Buffer,
+    /// The ring's memory, mapped for as long as the queue lives.
+    ring_data: *mut u8,
+    ring_space: StagingRing,
+    recording: Option<UploadBatch>,
+    in_flight: VecDeque<UploadBatch>,
+    free_command_buffers: Vec<vk::CommandBuffer>,
+    /// Value of the last batch submitted without a timeline.
+    last_value: u64,
+    completed: Rc<Cell<u64>>,
+    timeline: Option<Rc<Timeline>>,
+    /// The queue's family and the family using the copies, if they differ.
+    ownership_transfer: Option<(u32, u32)>,
+    /// Barriers acquiring what was released to the using family, not recorded yet.
+    buffer_acquires: Vec<vk::BufferMemoryBarrier>,
+    image_acquires: Vec<vk::ImageMemoryBarrier>,
+    queue: vk::Queue,
+    command_pool: CommandPool,
+    device: Arc<Device>,
+}
+
+/// Space in the staging ring, handed out in order and reclaimed in the same order.
+#[derive(Clone, Copy, Debug)]
+struct StagingRing {
+    /// Offsets into the ring, counting up across wraps: copies before `tail` have completed, and
+    /// those up to `head` are recorded.
+    head: vk::DeviceSize,
+    tail: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+impl StagingRing {
+    fn new(size: vk::DeviceSize) -> Self {
+        Self {
+            head: 0,
+            tail: 0,
+            size,
+        }
+    }
+
+    /// Reserves `size` bytes, returning their offset into the ring, or `None` if the copies that
+    /// are not complete yet leave too little room.
+    fn allocate(&mut self, size: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let mut start = self.head.next_multiple_of(STAGING_ALIGNMENT);
+        // copies never wrap around the end of the ring
+        let offset = start % self.size;
+        if offset + size > self.size {
+            start += self.size - offset;
+        }
+        if start + size - self.tail > self.size {
+            return None;
+        }
+
+        self.head = start + size;
+        Some(start % self.size)
+    }
+
+    /// Frees everything allocated before `head`, which the copies up to it have completed.
+    fn reclaim(&mut self, head: vk::DeviceSize) {
+        self.tail = head;
+    }
+}
+
+impl UploadQueue {
+    /// Submits to `queue`, which must be from the family at `queue_family_index` and support
+    /// transfers, for queues of the family at `dst_queue_family_index` to use the copies.
+    /// `timeline`, if given, has to be the queue's, which batches then take their values from.
+    pub fn new(
+        device: &Arc<Device>,
+        queue_family_index: u32,
+        queue: vk::Queue,
+        timeline: Option<Rc<Timeline>>,
+        dst_queue_family_index: u32,
+    ) -> Self {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(
+                vk::CommandPoolCreateFlags::TRANSIENT
+                    | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            )
+            .queue_family_index(queue_family_index);
+        let command_pool = unsafe {
+            device
+                .create_command_pool(&pool_info, None)
+                .expect("failed to create command pool!")
+        };
+
+        let ring = Buffer::new(
+            device,
+            STAGING_RING_SIZE,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        );
+        let ring_data = unsafe {
+            device
+                .map_memory(
+                    ring.memory(),
+                    0,
+                    vk::WHOLE_SIZE,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .expect("failed to map buffer memory!") as *mut u8
+        };
+
+        Self {
+            ring,
+            ring_data,
+            ring_space: StagingRing::new(STAGING_RING_SIZE),
+            recording: None,
+            in_flight: VecDeque::new(),
+            free_command_buffers: Vec::new(),
+            last_value: 0,
+            completed: Rc::new(Cell::new(0)),
+            timeline,
+            ownership_transfer: (queue_family_index != dst_queue_family_index)
+                .then_some((queue_family_index, dst_queue_family_index)),
+            buffer_acquires: Vec::new(),
+            image_acquires: Vec::new(),
+            queue,
+            command_pool: CommandPool::from_raw(device, command_pool),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// The timeline batches signal, if there is one.
+    pub fn timeline(&self) -> Option<&Rc<Timeline>> {
+        self.timeline.as_ref()
+    }
+
+    /// Returns a device local buffer with `usage` that `data` is copied into, and the copy's
+    /// handle.
+    pub fn create_buffer<T: Copy>(
+        &mut self,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+    ) -> (Buffer, UploadHandle) {
+        let buffer = Buffer::new(
+            &self.device,
+            std::mem::size_of_val(data) as vk::DeviceSize,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let handle = self.copy_to_buffer(&buffer, 0, data);
+        (buffer, handle)
+    }
+
+    /// Records copying `data` to `buffer` at `offset`. `buffer` needs `TRANSFER_DST` usage; the
+    /// copy is made available to vertex, index, uniform and shader reads.
+    pub fn copy_to_buffer<T: Copy>(
+        &mut self,
+        buffer: &Buffer,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> UploadHandle {
+        let (command_buffer, staging, staging_offset) = self.stage(data);
+        let region = vk::BufferCopy {
+            src_offset: staging_offset,
+            dst_offset: offset,
+            size: std::mem::size_of_val(data) as vk::DeviceSize,
+        };
+        unsafe {
+            self.device
+                .cmd_copy_buffer(command_buffer, staging, buffer.handle(), &[region]);
+        }
+
+        let barrier = |src_access, dst_access, (src_family, dst_family)| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .buffer(buffer.handle())
+                .offset(offset)
+                .size(region.size)
+                .build()
+        };
+        match self.ownership_transfer {
+            Some(families) => {
+                let release = barrier(
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::empty(),
+                    families,
+                );
+                self.release(command_buffer, &[release], &[]);
+                self.buffer_acquires.push(barrier(
+                    vk::AccessFlags::empty(),
+                    UPLOAD_BUFFER_READS,
+                    families,
+                ));
+            }
+            None => {
+                let families = (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED);
+                let barrier = barrier(
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    UPLOAD_BUFFER_READS,
+                    families,
+                );
+                unsafe {
+                    self.device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        UPLOAD_READ_STAGES,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[barrier],
+                        &[],
+                    );
+                }
+            }
+        }
+
+        self.handle()
+    }
+
+    /// Records copying tightly packed `pixels` to the first level and layer of the color image
+    /// `image` of `extent`, whose previous contents are discarded, and leaving it in
+    /// `SHADER_READ_ONLY_OPTIMAL` layout for fragment and compute shaders.
+    pub fn copy_to_image<T: Copy>(
+        &mut self,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        pixels: &[T],
+    ) -> UploadHandle {
+        let (command_buffer, staging, staging_offset) = self.stage(pixels);
+        let families = match self.ownership_transfer {
+            Some(families) => families,
+            None => {
+                record_image_copy(
+                    &self.device,
+                    command_buffer,
+                    staging,
+                    staging_offset,
+                    image,
+                    extent,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::COMPUTE_SHADER,
+                );
+                return self.handle();
+            }
+        };
+
+        record_copy_to_image(
+            &self.device,
+            command_buffer,
+            staging,
+            staging_offset,
+            image,
+            extent,
+        );
+        // the layout transition is part of both the release and the acquire
+        let barrier = |src_access, dst_access| {
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(src_access)
+                .dst_access_mask(dst_access)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(families.0)
+                .dst_queue_family_index(families.1)
+                .image(image)
+                .subresource_range(color_subresource_range())
+                .build()
+        };
+        let release = barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty());
+        self.release(command_buffer, &[], &[release]);
+        self.image_acquires.push(barrier(
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::SHADER_READ,
+        ));
+
+        self.handle()
+    }
+
+    /// Records acquiring what the copies recorded since the last call released to the family
+    /// using them, if they are submitted to a queue of another family. `command_buffer` has to
+    /// be submitted after [`Self::submit`], waiting for the timeline's [`Timeline::last_value`]
+    /// before [`UPLOAD_READ_STAGES`], and before anything using the copies.
+    pub fn record_acquires(&mut self, command_buffer: vk::CommandBuffer) {
+        if self.buffer_acquires.is_empty() && self.image_acquires.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                // chained to the semaphore wait by its stages
+                UPLOAD_READ_STAGES,
+                UPLOAD_READ_STAGES,
+                vk::DependencyFlags::empty(),
+                &[],
+                &self.buffer_acquires,
+                &self.image_acquires,
+            );
+        }
+        self.buffer_acquires.clear();
+        self.image_acquires.clear();
+    }
+
+    /// Records releasing the destinations of copies to the family using them. Queues without
+    /// graphics or compute cannot name the stages that will read them, which is left to the
+    /// acquire.
+    fn release(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        buffers: &[vk::BufferMemoryBarrier],
+        images: &[vk::ImageMemoryBarrier],
+    ) {
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                buffers,
+                images,
+            );
+        }
+    }
+
+    /// Submits the copies recorded since the last submit, if there are any. Commands submitted
+    /// to the same queue afterwards can use what they copied, as can those submitted to another
+    /// queue that wait for the timeline's [`Timeline::last_value`].
+    pub fn submit(&mut self) {
+        let mut batch = match self.recording.take() {
+            Some(batch) => batch,
+            None => return,
+        };
+        batch.ring_end = self.ring_space.head;
+
+        let submission = match &self.timeline {
+            Some(timeline) => {
+                batch.value.set(timeline.next_value());
+                Submission::default().signal_timeline(timeline, batch.value.get())
+            }
+            None => {
+                self.last_value += 1;
+                batch.value.set(self.last_value);
+                batch.fence = Some(Fence::new(&self.device, false));
+                Submission::default()
+            }
+        };
+        let fence = batch
+            .fence
+            .as_ref()
+            .map_or_else(vk::Fence::null, Fence::handle);
+
+        unsafe {
+            self.device
+                .end_command_buffer(batch.command_buffer)
+                .expect("failed to record command buffer!");
+        }
+        submission.submit(
+            &self.device,
+            self.queue,
+            std::slice::from_ref(&batch.command_buffer),
+            fence,
+        );
+
+        self.in_flight.push_back(batch);
+    }
+
+    /// Finds the batches that have completed, resolving their handles and freeing what they
+    /// used, without waiting for the others.
+    pub fn poll(&mut self) {
+        let completed = match &self.timeline {
+            Some(timeline) => timeline.completed_value(),
+            // batches complete in submission order, as far as anyone waiting on them can tell
+            None => self
+                .in_flight
+                .iter()
+                .take_while(|batch| {
+                    batch.fence.as_ref().is_some_and(|fence| unsafe {
+                        self.device.get_fence_status(fence.handle()) == Ok(true)
+                    })
+                })
+                .last()
+                .map_or(self.completed.get(), |batch| batch.value.get()),
+        };
+
+        while let Some(batch) = self.in_flight.front() {
+            if batch.value.get() > completed {
+                break;
+            }
+            let batch = self.in_flight.pop_front().unwrap();
+            self.ring_space.reclaim(batch.ring_end);
+            self.free_command_buffers.push(batch.command_buffer);
+        }
+        self.completed.set(completed.max(self.completed.get()));
+    }
+
+    /// Copies `data` into staging memory, returning the command buffer to record copying it in
+    /// and where it is.
+    fn stage<T: Copy>(&mut self, data: &[T]) -> (vk::CommandBuffer, vk::Buffer, vk::DeviceSize) {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        let command_buffer = self.recording().command_buffer;
+
+        match self.ring_space.allocate(size) {
+            Some(offset) => {
+                // the ring is coherent, so the copy sees what is written here without a flush
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        data.as_ptr() as *const u8,
+                        self.ring_data.add(offset as usize),
+                        size as usize,
+                    );
+                }
+                (command_buffer, self.ring.handle(), offset)
+            }
+            None => {
+                let staging = Buffer::new(
+                    &self.device,
+                    size.max(1),
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                );
+                staging.write(data);
+                let handle = staging.handle();
+                self.recording().staging.push(staging);
+                (command_buffer, handle, 0)
+            }
+        }
+    }
+
+    /// The batch being recorded, begun if there is none.
+    fn recording(&mut self) -> &mut UploadBatch {
+        if self.recording.is_none() {
+            let command_buffer = match self.free_command_buffers.pop() {
+                Some(command_buffer) => command_buffer,
+                None => {
+                    let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                        .command_pool(self.command_pool.handle())
+                        .level(vk::CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1);
+                    unsafe {
+                        self.device
+                            .allocate_command_buffers(&alloc_info)
+                            .expect("failed to allocate command buffers!")[0]
+                    }
+                }
+            };
+
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            unsafe {
+                self.device
+                    .begin_command_buffer(command_buffer, &begin_info)
+                    .expect("failed to begin recording command buffer!");
+            }
+
+            self.recording = Some(UploadBatch {
+                value: Rc::new(Cell::new(0)),
+                command_buffer,
+                ring_end: self.ring_space.head,
+                staging: Vec::new(),
+                fence: None,
+            });
+        }
+
+        self.recording.as_mut().unwrap()
+    }
+
+    /// A handle for the copies of the batch being recorded.
+    fn handle(&self) -> UploadHandle {
+        let batch = self
+            .recording
+            .as_ref()
+            .expect("no copies are being recorded");
+        UploadHandle {
+            value: Rc::clone(&batch.value),
+            completed: Rc::clone(&self.completed),
+        }
+    }
+}
+
+impl Drop for UploadQueue {
+    fn drop(&mut self) {
+        // the staging memory and command buffers must outlive the copies in flight; those never
+        // submitted are dropped
+        let fences = self
+            .in_flight
+            .iter()
+            .filter_map(|batch| batch.fence.as_ref().map(Fence::handle))
+            .collect::<Vec<_>>();
+        if let Some(timeline) = &self.timeline {
+            timeline.wait(self.in_flight.back().map_or(0, |batch| batch.value.get()));
+        } else if !fences.is_empty() {
+            unsafe {
+                self.device
+                    .wait_for_fences(&fences, true, u64::MAX)
+                    .expect("failed to wait for fences!");
+            }
+        }
+        unsafe { self.device.unmap_memory(self.ring.memory()) };
+    }
+}
+
+/// Records copying from `staging` at `offset` to the first level and layer of the color image
+/// `image` of `extent`, whose previous contents are discarded, and leaving it in
+/// `SHADER_READ_ONLY_OPTIMAL` layout for `dst_stages`.
+pub fn record_image_copy(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    staging: vk::Buffer,
+    offset: vk::DeviceSize,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    dst_stages: vk::PipelineStageFlags,
+) {
+    record_copy_to_image(device, command_buffer, staging, offset, image, extent);
+
+    let to_shader_read = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(color_subresource_range())
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_shader_read],
+        );
+    }
+}
+
+/// Records copying from `staging` at `offset` to the first level and layer of the color image
+/// `image` of `extent`, whose previous contents are discarded, leaving it in
+/// `TRANSFER_DST_OPTIMAL` layout.
+fn record_copy_to_image(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    staging: vk::Buffer,
+    offset: vk::DeviceSize,
+    image: vk::Image,
+    extent: vk::Extent2D,
+) {
+    let to_transfer_dst = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(color_subresource_range())
+        .build();
+
+    let region = vk::BufferImageCopy {
+        buffer_offset: offset,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        },
+    };
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer_dst],
+        );
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_allocations() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(3), Some(0));
+        assert_eq!(ring.allocate(20), Some(16));
+        assert_eq!(ring.allocate(16), Some(48));
+        assert_eq!(ring.head, 64);
+    }
+
+    #[test]
+    fn never_wraps_across_the_end() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(200), Some(0));
+        ring.reclaim(200);
+        // 48 bytes are left before the end, so the copy starts over at the beginning
+        assert_eq!(ring.allocate(64), Some(0));
+        assert_eq!(ring.head, 256 + 64);
+    }
+
+    #[test]
+    fn refuses_allocations_while_full() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(128), Some(0));
+        let first = ring.head;
+        assert_eq!(ring.allocate(128), Some(128));
+        // the copies fall back to staging buffers of their own until the ring is reclaimed
+        assert_eq!(ring.allocate(16), None);
+        assert_eq!(ring.allocate(512), None);
+        assert_eq!(ring.head, 256);
+
+        ring.reclaim(first);
+        assert_eq!(ring.allocate(128), Some(0));
+        assert_eq!(ring.allocate(16), None);
+    }
+
+    #[test]
+    fn reclaims_the_space_of_completed_copies() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(160), Some(0));
+        let first = ring.head;
+        assert_eq!(ring.allocate(64), Some(160));
+        // wrapping would overwrite the first copy, which has not completed
+        assert_eq!(ring.allocate(64), None);
+
+        ring.reclaim(first);
+        assert_eq!(ring.allocate(64), Some(0));
+        assert_eq!(ring.allocate(96), Some(64));
+        assert_eq!(ring.allocate(16), None);
+    }
+}